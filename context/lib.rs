@@ -1,8 +0,0 @@
-pub mod db;
-pub mod page_header;
-pub mod sql;
-pub mod sqlite;
-pub mod sqlite_header;
-pub mod sqlite_table;
-pub mod sqlite_value;
-pub mod varint;
\ No newline at end of file
@@ -0,0 +1,49 @@
+//! Print the depth of each table's b-tree and how full its pages are, using
+//! [`Database::walk_btree`].
+//!
+//! ```text
+//! cargo run --example btree_shape -- sample.db
+//! ```
+
+use anyhow::{bail, Result};
+use sqlite_starter_rust::{
+  btree::{BtreePage, BtreeVisitor},
+  database::Database,
+};
+
+/// The deepest leaf reached, and the unused bytes of every page on the way.
+#[derive(Default)]
+struct Shape {
+  depth: usize,
+  pages: usize,
+  free_bytes: usize,
+}
+
+impl BtreeVisitor for Shape {
+  fn enter_interior(&mut self, page: &BtreePage) {
+    self.pages += 1;
+    self.free_bytes += page.free_bytes;
+  }
+
+  fn enter_leaf(&mut self, page: &BtreePage) {
+    self.depth = self.depth.max(page.depth);
+    self.pages += 1;
+    self.free_bytes += page.free_bytes;
+  }
+}
+
+fn main() -> Result<()> {
+  let Some(path) = std::env::args().nth(1) else {
+    bail!("Usage: btree_shape <database path>");
+  };
+  let file = std::fs::File::open(&path)?;
+  let db = Database::parse_header_and_schema(&file)?;
+  for name in db.schema.table_names() {
+    let mut shape = Shape::default();
+    db.walk_btree(&file, db.schema.table_root_page(name)?, &mut shape)?;
+    let page_bytes = shape.pages * db.usable_page_size();
+    let fill = 1.0 - shape.free_bytes as f64 / page_bytes as f64;
+    println!("{}: depth {}, {} pages, {:.1}% full", name, shape.depth, shape.pages, 100.0 * fill);
+  }
+  Ok(())
+}
@@ -0,0 +1,81 @@
+//! Time full scans of a generated 100,000-row table, to compare the I/O backends. The page cache
+//! is emptied before each scan, so every page is read again, from the OS's cache of the file.
+//!
+//! ```text
+//! cargo run --release --example scan_bench
+//! cargo run --release --example scan_bench --features mmap
+//! ```
+
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Result};
+use sqlite_starter_rust::{
+  database::Database,
+  query::{InsertQuery, Query},
+  record::Value,
+};
+
+const ROWS: i64 = 100_000;
+const SCANS: usize = 10;
+
+/// Write a database at `path` with the table `items (id INTEGER PRIMARY KEY, name TEXT, price
+/// REAL)` holding [`ROWS`] rows.
+fn generate(path: &std::path::Path) -> Result<()> {
+  let _ = std::fs::remove_file(path);
+  let mut db = Database::create(path, 4096)?;
+  let mut file = std::fs::OpenOptions::new().read(true).write(true).open(path)?;
+  let Query::Create(create) = Query::parse("CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT, price REAL)")? else {
+    bail!("not a CREATE TABLE");
+  };
+  db.create_table(&mut file, &create)?;
+  let insert = InsertQuery {
+    table_name: "items".to_string(),
+    column_names: None,
+    rows: (1..=ROWS)
+      .map(|i| vec![Value::Null, Value::Text(format!("item {:06}", i)), Value::Real(i as f64 / 100.0)])
+      .collect(),
+  };
+  db.insert(&mut file, &insert)?;
+  Ok(())
+}
+
+fn main() -> Result<()> {
+  let path = std::env::temp_dir().join("scan_bench.db");
+  generate(&path)?;
+
+  let file = std::fs::File::open(&path)?;
+  #[cfg(not(feature = "mmap"))]
+  let (db, backend) = (Database::parse_header_and_schema(&file)?, "read");
+  #[cfg(feature = "mmap")]
+  let (db, backend) = (Database::open_mmap(&path)?, "mmap");
+  let table = db.schema.table("items")?;
+  let columns = table.stored_columns();
+
+  let mut times = Vec::with_capacity(SCANS);
+  for _ in 0..SCANS {
+    db.page_cache.lock().unwrap().clear();
+    let start = Instant::now();
+    let mut rows = 0;
+    for row in db.scan_table(&file, table.root_page, &columns.names, &columns.record_indices) {
+      row?;
+      rows += 1;
+    }
+    times.push(start.elapsed());
+    if rows != ROWS {
+      bail!("scanned {} rows of {}", rows, ROWS);
+    }
+  }
+  times.sort();
+  let total = times.iter().sum::<Duration>();
+  println!(
+    "{}: {} scans of {} rows, min {:?}, median {:?}, mean {:?}",
+    backend,
+    SCANS,
+    ROWS,
+    times[0],
+    times[SCANS / 2],
+    total / SCANS as u32
+  );
+  std::fs::remove_file(&path)?;
+  Ok(())
+}
@@ -0,0 +1,25 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sqlite_starter_rust::database::Database;
+
+// The input is a whole database file, which is opened, then has every table scanned and its
+// b-trees checked, as a corrupt file reaches the reader beyond the parsers of single pages.
+fuzz_target!(|data: &[u8]| {
+  let mut file = std::io::Cursor::new(data);
+  let Ok(db) = Database::parse_header_and_schema(&mut file) else {
+    return;
+  };
+  for name in db.schema.table_names() {
+    let Ok(table) = db.schema.table(name) else {
+      continue;
+    };
+    let columns = table.stored_columns();
+    for row in db.scan_table(&mut file, table.root_page, &columns.names, &columns.record_indices) {
+      if row.is_err() {
+        break;
+      }
+    }
+  }
+  let _ = db.check_integrity(&mut file);
+});
@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sqlite_starter_rust::header::Header;
+
+fuzz_target!(|data: &[u8]| {
+  if let Ok((_, header)) = Header::parse(data) {
+    // Whatever was accepted is written back as a header that reads the same.
+    let bytes = header.to_bytes();
+    let (_, reparsed) = Header::parse(&bytes).unwrap();
+    assert_eq!(reparsed.page_size, header.page_size);
+  }
+});
@@ -0,0 +1,37 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sqlite_starter_rust::page::{BTreePageType, Page, RawPage};
+
+// The first byte says whether this is the first page, which starts after the database header,
+// the next two give the usable page size, which the header guarantees is at least 480 bytes, and
+// the rest is the page.
+fuzz_target!(|data: &[u8]| {
+  let [flags, size_high, size_low, page @ ..] = data else {
+    return;
+  };
+  let is_first_page = flags & 1 != 0;
+  let usable_page_size = (u16::from_be_bytes([*size_high, *size_low]) as usize).max(480);
+
+  let _ = Page::parse(page, is_first_page, &["a", "b", "c"], &[0, 1, 2], usable_page_size);
+
+  let Ok((_, raw_page)) = RawPage::parse(page, is_first_page, usable_page_size) else {
+    return;
+  };
+  for i in 0..raw_page.cell_count() {
+    let _ = raw_page.cell(i, &["b"], &[1]);
+    let _ = raw_page.cell_bytes(i);
+    match raw_page.ty {
+      BTreePageType::TableInterior => {
+        let _ = raw_page.row_id(i);
+      }
+      BTreePageType::TableLeaf => {
+        let _ = raw_page.row_id(i);
+        let _ = raw_page.cell_payload(i);
+      }
+      BTreePageType::IndexInterior | BTreePageType::IndexLeaf => {
+        let _ = raw_page.cell_payload(i);
+      }
+    }
+  }
+});
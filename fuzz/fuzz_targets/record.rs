@@ -0,0 +1,23 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sqlite_starter_rust::record::Record;
+
+const COLUMN_NAMES: [&str; 8] = ["c0", "c1", "c2", "c3", "c4", "c5", "c6", "c7"];
+
+// The first byte picks which of the first 8 columns are decoded, and the rest is the payload.
+fuzz_target!(|data: &[u8]| {
+  let [columns, payload @ ..] = data else {
+    return;
+  };
+  let column_indices = (0..8).filter(|i| columns & (1 << i) != 0).collect::<Vec<_>>();
+  let column_names = column_indices.iter().map(|i| COLUMN_NAMES[*i]).collect::<Vec<_>>();
+
+  if let Ok((_, record)) = Record::parse(payload, Some(1), &column_names, &column_indices) {
+    // A decoded record encodes to one that decodes to the same values.
+    let all_columns = (0..record.values.len()).collect::<Vec<_>>();
+    let bytes = record.to_bytes();
+    let (_, reparsed) = Record::parse(&bytes, Some(1), &COLUMN_NAMES[..all_columns.len()], &all_columns).unwrap();
+    assert_eq!(reparsed.values.len(), record.values.len());
+  }
+});
@@ -0,0 +1,52 @@
+//! Seed the fuzzing corpora from the test fixtures: the whole files, their headers, their pages,
+//! and the records stored on those pages, in the input formats of the fuzz targets.
+
+use std::path::Path;
+
+use sqlite_starter_rust::{
+  cell::CellPayload,
+  header::{Header, HEADER_SIZE},
+  page::{BTreePageType, RawPage},
+};
+
+fn main() -> anyhow::Result<()> {
+  for dir in ["database", "header", "page", "record"] {
+    std::fs::create_dir_all(Path::new("corpus").join(dir))?;
+  }
+
+  for entry in std::fs::read_dir("../tests/fixtures")? {
+    let path = entry?.path();
+    let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+    let data = std::fs::read(&path)?;
+    let Ok((_, header)) = Header::parse(&data) else {
+      continue;
+    };
+    std::fs::write(Path::new("corpus/database").join(&name), &data)?;
+    std::fs::write(Path::new("corpus/header").join(&name), &data[..HEADER_SIZE])?;
+
+    let usable_page_size = header.page_size - header.end_page_reserved_bytes;
+    for (i, page) in data.chunks_exact(header.page_size).enumerate() {
+      let is_first_page = i == 0;
+      let page = if is_first_page { &page[HEADER_SIZE..] } else { page };
+      let mut input = vec![is_first_page as u8];
+      input.extend((usable_page_size as u16).to_be_bytes());
+      input.extend(page);
+      std::fs::write(Path::new("corpus/page").join(format!("{}-{}", name, i + 1)), input)?;
+
+      // Only leaf and index pages hold records; overflow and freelist pages fail to parse.
+      let raw_page = match RawPage::parse(page, is_first_page, usable_page_size) {
+        Ok((_, raw_page)) if !matches!(raw_page.ty, BTreePageType::TableInterior) => raw_page,
+        _ => continue,
+      };
+      for cell in 0..raw_page.cell_count() {
+        if let Ok((_, CellPayload::Local { payload, .. })) = raw_page.cell_payload(cell) {
+          let mut input = vec![0xff];
+          input.extend(payload);
+          let file_name = format!("{}-{}-{}", name, i + 1, cell);
+          std::fs::write(Path::new("corpus/record").join(file_name), input)?;
+        }
+      }
+    }
+  }
+  Ok(())
+}
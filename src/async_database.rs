@@ -0,0 +1,113 @@
+//! An async front end for [`Database`], behind the `async` feature.
+
+use std::{path::Path, sync::Arc};
+
+use crate::{
+  database::Database,
+  error::{Error, Result},
+  query::Query,
+  row::ResultSet,
+};
+
+/// A [`Database`] for use from async code. Cloning it is cheap, and clones share the schema and
+/// page cache.
+///
+/// Files are opened with `tokio::fs`, but a query's page reads run on tokio's blocking pool
+/// alongside its decoding. `tokio::fs::File` does each read there anyway, so this costs one hop
+/// per query rather than one per page, and reuses the synchronous b-tree code as is.
+#[derive(Clone)]
+pub struct AsyncDatabase {
+  db: Arc<Database>,
+  path: Arc<Path>,
+}
+
+impl AsyncDatabase {
+  pub async fn open<P>(path: P) -> Result<Self>
+    where
+      P: AsRef<Path>,
+  {
+    let path: Arc<Path> = path.as_ref().into();
+    let file = tokio::fs::File::open(&path).await?.into_std().await;
+    let db = run_blocking(move || Database::parse_header_and_schema(file)).await?;
+    Ok(AsyncDatabase {
+      db: Arc::new(db),
+      path,
+    })
+  }
+
+  pub fn database(&self) -> &Database {
+    &self.db
+  }
+
+  /// Like [`Query::query_rows`], reading through a file handle of its own so that queries can
+  /// run concurrently.
+  pub async fn query(&self, sql: &str) -> Result<ResultSet> {
+    let query = Query::parse(sql)?;
+    let file = tokio::fs::File::open(&self.path).await?.into_std().await;
+    let db = self.db.clone();
+    run_blocking(move || query.query_rows(&db, file)).await
+  }
+
+  /// Like [`Query::execute`].
+  pub async fn execute(&self, sql: &str) -> Result<Vec<Vec<String>>> {
+    let result_set = self.query(sql).await?;
+    Ok(
+      result_set
+        .rows
+        .iter()
+        .map(|row| row.values().iter().map(|value| value.to_string()).collect())
+        .collect(),
+    )
+  }
+}
+
+async fn run_blocking<T, F>(f: F) -> Result<T>
+  where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+  match tokio::task::spawn_blocking(f).await {
+    Ok(result) => result,
+    Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+    Err(e) => Err(Error::Io(std::io::Error::other(e))),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::AsyncDatabase;
+
+  #[tokio::test]
+  async fn concurrent_queries() {
+    let db = AsyncDatabase::open("tests/fixtures/companies.db").await.unwrap();
+    let (count, rows) = tokio::join!(
+      db.execute("SELECT COUNT(*) FROM companies"),
+      db.query("SELECT id, name FROM companies WHERE country = 'country_123'"),
+    );
+    assert_eq!(count.unwrap(), vec![vec!["2000".to_string()]]);
+
+    let mut ids = rows
+      .unwrap()
+      .rows
+      .iter()
+      .map(|row| row.get_by_name::<Option<i64>>("id").unwrap())
+      .collect::<Vec<_>>();
+    ids.sort();
+    assert_eq!(ids.len(), 3);
+  }
+
+  #[tokio::test]
+  async fn writes_are_errors() {
+    let db = AsyncDatabase::open("tests/fixtures/companies.db").await.unwrap();
+    for sql in [
+      "INSERT INTO companies (name) VALUES ('x')",
+      "DELETE FROM companies",
+      "UPDATE companies SET name = 'x'",
+      "CREATE TABLE t (a)",
+    ] {
+      let error = db.query(sql).await.unwrap_err();
+      assert!(error.to_string().ends_with("in a query that only reads"), "{}: {}", sql, error);
+    }
+    assert_eq!(db.execute("SELECT COUNT(*) FROM companies").await.unwrap(), vec![vec!["2000".to_string()]]);
+  }
+}
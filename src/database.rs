@@ -1,16 +1,167 @@
+use std::{
+  collections::{BTreeMap, HashMap},
+  ops::ControlFlow,
+  path::PathBuf,
+  sync::{
+    atomic::{self, AtomicU32, AtomicUsize},
+    Arc, Mutex,
+  },
+};
+
 use crate::{
-  cell::Cell,
-  header::{Header, HEADER_SIZE},
-  page::Page,
-  query::Query,
-  record::{Record, Value},
+  btree::MAX_DEPTH,
+  cell::{Cell, CellPayload, OverflowCell},
+  error::{Error, MyError, Result},
+  expr::Affinity,
+  header::{FormatVersion, Header, HEADER_SIZE},
+  journal,
+  output::quote_identifier,
+  page::{page_offset, BTreePageType, PageMut, RawPage},
+  query::{
+    Column, Condition, CreateQuery, DefaultValue, DeleteQuery, GeneratedColumn, InsertQuery, KeyConstraint, Query,
+    QueryPlan, SelectQuery, UpdateQuery,
+  },
+  record::{Collation, Record, RecordRef, RecordType, Value},
+  row::Row,
+  transaction::Transaction,
+  varint::{varint, write_varint},
 };
 
+/// Default byte budget of the page cache.
+pub const DEFAULT_PAGE_CACHE_SIZE: usize = 2 * 1024 * 1024;
+
+/// How many pages a query visits between calls to the progress handler, by default.
+pub const DEFAULT_PROGRESS_PAGES: usize = 64;
+
+/// What opening a database in WAL mode does when its `-wal` file isn't empty. Those changes
+/// aren't read, so the main file alone may return stale data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WalPolicy {
+  /// Print a warning on stderr and read the main file anyway.
+  #[default]
+  Warn,
+  /// Fail with [`Error::WalNotSupported`].
+  Fail,
+}
+
+/// An open database. Queries only need a shared reference, so one handle can serve several
+/// threads, each passing its own file handle to read through.
 pub struct Database {
   pub header: Header,
   pub schema: Schema,
-  pub table_pages_parsed: usize,
-  pub index_pages_parsed: usize,
+  /// Behind a mutex rather than a read-write lock, since lookups update the LRU order too. It is
+  /// never held while reading from the file.
+  pub page_cache: Mutex<PageCache>,
+  /// When opened with [`Database::open_mmap`], pages are served from this mapping instead of
+  /// being read through the `file` argument of each method.
+  #[cfg(feature = "mmap")]
+  mmap: Option<Arc<memmap2::Mmap>>,
+  /// The database file, when opened by path. Writes are then made atomic by saving the pages
+  /// they overwrite to a rollback journal next to it first.
+  path: Option<PathBuf>,
+  /// Pages written by the statements of the transaction started with [`Database::begin`], which
+  /// reads see in place of the file until it's committed.
+  batch: Mutex<Option<BTreeMap<usize, Arc<Vec<u8>>>>>,
+  /// The file change counter as of when the schema was loaded or this handle last wrote the file,
+  /// for [`Database::refresh`] to tell when another connection has written it since.
+  change_counter: AtomicU32,
+  page_counters: PageCounters,
+  /// Set with [`Database::set_page_observer`].
+  page_observer: Option<Mutex<Box<dyn PageObserver>>>,
+  overflow_bytes_read: AtomicUsize,
+  key_comparisons: AtomicUsize,
+  records_decoded: AtomicUsize,
+  /// Set with [`Database::set_progress_handler`].
+  progress_handler: Option<ProgressHandler>,
+  /// Pages visited by queries, counting towards the next call to the progress handler.
+  pages_visited: AtomicUsize,
+}
+
+/// What a page read from the file holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageKind {
+  Table,
+  Index,
+  Overflow,
+}
+
+impl From<RecordType> for PageKind {
+  fn from(kind: RecordType) -> Self {
+    match kind {
+      RecordType::Table => PageKind::Table,
+      RecordType::Index => PageKind::Index,
+    }
+  }
+}
+
+/// Told about each page read from the file, in order, as queries and the schema load read them:
+/// the misses of the page cache, which are the pages whose reading costs anything.
+pub trait PageObserver: Send {
+  fn on_page_read(&mut self, page_no: u32, kind: PageKind);
+}
+
+/// How many pages of each kind have been read, behind [`Database::table_pages_parsed`] and the
+/// like. Unlike an observer set with [`Database::set_page_observer`], it's always there.
+#[derive(Default)]
+struct PageCounters {
+  table: AtomicUsize,
+  index: AtomicUsize,
+  overflow: AtomicUsize,
+}
+
+impl PageObserver for &PageCounters {
+  fn on_page_read(&mut self, _page_no: u32, kind: PageKind) {
+    let counter = match kind {
+      PageKind::Table => &self.table,
+      PageKind::Index => &self.index,
+      PageKind::Overflow => &self.overflow,
+    };
+    counter.fetch_add(1, atomic::Ordering::Relaxed);
+  }
+}
+
+/// A callback run while queries walk b-trees, which can interrupt them.
+struct ProgressHandler {
+  pages: usize,
+  handler: Box<dyn Fn() -> ControlFlow<()> + Send + Sync>,
+}
+
+/// The bytes of a page, either read from the file and shared with the page cache, or borrowed
+/// from a memory mapping of the file.
+#[derive(Clone)]
+pub enum PageData {
+  Owned(Arc<Vec<u8>>),
+  #[cfg(feature = "mmap")]
+  Mapped(Arc<memmap2::Mmap>, std::ops::Range<usize>),
+}
+
+impl std::ops::Deref for PageData {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    match self {
+      PageData::Owned(data) => data,
+      #[cfg(feature = "mmap")]
+      PageData::Mapped(mmap, range) => &mmap[range.clone()],
+    }
+  }
+}
+
+/// Page contents keyed by page number, bounded by a byte budget with least recently used
+/// eviction. Pages are cached as raw bytes, since how cells are decoded depends on the columns a
+/// query asks for; `RawPage` only parses the page header and cell pointers on top of them.
+pub struct PageCache {
+  capacity: usize,
+  size: usize,
+  clock: u64,
+  pages: HashMap<usize, CachedPage>,
+  /// Buffers of evicted pages that are no longer referenced, to be reused for the next read.
+  spare_buffers: Vec<Vec<u8>>,
+}
+
+struct CachedPage {
+  data: Arc<Vec<u8>>,
+  last_used: u64,
 }
 
 #[derive(Debug)]
@@ -18,13 +169,12 @@ pub struct Schema {
   pub objects: Vec<ObjectSchema>,
 }
 
-#[allow(dead_code)]
 #[derive(Debug)]
 pub enum ObjectSchema {
   Table(TableSchema),
   Index(IndexSchema),
-  View,
-  Trigger,
+  View(ViewSchema),
+  Trigger(TriggerSchema),
 }
 
 impl ObjectSchema {
@@ -49,6 +199,67 @@ pub struct TableSchema {
   pub root_page: usize,
   pub sql: String,
   pub column_names: Vec<String>,
+  /// The affinity of each column, from its declared type.
+  pub column_affinities: Vec<Affinity>,
+  /// The collation comparing each column's text.
+  pub column_collations: Vec<Collation>,
+  /// Position of the `INTEGER PRIMARY KEY` column aliasing the rowid, if there is one.
+  pub row_id_column: Option<usize>,
+  /// Each column's DEFAULT, if it has one.
+  pub column_defaults: Vec<Option<DefaultValue>>,
+  /// Whether each column is declared NOT NULL.
+  pub column_not_null: Vec<bool>,
+  /// Whether a column or the table has a CHECK constraint, which writes can't evaluate.
+  pub checked: bool,
+  /// How each generated column is computed. VIRTUAL ones have no value in the record.
+  pub column_generated: Vec<Option<GeneratedColumn>>,
+  /// Whether the rows are stored in an index b-tree keyed on the primary key, not by rowid.
+  pub without_rowid: bool,
+  /// Whether each value written must be of its column's declared type.
+  pub strict: bool,
+}
+
+/// The columns of a table that are stored in its records, which VIRTUAL generated columns aren't,
+/// with what's needed to scan them all with [`Database::scan_table`].
+pub struct StoredColumns<'a> {
+  /// The position of each stored column among all the table's columns.
+  pub positions: Vec<usize>,
+  pub names: Vec<&'a str>,
+  /// The position of each stored column in the record, which is its place in this list.
+  pub record_indices: Vec<usize>,
+  /// The value of each stored column for rows written before it was added.
+  pub defaults: Vec<Value>,
+}
+
+impl TableSchema {
+  pub fn stored_columns(&self) -> StoredColumns<'_> {
+    let positions = (0..self.column_names.len())
+      .filter(|i| self.column_generated[*i].as_ref().is_none_or(|generated| generated.stored))
+      .collect::<Vec<_>>();
+    StoredColumns {
+      names: positions.iter().map(|i| self.column_names[*i].as_str()).collect(),
+      record_indices: (0..positions.len()).collect(),
+      defaults: positions
+        .iter()
+        .map(|i| self.column_defaults[*i].as_ref().and_then(DefaultValue::constant).unwrap_or(Value::Null))
+        .collect(),
+      positions,
+    }
+  }
+}
+
+/// A view, which is listed but can't be queried, having no b-tree of its own.
+#[derive(Debug)]
+pub struct ViewSchema {
+  pub name: String,
+  pub sql: String,
+}
+
+/// A trigger, which is never run, so writes to its table are refused instead.
+#[derive(Debug)]
+pub struct TriggerSchema {
+  pub name: String,
+  pub table_name: String,
 }
 
 #[derive(Debug)]
@@ -58,287 +269,4735 @@ pub struct IndexSchema {
   pub root_page: usize,
   pub sql: String,
   pub column_name: String,
+  /// The collation ordering the index's text keys.
+  pub collation: Collation,
+  /// Whether the keys are sorted DESC, which only schema format 4 allows.
+  pub descending: bool,
+  /// Whether the index is declared UNIQUE, so no two entries may have the same key unless it
+  /// holds a NULL.
+  pub unique: bool,
+  /// What `ANALYZE` recorded about the index, if the database has a `sqlite_stat1` table listing
+  /// it.
+  pub stats: Option<IndexStats>,
+}
+
+/// The statistics weighing a lookup in an index against a scan of its table: the index's row of
+/// `sqlite_stat1`, and the shape of both b-trees when the schema was loaded.
+#[derive(Debug, Clone)]
+pub struct IndexStats {
+  /// How many entries the index has.
+  pub rows: usize,
+  /// How many entries share a key, on average.
+  pub rows_per_key: usize,
+  pub index_depth: usize,
+  pub table_depth: usize,
+  /// An estimate of how many leaf pages the table has.
+  pub table_leaf_pages: usize,
+}
+
+impl IndexStats {
+  /// The pages read looking up a key: down the index to its entries, then down the table to
+  /// each of their rows.
+  pub fn lookup_cost(&self) -> usize {
+    self.index_depth + self.rows_per_key * self.table_depth
+  }
+
+  /// The pages read scanning the table instead.
+  pub fn scan_cost(&self) -> usize {
+    self.table_leaf_pages
+  }
 }
 
 impl Database {
-  /// Parse the first page of the database file, containing the header and a schema.
-  pub fn parse_header_and_schema<R>(mut file: R) -> anyhow::Result<Self>
+  /// Open the database at `path`, first rolling back a write that was interrupted before it
+  /// completed, if any. Writes through this handle are journaled.
+  pub fn open<P>(path: P) -> Result<Self>
+    where
+      P: AsRef<std::path::Path>,
+  {
+    Self::open_with(path, WalPolicy::default())
+  }
+
+  /// Like [`Database::open`], with `wal_policy` deciding what happens if the database is in WAL
+  /// mode with changes in its `-wal` file.
+  pub fn open_with<P>(path: P, wal_policy: WalPolicy) -> Result<Self>
+    where
+      P: AsRef<std::path::Path>,
+  {
+    Self::recover(path.as_ref())?;
+    let mut file = std::fs::File::open(path.as_ref())?;
+    let mut db = Self::parse_header(&mut file)?;
+    db.check_wal(path.as_ref(), wal_policy)?;
+    db.load_schema(file)?;
+    db.path = Some(path.as_ref().to_owned());
+    Ok(db)
+  }
+
+  /// Create a database at `path` with pages of `page_size` bytes, and open it. The file, which
+  /// mustn't exist yet, starts out as a single page holding the header and an empty schema table.
+  pub fn create<P>(path: P, page_size: usize) -> Result<Self>
     where
-      R: std::io::Read,
+      P: AsRef<std::path::Path>,
+  {
+    if !(512..=65536).contains(&page_size) || !page_size.is_power_of_two() {
+      return Err(Error::UnsupportedSql(format!("invalid page size: {}", page_size)));
+    }
+    let mut page = vec![0; page_size];
+    page[..HEADER_SIZE].copy_from_slice(&Header::new(page_size).to_bytes());
+    PageMut::new(&mut page, true, page_size).rebuild(BTreePageType::TableLeaf, &[], None);
+
+    let mut file = std::fs::OpenOptions::new()
+      .write(true)
+      .create_new(true)
+      .open(path.as_ref())?;
+    std::io::Write::write_all(&mut file, &page)?;
+    file.sync_all()?;
+    Self::open(path)
+  }
+
+  /// Roll back the hot journal left next to the database at `path` by an interrupted write.
+  fn recover(path: &std::path::Path) -> Result<()> {
+    let journal_path = journal::journal_path(path);
+    if journal_path.exists() {
+      let mut file = std::fs::OpenOptions::new().read(true).write(true).open(path)?;
+      journal::rollback(&mut file, &journal_path)?;
+    }
+    Ok(())
+  }
+
+  /// Warn or fail, as `wal_policy` says, if the database at `path` is in WAL mode and its `-wal`
+  /// file isn't empty. An empty or missing one means everything was checkpointed into the main
+  /// file, which can then be read as is.
+  fn check_wal(&self, path: &std::path::Path, wal_policy: WalPolicy) -> Result<()> {
+    if !self.header.is_wal() {
+      return Ok(());
+    }
+    let wal_path = journal::wal_path(path);
+    if std::fs::metadata(&wal_path).map_or(true, |metadata| metadata.len() == 0) {
+      return Ok(());
+    }
+    let error = Error::WalNotSupported(wal_path.display().to_string());
+    match wal_policy {
+      WalPolicy::Warn => {
+        eprintln!("warning: {}; reading the main database file only", error);
+        Ok(())
+      }
+      WalPolicy::Fail => Err(error),
+    }
+  }
+
+  /// Parse the database header, then load the schema by walking the `sqlite_schema` table
+  /// b-tree rooted at page 1.
+  pub fn parse_header_and_schema<R>(mut file: R) -> Result<Self>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let mut db = Self::parse_header(&mut file)?;
+    db.load_schema(file)?;
+    Ok(db)
+  }
+
+  /// Open the database at `path` with its file memory-mapped, so that pages are accessed in
+  /// place rather than copied. The `file` argument of the other methods is then ignored, so
+  /// e.g. `std::io::empty()` can be passed.
+  #[cfg(feature = "mmap")]
+  pub fn open_mmap<P>(path: P) -> Result<Self>
+    where
+      P: AsRef<std::path::Path>,
+  {
+    Self::recover(path.as_ref())?;
+    let file = std::fs::File::open(path.as_ref())?;
+    // Safety: like sqlite itself, we assume the file isn't truncated while it is open.
+    let mmap = unsafe { memmap2::Mmap::map(&file)? };
+    let mut db = Self::parse_header(std::io::Cursor::new(&mmap[..]))?;
+    db.check_wal(path.as_ref(), WalPolicy::default())?;
+    db.mmap = Some(Arc::new(mmap));
+    db.path = Some(path.as_ref().to_owned());
+    db.load_schema(std::io::empty())?;
+    Ok(db)
+  }
+
+  fn parse_header<R>(mut file: R) -> Result<Self>
+    where
+      R: std::io::Read + std::io::Seek,
   {
     let mut header_buf = [0; HEADER_SIZE];
-    file.read_exact(&mut header_buf)?;
-    let (rest, header) = Header::parse(&header_buf).expect("failed to parse header");
-    assert!(rest.is_empty());
-
-    let mut first_page_data = vec![0; header.page_size - HEADER_SIZE];
-    file.read_exact(&mut first_page_data)?;
-    let (_, first_page) = Page::parse(
-      &first_page_data,
-      true,
+    file.seek(std::io::SeekFrom::Start(0))?;
+    file.read_exact(&mut header_buf).map_err(|e| match e.kind() {
+      std::io::ErrorKind::UnexpectedEof => Error::NotADatabase("file is shorter than a header".to_string()),
+      _ => Error::Io(e),
+    })?;
+    let header = parse_header_bytes(&header_buf)?;
+    let change_counter = AtomicU32::new(header.file_change_counter as u32);
+
+    Ok(Database {
+      header,
+      schema: Schema {
+        objects: Vec::new(),
+      },
+      page_cache: Mutex::new(PageCache::new(DEFAULT_PAGE_CACHE_SIZE)),
+      #[cfg(feature = "mmap")]
+      mmap: None,
+      path: None,
+      batch: Mutex::new(None),
+      change_counter,
+      page_counters: PageCounters::default(),
+      page_observer: None,
+      overflow_bytes_read: AtomicUsize::new(0),
+      key_comparisons: AtomicUsize::new(0),
+      records_decoded: AtomicUsize::new(0),
+      progress_handler: None,
+      pages_visited: AtomicUsize::new(0),
+    })
+  }
+
+  fn load_schema<R>(&mut self, mut file: R) -> Result<()>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let object_records = self.get_full_table(
+      &mut file,
+      1,
       &["type", "name", "tbl_name", "rootpage", "sql"],
       &[0, 1, 2, 3, 4],
-      header.page_size - header.end_page_reserved_bytes,
-    )
-      .expect("failed to parse first page");
+    )?;
 
     let mut objects = Vec::new();
-    for object_cell in first_page.cells.iter() {
-      let object_record = object_cell.as_record().unwrap();
-      let object = match object_record.values[0].as_text().unwrap() {
+    for object_record in object_records.iter() {
+      let object = match schema_text(object_record, 0)? {
         "table" => {
-          let create_query_str = object_record.values[4].as_text().unwrap();
+          let create_query_str = schema_text(object_record, 4)?;
           let create_query = Query::parse(create_query_str)?;
-          let column_names = create_query.as_create().unwrap().column_names.clone();
+          let create_query = create_query.as_create().ok_or_else(|| malformed_schema(object_record))?;
 
           ObjectSchema::Table(TableSchema {
-            name: object_record.values[1].as_text().unwrap().to_owned(),
-            root_page: object_record.values[3].as_integer().unwrap() as usize,
+            name: schema_text(object_record, 1)?.to_owned(),
+            root_page: schema_root_page(object_record)?,
             sql: create_query_str.to_owned(),
-            column_names,
+            column_names: create_query.column_names.clone(),
+            column_affinities: create_query.column_affinities.clone(),
+            column_collations: create_query
+              .column_collations
+              .iter()
+              .map(|collation| collation.unwrap_or_default())
+              .collect(),
+            row_id_column: create_query.row_id_column,
+            column_defaults: create_query.column_default_values.clone(),
+            column_not_null: create_query.column_not_null.clone(),
+            checked: create_query.column_checks.iter().any(Option::is_some) || !create_query.table_checks.is_empty(),
+            column_generated: create_query.column_generated.clone(),
+            without_rowid: create_query.without_rowid,
+            strict: create_query.strict,
           })
         }
         "index" => {
-          let create_query_str = object_record.values[4].as_text().unwrap();
-          let create_query = Query::parse(create_query_str)?;
-          let column_name = create_query.as_create().unwrap().column_names[0].clone();
+          // The indexes SQLite makes for PRIMARY KEY and UNIQUE constraints have no SQL.
+          let create_query_str = object_record.values.get(4).and_then(Value::as_text);
+          let create_query = match create_query_str {
+            Some(create_query_str) => Query::parse(create_query_str)?,
+            None => {
+              let index_name = schema_text(object_record, 1)?;
+              let table_name = schema_text(object_record, 2)?;
+              let table = objects
+                .iter()
+                .filter_map(ObjectSchema::as_table)
+                .find(|table| table.name.eq_ignore_ascii_case(table_name))
+                .ok_or_else(|| Error::TableNotFound(table_name.to_owned()))?;
+              Query::parse(&automatic_index_sql(index_name, table)?)?
+            }
+          };
+          let create_query = create_query.as_create().ok_or_else(|| malformed_schema(object_record))?;
+          let column_name = create_query.column_names[0].clone();
+          let table_name = schema_text(object_record, 2)?;
+          // Without a COLLATE clause of its own, the key takes the collation of its column.
+          let collation = create_query.column_collations[0].unwrap_or_else(|| {
+            objects
+              .iter()
+              .filter_map(ObjectSchema::as_table)
+              .find(|table| table.name.eq_ignore_ascii_case(table_name))
+              .and_then(|table| {
+                let i = table.column_names.iter().position(|c| c.eq_ignore_ascii_case(&column_name))?;
+                Some(table.column_collations[i])
+              })
+              .unwrap_or_default()
+          });
 
           ObjectSchema::Index(IndexSchema {
-            name: object_record.values[1].as_text().unwrap().to_owned(),
-            table_name: object_record.values[2].as_text().unwrap().to_owned(),
-            root_page: object_record.values[3].as_integer().unwrap() as usize,
-            sql: create_query_str.to_owned(),
+            name: schema_text(object_record, 1)?.to_owned(),
+            table_name: schema_text(object_record, 2)?.to_owned(),
+            root_page: schema_root_page(object_record)?,
+            sql: create_query_str.unwrap_or_default().to_owned(),
             column_name,
+            collation,
+            descending: create_query.column_descending[0],
+            unique: create_query.unique,
+            stats: None,
           })
         }
-        _ => todo!("non-table/index object"),
+        "view" => ObjectSchema::View(ViewSchema {
+          name: schema_text(object_record, 1)?.to_owned(),
+          sql: schema_text(object_record, 4)?.to_owned(),
+        }),
+        "trigger" => ObjectSchema::Trigger(TriggerSchema {
+          name: schema_text(object_record, 1)?.to_owned(),
+          table_name: schema_text(object_record, 2)?.to_owned(),
+        }),
+        _ => continue,
       };
 
       objects.push(object);
     }
 
-    Ok(Database {
-      header,
-      schema: Schema { objects },
-      table_pages_parsed: 1,
-      index_pages_parsed: 0,
-    })
+    self.schema.objects = objects;
+    self.load_index_stats(file)
   }
 
-  pub fn get_full_table<R>(
-    &mut self,
-    mut file: R,
-    page_index: usize,
-    column_names: &[&str],
-    column_indices: &[usize],
-  ) -> anyhow::Result<Vec<Record>>
+  /// Give each index listed in `sqlite_stat1`, if there is such a table, its [`IndexStats`].
+  /// Only the pages down the left edge of each such index and its table are read.
+  fn load_index_stats<R>(&mut self, mut file: R) -> Result<()>
     where
       R: std::io::Read + std::io::Seek,
   {
-    assert!(page_index > 1);
-
-    let mut records: Vec<Record> = Vec::new();
-    let mut pages_to_read: Vec<usize> = vec![page_index];
-    let mut page_buffer = vec![0; self.header.page_size];
-    while let Some(page_index) = pages_to_read.pop() {
-      file.seek(std::io::SeekFrom::Start(
-        (self.header.page_size * (page_index - 1)) as u64,
-      ))?;
-      file.read_exact(&mut page_buffer)?;
-      let page = Page::parse(
-        &page_buffer,
-        false,
-        column_names,
-        column_indices,
-        self.header.page_size - self.header.end_page_reserved_bytes,
-      )
-        .expect("failed to parse page")
-        .1;
-      self.table_pages_parsed += 1;
+    let Ok(stat_table) = self.schema.table("sqlite_stat1") else {
+      return Ok(());
+    };
+    let stat_rows = self.get_full_table(&mut file, stat_table.root_page, &["tbl", "idx", "stat"], &[0, 1, 2])?;
 
-      if let Some(rightmost_pointer) = page.rightmost_pointer {
-        pages_to_read.push(rightmost_pointer);
+    let mut all_stats = Vec::new();
+    for (i, index) in self.schema.objects.iter().enumerate() {
+      let Some(index) = index.as_index() else {
+        continue;
+      };
+      let stat = stat_rows
+        .iter()
+        .find(|row| row.values[1].as_text().is_some_and(|name| name.eq_ignore_ascii_case(&index.name)))
+        .and_then(|row| row.values[2].as_text());
+      // The stat starts with the number of entries, then how many share each prefix of the key.
+      let mut numbers = stat.unwrap_or_default().split_ascii_whitespace().map(str::parse::<usize>);
+      let (Some(Ok(rows)), Some(Ok(rows_per_key))) = (numbers.next(), numbers.next()) else {
+        continue;
+      };
+      let table = self.schema.table(&index.table_name)?;
+      if table.without_rowid {
+        continue;
       }
-
-      for cell in page.cells {
-        match cell {
-          Cell::TableLeaf(record) => records.push(record),
-          Cell::TableInterior {
-            left_child_pointer, ..
-          } => pages_to_read.push(left_child_pointer as usize),
-          _ => unreachable!(),
-        }
+      let (index_depth, _) = self.btree_shape(&mut file, index.root_page, RecordType::Index)?;
+      let (table_depth, table_leaf_pages) = self.btree_shape(&mut file, table.root_page, RecordType::Table)?;
+      all_stats.push((
+        i,
+        IndexStats {
+          rows,
+          rows_per_key,
+          index_depth,
+          table_depth,
+          table_leaf_pages,
+        },
+      ));
+    }
+    for (i, stats) in all_stats {
+      if let ObjectSchema::Index(index) = &mut self.schema.objects[i] {
+        index.stats = Some(stats);
       }
     }
+    Ok(())
+  }
 
-    Ok(records)
+  /// How many levels the b-tree rooted at `root_page` has, a leaf root being one, found by
+  /// descending its left edge.
+  pub fn btree_depth<R>(&self, file: R, root_page: usize, kind: RecordType) -> Result<usize>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    Ok(self.btree_shape(file, root_page, kind)?.0)
   }
 
-  pub fn search_index<R>(
-    &mut self,
+  /// The depth of the b-tree rooted at `page_index`, and an estimate of how many leaf pages it
+  /// has: the product of the fanouts of the interior pages down its left edge.
+  fn btree_shape<R>(&self, mut file: R, mut page_index: usize, kind: RecordType) -> Result<(usize, usize)>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let (mut depth, mut leaf_pages) = (1, 1usize);
+    loop {
+      let page_data = self.read_page_data(&mut file, page_index, kind)?;
+      let page = self.parse_raw_page(&page_data, page_index)?;
+      let Some(rightmost_pointer) = page.rightmost_pointer else {
+        return Ok((depth, leaf_pages));
+      };
+      if depth == MAX_DEPTH {
+        return Err(Error::Corrupt {
+          page: page_index,
+          reason: "b-tree is too deep".to_string(),
+        });
+      }
+      leaf_pages = leaf_pages.saturating_mul(page.cell_count() + 1);
+      // Both kinds of interior cell start with the pointer to their left child.
+      page_index = if page.cell_count() == 0 {
+        rightmost_pointer
+      } else {
+        match page.cell_bytes(0) {
+          Ok((_, cell)) if cell.len() >= 4 => u32::from_be_bytes(cell[..4].try_into().unwrap()) as usize,
+          _ => {
+            return Err(Error::Corrupt {
+              page: page_index,
+              reason: "cell 0 is malformed".to_string(),
+            })
+          }
+        }
+      };
+      depth += 1;
+    }
+  }
+
+  /// Get the raw bytes of the page with the given (1-based) page number, reading it from the
+  /// file if it isn't cached. Reads are counted as table or index pages depending on `kind`.
+  pub fn read_page_data<R>(
+    &self,
     mut file: R,
     page_index: usize,
-    key: Value,
-  ) -> anyhow::Result<Vec<i64>>
+    kind: RecordType,
+  ) -> Result<PageData>
     where
       R: std::io::Read + std::io::Seek,
   {
-    assert!(page_index > 1);
-
-    let column_name = self
-      .schema
-      .objects
-      .iter()
-      .find(|o| {
-        matches!(o, ObjectSchema::Index(_)) && o.as_index().unwrap().root_page == page_index
-      })
-      .map(|o| o.as_index().unwrap().column_name.clone())
-      .unwrap();
+    if let Some(data) = self.batch.lock().unwrap().as_ref().and_then(|batch| batch.get(&page_index)) {
+      return Ok(PageData::Owned(data.clone()));
+    }
 
-    let mut row_ids = Vec::new();
-    let mut pages_to_read: Vec<usize> = vec![page_index];
-    let mut page_buffer = vec![0; self.header.page_size];
-    while let Some(page_index) = pages_to_read.pop() {
-      file.seek(std::io::SeekFrom::Start(
-        (self.header.page_size * (page_index - 1)) as u64,
-      ))?;
-      file.read_exact(&mut page_buffer)?;
-      let page = Page::parse(
-        &page_buffer,
-        false,
-        &[&column_name, "row_id"],
-        &[0, 1],
-        self.header.page_size - self.header.end_page_reserved_bytes,
-      )
-        .expect("failed to parse page")
-        .1;
-      self.index_pages_parsed += 1;
-
-      // TODO: Only parse `rightmost_pointer` page if the entries we're interested in
-      // will appear there
+    let offset = page_offset(self.header.page_size, page_index)?;
+    #[cfg(feature = "mmap")]
+    if let Some(mmap) = &self.mmap {
+      // An offset too big for `usize` is past the end of any file that could be mapped.
+      let start = usize::try_from(offset).unwrap_or(usize::MAX - self.header.page_size);
+      let range = start..start + self.header.page_size;
+      if range.end > mmap.len() {
+        return Err(Error::Corrupt {
+          page: page_index,
+          reason: "page is past the end of the file".to_string(),
+        });
+      }
+      let page_data = PageData::Mapped(mmap.clone(), range);
+      self.page_read(page_index, kind.into());
+      return Ok(page_data);
+    }
 
-      if let Some(rightmost_pointer) = page.rightmost_pointer {
-        pages_to_read.push(rightmost_pointer);
+    let mut page_buffer = {
+      let mut page_cache = self.page_cache.lock().unwrap();
+      if let Some(page_data) = page_cache.get(page_index) {
+        return Ok(PageData::Owned(page_data));
       }
+      page_cache.take_buffer(self.header.page_size)
+    };
+    file.seek(std::io::SeekFrom::Start(offset))?;
+    file.read_exact(&mut page_buffer)?;
+    self.page_read(page_index, kind.into());
 
-      for cell in page.cells {
-        match cell {
-          Cell::IndexLeaf(record) => {
-            if record.values[0] == key {
-              row_ids.push(record.values[1].as_integer().unwrap());
-            }
-          }
-          Cell::IndexInterior {
-            left_child_pointer,
-            record,
-          } => {
-            if record.values[0] >= key {
-              pages_to_read.push(left_child_pointer as usize);
-            }
-            if record.values[0] == key {
-              row_ids.push(record.values[1].as_integer().unwrap());
-            }
-          }
-          _ => unreachable!(),
-        }
+    let page_data = Arc::new(page_buffer);
+    self
+      .page_cache
+      .lock()
+      .unwrap()
+      .insert(page_index, page_data.clone());
+    Ok(PageData::Owned(page_data))
+  }
+
+  /// Read the `count` table pages from `first_page` on into the page cache with a single read,
+  /// for a scan about to visit them in turn. Pages already cached are kept as they are. Failing
+  /// to read them all isn't an error: each is then read on its own when the scan reaches it,
+  /// which reports the error if there is one.
+  fn read_ahead<R>(&self, mut file: R, first_page: usize, count: usize)
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    #[cfg(feature = "mmap")]
+    if self.mmap.is_some() {
+      return;
+    }
+    let page_size = self.header.page_size;
+    let count = count.min((self.header.size_in_pages + 1).saturating_sub(first_page));
+    if count < 2 || self.batch.lock().unwrap().is_some() || self.page_cache.lock().unwrap().get(first_page).is_some() {
+      return;
+    }
+    let Ok(offset) = page_offset(page_size, first_page) else {
+      return;
+    };
+    let mut buffer = vec![0; count * page_size];
+    if file.seek(std::io::SeekFrom::Start(offset)).and_then(|_| file.read_exact(&mut buffer)).is_err() {
+      return;
+    }
+    let mut page_cache = self.page_cache.lock().unwrap();
+    for (page_index, data) in (first_page..).zip(buffer.chunks_exact(page_size)) {
+      if page_cache.get(page_index).is_none() {
+        let mut page_buffer = page_cache.take_buffer(page_size);
+        page_buffer.copy_from_slice(data);
+        page_cache.insert(page_index, Arc::new(page_buffer));
+        self.page_read(page_index, PageKind::Table);
       }
     }
+  }
 
-    Ok(row_ids)
+  /// Read the schema cookie from the header in the file, which changes whenever the schema does,
+  /// unlike `self.header` which is only read when the database is opened.
+  pub fn read_schema_cookie<R>(&self, file: R) -> Result<u32>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    self.read_header_u32(file, 40)
   }
 
-  pub fn get_by_row_ids<R>(
-    &mut self,
-    mut file: R,
-    page_index: usize,
-    row_ids: &[i64],
-    column_names: &[&str],
-    column_indices: &[usize],
-  ) -> anyhow::Result<Vec<Record>>
+  /// Read the big-endian 32-bit field at `offset` of the header in the file, bypassing the page
+  /// cache.
+  fn read_header_u32<R>(&self, mut file: R, offset: usize) -> Result<u32>
     where
       R: std::io::Read + std::io::Seek,
   {
-    assert!(page_index > 1);
+    let mut field = [0; 4];
+    #[cfg(feature = "mmap")]
+    if let Some(mmap) = &self.mmap {
+      field.copy_from_slice(&mmap[offset..offset + 4]);
+      return Ok(u32::from_be_bytes(field));
+    }
+    file.seek(std::io::SeekFrom::Start(offset as u64))?;
+    file.read_exact(&mut field)?;
+    Ok(u32::from_be_bytes(field))
+  }
 
-    let mut records: Vec<Record> = Vec::new();
-    let mut pages_to_read: Vec<usize> = vec![page_index];
-    let mut page_buffer = vec![0; self.header.page_size];
-    while let Some(page_index) = pages_to_read.pop() {
-      file.seek(std::io::SeekFrom::Start(
-        (self.header.page_size * (page_index - 1)) as u64,
-      ))?;
-      file.read_exact(&mut page_buffer)?;
-      let page = Page::parse(
-        &page_buffer,
-        false,
-        column_names,
-        column_indices,
-        self.header.page_size - self.header.end_page_reserved_bytes,
-      )
-        .expect("failed to parse page")
-        .1;
-      self.table_pages_parsed += 1;
-
-      let id_column_index = column_names.iter().position(|c| *c == "id").unwrap();
-
-      // TODO: When to follow rightmost pointer?
-      if let Some(rightmost_pointer) = page.rightmost_pointer {
-        pages_to_read.push(rightmost_pointer);
-      }
+  /// Reload the header and schema, and empty the page cache, if another connection wrote the
+  /// file since they were read, returning whether it had. Only the 4-byte file change counter is
+  /// read otherwise, so this is cheap enough to call before every statement of a long session.
+  pub fn refresh<R>(&mut self, mut file: R) -> Result<bool>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let change_counter = self.read_header_u32(&mut file, 24)?;
+    if change_counter == self.change_counter.load(atomic::Ordering::Relaxed) {
+      return Ok(false);
+    }
+    self.page_cache.lock().unwrap().clear();
+    self.reload_schema(file)?;
+    Ok(true)
+  }
 
-      for cell in page.cells {
-        match cell {
-          Cell::TableLeaf(record) => {
-            if row_ids.contains(&record.values[id_column_index].as_integer().unwrap()) {
-              records.push(record)
-            }
-          }
-          Cell::TableInterior {
-            left_child_pointer,
-            key,
-          } => {
-            if row_ids.iter().any(|id| *id < key) {
-              pages_to_read.push(left_child_pointer as usize)
-            }
-          }
-          _ => unreachable!(),
-        }
-      }
+  /// Tell the page counters, and the observer if one is set, that page `page_index` was read.
+  fn page_read(&self, page_index: usize, kind: PageKind) {
+    (&self.page_counters).on_page_read(page_index as u32, kind);
+    if let Some(observer) = &self.page_observer {
+      observer.lock().unwrap().on_page_read(page_index as u32, kind);
     }
+  }
 
-    Ok(records)
+  /// Tell `observer` about each page read from now on, in place of any observer set before.
+  pub fn set_page_observer<O>(&mut self, observer: O)
+    where
+      O: PageObserver + 'static,
+  {
+    self.page_observer = Some(Mutex::new(Box::new(observer)));
   }
-}
 
-impl Schema {
-  pub fn table_count(&self) -> usize {
-    self.objects
-      .iter()
-      .filter(|o| matches!(o, ObjectSchema::Table(_)))
-      .count()
+  pub fn clear_page_observer(&mut self) {
+    self.page_observer = None;
   }
 
-  pub fn table_names(&self) -> Vec<&str> {
-    let mut tables = self
-      .objects
-      .iter()
-      .filter(|o| matches!(o, ObjectSchema::Table(_)))
-      .map(|o| o.as_table().unwrap().name.as_str())
-      .collect::<Vec<&str>>();
-    tables.sort();
-    tables
+  /// Table pages read from the file, i.e. page cache misses.
+  pub fn table_pages_parsed(&self) -> usize {
+    self.page_counters.table.load(atomic::Ordering::Relaxed)
   }
 
-  pub fn table_root_page(&self, table_name: &str) -> anyhow::Result<usize> {
-    Ok(self
-      .objects
-      .iter()
-      .find(|o| {
-        matches!(o, ObjectSchema::Table(_)) && o.as_table().unwrap().name == table_name
-      })
-      .expect("table not found")
-      .as_table()
-      .unwrap()
-      .root_page)
+  /// Index pages read from the file, i.e. page cache misses.
+  pub fn index_pages_parsed(&self) -> usize {
+    self.page_counters.index.load(atomic::Ordering::Relaxed)
+  }
+
+  /// Overflow pages read to reassemble payloads that spill off their b-tree page. Columns stored
+  /// on the page are decoded without them.
+  pub fn overflow_pages_parsed(&self) -> usize {
+    self.page_counters.overflow.load(atomic::Ordering::Relaxed)
+  }
+
+  /// Payload bytes read from overflow pages.
+  pub fn overflow_bytes_read(&self) -> usize {
+    self.overflow_bytes_read.load(atomic::Ordering::Relaxed)
+  }
+
+  pub fn key_comparisons(&self) -> usize {
+    self.key_comparisons.load(atomic::Ordering::Relaxed)
+  }
+
+  /// Records decoded from table and index cells.
+  pub fn records_decoded(&self) -> usize {
+    self.records_decoded.load(atomic::Ordering::Relaxed)
+  }
+
+  /// Call `handler` every `pages` pages visited by a query, from table scans, index searches and
+  /// scans, and rowid lookups alike. If it returns `ControlFlow::Break`, the query stops with
+  /// [`Error::Interrupted`].
+  pub fn set_progress_handler<F>(&mut self, pages: usize, handler: F)
+    where
+      F: Fn() -> ControlFlow<()> + Send + Sync + 'static,
+  {
+    self.progress_handler = Some(ProgressHandler {
+      pages: pages.max(1),
+      handler: Box::new(handler),
+    });
+  }
+
+  pub fn clear_progress_handler(&mut self) {
+    self.progress_handler = None;
+  }
+
+  /// Count a page visited by a query, calling the progress handler when it's due.
+  fn report_progress(&self) -> Result<()> {
+    let Some(progress) = &self.progress_handler else {
+      return Ok(());
+    };
+    let visited = self.pages_visited.fetch_add(1, atomic::Ordering::Relaxed) + 1;
+    if visited.is_multiple_of(progress.pages) && (progress.handler)().is_break() {
+      return Err(Error::Interrupted);
+    }
+    Ok(())
+  }
+
+  /// Parse the b-tree page `page_index` from `page_data`, checking that its cell pointers and
+  /// freeblocks stay within the page.
+  pub(crate) fn parse_raw_page<'a>(&self, page_data: &'a [u8], page_index: usize) -> Result<RawPage<'a>> {
+    let is_first_page = page_index == 1;
+    let page_data = if is_first_page {
+      &page_data[HEADER_SIZE..]
+    } else {
+      page_data
+    };
+    let (_, page) = RawPage::parse(page_data, is_first_page, self.usable_page_size()).map_err(|e| Error::Corrupt {
+      page: page_index,
+      reason: format!("failed to parse page: {:?}", e),
+    })?;
+    page.check_pointers().map_err(|reason| Error::Corrupt {
+      page: page_index,
+      reason,
+    })?;
+    Ok(page)
+  }
+
+  /// Like [`Database::parse_raw_page`], for a page whose cells are about to be removed or
+  /// replaced. Its free space is rebuilt around the cells, so they mustn't overlap each other or
+  /// a free block.
+  fn parse_page_to_modify<'a>(&self, page_data: &'a [u8], page_index: usize) -> Result<RawPage<'a>> {
+    let page = self.parse_raw_page(page_data, page_index)?;
+    page.check_cells().map_err(|reason| Error::Corrupt {
+      page: page_index,
+      reason,
+    })?;
+    Ok(page)
+  }
+
+  /// Decode a single cell of `page`, which is page `page_index`, reassembling its payload if it
+  /// spills into overflow pages.
+  fn read_cell<R>(
+    &self,
+    mut file: R,
+    page: &RawPage,
+    page_index: usize,
+    i: usize,
+    column_names: &[&str],
+    column_indices: &[usize],
+  ) -> Result<Cell>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let cell = page.cell(i, column_names, column_indices).map_err(corrupt_cell(page_index))?.1;
+    self.records_decoded.fetch_add(1, atomic::Ordering::Relaxed);
+    match cell {
+      Cell::Overflow(overflow) => {
+        self.resolve_overflow(&mut file, &overflow, column_names, column_indices)
+      }
+      cell => Ok(cell),
+    }
+  }
+
+  /// Decode an overflowing cell, only reading its overflow pages if a requested column isn't
+  /// stored on the page itself.
+  fn resolve_overflow<R>(
+    &self,
+    file: R,
+    overflow: &OverflowCell,
+    column_names: &[&str],
+    column_indices: &[usize],
+  ) -> Result<Cell>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    if let Some(cell) = Cell::from_local_payload(overflow, column_names, column_indices) {
+      return Ok(cell);
+    }
+    let payload = self.read_overflow_payload(file, overflow)?;
+    Cell::from_overflow(overflow, &payload, column_names, column_indices)
+  }
+
+  /// Follow the overflow page chain of `overflow`, returning its complete payload.
+  fn read_overflow_payload<R>(&self, mut file: R, overflow: &OverflowCell) -> Result<Vec<u8>>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let mut payload = Vec::with_capacity(overflow.payload_size);
+    payload.extend_from_slice(&overflow.local_payload);
+
+    let mut next_page = overflow.first_overflow_page;
+    let mut page_buffer = vec![0; self.header.page_size];
+    while payload.len() < overflow.payload_size {
+      if next_page == 0 {
+        return Err(Error::Corrupt {
+          page: overflow.first_overflow_page,
+          reason: "overflow chain ended before the end of the payload".to_string(),
+        });
+      }
+      let page_buffer = self.read_overflow_page(&mut file, next_page, &mut page_buffer)?;
+
+      let content = &page_buffer[4..self.usable_page_size()];
+      let len = (overflow.payload_size - payload.len()).min(content.len());
+      payload.extend_from_slice(&content[..len]);
+      self.page_read(next_page, PageKind::Overflow);
+      self.overflow_bytes_read.fetch_add(len, atomic::Ordering::Relaxed);
+      next_page = u32::from_be_bytes([
+        page_buffer[0],
+        page_buffer[1],
+        page_buffer[2],
+        page_buffer[3],
+      ]) as usize;
+    }
+
+    Ok(payload)
+  }
+
+  fn read_overflow_page<'a, R>(
+    &'a self,
+    mut file: R,
+    page_index: usize,
+    buffer: &'a mut [u8],
+  ) -> Result<&'a [u8]>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let offset = page_offset(self.header.page_size, page_index)?;
+    #[cfg(feature = "mmap")]
+    if let Some(mmap) = &self.mmap {
+      let start = usize::try_from(offset).unwrap_or(usize::MAX - self.header.page_size);
+      return mmap
+        .get(start..start + self.header.page_size)
+        .ok_or_else(|| Error::Corrupt {
+          page: page_index,
+          reason: "page is past the end of the file".to_string(),
+        });
+    }
+
+    file.seek(std::io::SeekFrom::Start(offset))?;
+    file.read_exact(buffer)?;
+    Ok(buffer)
+  }
+
+  /// Describe how `sql` would be run, without reading any pages beyond the schema.
+  pub fn explain(&self, sql: &str) -> Result<QueryPlan> {
+    match Query::parse(sql)? {
+      Query::Select(select) => Ok(select.resolve(self)?.plan(&[])),
+      _ => Err(Error::UnsupportedSql(sql.to_owned())),
+    }
+  }
+
+  pub fn usable_page_size(&self) -> usize {
+    self.header.page_size - self.header.end_page_reserved_bytes
+  }
+
+  pub fn get_full_table<R>(
+    &self,
+    file: R,
+    page_index: usize,
+    column_names: &[&str],
+    column_indices: &[usize],
+  ) -> Result<Vec<Record>>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    self
+      .scan_table(file, page_index, column_names, column_indices)
+      .collect()
+  }
+
+  /// Walk the table b-tree rooted at `page_index` depth-first, yielding its records one at a
+  /// time in rowid order. Only the records of the current leaf page are held in memory.
+  pub fn scan_table<'a, R>(
+    &'a self,
+    file: R,
+    page_index: usize,
+    column_names: &'a [&'a str],
+    column_indices: &'a [usize],
+  ) -> TableScan<'a, R>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    TableScan {
+      db: self,
+      file,
+      column_names,
+      column_indices,
+      defaults: &[],
+      filter: None,
+      readahead: 1,
+      pages_to_read: vec![page_index],
+      leaf_records: Vec::new().into_iter(),
+    }
+  }
+
+  /// Walk the index b-tree rooted at `page_index` in ascending key order, or descending if
+  /// `descending`, yielding the rowid of each entry. Pages are read only as the walk reaches them.
+  pub fn scan_index(&self, page_index: usize, descending: bool) -> Result<IndexScan<'_>> {
+    let index = self.index_at(page_index)?;
+    self.check_key_order(page_index, &[index.descending])?;
+
+    // The entries of a DESC index are stored largest first, so are walked backwards for
+    // ascending order.
+    Ok(IndexScan {
+      db: self,
+      column_name: index.column_name.clone(),
+      descending: descending != index.descending,
+      steps: vec![IndexStep::Page(page_index)],
+    })
+  }
+
+  /// The index whose b-tree is rooted at `page_index`.
+  fn index_at(&self, page_index: usize) -> Result<&IndexSchema> {
+    self
+      .schema
+      .objects
+      .iter()
+      .filter_map(ObjectSchema::as_index)
+      .find(|index| index.root_page == page_index)
+      .ok_or_else(|| Error::Corrupt {
+        page: page_index,
+        reason: "not the root page of an index".to_string(),
+      })
+  }
+
+  /// The user version in the header, which applications use to track their schema migrations.
+  pub fn user_version(&self) -> i32 {
+    self.header.user_version as i32
+  }
+
+  /// The application ID in the header, naming the application file format the database is in.
+  pub fn application_id(&self) -> i32 {
+    self.header.application_id as i32
+  }
+
+  /// Set the user version in the header, like `PRAGMA user_version = n`.
+  pub fn set_user_version<F>(&mut self, file: F, user_version: i32) -> Result<()>
+    where
+      F: std::io::Read + std::io::Write + std::io::Seek,
+  {
+    self.write_header_field(file, 60, user_version as u32)
+  }
+
+  /// Set the application ID in the header, like `PRAGMA application_id = n`.
+  pub fn set_application_id<F>(&mut self, file: F, application_id: i32) -> Result<()>
+    where
+      F: std::io::Read + std::io::Write + std::io::Seek,
+  {
+    self.write_header_field(file, 68, application_id as u32)
+  }
+
+  /// Overwrite the 4-byte header field at `offset`. Committing bumps the file change counter, so
+  /// other connections see the change, and the header is then re-read.
+  fn write_header_field<F>(&mut self, mut file: F, offset: usize, value: u32) -> Result<()>
+    where
+      F: std::io::Read + std::io::Write + std::io::Seek,
+  {
+    self.check_writable()?;
+    let mut transaction = Transaction::new(self);
+    let page_data = transaction.page_mut(&mut file, 1, RecordType::Table)?;
+    page_data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+    transaction.commit(&mut file)?;
+    let page_data = self.read_page_data(&mut file, 1, RecordType::Table)?;
+    self.header = parse_header_bytes(&page_data[..HEADER_SIZE])?;
+    Ok(())
+  }
+
+  /// The schema format number of the file. Format 4, the default since SQLite 3.3.0, is the
+  /// first to allow DESC indexes and the serial types 8 and 9 for the integers 0 and 1.
+  pub fn schema_format(&self) -> u32 {
+    self.header.schema_format
+  }
+
+  /// Fail if any key column of the index rooted at `root_page` sorts DESC, as marked in
+  /// `descending`, in a file whose schema format predates DESC indexes.
+  fn check_key_order(&self, root_page: usize, descending: &[bool]) -> Result<()> {
+    if self.schema_format() < 4 && descending.contains(&true) {
+      return Err(Error::Corrupt {
+        page: root_page,
+        reason: format!("descending index in a file of schema format {}", self.schema_format()),
+      });
+    }
+    Ok(())
+  }
+
+  /// The smallest rowid of the table b-tree rooted at `page_index`, or the largest if `last`, or
+  /// `None` if the table is empty. Only the pages along its edge are read, unless a leaf there
+  /// is empty and the descent has to back up to the next child.
+  pub fn edge_row_id<R>(&self, mut file: R, page_index: usize, last: bool) -> Result<Option<i64>>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let mut pages_to_read = vec![page_index];
+    while let Some(page_index) = pages_to_read.pop() {
+      self.report_progress()?;
+      let page_data = self.read_page_data(&mut file, page_index, RecordType::Table)?;
+      let page = self.parse_raw_page(&page_data, page_index)?;
+      check_page_kind(&page, page_index, RecordType::Table)?;
+
+      if matches!(page.ty, BTreePageType::TableLeaf) {
+        if page.cell_count() > 0 {
+          let i = if last { page.cell_count() - 1 } else { 0 };
+          return Ok(Some(page.row_id(i).map_err(corrupt_cell(page_index))?.1));
+        }
+        continue;
+      }
+
+      // Children are pushed so that the one on the wanted edge is visited first.
+      let mut children = Vec::new();
+      for i in 0..page.cell_count() {
+        match page.cell(i, &[], &[]).map_err(corrupt_cell(page_index))?.1 {
+          Cell::TableInterior {
+            left_child_pointer, ..
+          } => children.push(left_child_pointer as usize),
+          _ => unreachable!(),
+        }
+      }
+      children.extend(page.rightmost_pointer);
+      if !last {
+        children.reverse();
+      }
+      pages_to_read.extend(children);
+    }
+    Ok(None)
+  }
+
+  /// Count the rows of the table b-tree rooted at `page_index` by summing the cell counts of its
+  /// leaf pages. Only interior cells are decoded, to find the children.
+  pub fn count_rows<R>(&self, mut file: R, page_index: usize) -> Result<usize>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let mut count = 0;
+    let mut pages_to_read = vec![page_index];
+    while let Some(page_index) = pages_to_read.pop() {
+      self.report_progress()?;
+      let page_data = self.read_page_data(&mut file, page_index, RecordType::Table)?;
+      let page = self.parse_raw_page(&page_data, page_index)?;
+      check_page_kind(&page, page_index, RecordType::Table)?;
+      match page.ty {
+        BTreePageType::TableLeaf => count += page.cell_count(),
+        BTreePageType::TableInterior => {
+          pages_to_read.extend(page.rightmost_pointer);
+          for i in 0..page.cell_count() {
+            match page.cell(i, &[], &[]).map_err(corrupt_cell(page_index))?.1 {
+              Cell::TableInterior {
+                left_child_pointer, ..
+              } => pages_to_read.push(left_child_pointer as usize),
+              _ => unreachable!(),
+            }
+          }
+        }
+        _ => {
+          return Err(Error::Corrupt {
+            page: page_index,
+            reason: "not a table b-tree page".to_string(),
+          })
+        }
+      }
+    }
+    Ok(count)
+  }
+
+  /// The rowids of the entries of the index rooted at `page_index` whose key equals `key`, sorted
+  /// and each only once, as [`Database::get_by_row_ids`] returns rows for them. The
+  /// key is first converted by the affinity of the indexed column, as SQLite does, so that the
+  /// text '74' finds the integer 74 in an INTEGER column and 105 finds the text '105' in a TEXT
+  /// one. A key still of another type than the entries finds nothing, as values of different
+  /// types are never equal, and NULL equals nothing: the entries with a NULL key, which sort
+  /// first, are passed over, and a NULL key finds no entries at all.
+  pub fn search_index<R>(
+    &self,
+    mut file: R,
+    page_index: usize,
+    key: Value,
+  ) -> Result<Vec<i64>>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let index = self.index_at(page_index)?;
+    self.check_key_order(page_index, &[index.descending])?;
+    let affinity = self.schema.table(&index.table_name).ok().and_then(|table| {
+      let column = table.column_names.iter().position(|c| c.eq_ignore_ascii_case(&index.column_name))?;
+      Some(table.column_affinities[column])
+    });
+    let key = match affinity {
+      Some(affinity) => affinity.apply(&key).into_owned(),
+      None => key,
+    };
+    // Keys are ordered and matched by the index's collation, so with NOCASE a probe finds the
+    // entries differing from it only in case.
+    let collation = index.collation;
+    let descending = index.descending;
+    let is_key = |value: &Value| {
+      value.as_value_ref().sql_cmp(&key.as_value_ref(), collation) == Some(std::cmp::Ordering::Equal)
+    };
+
+    let column_names = [index.column_name.as_str(), "row_id"];
+    let mut row_ids = Vec::new();
+    let mut pages_to_read: Vec<usize> = vec![page_index];
+    while let Some(page_index) = pages_to_read.pop() {
+      self.report_progress()?;
+      let page_data = self.read_page_data(&mut file, page_index, RecordType::Index)?;
+      let page = self.parse_raw_page(&page_data, page_index)?;
+      check_page_kind(&page, page_index, RecordType::Index)?;
+
+      // Cells are in key order, so binary search for the first one not before `key`. Only the left
+      // children of that cell and of the following cells equal to `key` can hold matching entries.
+      let mut comparisons = 0;
+      let start = page.lower_bound(|i| {
+        comparisons += 1;
+        let (entry_key, _) = match self.read_cell(&mut file, &page, page_index, i, &column_names, &[0, 1])? {
+          Cell::IndexLeaf(record) | Cell::IndexInterior { record, .. } => index_entry(record, page_index)?,
+          _ => unreachable!(),
+        };
+        let ordering = entry_key.as_value_ref().collated_cmp(&key.as_value_ref(), collation);
+        Ok(if descending { ordering.reverse() } else { ordering })
+      })?;
+
+      let mut past_key = false;
+      for i in start..page.cell_count() {
+        comparisons += 1;
+        let (entry_key, row_id) = match self.read_cell(&mut file, &page, page_index, i, &column_names, &[0, 1])? {
+          Cell::IndexLeaf(record) => index_entry(record, page_index)?,
+          Cell::IndexInterior {
+            left_child_pointer,
+            record,
+          } => {
+            pages_to_read.push(left_child_pointer as usize);
+            index_entry(record, page_index)?
+          }
+          _ => unreachable!(),
+        };
+        if !is_key(&entry_key) {
+          past_key = true;
+          break;
+        }
+        row_ids.push(row_id);
+      }
+      self.key_comparisons.fetch_add(comparisons, atomic::Ordering::Relaxed);
+
+      if !past_key {
+        if let Some(rightmost_pointer) = page.rightmost_pointer {
+          pages_to_read.push(rightmost_pointer);
+        }
+      }
+    }
+
+    // Entries are visited page by page rather than in key order, and a broken index can hold the
+    // same rowid twice.
+    row_ids.sort_unstable();
+    row_ids.dedup();
+    Ok(row_ids)
+  }
+
+  /// The rows of the table rooted at `page_index` with the given rowids, in rowid order. The ids
+  /// can come in any order and more than once, as when the lists of several lookups are joined,
+  /// but each row is returned once.
+  pub fn get_by_row_ids<R>(
+    &self,
+    mut file: R,
+    page_index: usize,
+    row_ids: &[i64],
+    column_names: &[&str],
+    column_indices: &[usize],
+  ) -> Result<Vec<Record>>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let mut row_ids = row_ids.to_vec();
+    row_ids.sort_unstable();
+    row_ids.dedup();
+
+    // Each page to read is paired with the range of `row_ids` that can be found beneath it. The
+    // children of a page are pushed in reverse, so the records come out in rowid order.
+    let mut records: Vec<Record> = Vec::new();
+    let mut pages_to_read: Vec<(usize, std::ops::Range<usize>)> =
+      vec![(page_index, 0..row_ids.len())];
+    while let Some((page_index, ids_range)) = pages_to_read.pop() {
+      self.report_progress()?;
+      let page_data = self.read_page_data(&mut file, page_index, RecordType::Table)?;
+      let page = self.parse_raw_page(&page_data, page_index)?;
+      check_page_kind(&page, page_index, RecordType::Table)?;
+
+      let mut comparisons = 0;
+      let mut children = Vec::new();
+      let mut start = ids_range.start;
+      while start < ids_range.end {
+        let row_id = row_ids[start];
+        let i = page.lower_bound(|i| {
+          comparisons += 1;
+          Ok(page.row_id(i).map_err(corrupt_cell(page_index))?.1.cmp(&row_id))
+        })?;
+
+        match page.ty {
+          BTreePageType::TableLeaf => {
+            if i < page.cell_count() && page.row_id(i).map_err(corrupt_cell(page_index))?.1 == row_id {
+              match self.read_cell(&mut file, &page, page_index, i, column_names, column_indices)? {
+                Cell::TableLeaf(record) => records.push(record),
+                _ => unreachable!(),
+              }
+            }
+            start += 1;
+          }
+          BTreePageType::TableInterior => {
+            if i == page.cell_count() {
+              // Every remaining id is beyond the last separator.
+              children.push((page.rightmost_pointer.unwrap(), start..ids_range.end));
+              break;
+            }
+
+            // The left child holds the rows with rowid <= key.
+            let key = page.row_id(i).map_err(corrupt_cell(page_index))?.1;
+            let end = start + row_ids[start..ids_range.end].partition_point(|id| *id <= key);
+            let left_child_pointer = match page.cell(i, column_names, column_indices) {
+              Ok((_, Cell::TableInterior { left_child_pointer, .. })) => left_child_pointer,
+              _ => unreachable!(),
+            };
+            children.push((left_child_pointer as usize, start..end));
+            start = end;
+          }
+          _ => unreachable!(),
+        }
+      }
+      pages_to_read.extend(children.into_iter().rev());
+      self.key_comparisons.fetch_add(comparisons, atomic::Ordering::Relaxed);
+    }
+
+    Ok(records)
+  }
+
+  /// Insert the rows of `insert` into its table and the table's indexes. The modified pages are
+  /// only written back once every row has fit, so a failed insert leaves the file unchanged.
+  pub fn insert<F>(&self, mut file: F, insert: &InsertQuery) -> Result<usize>
+    where
+      F: std::io::Read + std::io::Write + std::io::Seek,
+  {
+    self.check_writable()?;
+    let table = self.schema.writable_table(&insert.table_name)?;
+    let column_positions = match &insert.column_names {
+      Some(column_names) => column_names
+        .iter()
+        .map(|column_name| {
+          table
+            .column_names
+            .iter()
+            .position(|c| c.eq_ignore_ascii_case(column_name))
+            .ok_or_else(|| Error::ColumnNotFound {
+              table: table.name.clone(),
+              column: column_name.clone(),
+              available: table.column_names.clone(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?,
+      None => (0..table.column_names.len()).collect(),
+    };
+
+    let indexes = self.index_key_columns(table)?;
+
+    let mut transaction = Transaction::new(self);
+    for row in insert.rows.iter() {
+      if row.len() != column_positions.len() {
+        return Err(Error::ValueCount {
+          expected: column_positions.len(),
+          found: row.len(),
+        });
+      }
+      let mut values = vec![Value::Null; table.column_names.len()];
+      for (position, value) in column_positions.iter().zip(row.iter()) {
+        values[*position] = value.clone();
+      }
+      // A column left out takes its default, which can only be written if it's a constant.
+      for (i, default) in table.column_defaults.iter().enumerate() {
+        if let (false, Some(default)) = (column_positions.contains(&i), default) {
+          values[i] = default
+            .constant()
+            .ok_or_else(|| Error::UnsupportedSql(format!("DEFAULT of column {}", table.column_names[i])))?;
+        }
+      }
+      if table.strict {
+        values = strict_values(table, values)?;
+      }
+      check_not_null(table, &values)?;
+
+      let row_id = match table.row_id_column.map(|i| &values[i]) {
+        Some(Value::Integer(row_id)) => *row_id,
+        Some(Value::Null) | None => self.max_row_id(&transaction, &mut file, table.root_page)? + 1,
+        Some(_) => return Err(Error::DatatypeMismatch),
+      };
+
+      for index in indexes.iter() {
+        let key = index_key(table, &index.columns, row_id, &values);
+        self.check_unique(&transaction, &mut file, table, index, &key)?;
+        self.insert_index_entry(&mut transaction, &mut file, index, key)?;
+      }
+
+      let cell = self.table_leaf_cell(table.row_id_column, row_id, values)?;
+      let path = self.find_table_leaf(&transaction, &mut file, table.root_page, row_id)?;
+      let (page_index, i) = *path.last().unwrap();
+      let page_data = transaction.page(&mut file, page_index, RecordType::Table)?;
+      let page = self.parse_raw_page(&page_data, page_index)?;
+      if i < page.cell_count() && page.row_id(i).map_err(corrupt_cell(page_index))?.1 == row_id {
+        let column_name = match table.row_id_column {
+          Some(row_id_column) => table.column_names[row_id_column].as_str(),
+          None => "rowid",
+        };
+        return Err(Error::Constraint(format!(
+          "UNIQUE constraint failed: {}.{}",
+          table.name, column_name
+        )));
+      }
+      self.insert_cell(&mut transaction, &mut file, &path, cell, RecordType::Table)?;
+    }
+
+    transaction.commit(file)?;
+    Ok(insert.rows.len())
+  }
+
+  /// Delete the rows of the table matching the filter of `delete`, or every row without one,
+  /// along with their index entries.
+  pub fn delete<F>(&self, mut file: F, delete: &DeleteQuery) -> Result<usize>
+    where
+      F: std::io::Read + std::io::Write + std::io::Seek,
+  {
+    self.check_writable()?;
+    let table = self.schema.writable_table(&delete.table_name)?;
+    let indexes = self.index_key_columns(table)?;
+
+    let rows = self.matching_rows(&mut file, table, &delete.filters)?;
+
+    let mut transaction = Transaction::new(self);
+    for row in rows.iter() {
+      let row_id = row.row_id().unwrap();
+      for index in indexes.iter() {
+        let key = index_key(table, &index.columns, row_id, row.values());
+        self.delete_index_entry(&mut transaction, &mut file, index, &key)?;
+      }
+
+      let path = self.find_table_leaf(&transaction, &mut file, table.root_page, row_id)?;
+      let (page_index, i) = *path.last().unwrap();
+      let page_data = transaction.page(&mut file, page_index, RecordType::Table)?;
+      let page = self.parse_raw_page(&page_data, page_index)?;
+      if i == page.cell_count() || page.row_id(i).map_err(corrupt_cell(page_index))?.1 != row_id {
+        return Err(Error::Corrupt {
+          page: page_index,
+          reason: format!("rowid {} not found", row_id),
+        });
+      }
+      self.free_overflow_pages(&mut transaction, &mut file, &path, RecordType::Table)?;
+      self.remove_leaf_cell(&mut transaction, &mut file, &path, RecordType::Table)?;
+    }
+
+    transaction.commit(file)?;
+    Ok(rows.len())
+  }
+
+  /// Create the table described by `create`: its root is a new empty leaf page, and it's added
+  /// to the schema table with the schema cookie bumped so that other connections reload it. The
+  /// in-memory schema is then reloaded from the file.
+  pub fn create_table<F>(&mut self, mut file: F, create: &CreateQuery) -> Result<()>
+    where
+      F: std::io::Read + std::io::Write + std::io::Seek,
+  {
+    self.check_writable()?;
+    if create.is_index {
+      return Err(Error::UnsupportedSql("creating an index".to_string()));
+    }
+    if create.without_rowid {
+      return Err(Error::UnsupportedSql("creating a WITHOUT ROWID table".to_string()));
+    }
+    // SQLite expects an index for each of these, which would have to be created along with it.
+    if !create.key_constraints.is_empty() {
+      return Err(Error::UnsupportedSql(
+        "creating a table with a UNIQUE or non-rowid PRIMARY KEY constraint".to_string(),
+      ));
+    }
+    if create.name.len() >= 7 && create.name[..7].eq_ignore_ascii_case("sqlite_") {
+      return Err(Error::UnsupportedSql(format!(
+        "object name reserved for internal use: {}",
+        create.name
+      )));
+    }
+    for object in self.schema.objects.iter() {
+      let (kind, name) = match object {
+        ObjectSchema::Table(table) => ("table", &table.name),
+        ObjectSchema::Index(index) => ("index", &index.name),
+        _ => continue,
+      };
+      if name.eq_ignore_ascii_case(&create.name) {
+        if create.if_not_exists && kind == "table" {
+          return Ok(());
+        }
+        return Err(Error::AlreadyExists {
+          kind: kind.to_string(),
+          name: name.clone(),
+        });
+      }
+    }
+
+    let mut transaction = Transaction::new(self);
+    let root_page = transaction.allocate_page(&mut file)?;
+    let page_data = transaction.page_mut(&mut file, root_page, RecordType::Table)?;
+    PageMut::new(page_data, false, self.usable_page_size()).rebuild(BTreePageType::TableLeaf, &[], None);
+
+    let row_id = self.max_row_id(&transaction, &mut file, 1)? + 1;
+    let values = vec![
+      Value::Text("table".to_string()),
+      Value::Text(create.name.clone()),
+      Value::Text(create.name.clone()),
+      Value::Integer(root_page as i64),
+      Value::Text(create.sql.clone()),
+    ];
+    let cell = self.table_leaf_cell(None, row_id, values)?;
+    let path = self.find_table_leaf(&transaction, &mut file, 1, row_id)?;
+    self.insert_cell(&mut transaction, &mut file, &path, cell, RecordType::Table)?;
+    transaction.increment_schema_cookie(&mut file)?;
+    transaction.commit(&mut file)?;
+    self.reload_schema(&mut file)
+  }
+
+  /// Re-read the header and schema, through the pages of the current transaction if any.
+  fn reload_schema<R>(&mut self, mut file: R) -> Result<()>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let page_data = self.read_page_data(&mut file, 1, RecordType::Table)?;
+    self.header = parse_header_bytes(&page_data[..HEADER_SIZE])?;
+    self.change_counter.store(self.header.file_change_counter as u32, atomic::Ordering::Relaxed);
+    self.load_schema(file)
+  }
+
+  /// Start a transaction: the writes of the following statements are kept in memory, visible to
+  /// reads through this handle, until [`Database::commit`] writes them all at once.
+  pub fn begin(&self) -> Result<()> {
+    let mut batch = self.batch.lock().unwrap();
+    if batch.is_some() {
+      return Err(Error::TransactionState(
+        "cannot start a transaction within a transaction".to_string(),
+      ));
+    }
+    *batch = Some(BTreeMap::new());
+    Ok(())
+  }
+
+  /// Write the pages of the current transaction to `file`.
+  pub fn commit<F>(&self, file: F) -> Result<()>
+    where
+      F: std::io::Read + std::io::Write + std::io::Seek,
+  {
+    let pages = self.batch.lock().unwrap().take().ok_or_else(|| {
+      Error::TransactionState("cannot commit - no transaction is active".to_string())
+    })?;
+    self.write_pages(file, &pages)
+  }
+
+  /// Discard the writes of the current transaction, reloading the schema they may have changed.
+  pub fn rollback<R>(&mut self, file: R) -> Result<()>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    if self.batch.lock().unwrap().take().is_none() {
+      return Err(Error::TransactionState(
+        "cannot rollback - no transaction is active".to_string(),
+      ));
+    }
+    self.reload_schema(file)
+  }
+
+  /// Write `pages` to `file`, or add them to the current transaction if there is one. When the
+  /// database was opened by path, their original contents are journaled first, and the journal
+  /// is only deleted once the database file is synced.
+  pub(crate) fn write_pages<F>(&self, mut file: F, pages: &BTreeMap<usize, Arc<Vec<u8>>>) -> Result<()>
+    where
+      F: std::io::Read + std::io::Write + std::io::Seek,
+  {
+    if let Some(batch) = self.batch.lock().unwrap().as_mut() {
+      batch.extend(pages.iter().map(|(page_index, data)| (*page_index, data.clone())));
+      return Ok(());
+    }
+
+    let page_size = self.header.page_size;
+    let journal_path = self.path.as_ref().map(journal::journal_path);
+    if let Some(journal_path) = journal_path.as_ref() {
+      let database_size = (file.seek(std::io::SeekFrom::End(0))? / page_size as u64) as usize;
+      let originals =
+        journal::original_pages(&mut file, page_size, database_size, pages.keys().copied())?;
+      journal::write_journal(journal_path, page_size, database_size, &originals)?;
+    }
+
+    for (page_index, data) in pages.iter() {
+      file.seek(std::io::SeekFrom::Start(page_offset(page_size, *page_index)?))?;
+      file.write_all(data)?;
+    }
+    file.flush()?;
+
+    let mut page_cache = self.page_cache.lock().unwrap();
+    for page_index in pages.keys() {
+      page_cache.remove(*page_index);
+    }
+    drop(page_cache);
+    // A write of this handle's own isn't a reason to refresh.
+    if let Some(header) = pages.get(&1) {
+      let change_counter = u32::from_be_bytes(header[24..28].try_into().unwrap());
+      self.change_counter.store(change_counter, atomic::Ordering::Relaxed);
+    }
+
+    if let (Some(path), Some(journal_path)) = (self.path.as_ref(), journal_path.as_ref()) {
+      std::fs::OpenOptions::new().write(true).open(path)?.sync_all()?;
+      journal::delete_journal(journal_path)?;
+    }
+    Ok(())
+  }
+
+  /// Set the columns assigned by `update` in the rows matching its filter, or every row without
+  /// one, and update the index entries whose key changes. A record which still fits where the old
+  /// one was is rewritten in place, otherwise it is reinserted into its leaf.
+  pub fn update<F>(&self, mut file: F, update: &UpdateQuery) -> Result<usize>
+    where
+      F: std::io::Read + std::io::Write + std::io::Seek,
+  {
+    self.check_writable()?;
+    let table = self.schema.writable_table(&update.table_name)?;
+    let assignments = update
+      .assignments
+      .iter()
+      .map(|(column_name, value)| {
+        let position = table
+          .column_names
+          .iter()
+          .position(|c| c.eq_ignore_ascii_case(column_name))
+          .ok_or_else(|| Error::ColumnNotFound {
+            table: table.name.clone(),
+            column: column_name.clone(),
+            available: table.column_names.clone(),
+          })?;
+        if table.row_id_column == Some(position) {
+          return Err(Error::UnsupportedSql(format!(
+            "updating the rowid column {}.{}",
+            table.name, table.column_names[position]
+          )));
+        }
+        Ok((position, value.clone()))
+      })
+      .collect::<Result<Vec<_>>>()?;
+    let indexes = self.index_key_columns(table)?;
+    let rows = self.matching_rows(&mut file, table, &update.filters)?;
+
+    let mut transaction = Transaction::new(self);
+    for row in rows.iter() {
+      let row_id = row.row_id().unwrap();
+      let mut values = row.values().to_vec();
+      for (position, value) in assignments.iter() {
+        values[*position] = value.clone();
+      }
+      if table.strict {
+        values = strict_values(table, values)?;
+      }
+      check_not_null(table, &values)?;
+
+      for index in indexes.iter() {
+        let old_key = index_key(table, &index.columns, row_id, row.values());
+        let key = index_key(table, &index.columns, row_id, &values);
+        // Any change to the bytes is written, even one the index's collation ignores. The old
+        // entry goes first, so that the row doesn't conflict with itself.
+        if compare_keys(&old_key, &key, &[], &[]).is_ne() {
+          self.delete_index_entry(&mut transaction, &mut file, index, &old_key)?;
+          self.check_unique(&transaction, &mut file, table, index, &key)?;
+          self.insert_index_entry(&mut transaction, &mut file, index, key)?;
+        }
+      }
+
+      let cell = self.table_leaf_cell(table.row_id_column, row_id, values)?;
+      let path = self.find_table_leaf(&transaction, &mut file, table.root_page, row_id)?;
+      let (page_index, i) = *path.last().unwrap();
+      let page_data = transaction.page(&mut file, page_index, RecordType::Table)?;
+      let page = self.parse_page_to_modify(&page_data, page_index)?;
+      if i == page.cell_count() || page.row_id(i).map_err(corrupt_cell(page_index))?.1 != row_id {
+        return Err(Error::Corrupt {
+          page: page_index,
+          reason: format!("rowid {} not found", row_id),
+        });
+      }
+      let old_cell_len = self.cell_bytes(&page, page_index, i)?.len();
+      self.free_overflow_pages(&mut transaction, &mut file, &path, RecordType::Table)?;
+      let page_data = transaction.page_mut(&mut file, page_index, RecordType::Table)?;
+      let mut page = PageMut::new(page_data, page_index == 1, self.usable_page_size());
+      if !page.replace_cell(i, &cell, old_cell_len) {
+        page.remove_cell(i, old_cell_len);
+        self.insert_cell(&mut transaction, &mut file, &path, cell, RecordType::Table)?;
+      }
+    }
+
+    transaction.commit(file)?;
+    Ok(rows.len())
+  }
+
+  /// Every column of the rows of `table` matching `filters`, found before any page is modified.
+  fn matching_rows<R>(&self, file: R, table: &TableSchema, filters: &[Condition]) -> Result<Vec<Row>>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let select = SelectQuery {
+      table_name: Some(table.name.clone()),
+      columns: table
+        .column_names
+        .iter()
+        .map(|column_name| Column::ColumnName(column_name.clone()))
+        .collect(),
+      filters: filters.to_vec(),
+      order_by: Vec::new(),
+      limit: None,
+      offset: None,
+    };
+    Ok(select.resolve(self)?.execute(self, file, &[])?.rows)
+  }
+
+  /// The cell of a table leaf page holding the row `values`.
+  fn table_leaf_cell(
+    &self,
+    row_id_column: Option<usize>,
+    row_id: i64,
+    mut values: Vec<Value>,
+  ) -> Result<Vec<u8>> {
+    // The rowid alias column is stored as NULL, its value is read from the cell's rowid.
+    if let Some(row_id_column) = row_id_column {
+      values[row_id_column] = Value::Null;
+    }
+    let payload = Record {
+      row_id: Some(row_id),
+      values,
+    }
+    .to_bytes();
+    if payload.len() > self.usable_page_size() - 35 {
+      return Err(Error::UnsupportedSql(
+        "writing a row that overflows its page".to_string(),
+      ));
+    }
+    let mut cell = Vec::with_capacity(payload.len() + 18);
+    write_varint(payload.len() as i64, &mut cell);
+    write_varint(row_id, &mut cell);
+    cell.extend(payload);
+    Ok(cell)
+  }
+
+  /// Add the overflow pages of the cell at the end of `path` to the freelist, before the cell is
+  /// removed or rewritten.
+  fn free_overflow_pages<R>(
+    &self,
+    transaction: &mut Transaction,
+    mut file: R,
+    path: &[(usize, usize)],
+    kind: RecordType,
+  ) -> Result<()>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let (page_index, i) = *path.last().unwrap();
+    let page_data = transaction.page(&mut file, page_index, kind)?;
+    let page = self.parse_raw_page(&page_data, page_index)?;
+    let overflow = match page.cell_payload(i).map_err(corrupt_cell(page_index))?.1 {
+      CellPayload::Overflow(overflow) => overflow,
+      CellPayload::Local { .. } => return Ok(()),
+    };
+
+    // Each overflow page starts with the number of the next one, and holds the payload after it.
+    let mut remaining = overflow.payload_size - overflow.local_payload.len();
+    let mut overflow_page = overflow.first_overflow_page;
+    while remaining > 0 {
+      if overflow_page == 0 {
+        return Err(Error::Corrupt {
+          page: overflow.first_overflow_page,
+          reason: "overflow chain ended before the end of the payload".to_string(),
+        });
+      }
+      let page_data = transaction.page(&mut file, overflow_page, kind)?;
+      let next_page = u32::from_be_bytes(page_data[..4].try_into().unwrap()) as usize;
+      transaction.free_page(&mut file, overflow_page)?;
+      remaining = remaining.saturating_sub(self.usable_page_size() - 4);
+      overflow_page = next_page;
+    }
+    Ok(())
+  }
+
+  /// Writes go straight to the database file, which is only valid without a write-ahead log, and
+  /// they don't maintain the pointer map pages of auto-vacuum databases.
+  fn check_writable(&self) -> Result<()> {
+    if matches!(self.header.write_version, FormatVersion::WriteAheadLog) {
+      return Err(Error::UnsupportedSql(
+        "writing to a database in WAL mode".to_string(),
+      ));
+    }
+    if self.header.largest_root_btree_page != 0 {
+      return Err(Error::UnsupportedSql(
+        "writing to an auto-vacuum database".to_string(),
+      ));
+    }
+    Ok(())
+  }
+
+  /// The root page of each index on `table`, with the positions of the table columns it indexes.
+  pub(crate) fn index_key_columns(&self, table: &TableSchema) -> Result<Vec<IndexKeyColumns>> {
+    let mut indexes = Vec::new();
+    for index in self.schema.indexes(&table.name) {
+      let create_query = self.schema.index_query(index)?;
+      let create_query = create_query.as_create().unwrap();
+      let key_columns = create_query
+        .column_names
+        .iter()
+        .map(|column_name| {
+          table
+            .column_names
+            .iter()
+            .position(|c| c.eq_ignore_ascii_case(column_name))
+            .ok_or_else(|| Error::ColumnNotFound {
+              table: table.name.clone(),
+              column: column_name.clone(),
+              available: table.column_names.clone(),
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+      // A key column without a COLLATE clause of its own takes its table column's collation.
+      let collations = key_columns
+        .iter()
+        .zip(create_query.column_collations.iter())
+        .map(|(i, collation)| collation.unwrap_or(table.column_collations[*i]))
+        .collect();
+      self.check_key_order(index.root_page, &create_query.column_descending)?;
+      indexes.push(IndexKeyColumns {
+        root_page: index.root_page,
+        columns: key_columns,
+        collations,
+        descending: create_query.column_descending.clone(),
+        unique: index.unique,
+      });
+    }
+    Ok(indexes)
+  }
+
+  fn cell_bytes(&self, page: &RawPage, page_index: usize, i: usize) -> Result<Vec<u8>> {
+    page.cell_bytes(i).map(|(_, cell)| cell.to_vec()).map_err(corrupt_cell(page_index))
+  }
+
+
+  /// Descend the table b-tree rooted at `page_index` to the leaf page where `row_id` belongs.
+  /// Returns each page on the way with the position of the child followed in it, ending with the
+  /// leaf and the position of the first cell with a rowid >= `row_id` on it.
+  fn find_table_leaf<R>(
+    &self,
+    transaction: &Transaction,
+    mut file: R,
+    mut page_index: usize,
+    row_id: i64,
+  ) -> Result<Vec<(usize, usize)>>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let mut path = Vec::new();
+    loop {
+      let page_data = transaction.page(&mut file, page_index, RecordType::Table)?;
+      let page = self.parse_raw_page(&page_data, page_index)?;
+      check_page_kind(&page, page_index, RecordType::Table)?;
+      let i = page.lower_bound(|i| Ok(page.row_id(i).map_err(corrupt_cell(page_index))?.1.cmp(&row_id)))?;
+      path.push((page_index, i));
+      match page.ty {
+        BTreePageType::TableLeaf => return Ok(path),
+        BTreePageType::TableInterior if i == page.cell_count() => {
+          page_index = page.rightmost_pointer.unwrap();
+        }
+        BTreePageType::TableInterior => match page.cell(i, &[], &[]) {
+          Ok((_, Cell::TableInterior { left_child_pointer, .. })) => {
+            page_index = left_child_pointer as usize;
+          }
+          _ => unreachable!(),
+        },
+        _ => {
+          return Err(Error::Corrupt {
+            page: page_index,
+            reason: "not a table b-tree page".to_string(),
+          })
+        }
+      }
+    }
+  }
+
+  /// The largest rowid in the table b-tree rooted at `page_index`, or 0 if it's empty.
+  fn max_row_id<R>(&self, transaction: &Transaction, mut file: R, mut page_index: usize) -> Result<i64>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    loop {
+      let page_data = transaction.page(&mut file, page_index, RecordType::Table)?;
+      let page = self.parse_raw_page(&page_data, page_index)?;
+      match (page.ty, page.rightmost_pointer) {
+        (BTreePageType::TableInterior, Some(rightmost_pointer)) => page_index = rightmost_pointer,
+        (BTreePageType::TableLeaf, _) if page.cell_count() == 0 => return Ok(0),
+        (BTreePageType::TableLeaf, _) => {
+          return Ok(page.row_id(page.cell_count() - 1).map_err(corrupt_cell(page_index))?.1)
+        }
+        _ => {
+          return Err(Error::Corrupt {
+            page: page_index,
+            reason: "not a table b-tree page".to_string(),
+          })
+        }
+      }
+    }
+  }
+
+  /// Insert the index entry `key`, the indexed values followed by the rowid, into the b-tree of
+  /// `index`, keeping the entries in key order.
+  fn insert_index_entry<R>(
+    &self,
+    transaction: &mut Transaction,
+    mut file: R,
+    index: &IndexKeyColumns,
+    key: Vec<Value>,
+  ) -> Result<()>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let payload = Record {
+      row_id: None,
+      values: key.clone(),
+    }
+    .to_bytes();
+    if payload.len() > ((self.usable_page_size() - 12) * 64 / 255) - 23 {
+      return Err(Error::UnsupportedSql(
+        "inserting an index entry that overflows its page".to_string(),
+      ));
+    }
+    let mut cell = Vec::with_capacity(payload.len() + 9);
+    write_varint(payload.len() as i64, &mut cell);
+    cell.extend(payload);
+
+    let column_names = vec!["key"; key.len()];
+    let column_indices = (0..key.len()).collect::<Vec<_>>();
+    let mut path = Vec::new();
+    let mut page_index = index.root_page;
+    loop {
+      let page_data = transaction.page(&mut file, page_index, RecordType::Index)?;
+      let page = self.parse_raw_page(&page_data, page_index)?;
+      check_page_kind(&page, page_index, RecordType::Index)?;
+      let i = page.lower_bound(|i| {
+        match self.read_cell(&mut file, &page, page_index, i, &column_names, &column_indices)? {
+          Cell::IndexLeaf(record) | Cell::IndexInterior { record, .. } => {
+            Ok(compare_keys(&record.values, &key, &index.collations, &index.descending))
+          }
+          _ => unreachable!(),
+        }
+      })?;
+      path.push((page_index, i));
+
+      match page.ty {
+        BTreePageType::IndexLeaf => {
+          return self.insert_cell(transaction, &mut file, &path, cell, RecordType::Index);
+        }
+        BTreePageType::IndexInterior if i == page.cell_count() => {
+          page_index = page.rightmost_pointer.unwrap();
+        }
+        BTreePageType::IndexInterior => match self.read_cell(&mut file, &page, page_index, i, &[], &[])? {
+          Cell::IndexInterior {
+            left_child_pointer, ..
+          } => page_index = left_child_pointer as usize,
+          _ => unreachable!(),
+        },
+        _ => {
+          return Err(Error::Corrupt {
+            page: page_index,
+            reason: "not an index b-tree page".to_string(),
+          })
+        }
+      }
+    }
+  }
+
+  /// Fail if `index` is UNIQUE and already has an entry with the indexed values of `key`, which
+  /// is an entry as made by [`index_key`]. A key holding a NULL conflicts with none.
+  fn check_unique<R>(
+    &self,
+    transaction: &Transaction,
+    mut file: R,
+    table: &TableSchema,
+    index: &IndexKeyColumns,
+    key: &[Value],
+  ) -> Result<()>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let indexed = &key[..index.columns.len()];
+    if !index.unique || indexed.iter().any(|value| matches!(value, Value::Null)) {
+      return Ok(());
+    }
+    let column_names = vec!["key"; indexed.len()];
+    let column_indices = (0..indexed.len()).collect::<Vec<_>>();
+    let mut page_index = index.root_page;
+    // Entries with the same indexed values sort together, so one is either the first entry not
+    // below them on some page on the way down, or isn't there.
+    let found = loop {
+      let page_data = transaction.page(&mut file, page_index, RecordType::Index)?;
+      let page = self.parse_raw_page(&page_data, page_index)?;
+      check_page_kind(&page, page_index, RecordType::Index)?;
+      let mut found = false;
+      let i = page.lower_bound(|i| {
+        match self.read_cell(&mut file, &page, page_index, i, &column_names, &column_indices)? {
+          Cell::IndexLeaf(record) | Cell::IndexInterior { record, .. } => {
+            let ordering = compare_keys(&record.values, indexed, &index.collations, &index.descending);
+            found |= ordering.is_eq();
+            Ok(ordering)
+          }
+          _ => unreachable!(),
+        }
+      })?;
+      match page.ty {
+        _ if found => break true,
+        BTreePageType::IndexLeaf => break false,
+        BTreePageType::IndexInterior if i == page.cell_count() => {
+          page_index = page.rightmost_pointer.unwrap();
+        }
+        BTreePageType::IndexInterior => {
+          let cell = self.cell_bytes(&page, page_index, i)?;
+          page_index = u32::from_be_bytes(cell[..4].try_into().unwrap()) as usize;
+        }
+        _ => {
+          return Err(Error::Corrupt {
+            page: page_index,
+            reason: "not an index b-tree page".to_string(),
+          })
+        }
+      }
+    };
+    if !found {
+      return Ok(());
+    }
+    let columns = index
+      .columns
+      .iter()
+      .map(|i| format!("{}.{}", table.name, table.column_names[*i]))
+      .collect::<Vec<_>>();
+    Err(Error::Constraint(format!("UNIQUE constraint failed: {}", columns.join(", "))))
+  }
+
+  /// Remove the index entry `key` from the b-tree of `index`. An entry on an interior page is
+  /// replaced by its predecessor, which is then removed from its leaf.
+  fn delete_index_entry<R>(
+    &self,
+    transaction: &mut Transaction,
+    mut file: R,
+    index: &IndexKeyColumns,
+    key: &[Value],
+  ) -> Result<()>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let path = self.find_index_entry(transaction, &mut file, index, key, false)?;
+    let (page_index, i) = *path.last().unwrap();
+    let page_data = transaction.page(&mut file, page_index, RecordType::Index)?;
+    let page = self.parse_page_to_modify(&page_data, page_index)?;
+    self.free_overflow_pages(transaction, &mut file, &path, RecordType::Index)?;
+    if matches!(page.ty, BTreePageType::IndexLeaf) {
+      return self.remove_leaf_cell(transaction, &mut file, &path, RecordType::Index);
+    }
+
+    // The predecessor is the last entry of the rightmost leaf under the entry's left child. Its
+    // cell moves along with its overflow pages.
+    let cell = self.cell_bytes(&page, page_index, i)?;
+    let mut leaf_index = u32::from_be_bytes(cell[..4].try_into().unwrap()) as usize;
+    let (predecessor, predecessor_key) = loop {
+      let leaf_data = transaction.page(&mut file, leaf_index, RecordType::Index)?;
+      let leaf = self.parse_raw_page(&leaf_data, leaf_index)?;
+      check_page_kind(&leaf, leaf_index, RecordType::Index)?;
+      if let Some(rightmost_pointer) = leaf.rightmost_pointer {
+        leaf_index = rightmost_pointer;
+        continue;
+      }
+      // Only the root of a b-tree may be without cells, and a leaf reached through an interior
+      // page isn't one.
+      let last = leaf.cell_count().checked_sub(1).ok_or_else(|| Error::Corrupt {
+        page: leaf_index,
+        reason: "index leaf without cells".to_string(),
+      })?;
+      let column_names = vec!["key"; key.len()];
+      let column_indices = (0..key.len()).collect::<Vec<_>>();
+      match self.read_cell(&mut file, &leaf, leaf_index, last, &column_names, &column_indices)? {
+        Cell::IndexLeaf(record) => {
+          break (self.cell_bytes(&leaf, leaf_index, last)?, record.values)
+        }
+        _ => unreachable!(),
+      }
+    };
+
+    let mut replacement = cell[..4].to_vec();
+    replacement.extend(predecessor);
+    let page_data = transaction.page_mut(&mut file, page_index, RecordType::Index)?;
+    PageMut::new(page_data, page_index == 1, self.usable_page_size()).remove_cell(i, cell.len());
+    self.insert_cell(transaction, &mut file, &path, replacement, RecordType::Index)?;
+
+    let path = self.find_index_entry(transaction, &mut file, index, &predecessor_key, true)?;
+    self.remove_leaf_cell(transaction, &mut file, &path, RecordType::Index)
+  }
+
+  /// Descend the b-tree of `index` to the entry `key`, returning the path of (page, cell) to it.
+  /// With `in_leaf`, an entry on an interior page is passed over for a copy of it at the end of
+  /// its left subtree, as left while it replaces a deleted entry.
+  fn find_index_entry<R>(
+    &self,
+    transaction: &Transaction,
+    mut file: R,
+    index: &IndexKeyColumns,
+    key: &[Value],
+    in_leaf: bool,
+  ) -> Result<Vec<(usize, usize)>>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let column_names = vec!["key"; key.len()];
+    let column_indices = (0..key.len()).collect::<Vec<_>>();
+    let mut path = Vec::new();
+    let mut page_index = index.root_page;
+    loop {
+      let page_data = transaction.page(&mut file, page_index, RecordType::Index)?;
+      let page = self.parse_raw_page(&page_data, page_index)?;
+      check_page_kind(&page, page_index, RecordType::Index)?;
+      let mut found = false;
+      let i = page.lower_bound(|i| {
+        match self.read_cell(&mut file, &page, page_index, i, &column_names, &column_indices)? {
+          Cell::IndexLeaf(record) | Cell::IndexInterior { record, .. } => {
+            let ordering = compare_keys(&record.values, key, &index.collations, &index.descending);
+            found |= ordering.is_eq();
+            Ok(ordering)
+          }
+          _ => unreachable!(),
+        }
+      })?;
+      path.push((page_index, i));
+
+      match page.ty {
+        BTreePageType::IndexLeaf if found => return Ok(path),
+        BTreePageType::IndexInterior if found && !in_leaf => return Ok(path),
+        BTreePageType::IndexLeaf => {
+          return Err(Error::Corrupt {
+            page: page_index,
+            reason: "index entry not found".to_string(),
+          })
+        }
+        BTreePageType::IndexInterior if i == page.cell_count() => {
+          page_index = page.rightmost_pointer.unwrap();
+        }
+        BTreePageType::IndexInterior => {
+          let cell = self.cell_bytes(&page, page_index, i)?;
+          page_index = u32::from_be_bytes(cell[..4].try_into().unwrap()) as usize;
+        }
+        _ => {
+          return Err(Error::Corrupt {
+            page: page_index,
+            reason: "not an index b-tree page".to_string(),
+          })
+        }
+      }
+    }
+  }
+
+  /// Remove the cell at the end of `path` from its leaf page. sqlite considers pages without
+  /// cells below the root corrupt, so a leaf left empty is unlinked from its parent and freed.
+  fn remove_leaf_cell<R>(
+    &self,
+    transaction: &mut Transaction,
+    mut file: R,
+    path: &[(usize, usize)],
+    kind: RecordType,
+  ) -> Result<()>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let usable_page_size = self.usable_page_size();
+    let (page_index, i) = *path.last().unwrap();
+    let page_data = transaction.page(&mut file, page_index, kind)?;
+    let cell_len = self.cell_bytes(&self.parse_page_to_modify(&page_data, page_index)?, page_index, i)?.len();
+    let page_data = transaction.page_mut(&mut file, page_index, kind)?;
+    let mut page = PageMut::new(page_data, page_index == 1, usable_page_size);
+    page.remove_cell(i, cell_len);
+    if page.cell_count() > 0 || path.len() == 1 {
+      return Ok(());
+    }
+    self.remove_empty_page(transaction, &mut file, path, kind)
+  }
+
+  /// Unlink the page at the end of `path`, which has no cells left, from its parent and free it.
+  /// An interior page still has its rightmost child, which moves over to the sibling taking the
+  /// page's place, so that every leaf stays at the same depth. A parent left without cells is
+  /// removed the same way, except for the root, which is replaced by its only child.
+  fn remove_empty_page<R>(
+    &self,
+    transaction: &mut Transaction,
+    mut file: R,
+    path: &[(usize, usize)],
+    kind: RecordType,
+  ) -> Result<()>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let usable_page_size = self.usable_page_size();
+    for depth in (1..=path.len()).rev() {
+      let path = &path[..depth];
+      let (page_index, _) = *path.last().unwrap();
+      let page_data = transaction.page(&mut file, page_index, kind)?;
+      let orphan = self.parse_raw_page(&page_data, page_index)?.rightmost_pointer;
+
+      if path.len() == 1 {
+        let Some(child) = orphan else {
+          return Ok(());
+        };
+        // The root keeps its page number, so the child's contents move up into it. Page 1 would
+        // need its b-tree header moved past the file header, but its b-tree, the schema table's,
+        // is never written here, as `Schema::writable_table` rejects it.
+        debug_assert!(page_index != 1, "the schema table's root is never emptied");
+        let child_data = transaction.page(&mut file, child, kind)?.to_vec();
+        *transaction.page_mut(&mut file, page_index, kind)? = child_data;
+        return transaction.free_page(&mut file, child);
+      }
+
+      // The parent loses the cell pointing at the empty page, or the one before it if the page is
+      // its rightmost child, whose left child then becomes the rightmost child.
+      let (parent_index, child) = path[path.len() - 2];
+      let parent_data = transaction.page(&mut file, parent_index, kind)?;
+      let parent = self.parse_page_to_modify(&parent_data, parent_index)?;
+      let cell_count = parent.cell_count();
+      let removed = child.min(cell_count - 1);
+      let cell = self.cell_bytes(&parent, parent_index, removed)?;
+      let left_child_pointer = u32::from_be_bytes(cell[..4].try_into().unwrap());
+      let parent_data = transaction.page_mut(&mut file, parent_index, kind)?;
+      let mut parent = PageMut::new(parent_data, parent_index == 1, usable_page_size);
+      parent.remove_cell(removed, cell.len());
+      if child == cell_count {
+        parent.set_rightmost_pointer(left_child_pointer);
+      }
+      let sibling = match parent.cell_count() {
+        _ if child == cell_count => left_child_pointer,
+        count if removed < count => parent.left_child_pointer(removed),
+        _ => parent.rightmost_pointer().unwrap(),
+      } as usize;
+      transaction.free_page(&mut file, page_index)?;
+
+      // The divider moves down into the sibling, at its end if the sibling was on the left and at
+      // its start otherwise: an index entry between the sibling's and the empty leaf's, or the key
+      // between the sibling's children and the orphan. A table leaf's divider is only a copy of a
+      // rowid, which goes away.
+      let sibling_data = transaction.page(&mut file, sibling, kind)?;
+      let sibling_cell_count = self.parse_raw_page(&sibling_data, sibling)?.cell_count();
+      let moved = match orphan {
+        None if matches!(kind, RecordType::Table) => None,
+        None if child == cell_count => Some((sibling_cell_count, cell[4..].to_vec())),
+        None => Some((0, cell[4..].to_vec())),
+        Some(orphan) if child == cell_count => {
+          let sibling_data = transaction.page_mut(&mut file, sibling, kind)?;
+          let mut sibling_page = PageMut::new(sibling_data, sibling == 1, usable_page_size);
+          let mut moved = sibling_page.rightmost_pointer().unwrap().to_be_bytes().to_vec();
+          sibling_page.set_rightmost_pointer(orphan as u32);
+          moved.extend(&cell[4..]);
+          Some((sibling_cell_count, moved))
+        }
+        Some(orphan) => {
+          let mut moved = (orphan as u32).to_be_bytes().to_vec();
+          moved.extend(&cell[4..]);
+          Some((0, moved))
+        }
+      };
+      if let Some((position, moved)) = moved {
+        let mut sibling_path = path[..path.len() - 2].to_vec();
+        sibling_path.extend([(parent_index, removed), (sibling, position)]);
+        self.insert_cell(transaction, &mut file, &sibling_path, moved, kind)?;
+      }
+
+      // The parent is removed next if it has no cells left, unless splitting the sibling gave it
+      // one.
+      let parent_data = transaction.page(&mut file, parent_index, kind)?;
+      if self.parse_raw_page(&parent_data, parent_index)?.cell_count() > 0 {
+        return Ok(());
+      }
+    }
+    Ok(())
+  }
+
+  /// Insert `cell` into the leaf at the end of `path`, as returned by [`Self::find_table_leaf`].
+  /// A page without room for the cell is split in two: its lower half moves to a new page, which
+  /// becomes the left child of a divider cell inserted into the parent the same way. The root
+  /// keeps its page number, so when it splits both halves move to new pages beneath it.
+  fn insert_cell<R>(
+    &self,
+    transaction: &mut Transaction,
+    mut file: R,
+    path: &[(usize, usize)],
+    mut cell: Vec<u8>,
+    kind: RecordType,
+  ) -> Result<()>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let usable_page_size = self.usable_page_size();
+    for (level, &(page_index, i)) in path.iter().enumerate().rev() {
+      let page_data = transaction.page_mut(&mut file, page_index, kind)?;
+      let mut page = PageMut::new(page_data, page_index == 1, usable_page_size);
+      if page.insert_cell(i, &cell) {
+        return Ok(());
+      }
+
+      let page_data = transaction.page(&mut file, page_index, kind)?;
+      let page = self.parse_raw_page(&page_data, page_index)?;
+      let ty = page.ty;
+      let rightmost_pointer = page.rightmost_pointer.map(|pointer| pointer as u32);
+      let mut cells = (0..page.cell_count())
+        .map(|i| self.cell_bytes(&page, page_index, i))
+        .collect::<Result<Vec<_>>>()?;
+      cells.insert(i, cell);
+
+      // The page may have enough room in free blocks left by deleted cells.
+      let page_data = transaction.page_mut(&mut file, page_index, kind)?;
+      let mut page = PageMut::new(page_data, page_index == 1, usable_page_size);
+      if page.rebuild(ty, &cells, rightmost_pointer) {
+        return Ok(());
+      }
+
+      let split = split_cells(ty, cells).ok_or(Error::PageFull { page: page_index })?;
+
+      let left_page_index = transaction.allocate_page(&mut file)?;
+      let right_page_index = if level == 0 {
+        transaction.allocate_page(&mut file)?
+      } else {
+        page_index
+      };
+      for (split_page_index, cells, rightmost_pointer) in [
+        (left_page_index, &split.left, split.left_rightmost_pointer),
+        (right_page_index, &split.right, rightmost_pointer),
+      ] {
+        let page_data = transaction.page_mut(&mut file, split_page_index, kind)?;
+        let mut page = PageMut::new(page_data, split_page_index == 1, usable_page_size);
+        if !page.rebuild(ty, cells, rightmost_pointer) {
+          return Err(Error::PageFull { page: split_page_index });
+        }
+      }
+
+      let mut divider = (left_page_index as u32).to_be_bytes().to_vec();
+      divider.extend(split.divider);
+      if level == 0 {
+        let interior_ty = match ty {
+          BTreePageType::TableLeaf | BTreePageType::TableInterior => BTreePageType::TableInterior,
+          BTreePageType::IndexLeaf | BTreePageType::IndexInterior => BTreePageType::IndexInterior,
+        };
+        let page_data = transaction.page_mut(&mut file, page_index, kind)?;
+        let mut page = PageMut::new(page_data, page_index == 1, usable_page_size);
+        if !page.rebuild(interior_ty, &[divider], Some(right_page_index as u32)) {
+          return Err(Error::PageFull { page: page_index });
+        }
+        return Ok(());
+      }
+      cell = divider;
+    }
+    unreachable!("splitting the root page always returns")
+  }
+}
+
+/// The cells of an overfull page split into two pages, and the divider between them.
+struct Split {
+  left: Vec<Vec<u8>>,
+  left_rightmost_pointer: Option<u32>,
+  right: Vec<Vec<u8>>,
+  /// The parent's cell pointing at the left page, without its left child pointer.
+  divider: Vec<u8>,
+}
+
+/// Split the cells of an overfull page in two halves of about the same size. A table leaf keeps
+/// every cell, with the largest rowid on the left as the divider. Other pages give up their
+/// middle cell to become the divider, whose left child becomes the left half's rightmost child.
+fn split_cells(ty: BTreePageType, mut cells: Vec<Vec<u8>>) -> Option<Split> {
+  let keeps_divider = matches!(ty, BTreePageType::TableLeaf);
+  let sizes = cells.iter().map(|cell| cell.len() + 2).collect::<Vec<_>>();
+  let total = sizes.iter().sum::<usize>();
+  let candidates = if keeps_divider { 1..cells.len() } else { 1..cells.len().saturating_sub(1) };
+  let mid = candidates.min_by_key(|&mid| {
+    let left = sizes[..mid].iter().sum::<usize>();
+    let right = total - left - if keeps_divider { 0 } else { sizes[mid] };
+    left.abs_diff(right)
+  })?;
+
+  if keeps_divider {
+    let right = cells.split_off(mid);
+    let (rest, _payload_size) = varint(&cells[mid - 1]).ok()?;
+    let (_, row_id) = varint(rest).ok()?;
+    let mut divider = Vec::new();
+    write_varint(row_id, &mut divider);
+    return Some(Split {
+      left: cells,
+      left_rightmost_pointer: None,
+      right,
+      divider,
+    });
+  }
+
+  let right = cells.split_off(mid + 1);
+  let middle = cells.pop().unwrap();
+  let (left_rightmost_pointer, divider) = match ty {
+    BTreePageType::IndexLeaf => (None, middle),
+    _ => (
+      Some(u32::from_be_bytes(middle[..4].try_into().unwrap())),
+      middle[4..].to_vec(),
+    ),
+  };
+  Some(Split {
+    left: cells,
+    left_rightmost_pointer,
+    right,
+    divider,
+  })
+}
+
+/// The table columns an index is keyed on, the collation each is compared with, and whether each
+/// sorts DESC.
+pub(crate) struct IndexKeyColumns {
+  pub root_page: usize,
+  pub columns: Vec<usize>,
+  pub collations: Vec<Collation>,
+  pub descending: Vec<bool>,
+  /// Whether no two entries may have the same indexed values, unless one of them is NULL.
+  pub unique: bool,
+}
+
+/// The error for a row of the schema table that doesn't describe a schema object.
+fn malformed_schema(record: &Record) -> Error {
+  Error::Corrupt {
+    page: 1,
+    reason: format!("malformed schema entry with rowid {}", record.row_id.unwrap_or_default()),
+  }
+}
+
+/// Column `i` of the schema table row `record`, which is text.
+fn schema_text(record: &Record, i: usize) -> Result<&str> {
+  record.values.get(i).and_then(Value::as_text).ok_or_else(|| malformed_schema(record))
+}
+
+/// The root page of the object in the schema table row `record`.
+fn schema_root_page(record: &Record) -> Result<usize> {
+  match record.values.get(3) {
+    Some(Value::Integer(root_page)) if (1..=u32::MAX as i64).contains(root_page) => Ok(*root_page as usize),
+    _ => Err(malformed_schema(record)),
+  }
+}
+
+/// Fail unless `page`, which is page `page_index`, belongs to a b-tree of `kind` records, as every
+/// page a b-tree points to must.
+fn check_page_kind(page: &RawPage, page_index: usize, kind: RecordType) -> Result<()> {
+  let reason = match (kind, page.ty) {
+    (RecordType::Table, BTreePageType::TableInterior | BTreePageType::TableLeaf)
+    | (RecordType::Index, BTreePageType::IndexInterior | BTreePageType::IndexLeaf) => return Ok(()),
+    (RecordType::Table, _) => "not a table b-tree page",
+    (RecordType::Index, _) => "not an index b-tree page",
+  };
+  Err(Error::Corrupt {
+    page: page_index,
+    reason: reason.to_string(),
+  })
+}
+
+/// The key and rowid of an index entry read from page `page_index` as its first two columns.
+fn index_entry(mut record: Record, page_index: usize) -> Result<(Value, i64)> {
+  match (record.values.pop(), record.values.pop()) {
+    (Some(Value::Integer(row_id)), Some(key)) if record.values.is_empty() => Ok((key, row_id)),
+    _ => Err(Error::Corrupt {
+      page: page_index,
+      reason: "index entry without a key and rowid".to_string(),
+    }),
+  }
+}
+
+/// Map the error of parsing a cell of page `page_index` to [`Error::Corrupt`].
+fn corrupt_cell<E: std::fmt::Debug>(page_index: usize) -> impl FnOnce(E) -> Error {
+  move |e| Error::Corrupt {
+    page: page_index,
+    reason: format!("failed to parse cell: {:?}", e),
+  }
+}
+
+/// Parse the database header in `bytes`, failing with [`Error::NotADatabase`] if it isn't one.
+fn parse_header_bytes(bytes: &[u8]) -> Result<Header> {
+  match Header::parse(bytes) {
+    Ok((_, header)) => Ok(header),
+    Err(nom::Err::Error(MyError::InvalidValueError(e)) | nom::Err::Failure(MyError::InvalidValueError(e))) => {
+      Err(Error::NotADatabase(e.0))
+    }
+    Err(_) => Err(Error::NotADatabase("truncated header".to_string())),
+  }
+}
+
+/// The values of a row of the STRICT `table` as they are stored: each converted by its column's
+/// affinity, after which it must be of the column's type or NULL. An ANY column keeps any value
+/// as it is.
+fn strict_values(table: &TableSchema, values: Vec<Value>) -> Result<Vec<Value>> {
+  values
+    .into_iter()
+    .enumerate()
+    .map(|(i, value)| {
+      let affinity = table.column_affinities[i];
+      let value = match (affinity, affinity.apply(&value).into_owned()) {
+        // ANY is the only type of a STRICT table with NUMERIC affinity.
+        (Affinity::Numeric, _) => return Ok(value),
+        (Affinity::Integer, Value::Real(f)) if f.fract() == 0.0 && f.abs() < 9.2e18 => Value::Integer(f as i64),
+        (Affinity::Real, Value::Integer(n)) => Value::Real(n as f64),
+        (_, value) => value,
+      };
+      match (affinity, &value) {
+        (_, Value::Null)
+        | (Affinity::Integer, Value::Integer(_))
+        | (Affinity::Real, Value::Real(_))
+        | (Affinity::Text, Value::Text(_))
+        | (Affinity::Blob, Value::Blob(_)) => Ok(value),
+        _ => {
+          let query = Query::parse(&table.sql)?;
+          let declared_type = query.as_create().map_or("", |create| create.column_types[i].as_str());
+          // SQLite names the value's type as it would be declared in a STRICT table.
+          let value_type = match value {
+            Value::Integer(_) => "INT",
+            _ => value.type_name(),
+          };
+          Err(Error::Constraint(format!(
+            "cannot store {} value in {} column {}.{}",
+            value_type.to_ascii_uppercase(),
+            declared_type.to_ascii_uppercase(),
+            table.name,
+            table.column_names[i]
+          )))
+        }
+      }
+    })
+    .collect()
+}
+
+/// Fail if a NOT NULL column of `table` is NULL in `values`. The rowid alias column is given a
+/// new rowid instead.
+fn check_not_null(table: &TableSchema, values: &[Value]) -> Result<()> {
+  let column = (0..values.len())
+    .find(|i| table.column_not_null[*i] && table.row_id_column != Some(*i) && matches!(values[*i], Value::Null));
+  match column {
+    Some(i) => Err(Error::Constraint(format!(
+      "NOT NULL constraint failed: {}.{}",
+      table.name, table.column_names[i]
+    ))),
+    None => Ok(()),
+  }
+}
+
+/// The index entry of a row: the values of the indexed columns followed by the rowid.
+pub(crate) fn index_key(table: &TableSchema, key_columns: &[usize], row_id: i64, values: &[Value]) -> Vec<Value> {
+  let mut key = key_columns
+    .iter()
+    .map(|i| match table.row_id_column {
+      Some(row_id_column) if row_id_column == *i => Value::Integer(row_id),
+      _ => values[*i].clone(),
+    })
+    .collect::<Vec<_>>();
+  key.push(Value::Integer(row_id));
+  key
+}
+
+/// Compare index keys column by column in SQLite's sort order, comparing the text of each column
+/// with its collation among `collations`, or bytewise past them, as for the trailing rowid. The
+/// columns marked in `descending` sort in reverse.
+pub(crate) fn compare_keys(
+  a: &[Value],
+  b: &[Value],
+  collations: &[Collation],
+  descending: &[bool],
+) -> std::cmp::Ordering {
+  a.iter()
+    .zip(b.iter())
+    .enumerate()
+    .map(|(i, (a, b))| {
+      let collation = collations.get(i).copied().unwrap_or_default();
+      let ordering = a.as_value_ref().collated_cmp(&b.as_value_ref(), collation);
+      if descending.get(i) == Some(&true) { ordering.reverse() } else { ordering }
+    })
+    .find(|ordering| ordering.is_ne())
+    .unwrap_or_else(|| a.len().cmp(&b.len()))
+}
+
+enum IndexStep {
+  Page(usize),
+  Entry(Value, i64),
+}
+
+/// An in-order walk of an index b-tree, returned by [`Database::scan_index`]. It doesn't own the
+/// file, so rows can be looked up between entries.
+pub struct IndexScan<'a> {
+  db: &'a Database,
+  column_name: String,
+  descending: bool,
+  /// What's left to visit, the next step last. Only the pages on the path to the current entry
+  /// have been read.
+  steps: Vec<IndexStep>,
+}
+
+impl IndexScan<'_> {
+  /// The rowid of the next entry, or `None` once the walk is over.
+  pub fn next_row_id<R>(&mut self, file: R) -> Result<Option<i64>>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    Ok(self.next_entry(file)?.map(|(_, row_id)| row_id))
+  }
+
+  /// The key and rowid of the next entry, or `None` once the walk is over.
+  pub fn next_entry<R>(&mut self, mut file: R) -> Result<Option<(Value, i64)>>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let column_names = [self.column_name.as_str(), "row_id"];
+    loop {
+      let page_index = match self.steps.pop() {
+        None => return Ok(None),
+        Some(IndexStep::Entry(key, row_id)) => return Ok(Some((key, row_id))),
+        Some(IndexStep::Page(page_index)) => page_index,
+      };
+      self.db.report_progress()?;
+      let page_data = self.db.read_page_data(&mut file, page_index, RecordType::Index)?;
+      let page = self.db.parse_raw_page(&page_data, page_index)?;
+      check_page_kind(&page, page_index, RecordType::Index)?;
+
+      // An interior cell's entry sorts after everything in its left child, and the rightmost
+      // child after every cell. The steps are pushed in reverse of the order they're visited in.
+      let mut steps = Vec::new();
+      for i in 0..page.cell_count() {
+        match self.db.read_cell(&mut file, &page, page_index, i, &column_names, &[0, 1])? {
+          Cell::IndexLeaf(record) => {
+            let (key, row_id) = index_entry(record, page_index)?;
+            steps.push(IndexStep::Entry(key, row_id));
+          }
+          Cell::IndexInterior {
+            left_child_pointer,
+            record,
+          } => {
+            steps.push(IndexStep::Page(left_child_pointer as usize));
+            let (key, row_id) = index_entry(record, page_index)?;
+            steps.push(IndexStep::Entry(key, row_id));
+          }
+          _ => unreachable!(),
+        }
+      }
+      if let Some(rightmost_pointer) = page.rightmost_pointer {
+        steps.push(IndexStep::Page(rightmost_pointer));
+      }
+      if !self.descending {
+        steps.reverse();
+      }
+      self.steps.extend(steps);
+    }
+  }
+}
+
+/// A predicate evaluated on records borrowed from their page.
+pub type RecordFilter<'a> = Box<dyn Fn(&RecordRef) -> bool + 'a>;
+
+pub struct TableScan<'a, R> {
+  db: &'a Database,
+  file: R,
+  column_names: &'a [&'a str],
+  column_indices: &'a [usize],
+  defaults: &'a [Value],
+  filter: Option<RecordFilter<'a>>,
+  /// The most pages read at once, set with [`TableScan::with_readahead`].
+  readahead: usize,
+  pages_to_read: Vec<usize>,
+  leaf_records: std::vec::IntoIter<Record>,
+}
+
+impl<'a, R> TableScan<'a, R>
+  where
+    R: std::io::Read + std::io::Seek,
+{
+  /// Only yield the records matching `filter`. It's evaluated on records borrowed from the page,
+  /// so rows that don't match are never copied.
+  pub fn with_filter<F>(mut self, filter: F) -> Self
+    where
+      F: Fn(&RecordRef) -> bool + 'a,
+  {
+    self.filter = Some(Box::new(filter));
+    self
+  }
+
+  /// Give the columns a record has no value for, having been added to the table since it was
+  /// written, these `defaults`, one for each decoded column.
+  pub fn with_defaults(mut self, defaults: &'a [Value]) -> Self {
+    self.defaults = defaults;
+    self
+  }
+
+  /// Read up to `pages` pages at once when the next pages to visit follow each other in the
+  /// file, as the leaves of a table written in rowid order mostly do, rather than one at a time.
+  /// The pages read ahead are kept in the page cache until the scan reaches them.
+  pub fn with_readahead(mut self, pages: usize) -> Self {
+    self.readahead = pages.max(1);
+    self
+  }
+
+  fn read_next_page(&mut self, page_index: usize) -> Result<()> {
+    self.db.report_progress()?;
+    // The pages to visit next are at the end of the queue, the first of them already taken off.
+    let run = 1 + self
+      .pages_to_read
+      .iter()
+      .rev()
+      .zip(page_index + 1..)
+      .take(self.readahead - 1)
+      .take_while(|(next, expected)| *next == expected)
+      .count();
+    if run > 1 {
+      self.db.read_ahead(&mut self.file, page_index, run);
+    }
+    let page_data = self
+      .db
+      .read_page_data(&mut self.file, page_index, RecordType::Table)?;
+    let page = self.db.parse_raw_page(&page_data, page_index)?;
+    check_page_kind(&page, page_index, RecordType::Table)?;
+
+    if matches!(page.ty, BTreePageType::TableInterior) {
+      // Children are pushed in reverse so that the leftmost one is visited first.
+      if let Some(rightmost_pointer) = page.rightmost_pointer {
+        self.pages_to_read.push(rightmost_pointer);
+      }
+      for i in (0..page.cell_count()).rev() {
+        match page.cell(i, &[], &[]).map_err(corrupt_cell(page_index))?.1 {
+          Cell::TableInterior {
+            left_child_pointer, ..
+          } => self.pages_to_read.push(left_child_pointer as usize),
+          _ => unreachable!(),
+        }
+      }
+      return Ok(());
+    }
+
+    let mut records = Vec::new();
+    for i in 0..page.cell_count() {
+      let payload = page.cell_payload(i).map_err(corrupt_cell(page_index))?.1;
+      let record = match payload {
+        CellPayload::Local { row_id, payload, .. } => self.parse_record(page_index, payload, row_id)?,
+        CellPayload::Overflow(overflow) => {
+          match self.parse_record(page_index, &overflow.local_payload, overflow.row_id) {
+            Ok(record) => record,
+            Err(_) => {
+              let payload = self.db.read_overflow_payload(&mut self.file, &overflow)?;
+              self.parse_record(page_index, &payload, overflow.row_id)?
+            }
+          }
+        }
+      };
+      if let Some(record) = record {
+        records.push(record);
+      }
+    }
+    self.leaf_records = records.into_iter();
+    Ok(())
+  }
+
+  /// Decode a record in place, copying it out only if it passes the filter.
+  fn parse_record(
+    &self,
+    page_index: usize,
+    payload: &[u8],
+    row_id: Option<i64>,
+  ) -> Result<Option<Record>> {
+    self.db.records_decoded.fetch_add(1, atomic::Ordering::Relaxed);
+    let (_, mut record) = RecordRef::parse(
+      payload,
+      row_id,
+      self.column_names,
+      self.column_indices,
+    )
+      .map_err(|e| Error::Corrupt {
+        page: page_index,
+        reason: format!("failed to parse record: {:?}", e),
+      })?;
+    if let Some(missing) = self.defaults.get(record.values.len()..) {
+      record.values.extend(missing.iter().map(Value::as_value_ref));
+    }
+    match &self.filter {
+      Some(filter) if !filter(&record) => Ok(None),
+      _ => Ok(Some(record.to_record())),
+    }
+  }
+}
+
+impl<'a, R> Iterator for TableScan<'a, R>
+  where
+    R: std::io::Read + std::io::Seek,
+{
+  type Item = Result<Record>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    loop {
+      if let Some(record) = self.leaf_records.next() {
+        return Some(Ok(record));
+      }
+
+      let page_index = self.pages_to_read.pop()?;
+      if let Err(e) = self.read_next_page(page_index) {
+        self.pages_to_read.clear();
+        return Some(Err(e));
+      }
+    }
+  }
+}
+
+impl PageCache {
+  pub fn new(capacity: usize) -> Self {
+    PageCache {
+      capacity,
+      size: 0,
+      clock: 0,
+      pages: HashMap::new(),
+      spare_buffers: Vec::new(),
+    }
+  }
+
+  /// A zeroed buffer of `len` bytes, reusing the allocation of an evicted page if possible.
+  pub fn take_buffer(&mut self, len: usize) -> Vec<u8> {
+    let mut buffer = self.spare_buffers.pop().unwrap_or_default();
+    buffer.clear();
+    buffer.resize(len, 0);
+    buffer
+  }
+
+  pub fn get(&mut self, page_index: usize) -> Option<Arc<Vec<u8>>> {
+    self.clock += 1;
+    let page = self.pages.get_mut(&page_index)?;
+    page.last_used = self.clock;
+    Some(page.data.clone())
+  }
+
+  /// Drop a page that was modified in the file.
+  pub fn remove(&mut self, page_index: usize) {
+    if let Some(page) = self.pages.remove(&page_index) {
+      self.size -= page.data.len();
+    }
+  }
+
+  /// Drop every page, as the file may have changed under them all.
+  pub fn clear(&mut self) {
+    for (_, page) in self.pages.drain() {
+      if let Ok(buffer) = Arc::try_unwrap(page.data) {
+        self.spare_buffers.push(buffer);
+      }
+    }
+    self.size = 0;
+  }
+
+  pub fn insert(&mut self, page_index: usize, data: Arc<Vec<u8>>) {
+    self.clock += 1;
+    self.size += data.len();
+    let previous = self.pages.insert(
+      page_index,
+      CachedPage {
+        data,
+        last_used: self.clock,
+      },
+    );
+    if let Some(previous) = previous {
+      self.size -= previous.data.len();
+    }
+    self.evict();
+  }
+
+  fn evict(&mut self) {
+    while self.size > self.capacity {
+      let oldest = *self
+        .pages
+        .iter()
+        .min_by_key(|(_, page)| page.last_used)
+        .map(|(page_index, _)| page_index)
+        .unwrap();
+      let page = self.pages.remove(&oldest).unwrap();
+      self.size -= page.data.len();
+      if let Ok(buffer) = Arc::try_unwrap(page.data) {
+        self.spare_buffers.push(buffer);
+      }
+    }
+  }
+}
+
+/// The names the schema table is queried by. It isn't listed in itself, so it isn't one of the
+/// objects of a [`Schema`].
+const SCHEMA_TABLE_NAMES: [&str; 2] = ["sqlite_schema", "sqlite_master"];
+
+/// The definition of the schema table, rooted at page 1.
+fn schema_table() -> &'static TableSchema {
+  static SCHEMA_TABLE: std::sync::OnceLock<TableSchema> = std::sync::OnceLock::new();
+  SCHEMA_TABLE.get_or_init(|| {
+    let sql = "CREATE TABLE sqlite_schema (type text, name text, tbl_name text, rootpage int, sql text)";
+    let query = Query::parse(sql).unwrap();
+    let create = query.as_create().unwrap();
+    TableSchema {
+      name: SCHEMA_TABLE_NAMES[0].to_owned(),
+      root_page: 1,
+      sql: sql.to_owned(),
+      column_names: create.column_names.clone(),
+      column_affinities: create.column_affinities.clone(),
+      column_collations: vec![Collation::Binary; create.column_names.len()],
+      row_id_column: None,
+      column_defaults: vec![None; create.column_names.len()],
+      column_not_null: vec![false; create.column_names.len()],
+      checked: false,
+      column_generated: vec![None; create.column_names.len()],
+      without_rowid: false,
+      strict: false,
+    }
+  })
+}
+
+impl Schema {
+  pub fn table_count(&self) -> usize {
+    self.objects
+      .iter()
+      .filter(|o| matches!(o, ObjectSchema::Table(_)))
+      .count()
+  }
+
+  /// The names of the tables, sorted. Views aren't included, see [`Schema::view_names`].
+  pub fn table_names(&self) -> Vec<&str> {
+    let mut tables = self
+      .objects
+      .iter()
+      .filter(|o| matches!(o, ObjectSchema::Table(_)))
+      .map(|o| o.as_table().unwrap().name.as_str())
+      .collect::<Vec<&str>>();
+    tables.sort();
+    tables
+  }
+
+  /// The names of the views, sorted.
+  pub fn view_names(&self) -> Vec<&str> {
+    let mut views = self
+      .objects
+      .iter()
+      .filter_map(|o| match o {
+        ObjectSchema::View(view) => Some(view.name.as_str()),
+        _ => None,
+      })
+      .collect::<Vec<&str>>();
+    views.sort();
+    views
+  }
+
+  pub fn table_root_page(&self, table_name: &str) -> Result<usize> {
+    self.table(table_name).map(|table| table.root_page)
+  }
+
+  /// The table `table_name`, which may also be the schema table itself, by either of its names.
+  pub fn table(&self, table_name: &str) -> Result<&TableSchema> {
+    self
+      .objects
+      .iter()
+      .filter_map(|o| o.as_table())
+      .find(|table| table.name.eq_ignore_ascii_case(table_name))
+      .or_else(|| {
+        SCHEMA_TABLE_NAMES
+          .iter()
+          .any(|name| name.eq_ignore_ascii_case(table_name))
+          .then(schema_table)
+      })
+      .ok_or_else(|| Error::TableNotFound(table_name.to_owned()))
+  }
+
+  /// Like [`Schema::table`], but fails for the schema table, which is only changed by creating
+  /// tables and indexes, and for tables with what writes don't maintain or enforce.
+  pub fn writable_table(&self, table_name: &str) -> Result<&TableSchema> {
+    let table = self.table(table_name)?;
+    if table.root_page == 1 {
+      return Err(Error::UnsupportedSql(format!("table {} may not be modified", table_name)));
+    }
+    if table.without_rowid {
+      return Err(Error::UnsupportedSql(format!("writing to WITHOUT ROWID table {}", table_name)));
+    }
+    // Rows are written with a value for every column, which a generated one can't be given.
+    if table.column_generated.iter().any(Option::is_some) {
+      return Err(Error::UnsupportedSql(format!("writing to table {} with generated columns", table_name)));
+    }
+    if table.checked {
+      return Err(Error::UnsupportedSql(format!("writing to table {} with CHECK constraints", table_name)));
+    }
+    // A write would skip what the trigger does.
+    let trigger = self.objects.iter().find_map(|o| match o {
+      ObjectSchema::Trigger(trigger) if trigger.table_name.eq_ignore_ascii_case(&table.name) => Some(trigger),
+      _ => None,
+    });
+    if let Some(trigger) = trigger {
+      return Err(Error::UnsupportedSql(format!("writing to table {} with trigger {}", table_name, trigger.name)));
+    }
+    Ok(table)
+  }
+
+  /// The indexes on the table `table_name`.
+  pub fn indexes<'a>(&'a self, table_name: &'a str) -> impl Iterator<Item = &'a IndexSchema> {
+    self
+      .objects
+      .iter()
+      .filter_map(|o| o.as_index())
+      .filter(move |index| index.table_name.eq_ignore_ascii_case(table_name))
+  }
+
+  /// The CREATE INDEX statement of `index`, or for one SQLite made for a PRIMARY KEY or UNIQUE
+  /// constraint, which has none, a statement making the same index.
+  pub fn index_query(&self, index: &IndexSchema) -> Result<Query> {
+    if !index.sql.is_empty() {
+      return Query::parse(&index.sql);
+    }
+    Query::parse(&automatic_index_sql(&index.name, self.table(&index.table_name)?)?)
+  }
+}
+
+/// The key of `index_name`, the index SQLite made for the nth PRIMARY KEY or UNIQUE constraint
+/// of `table`, as it's named `sqlite_autoindex_<table>_<n>`.
+pub(crate) fn automatic_index_key(index_name: &str, table: &TableSchema) -> Result<KeyConstraint> {
+  let n = index_name
+    .rsplit_once('_')
+    .and_then(|(_, n)| n.parse::<usize>().ok())
+    .filter(|n| *n > 0);
+  let query = Query::parse(&table.sql)?;
+  let key = n.and_then(|n| query.as_create()?.key_constraints.get(n - 1));
+  key.cloned().ok_or_else(|| Error::Corrupt {
+    page: 1,
+    reason: format!("{} is not the index of a PRIMARY KEY or UNIQUE constraint of {}", index_name, table.name),
+  })
+}
+
+/// A CREATE INDEX statement making the same index as SQLite's `index_name` on `table`.
+fn automatic_index_sql(index_name: &str, table: &TableSchema) -> Result<String> {
+  let key = automatic_index_key(index_name, table)?;
+  let key_columns = key
+    .columns
+    .iter()
+    .zip(key.descending.iter())
+    .map(|(i, descending)| {
+      let column_name = quote_identifier(&table.column_names[*i]);
+      if *descending { format!("{} DESC", column_name) } else { column_name.into_owned() }
+    })
+    .collect::<Vec<_>>();
+  Ok(format!(
+    "CREATE UNIQUE INDEX {} ON {} ({})",
+    quote_identifier(index_name),
+    quote_identifier(&table.name),
+    key_columns.join(", ")
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    io::{Seek, Write},
+    ops::ControlFlow,
+    sync::{
+      atomic::{AtomicUsize, Ordering},
+      Arc, Mutex,
+    },
+  };
+
+  use super::{Database, ObjectSchema, PageCache, PageKind, PageObserver, WalPolicy};
+  use crate::{journal, page::BTreePageType, record::RecordType};
+  use crate::error::Error;
+  use crate::{query::Query, record::Value};
+
+  const TEST_DB_INTERIOR: &str = "tests/fixtures/interior.db";
+  const TEST_DB_SCHEMA: &str = "tests/fixtures/schema.db";
+  const TEST_DB_COMPANIES: &str = "tests/fixtures/companies.db";
+  const TEST_DB_PRODUCTS: &str = "tests/fixtures/products.db";
+  const TEST_DB_DOCUMENTS: &str = "tests/fixtures/documents.db";
+  const TEST_DB_NOCASE: &str = "tests/fixtures/nocase.db";
+  const TEST_DB_ROWID_ALIAS: &str = "tests/fixtures/rowid_alias.db";
+  const TEST_DB_DEEP: &str = "tests/fixtures/deep.db";
+  const TEST_DB_MIXED_CASE: &str = "tests/fixtures/mixed_case.db";
+  const TEST_DB_VIEWS: &str = "tests/fixtures/views.db";
+  const TEST_DB_TRIGGERS: &str = "tests/fixtures/triggers.db";
+  const TEST_DB_REALS: &str = "tests/fixtures/reals.db";
+  const TEST_DB_SCAN: &str = "tests/fixtures/scan.db";
+  const TEST_DB_PK_DESC: &str = "tests/fixtures/pk_desc.db";
+  const TEST_DB_EMPTY: &str = "tests/fixtures/empty.db";
+  const TEST_DB_CONSTRAINTS: &str = "tests/fixtures/constraints.db";
+
+  /// Open a fixture with the I/O backend selected by the enabled features, so the same tests
+  /// cover both.
+  fn open_fixture(path: &str) -> (Database, std::fs::File) {
+    let file = std::fs::File::open(path).unwrap();
+    #[cfg(not(feature = "mmap"))]
+    let db = Database::parse_header_and_schema(&file).unwrap();
+    #[cfg(feature = "mmap")]
+    let db = Database::open_mmap(path).unwrap();
+    (db, file)
+  }
+
+  #[test]
+  fn files_that_are_not_databases_are_errors() {
+    let mut payload_fraction = std::fs::read(TEST_DB_INTERIOR).unwrap();
+    payload_fraction[21] = 65;
+    for (data, reason) in [
+      (b"name,age\nAlice,30\n".repeat(10), "missing the \"SQLite format 3\" header string"),
+      (Vec::new(), "file is shorter than a header"),
+      (payload_fraction, "invalid payload fractions [65, 32, 32]"),
+    ] {
+      let result = Database::parse_header_and_schema(std::io::Cursor::new(data));
+      assert!(matches!(&result, Err(Error::NotADatabase(message)) if message == reason), "{:?}", result.err());
+    }
+  }
+
+  #[test]
+  fn corrupt_cells_are_errors() {
+    // The first cell of page 4, the first leaf of items, made to claim a payload running past the
+    // end of the page. Scans and lookups reaching it used to panic.
+    let mut data = std::fs::read("tests/fixtures/null_keys.db").unwrap();
+    data[3 * 512 + 500] = 0x7f;
+    let mut file = std::io::Cursor::new(data);
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    for sql in [
+      "SELECT * FROM items",
+      "SELECT name FROM items WHERE id = 1",
+      "DELETE FROM items WHERE id = 1",
+    ] {
+      let result = match Query::parse(sql).unwrap() {
+        Query::Delete(delete) => db.delete(&mut file, &delete).map(|_| ()),
+        query => query.execute(&db, &mut file).map(|_| ()),
+      };
+      assert!(matches!(&result, Err(Error::Corrupt { page: 4, .. })), "{}: {:?}", sql, result);
+    }
+
+    // A child pointer of the table leading to the root of its index instead.
+    let mut data = std::fs::read("tests/fixtures/null_keys.db").unwrap();
+    let pointer = u16::from_be_bytes([data[512 + 12], data[512 + 13]]) as usize;
+    data[512 + pointer..512 + pointer + 4].copy_from_slice(&3u32.to_be_bytes());
+    let mut file = std::io::Cursor::new(data);
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    for sql in ["SELECT * FROM items", "SELECT name FROM items WHERE id = 1", "SELECT min(id) FROM items"] {
+      let result = Query::parse(sql).unwrap().execute(&db, &mut file);
+      assert!(
+        matches!(&result, Err(Error::Corrupt { page: 3, reason }) if reason == "not a table b-tree page"),
+        "{}: {:?}",
+        sql,
+        result
+      );
+    }
+  }
+
+  #[test]
+  fn schema_spanning_interior_pages() {
+    let (db, _) = open_fixture(TEST_DB_SCHEMA);
+    assert_eq!(db.schema.table_count(), 201);
+    assert!(db.table_pages_parsed() > 1);
+
+    let table_names = db.schema.table_names();
+    assert_eq!(table_names.first(), Some(&"t000"));
+    assert_eq!(table_names.last(), Some(&"wide"));
+  }
+
+  #[test]
+  fn views_are_listed_apart_from_tables() {
+    let (db, mut file) = open_fixture(TEST_DB_VIEWS);
+    assert_eq!(db.schema.table_names(), ["owners", "pets"]);
+    assert_eq!(db.schema.view_names(), ["pet_owners"]);
+    assert_eq!(db.schema.table_count(), 2);
+
+    // The tables are still queried as usual, but views can't be.
+    let query = Query::parse("SELECT name FROM pets").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["Rex"], vec!["Tom"]]);
+    let result = Query::parse("SELECT pet FROM pet_owners").unwrap().execute(&db, &mut file);
+    assert!(matches!(result, Err(Error::TableNotFound(name)) if name == "pet_owners"));
+  }
+
+  #[test]
+  fn triggers_are_in_the_schema_but_never_run() {
+    let (db, mut file) = open_fixture(TEST_DB_TRIGGERS);
+    assert_eq!(db.schema.table_names(), ["accounts", "audit"]);
+    assert_eq!(db.schema.view_names(), ["rich"]);
+    let triggers = db
+      .schema
+      .objects
+      .iter()
+      .filter_map(|o| match o {
+        ObjectSchema::Trigger(trigger) => Some((trigger.name.as_str(), trigger.table_name.as_str())),
+        _ => None,
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(triggers, [("accounts_insert", "accounts"), ("rich_delete", "rich")]);
+
+    let query = Query::parse("SELECT type, name FROM sqlite_master WHERE type = 'trigger'").unwrap();
+    assert_eq!(
+      query.execute(&db, &mut file).unwrap(),
+      vec![vec!["trigger", "accounts_insert"], vec!["trigger", "rich_delete"]]
+    );
+
+    // The rows the insert trigger added are read like any others, but as it wouldn't run, its
+    // table can't be written.
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_TRIGGERS).unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    assert!(matches!(
+      insert(&db, &mut file, "INSERT INTO accounts (name, balance) VALUES ('carol', 5)"),
+      Err(Error::UnsupportedSql(message)) if message == "writing to table accounts with trigger accounts_insert"
+    ));
+    insert(&db, &mut file, "INSERT INTO audit (account_id, action) VALUES (3, 'manual')").unwrap();
+    let query = Query::parse("SELECT account_id, action FROM audit").unwrap();
+    assert_eq!(
+      query.execute(&db, &mut file).unwrap(),
+      vec![vec!["1", "insert"], vec!["2", "insert"], vec!["3", "manual"]]
+    );
+  }
+
+  #[test]
+  fn schema_table_is_queried_like_any_other() {
+    let (db, mut file) = open_fixture(TEST_DB_MIXED_CASE);
+    for (sql, expected) in [
+      (
+        "SELECT name, rootpage FROM sqlite_master WHERE type = 'table' ORDER BY name",
+        vec![vec!["MixedCase", "2"], vec!["Users", "5"], vec!["apples", "4"]],
+      ),
+      ("SELECT tbl_name FROM SQLITE_SCHEMA WHERE type = 'index'", vec![vec!["MixedCase"], vec!["Users"]]),
+      // Rowid lookups and edges of the schema table start at page 1, like its scans.
+      ("SELECT name FROM sqlite_master WHERE rowid = 2", vec![vec!["Idx_MixedCase_Name"]]),
+      ("SELECT max(rowid) FROM sqlite_master", vec![vec!["5"]]),
+    ] {
+      assert_eq!(Query::parse(sql).unwrap().execute(&db, &mut file).unwrap(), expected, "{}", sql);
+    }
+    // It isn't one of the tables in the schema, only queryable as one.
+    assert_eq!(db.schema.table_names(), ["MixedCase", "Users", "apples"]);
+
+    // A schema spanning interior pages is read in full.
+    let (db, mut file) = open_fixture(TEST_DB_SCHEMA);
+    let query = Query::parse("SELECT COUNT(*) FROM sqlite_schema WHERE type = 'table'").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["201"]]);
+    let query = Query::parse("SELECT name FROM sqlite_master ORDER BY name DESC LIMIT 2").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["wide"], vec!["t199"]]);
+
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_INTERIOR).unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    assert!(matches!(
+      delete(&db, &mut file, "DELETE FROM sqlite_master"),
+      Err(Error::UnsupportedSql(message)) if message == "table sqlite_master may not be modified"
+    ));
+  }
+
+  #[test]
+  fn schema_entry_with_overflowing_sql() {
+    let (db, _) = open_fixture(TEST_DB_SCHEMA);
+    let wide = db
+      .schema
+      .objects
+      .iter()
+      .filter_map(|o| o.as_table())
+      .find(|t| t.name == "wide")
+      .unwrap();
+    assert!(wide.sql.len() > db.header.page_size);
+    assert_eq!(wide.column_names.len(), 61);
+    assert_eq!(wide.column_names[60], "column_59");
+  }
+
+  #[test]
+  fn index_probe_reads_one_page_per_level() {
+    let (db, mut file) = open_fixture(TEST_DB_COMPANIES);
+    let index_root_page = db
+      .schema
+      .objects
+      .iter()
+      .filter_map(|o| o.as_index())
+      .find(|i| i.name == "idx_companies_country")
+      .unwrap()
+      .root_page;
+
+    let mut row_ids = db
+      .search_index(&mut file, index_root_page, Value::Text("country_123".into()))
+      .unwrap();
+    row_ids.sort_unstable();
+    assert_eq!(row_ids, vec![19, 378, 467]);
+    assert!(db.index_pages_parsed() <= 4, "{} index pages", db.index_pages_parsed());
+  }
+
+  #[test]
+  fn index_walks_need_an_index_root() {
+    let (db, mut file) = open_fixture(TEST_DB_COMPANIES);
+    let table_root_page = db.schema.table_root_page("companies").unwrap();
+    for page_index in [1, table_root_page] {
+      let not_an_index = |result: &Result<(), Error>| {
+        let reason = "not the root page of an index";
+        matches!(result, Err(Error::Corrupt { page, reason: r }) if *page == page_index && r == reason)
+      };
+      assert!(not_an_index(&db.scan_index(page_index, false).map(|_| ())), "{}", page_index);
+      let result = db.search_index(&mut file, page_index, Value::Text("country_123".into()));
+      assert!(not_an_index(&result.map(|_| ())), "{}", page_index);
+    }
+  }
+
+  #[test]
+  fn get_by_row_ids_matches_cell_rowids() {
+    let (db, mut file) = open_fixture(TEST_DB_PRODUCTS);
+    let table_root_page = db.schema.table_root_page("products").unwrap();
+
+    // 17 and 34 sit exactly on interior separator keys, 600 is past the last separator.
+    let pages_before = db.table_pages_parsed();
+    let records = db
+      .get_by_row_ids(&mut file, table_root_page, &[600, 34, 17, 122, 17], &["name"], &[1])
+      .unwrap();
+    let mut names = records
+      .iter()
+      .map(|r| (r.row_id.unwrap(), r.values[0].to_string()))
+      .collect::<Vec<_>>();
+    names.sort_unstable();
+    assert_eq!(
+      names,
+      vec![
+        (17, "product 17".to_string()),
+        (34, "product 34".to_string()),
+        (122, "product 122".to_string()),
+        (600, "product 600".to_string()),
+      ]
+    );
+    // The interior root plus one leaf per distinct child.
+    assert_eq!(db.table_pages_parsed() - pages_before, 5);
+    // Binary searches over a root with 37 cells and leaves with ~17 cells.
+    assert!(db.key_comparisons() <= 3 * 6 + 4 * 5, "{} comparisons", db.key_comparisons());
+  }
+
+  #[test]
+  fn repeated_query_is_served_from_page_cache() {
+    let (db, mut file) = open_fixture(TEST_DB_COMPANIES);
+    let query = Query::parse("SELECT id, name FROM companies WHERE country = 'country_123'").unwrap();
+
+    let first = query.execute(&db, &mut file).unwrap();
+    #[cfg(not(feature = "mmap"))]
+    let pages_read = (db.table_pages_parsed(), db.index_pages_parsed());
+    let second = query.execute(&db, &mut file).unwrap();
+    assert_eq!(first, second);
+    // Mapped pages aren't cached, they're served from the mapping on every access.
+    #[cfg(not(feature = "mmap"))]
+    assert_eq!((db.table_pages_parsed(), db.index_pages_parsed()), pages_read);
+  }
+
+  #[test]
+  fn page_cache_evicts_least_recently_used() {
+    let mut cache = PageCache::new(3 * 512);
+    for page_index in 1..=3 {
+      cache.insert(page_index, Arc::new(vec![0; 512]));
+    }
+    assert!(cache.get(1).is_some());
+    cache.insert(4, Arc::new(vec![0; 512]));
+
+    assert!(cache.get(2).is_none());
+    assert!(cache.get(1).is_some());
+    assert!(cache.get(3).is_some());
+    assert!(cache.get(4).is_some());
+
+    // The evicted page's buffer is reused for the next read.
+    let buffer = cache.take_buffer(512);
+    assert_eq!(buffer.capacity(), 512);
+    assert!(cache.spare_buffers.is_empty());
+  }
+
+  #[test]
+  fn scan_table_streams_rows_in_rowid_order() {
+    let (db, mut file) = open_fixture(TEST_DB_PRODUCTS);
+    let table_root_page = db.schema.table_root_page("products").unwrap();
+
+    let pages_before = db.table_pages_parsed();
+    let first = db
+      .scan_table(&mut file, table_root_page, &["name"], &[1])
+      .next()
+      .unwrap()
+      .unwrap();
+    assert_eq!(first.row_id, Some(1));
+    // Only the root and the leftmost leaf are read to produce the first row.
+    assert_eq!(db.table_pages_parsed() - pages_before, 2);
+
+    let row_ids = db
+      .scan_table(&mut file, table_root_page, &["name"], &[1])
+      .map(|r| r.unwrap().row_id.unwrap())
+      .collect::<Vec<_>>();
+    assert_eq!(row_ids, (1..=600).collect::<Vec<_>>());
+  }
+
+  /// A file that counts the reads made from it.
+  struct CountingReads<R> {
+    inner: R,
+    reads: usize,
+  }
+
+  impl<R: std::io::Read> std::io::Read for CountingReads<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+      self.reads += 1;
+      self.inner.read(buf)
+    }
+  }
+
+  impl<R: std::io::Seek> std::io::Seek for CountingReads<R> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+      self.inner.seek(pos)
+    }
+  }
+
+  #[test]
+  fn readahead_reads_consecutive_pages_at_once() {
+    // 1000 leaf pages and 19 interior ones, written in order by VACUUM.
+    let data = std::fs::read(TEST_DB_SCAN).unwrap();
+    let scan = |readahead: usize| {
+      let mut file = CountingReads {
+        inner: std::io::Cursor::new(&data),
+        reads: 0,
+      };
+      let db = Database::parse_header_and_schema(&mut file).unwrap();
+      let (pages_before, reads_before) = (db.table_pages_parsed(), file.reads);
+      let root_page = db.schema.table_root_page("rows").unwrap();
+      let rows = db
+        .scan_table(&mut file, root_page, &["id", "body"], &[0, 1])
+        .with_readahead(readahead)
+        .collect::<crate::error::Result<Vec<_>>>()
+        .unwrap();
+      (format!("{:?}", rows), db.table_pages_parsed() - pages_before, file.reads - reads_before)
+    };
+    let (rows, pages, reads) = scan(1);
+    assert_eq!((pages, reads), (1019, 1019));
+    let (readahead_rows, readahead_pages, readahead_reads) = scan(32);
+    assert_eq!((readahead_rows, readahead_pages), (rows, pages));
+    // A read for each run of up to 32 leaves, and for the interior pages between the runs.
+    assert!(readahead_reads < reads / 10, "{} reads", readahead_reads);
+  }
+
+  /// The pages read, in order, shared with the test that sets it as the page observer.
+  #[derive(Clone, Default)]
+  struct PageTrace(Arc<Mutex<Vec<(u32, PageKind)>>>);
+
+  impl PageObserver for PageTrace {
+    fn on_page_read(&mut self, page_no: u32, kind: PageKind) {
+      self.0.lock().unwrap().push((page_no, kind));
+    }
+  }
+
+  impl PageTrace {
+    fn take(&self) -> Vec<(u32, PageKind)> {
+      std::mem::take(&mut self.0.lock().unwrap())
+    }
+  }
+
+  #[test]
+  fn page_reads_are_traced_in_order() {
+    let mut file = std::fs::File::open(TEST_DB_DEEP).unwrap();
+    let mut db = Database::parse_header_and_schema(&mut file).unwrap();
+    let trace = PageTrace::default();
+    db.set_page_observer(trace.clone());
+    let root_page = db.schema.table_root_page("events").unwrap();
+    assert_eq!(db.btree_depth(&mut file, root_page, RecordType::Table).unwrap(), 3);
+    let left_edge = trace.take();
+    assert_eq!(left_edge.len(), 3);
+    assert_eq!(left_edge[0], (root_page as u32, PageKind::Table));
+
+    // A rowid lookup reads one page on each level, and a LIMIT stops a scan at its first leaf.
+    let run = |db: &Database, file: &mut std::fs::File, sql: &str| {
+      db.page_cache.lock().unwrap().clear();
+      Query::parse(sql).unwrap().execute(db, &mut *file).unwrap();
+      trace.take()
+    };
+    let lookup = run(&db, &mut file, "SELECT name FROM events WHERE id = 500");
+    assert_eq!(lookup.len(), 3);
+    assert!(lookup.iter().all(|(_, kind)| *kind == PageKind::Table));
+    assert_eq!(run(&db, &mut file, "SELECT name FROM events LIMIT 1"), left_edge);
+    assert!(run(&db, &mut file, "SELECT name FROM events").len() > 3);
+
+    // Pages read again from the cache aren't read from the file.
+    Query::parse("SELECT name FROM events LIMIT 1").unwrap().execute(&db, &mut file).unwrap();
+    assert_eq!(trace.take(), Vec::new());
+    db.clear_page_observer();
+  }
+
+  #[test]
+  fn index_root_page_is_not_scanned_as_a_table() {
+    let file = std::fs::File::open(TEST_DB_INTERIOR).unwrap();
+    let db = Database::parse_header_and_schema(&file).unwrap();
+    // Page 3 is the root of idx_users_email.
+    assert_eq!(db.schema.indexes("users").next().unwrap().root_page, 3);
+    let error = db.get_full_table(&file, 3, &["email"], &[0]).unwrap_err();
+    assert_eq!(error.to_string(), "database disk image is malformed: page 3: not a table b-tree page");
+  }
+
+  #[test]
+  fn pages_read_the_same_from_a_file_and_from_memory() {
+    // The old context/ engine's page tests.
+    let data = std::fs::read(TEST_DB_INTERIOR).unwrap();
+    let mut file = std::fs::File::open(TEST_DB_INTERIOR).unwrap();
+    let mut memory = std::io::Cursor::new(&data[..]);
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    for page_index in 1..=5 {
+      db.page_cache.lock().unwrap().clear();
+      let from_file = db.read_page_data(&mut file, page_index, RecordType::Table).unwrap();
+      db.page_cache.lock().unwrap().clear();
+      let from_memory = db.read_page_data(&mut memory, page_index, RecordType::Table).unwrap();
+      assert_eq!(from_file[..], from_memory[..], "page {}", page_index);
+    }
+    assert!(db.read_page_data(&mut memory, 0, RecordType::Table).is_err());
+
+    // The schema table's only page holds the users table and its index. The users table has
+    // rowids up to 15 on page 4 and the rest on page 5.
+    let page_data = db.read_page_data(&mut memory, 1, RecordType::Table).unwrap();
+    let page = db.parse_raw_page(&page_data, 1).unwrap();
+    assert!(matches!(page.ty, BTreePageType::TableLeaf));
+    assert_eq!(page.cell_count(), 2);
+    let page_data = db.read_page_data(&mut memory, 2, RecordType::Table).unwrap();
+    let page = db.parse_raw_page(&page_data, 2).unwrap();
+    assert!(matches!(page.ty, BTreePageType::TableInterior));
+    assert_eq!((page.cell_count(), page.row_id(0).unwrap().1, page.rightmost_pointer), (1, 15, Some(5)));
+    let page_data = db.read_page_data(&mut memory, 5, RecordType::Table).unwrap();
+    assert!(matches!(db.parse_raw_page(&page_data, 5).unwrap().ty, BTreePageType::TableLeaf));
+  }
+
+  #[test]
+  fn queries_do_not_read_the_schema_page_again() {
+    let mut file = std::fs::File::open(TEST_DB_INTERIOR).unwrap();
+    let mut db = Database::parse_header_and_schema(&mut file).unwrap();
+    let trace = PageTrace::default();
+    db.set_page_observer(trace.clone());
+    for (sql, expected) in [
+      ("SELECT username FROM users WHERE email = 'dave18@example.com'", "Dave18"),
+      ("SELECT COUNT(*) FROM users", "22"),
+    ] {
+      db.page_cache.lock().unwrap().clear();
+      let rows = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+      assert_eq!(rows, vec![vec![expected]]);
+    }
+    let pages = trace.take();
+    assert!(!pages.is_empty());
+    assert!(pages.iter().all(|(page, _)| *page != 1), "{:?}", pages);
+    db.clear_page_observer();
+  }
+
+  #[test]
+  fn rows_come_in_rowid_order_from_a_three_level_tree() {
+    let (db, mut file) = open_fixture(TEST_DB_DEEP);
+    let table_root_page = db.schema.table_root_page("events").unwrap();
+
+    // The rows were inserted in random order, so pages split all over the rowid range.
+    let row_ids = db
+      .get_full_table(&mut file, table_root_page, &["name"], &[1])
+      .unwrap()
+      .iter()
+      .map(|r| r.row_id.unwrap())
+      .collect::<Vec<_>>();
+    assert_eq!(row_ids.len(), 1500);
+    assert_eq!(row_ids[..5], [6, 8, 14, 36, 38]);
+    assert!(row_ids.windows(2).all(|w| w[0] < w[1]));
+
+    let rows = Query::parse("SELECT id FROM events").unwrap().execute(&db, &mut file).unwrap();
+    assert_eq!(rows.concat(), row_ids.iter().map(|id| id.to_string()).collect::<Vec<_>>());
+
+    // Looked up from every leaf at once, the rows still come back in rowid order.
+    let wanted = row_ids.iter().rev().step_by(7).copied().collect::<Vec<_>>();
+    let found = db
+      .get_by_row_ids(&mut file, table_root_page, &wanted, &["name"], &[1])
+      .unwrap()
+      .iter()
+      .map(|r| r.row_id.unwrap())
+      .collect::<Vec<_>>();
+    let mut expected = wanted.clone();
+    expected.sort_unstable();
+    assert_eq!(found, expected);
+  }
+
+  #[test]
+  fn count_rows_does_not_decode_records() {
+    let (db, mut file) = open_fixture(TEST_DB_INTERIOR);
+    let table_root_page = db.schema.table_root_page("users").unwrap();
+
+    let records_before = db.records_decoded();
+    assert_eq!(db.count_rows(&mut file, table_root_page).unwrap(), 22);
+    assert_eq!(db.records_decoded(), records_before);
+
+    let query = Query::parse("SELECT COUNT(*) FROM users").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["22".to_string()]]);
+    assert_eq!(db.records_decoded(), records_before);
+  }
+
+  #[test]
+  fn limit_stops_scanning_early() {
+    let (db, mut file) = open_fixture(TEST_DB_INTERIOR);
+    let pages_before = db.table_pages_parsed();
+    let query = Query::parse("SELECT username FROM users LIMIT 3").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap().len(), 3);
+    let limited_pages = db.table_pages_parsed() - pages_before;
+
+    let (db, mut file) = open_fixture(TEST_DB_INTERIOR);
+    let pages_before = db.table_pages_parsed();
+    let query = Query::parse("SELECT username FROM users").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap().len(), 22);
+    let all_pages = db.table_pages_parsed() - pages_before;
+
+    assert!(limited_pages < all_pages, "{} >= {}", limited_pages, all_pages);
+  }
+
+  #[test]
+  fn progress_handler_interrupts_scans_and_searches() {
+    let (mut db, mut file) = open_fixture(TEST_DB_COMPANIES);
+    let calls = Arc::new(AtomicUsize::new(0));
+    let handler_calls = calls.clone();
+    db.set_progress_handler(2, move || {
+      handler_calls.fetch_add(1, Ordering::Relaxed);
+      ControlFlow::Break(())
+    });
+
+    let table_root_page = db.schema.table_root_page("companies").unwrap();
+    let result = db.get_full_table(&mut file, table_root_page, &["name"], &[1]);
+    assert!(matches!(result, Err(Error::Interrupted)), "{:?}", result.map(|records| records.len()));
+    let query = Query::parse("SELECT name FROM companies WHERE country = 'country_123'").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap_err().to_string(), "query interrupted");
+    let interrupted = db
+      .scan_table(&mut file, table_root_page, &[], &[])
+      .any(|record| matches!(record, Err(Error::Interrupted)));
+    assert!(interrupted);
+    assert_eq!(calls.load(Ordering::Relaxed), 3);
+
+    // Continuing lets the same queries run to the end.
+    db.set_progress_handler(1, || ControlFlow::Continue(()));
+    assert_eq!(query.execute(&db, &mut file).unwrap().len(), 3);
+    db.clear_progress_handler();
+    assert!(db.get_full_table(&mut file, table_root_page, &["name"], &[1]).is_ok());
+  }
+
+  #[test]
+  fn table_names_match_in_any_case() {
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_MIXED_CASE).unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    // Names are listed as created, as sqlite3's .tables does.
+    assert_eq!(db.schema.table_names(), ["MixedCase", "Users", "apples"]);
+    assert_eq!(db.schema.indexes("mixedCASE").count(), 1);
+
+    for sql in [
+      "SELECT name FROM MixedCase WHERE name = 'two'",
+      "SELECT name FROM mixedcase WHERE name = 'two'",
+      "SELECT name FROM \"MIXEDCASE\" WHERE id = 2",
+    ] {
+      let rows = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+      assert_eq!(rows, vec![vec!["two"]], "{}", sql);
+    }
+    assert_eq!(
+      db.explain("SELECT name FROM mixedcase WHERE name = 'two'").unwrap().to_string(),
+      "SEARCH MixedCase USING COVERING INDEX Idx_MixedCase_Name"
+    );
+
+    // Writes find the table, and its index, the same way.
+    insert(&db, &mut file, "INSERT INTO MIXEDCASE (name) VALUES ('four')").unwrap();
+    assert_eq!(update(&db, &mut file, "UPDATE mixedCase SET name = 'FOUR' WHERE id = 4").unwrap(), 1);
+    assert_eq!(delete(&db, &mut file, "DELETE FROM Apples").unwrap(), 1);
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let rows = Query::parse("SELECT id FROM mixedcase WHERE name = 'FOUR'").unwrap().execute(&db, &mut file);
+    assert_eq!(rows.unwrap(), vec![vec!["4"]]);
+    if let Some(output) = sqlite3_output(file.get_ref(), "PRAGMA integrity_check;") {
+      assert_eq!(output, "ok\n");
+    }
+  }
+
+  #[test]
+  fn column_names_match_in_any_case() {
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_MIXED_CASE).unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    for (sql, expected) in [
+      ("SELECT Id FROM Users WHERE Name = 'b'", vec![vec!["2"]]),
+      ("SELECT id FROM users WHERE name = 'b'", vec![vec!["2"]]),
+      ("SELECT name FROM users", vec![vec!["a"], vec!["b"], vec!["c"]]),
+      ("SELECT Name FROM Users ORDER BY Age", vec![vec!["b"], vec!["a"], vec!["c"]]),
+      ("SELECT NAME FROM USERS ORDER BY \"Age\" DESC", vec![vec!["c"], vec!["a"], vec!["b"]]),
+      ("SELECT \"Name\" FROM Users WHERE \"AGE\" > 26 AND ID < 3", vec![vec!["a"]]),
+      ("SELECT max(age) FROM users", vec![vec!["41"]]),
+    ] {
+      let rows = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+      assert_eq!(rows, expected, "{}", sql);
+    }
+    // The index on Age is used whatever case the column is named in.
+    assert_eq!(
+      db.explain("SELECT name FROM users WHERE age = 30").unwrap().to_string(),
+      "SEARCH Users USING INDEX Idx_Users_Age"
+    );
+
+    assert_eq!(update(&db, &mut file, "UPDATE users SET AGE = 26, name = 'B' WHERE NAME = 'b'").unwrap(), 1);
+    let rows = Query::parse("SELECT Name, Age FROM Users WHERE Id = 2").unwrap().execute(&db, &mut file);
+    assert_eq!(rows.unwrap(), vec![vec!["B", "26"]]);
+    if let Some(output) = sqlite3_output(file.get_ref(), "PRAGMA integrity_check;") {
+      assert_eq!(output, "ok\n");
+    }
+  }
+
+  #[test]
+  fn missing_table_and_column_errors() {
+    let (db, mut file) = open_fixture(TEST_DB_INTERIOR);
+
+    let query = Query::parse("SELECT name FROM accounts").unwrap();
+    assert!(matches!(
+      query.execute(&db, &mut file),
+      Err(Error::TableNotFound(table)) if table == "accounts"
+    ));
+
+    let query = Query::parse("SELECT name FROM users").unwrap();
+    assert!(matches!(
+      query.execute(&db, &mut file),
+      Err(Error::ColumnNotFound { table, column, .. }) if table == "users" && column == "name"
+    ));
+
+    assert!(matches!(Query::parse("DROP TABLE users"), Err(Error::UnsupportedSql(_))));
+  }
+
+  #[test]
+  fn database_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Database>();
+  }
+
+  #[test]
+  fn concurrent_queries_share_one_handle() {
+    let (db, _) = open_fixture(TEST_DB_COMPANIES);
+    let query = Query::parse("SELECT COUNT(*) FROM companies WHERE country = 'country_123'").unwrap();
+    let results = std::thread::scope(|scope| {
+      let handles = (0..8)
+        .map(|_| {
+          scope.spawn(|| {
+            let mut file = std::fs::File::open(TEST_DB_COMPANIES).unwrap();
+            query.execute(&db, &mut file).unwrap()
+          })
+        })
+        .collect::<Vec<_>>();
+      handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .collect::<Vec<_>>()
+    });
+    assert!(results.iter().all(|result| *result == vec![vec!["3".to_string()]]));
+  }
+
+  #[test]
+  fn scan_table_filters_borrowed_records() {
+    let (db, mut file) = open_fixture(TEST_DB_PRODUCTS);
+    let table_root_page = db.schema.table_root_page("products").unwrap();
+    let key = Value::Text("category_01".to_string());
+
+    let records = db
+      .scan_table(&mut file, table_root_page, &["name", "category"], &[1, 2])
+      .with_filter(|record| record.values[1] == key)
+      .collect::<crate::error::Result<Vec<_>>>()
+      .unwrap();
+    assert_eq!(records.len(), 14);
+    assert!(records.iter().all(|record| record.values[1] == key));
+    assert_eq!(records[0].row_id, Some(51));
+  }
+
+  #[test]
+  fn overflow_pages_are_skipped_when_not_selected() {
+    // Zero out the overflow pages holding the 10KB `body` blobs: selecting only `title` must not
+    // need them.
+    let mut data = std::fs::read(TEST_DB_DOCUMENTS).unwrap();
+    let page_size = 4096;
+    for page_index in [3, 4, 5, 6, 9, 10] {
+      data[page_size * (page_index - 1)..page_size * page_index].fill(0);
+    }
+    let mut file = std::io::Cursor::new(data);
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let table_root_page = db.schema.table_root_page("documents").unwrap();
+
+    let titles = db
+      .get_full_table(&mut file, table_root_page, &["title"], &[1])
+      .unwrap()
+      .iter()
+      .map(|r| r.values[0].to_string())
+      .collect::<Vec<_>>();
+    assert_eq!(titles, vec!["document 1", "document 2", "document 3"]);
+
+    assert!(db
+      .get_full_table(&mut file, table_root_page, &["title", "body"], &[1, 2])
+      .is_err());
+  }
+
+  #[test]
+  fn overflowing_column_is_reassembled() {
+    let (db, mut file) = open_fixture(TEST_DB_DOCUMENTS);
+    let table_root_page = db.schema.table_root_page("documents").unwrap();
+    let records = db
+      .get_full_table(&mut file, table_root_page, &["body"], &[2])
+      .unwrap();
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[1].values[0].to_string(), "2".repeat(10240));
+    // Each 10256 byte payload keeps 2072 bytes on its leaf page and the rest on two overflow
+    // pages.
+    assert_eq!((db.overflow_pages_parsed(), db.overflow_bytes_read()), (6, 3 * (10256 - 2072)));
+
+    // Columns before the one that spills are on the leaf page.
+    let (db, mut file) = open_fixture(TEST_DB_DOCUMENTS);
+    for sql in ["SELECT id, title FROM documents", "SELECT count(*) FROM documents WHERE title LIKE '%2'"] {
+      Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+    }
+    assert_eq!((db.overflow_pages_parsed(), db.overflow_bytes_read()), (0, 0));
+    let sql = "SELECT length(body) FROM documents WHERE id = 2";
+    let rows = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+    assert_eq!((rows, db.overflow_pages_parsed()), (vec![vec!["10240".to_string()]], 2));
+  }
+
+  fn insert(db: &Database, file: &mut std::io::Cursor<Vec<u8>>, sql: &str) -> crate::error::Result<usize> {
+    match Query::parse(sql)? {
+      Query::Insert(insert) => db.insert(file, &insert),
+      query => panic!("not an insert: {:?}", query),
+    }
+  }
+
+  #[test]
+  fn inserted_row_is_read_back_after_reopening() {
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_INTERIOR).unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let change_counter = db.header.file_change_counter;
+    let inserted = insert(
+      &db,
+      &mut file,
+      "INSERT INTO users (id, username, age, email) VALUES (23, 'Zoe', 31, 'zoe@example.com')",
+    )
+    .unwrap();
+    assert_eq!(inserted, 1);
+    assert!(matches!(
+      insert(&db, &mut file, "INSERT INTO users (id, username) VALUES (23, 'Zoe')"),
+      Err(Error::Constraint(message)) if message == "UNIQUE constraint failed: users.id"
+    ));
+    assert!(matches!(
+      insert(&db, &mut file, "INSERT INTO users (id, username) VALUES (24)"),
+      Err(Error::ValueCount { expected: 2, found: 1 })
+    ));
+
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    assert_eq!(db.header.file_change_counter, change_counter + 1);
+    // Found both by scanning the table and through the index on email.
+    for sql in [
+      "SELECT id, username, age FROM users WHERE username = 'Zoe'",
+      "SELECT id, username, age FROM users WHERE email = 'zoe@example.com'",
+    ] {
+      let rows = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+      assert_eq!(rows, vec![vec!["23", "Zoe", "31"]]);
+    }
+    let rows = Query::parse("SELECT COUNT(*) FROM users").unwrap().execute(&db, &mut file).unwrap();
+    assert_eq!(rows, vec![vec!["23"]]);
+
+    if let Some(output) = sqlite3_output(
+      file.get_ref(),
+      "PRAGMA integrity_check; SELECT username FROM users WHERE id = 23;",
+    ) {
+      assert_eq!(output, "ok\nZoe\n");
+    }
+  }
+
+  /// Run `sql` with the real sqlite3 on a copy of the database `data`, if it's installed.
+  fn sqlite3_output(data: &[u8], sql: &str) -> Option<String> {
+    let path = std::env::temp_dir().join(format!(
+      "sqlite-rust-{}-{:?}.db",
+      std::process::id(),
+      std::thread::current().id()
+    ));
+    std::fs::write(&path, data).unwrap();
+    let output = std::process::Command::new("sqlite3").arg(&path).arg(sql).output();
+    std::fs::remove_file(&path).unwrap();
+    output.ok().map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+  }
+
+  #[test]
+  fn columns_added_by_alter_table_read_as_their_defaults() {
+    let mut file = std::io::Cursor::new(std::fs::read("tests/fixtures/added_columns.db").unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    // The first three rows were written before `stock` and the columns after it were added, so
+    // their records stop at `added`. The rows are those sqlite3 returns.
+    for (sql, expected) in [
+      ("SELECT name, stock, note, weight, label FROM items WHERE id = 2", "nut,-1,null,6.0,none"),
+      ("SELECT name FROM items WHERE stock = -1 AND label = 'none'", "bolt,nut,gear"),
+      ("SELECT name, stock FROM items ORDER BY stock DESC LIMIT 2", "spring,40,bolt,-1"),
+      ("SELECT max(weight) FROM items", "6.0"),
+    ] {
+      let rows = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+      assert_eq!(rows.concat().join(","), expected, "{}", sql);
+    }
+
+    // A column an INSERT leaves out takes its default too.
+    insert(&db, &mut file, "INSERT INTO items (name) VALUES ('washer')").unwrap();
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let sql = "SELECT price, added, stock, note, weight, label FROM items WHERE name = 'washer'";
+    let rows = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+    assert_eq!(rows.concat().join(","), "0.5,3,-1,null,6.0,none");
+  }
+
+  #[test]
+  fn strict_tables_only_store_values_of_the_declared_type() {
+    let mut file = std::io::Cursor::new(std::fs::read("tests/fixtures/strict.db").unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    // Values are converted as in any table first, then must have the column's type. The errors
+    // are those sqlite3 reports.
+    insert(&db, &mut file, "INSERT INTO readings (sensor, value, extra) VALUES (12, 3, '4')").unwrap();
+    for (sql, message) in [
+      ("INSERT INTO readings (sensor, value) VALUES ('c', 'high')", "cannot store TEXT value in REAL column readings.value"),
+      ("INSERT INTO readings (sensor, raw) VALUES ('c', 'A')", "cannot store TEXT value in BLOB column readings.raw"),
+      ("UPDATE readings SET raw = 7 WHERE id = 1", "cannot store INT value in BLOB column readings.raw"),
+    ] {
+      let result = match Query::parse(sql).unwrap() {
+        Query::Insert(insert) => db.insert(&mut file, &insert),
+        Query::Update(update) => db.update(&mut file, &update),
+        query => panic!("not a write: {:?}", query),
+      };
+      assert!(matches!(result, Err(Error::Constraint(m)) if m == message), "{}", sql);
+    }
+
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let sql = "SELECT sensor, typeof(sensor), value, typeof(value), typeof(extra) FROM readings WHERE id = 4";
+    let rows = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+    assert_eq!(rows, [["12", "text", "3.0", "real", "text"]]);
+
+    // Tables without a rowid are in the schema, but their rows aren't read or written.
+    assert!(db.schema.table("sensors").unwrap().without_rowid);
+    let query = Query::parse("SELECT name FROM sensors").unwrap();
+    assert!(matches!(query.execute(&db, &mut file), Err(Error::UnsupportedSql(_))));
+    assert!(matches!(
+      insert(&db, &mut file, "INSERT INTO tags VALUES ('green', 2)"),
+      Err(Error::UnsupportedSql(_))
+    ));
+  }
+
+  #[test]
+  fn writes_enforce_not_null_and_unique_constraints() {
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_CONSTRAINTS).unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let before = file.get_ref().clone();
+    // The unique column of r is compared with NOCASE. The errors are those sqlite3 reports.
+    for (sql, message) in [
+      ("INSERT INTO r (u) VALUES ('y')", "NOT NULL constraint failed: r.n"),
+      ("INSERT INTO r (n, u) VALUES ('c', 'X')", "UNIQUE constraint failed: r.u"),
+      ("INSERT INTO r (n, u) VALUES ('c', 'y'), ('d', 'Y')", "UNIQUE constraint failed: r.u"),
+      ("UPDATE r SET n = NULL WHERE id = 1", "NOT NULL constraint failed: r.n"),
+      ("UPDATE r SET u = 'X' WHERE id = 2", "UNIQUE constraint failed: r.u"),
+    ] {
+      let result = match Query::parse(sql).unwrap() {
+        Query::Insert(insert) => db.insert(&mut file, &insert),
+        Query::Update(update) => db.update(&mut file, &update),
+        query => panic!("not a write: {:?}", query),
+      };
+      assert!(matches!(result, Err(Error::Constraint(m)) if m == message), "{}", sql);
+      assert!(file.get_ref() == &before, "{}", sql);
+    }
+
+    // Keys holding a NULL never conflict, and a row doesn't conflict with itself.
+    insert(&db, &mut file, "INSERT INTO r (n, u) VALUES ('c', NULL), ('d', NULL), ('e', 'y')").unwrap();
+    update(&db, &mut file, "UPDATE r SET u = 'X' WHERE id = 1").unwrap();
+    if let Some(output) = sqlite3_output(file.get_ref(), "PRAGMA integrity_check; SELECT count(*), count(u) FROM r;") {
+      assert_eq!(output, "ok\n5|2\n");
+    }
+
+    for (fixture, sql, message) in [
+      (
+        "tests/fixtures/unique_index.db",
+        "INSERT INTO accounts (email, name) VALUES ('bob@example.com', 'Bo')",
+        "UNIQUE constraint failed: accounts.email",
+      ),
+      (
+        "tests/fixtures/strict.db",
+        "INSERT INTO readings (value) VALUES (1.0)",
+        "NOT NULL constraint failed: readings.sensor",
+      ),
+    ] {
+      let mut file = std::io::Cursor::new(std::fs::read(fixture).unwrap());
+      let db = Database::parse_header_and_schema(&mut file).unwrap();
+      assert!(matches!(insert(&db, &mut file, sql), Err(Error::Constraint(m)) if m == message), "{}", sql);
+      if let Some(output) = sqlite3_output(file.get_ref(), "PRAGMA integrity_check;") {
+        assert_eq!(output, "ok\n");
+      }
+    }
+  }
+
+  #[test]
+  fn tables_with_check_constraints_are_not_written() {
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_CONSTRAINTS).unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    // p has a column CHECK constraint and q one on the whole table, which writes can't evaluate.
+    for (sql, table) in [
+      ("INSERT INTO p (price, n) VALUES (-5, 'a')", "p"),
+      ("UPDATE p SET price = -5", "p"),
+      ("INSERT INTO q (n) VALUES ('')", "q"),
+    ] {
+      let result = match Query::parse(sql).unwrap() {
+        Query::Insert(insert) => db.insert(&mut file, &insert),
+        Query::Update(update) => db.update(&mut file, &update),
+        query => panic!("not a write: {:?}", query),
+      };
+      let expected = format!("writing to table {} with CHECK constraints", table);
+      assert!(matches!(result, Err(Error::UnsupportedSql(m)) if m == expected), "{}", sql);
+    }
+    if let Some(output) = sqlite3_output(file.get_ref(), "PRAGMA integrity_check; SELECT count(*) FROM p;") {
+      assert_eq!(output, "ok\n1\n");
+    }
+  }
+
+  #[test]
+  fn failed_insert_leaves_file_unchanged() {
+    let mut file = std::io::Cursor::new(std::fs::read("tests/fixtures/leaf.db").unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let before = file.get_ref().clone();
+
+    // The table is a single 4KB leaf page, which these rows only fit on once it has been split,
+    // but the last one fails after the split.
+    let rows = (5..10)
+      .map(|id| format!("({}, '{}', 20)", id, "x".repeat(1000)))
+      .collect::<Vec<_>>()
+      .join(", ");
+    let sql = format!("INSERT INTO users (id, username, age) VALUES {}, (1, 'Zoe', 31)", rows);
+    assert!(matches!(insert(&db, &mut file, &sql), Err(Error::Constraint(_))));
+    assert!(file.get_ref() == &before);
+
+    let sql = format!("INSERT INTO users (id, username, age) VALUES {}", rows);
+    assert_eq!(insert(&db, &mut file, &sql).unwrap(), 5);
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let rows = Query::parse("SELECT COUNT(*) FROM users").unwrap().execute(&db, &mut file).unwrap();
+    assert_eq!(rows, vec![vec!["9"]]);
+  }
+
+  #[test]
+  fn inserts_split_pages_up_to_new_levels() {
+    let mut file = std::io::Cursor::new(std::fs::read("tests/fixtures/small_pages.db").unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    // Emails are a permutation of the usernames, so index entries go all over the index b-tree.
+    for batch in 0..10 {
+      let rows = (batch * 100..(batch + 1) * 100)
+        .map(|i| format!("('user{}', {}, 'user{}@example.com')", i, i % 90, (i * 7919) % 1000))
+        .collect::<Vec<_>>()
+        .join(", ");
+      let sql = format!("INSERT INTO users (username, age, email) VALUES {}", rows);
+      assert_eq!(insert(&db, &mut file, &sql).unwrap(), 100);
+    }
+
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let root_page = db.schema.table_root_page("users").unwrap();
+    assert_eq!(root_page, 2);
+    let mut interior_levels = 0;
+    let mut page_index = root_page;
+    loop {
+      let page_data = db.read_page_data(&mut file, page_index, RecordType::Table).unwrap();
+      let page = db.parse_raw_page(&page_data, page_index).unwrap();
+      match page.ty {
+        BTreePageType::TableInterior => {
+          interior_levels += 1;
+          page_index = page.rightmost_pointer.unwrap();
+        }
+        _ => break,
+      }
+    }
+    assert!(interior_levels >= 2, "{} interior levels", interior_levels);
+
+    let records = db
+      .get_full_table(&mut file, root_page, &["id", "username"], &[0, 1])
+      .unwrap();
+    assert_eq!(records.len(), 1000);
+    for (i, record) in records.iter().enumerate() {
+      // The INTEGER PRIMARY KEY is stored as NULL, its value being the rowid.
+      assert!(matches!(record.values[0], Value::Null));
+      assert_eq!(record.row_id, Some(i as i64 + 1));
+      assert_eq!(record.values[1].to_string(), format!("user{}", i));
+    }
+    let records = db
+      .get_by_row_ids(&mut file, root_page, &[1, 500, 1000], &["username"], &[1])
+      .unwrap();
+    let mut usernames = records.iter().map(|r| r.values[0].to_string()).collect::<Vec<_>>();
+    usernames.sort();
+    assert_eq!(usernames, ["user0", "user499", "user999"]);
+    for (email, username) in [("user0", "user0"), ("user919", "user1"), ("user81", "user999")] {
+      let sql = format!("SELECT username FROM users WHERE email = '{}@example.com'", email);
+      let rows = Query::parse(&sql).unwrap().execute(&db, &mut file).unwrap();
+      assert_eq!(rows, vec![vec![username]]);
+    }
+
+    if let Some(output) = sqlite3_output(
+      file.get_ref(),
+      "PRAGMA integrity_check; SELECT count(*) FROM users;",
+    ) {
+      assert_eq!(output, "ok\n1000\n");
+    }
+  }
+
+  fn delete(db: &Database, file: &mut std::io::Cursor<Vec<u8>>, sql: &str) -> crate::error::Result<usize> {
+    match Query::parse(sql)? {
+      Query::Delete(delete) => db.delete(file, &delete),
+      query => panic!("not a delete: {:?}", query),
+    }
+  }
+
+  #[test]
+  fn deleted_rows_are_gone_from_table_and_index() {
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_INTERIOR).unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let emails = Query::parse("SELECT email, age FROM users")
+      .unwrap()
+      .execute(&db, &mut file)
+      .unwrap()
+      .into_iter()
+      .filter(|row| row[1] == "105")
+      .collect::<Vec<_>>();
+    assert_eq!(delete(&db, &mut file, "DELETE FROM users WHERE age = 105").unwrap(), 18);
+
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let rows = Query::parse("SELECT COUNT(*) FROM users").unwrap().execute(&db, &mut file).unwrap();
+    assert_eq!(rows, vec![vec!["4"]]);
+    let rows = Query::parse("SELECT age FROM users").unwrap().execute(&db, &mut file).unwrap();
+    assert!(rows.iter().all(|row| row[0] != "105"));
+    for email in emails.iter() {
+      let sql = format!("SELECT id FROM users WHERE email = '{}'", email[0]);
+      let rows = Query::parse(&sql).unwrap().execute(&db, &mut file).unwrap();
+      assert!(rows.is_empty(), "{} is still in the index", email[0]);
+    }
+
+    if let Some(output) = sqlite3_output(
+      file.get_ref(),
+      "PRAGMA integrity_check; SELECT count(*) FROM users;",
+    ) {
+      assert_eq!(output, "ok\n4\n");
+    }
+  }
+
+  #[test]
+  fn delete_without_filter_removes_every_row() {
+    let mut file = std::io::Cursor::new(std::fs::read("tests/fixtures/small_pages.db").unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let rows = (0..500)
+      .map(|i| format!("('user{}', {}, 'user{}@example.com')", i, i % 90, (i * 7919) % 500))
+      .collect::<Vec<_>>()
+      .join(", ");
+    let sql = format!("INSERT INTO users (username, age, email) VALUES {}", rows);
+    assert_eq!(insert(&db, &mut file, &sql).unwrap(), 500);
+
+    // Deleting from a multi-level index b-tree moves entries up from leaves to interior pages.
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    assert_eq!(delete(&db, &mut file, "DELETE FROM users WHERE age = 7").unwrap(), 6);
+    if let Some(output) = sqlite3_output(
+      file.get_ref(),
+      "PRAGMA integrity_check; SELECT count(*) FROM users;",
+    ) {
+      assert_eq!(output, "ok\n494\n");
+    }
+
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    assert_eq!(delete(&db, &mut file, "DELETE FROM users").unwrap(), 494);
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let rows = Query::parse("SELECT COUNT(*) FROM users").unwrap().execute(&db, &mut file).unwrap();
+    assert_eq!(rows, vec![vec!["0"]]);
+    let rows = Query::parse("SELECT username FROM users WHERE email = 'user1@example.com'")
+      .unwrap()
+      .execute(&db, &mut file)
+      .unwrap();
+    assert!(rows.is_empty());
+
+    if let Some(output) = sqlite3_output(
+      file.get_ref(),
+      "PRAGMA integrity_check; SELECT count(*) FROM users;",
+    ) {
+      assert_eq!(output, "ok\n0\n");
+    }
+
+    // Space freed by the deletes is reused.
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let page_count = db.header.size_in_pages;
+    assert_eq!(insert(&db, &mut file, &sql).unwrap(), 500);
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    assert!(db.header.size_in_pages <= page_count + 1);
+    if let Some(output) = sqlite3_output(file.get_ref(), "PRAGMA integrity_check;") {
+      assert_eq!(output, "ok\n");
+    }
+  }
+
+  #[test]
+  fn large_deletes_keep_a_deep_index_valid() {
+    let mut file = std::io::Cursor::new(std::fs::read("tests/fixtures/small_pages.db").unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let rows = (0..1500)
+      .map(|i| format!("('user{}', {}, 'user{:04}{}@example.com')", i, i % 100, (i * 37) % 1500, "x".repeat(40)))
+      .collect::<Vec<_>>()
+      .join(", ");
+    let sql = format!("INSERT INTO users (username, age, email) VALUES {}", rows);
+    assert_eq!(insert(&db, &mut file, &sql).unwrap(), 1500);
+
+    // Only a few entries fit on a page, so leaves and then interior pages of the index empty out
+    // and are removed while their siblings are still in use.
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let root_page = db.schema.indexes("users").next().unwrap().root_page;
+    assert!(db.btree_depth(&mut file, root_page, RecordType::Index).unwrap() >= 3);
+
+    for (sql, deleted) in [
+      ("DELETE FROM users WHERE age > 20", 1185),
+      ("DELETE FROM users WHERE age < 90", 1350),
+      ("DELETE FROM users WHERE id % 7 != 0", 1286),
+      ("DELETE FROM users WHERE id > 10", 1490),
+      ("DELETE FROM users", 1500),
+    ] {
+      let mut file = file.clone();
+      assert_eq!(delete(&db, &mut file, sql).unwrap(), deleted, "{}", sql);
+      if let Some(output) = sqlite3_output(
+        file.get_ref(),
+        "PRAGMA integrity_check; SELECT count(*) FROM users;",
+      ) {
+        assert_eq!(output, format!("ok\n{}\n", 1500 - deleted), "{}", sql);
+      }
+    }
+  }
+
+  fn update(db: &Database, file: &mut std::io::Cursor<Vec<u8>>, sql: &str) -> crate::error::Result<usize> {
+    match Query::parse(sql)? {
+      Query::Update(update) => db.update(file, &update),
+      query => panic!("not an update: {:?}", query),
+    }
+  }
+
+  #[test]
+  fn updated_rows_keep_other_columns_and_index_entries() {
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_INTERIOR).unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    // Shrinks the record in place, then grows the username past the room left on its leaf page.
+    assert_eq!(update(&db, &mut file, "UPDATE users SET age = 3 WHERE username = 'Alice'").unwrap(), 1);
+    let username = "Alice".repeat(60);
+    let sql = format!("UPDATE users SET username = '{}' WHERE username = 'Alice'", username);
+    assert_eq!(update(&db, &mut file, &sql).unwrap(), 1);
+    let sql = "UPDATE users SET email = 'bobby@example.com', age = 46 WHERE id = 2";
+    assert_eq!(update(&db, &mut file, sql).unwrap(), 1);
+    assert!(matches!(
+      update(&db, &mut file, "UPDATE users SET id = 30 WHERE id = 2"),
+      Err(Error::UnsupportedSql(message)) if message == "updating the rowid column users.id"
+    ));
+
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let rows = Query::parse("SELECT id, username, age, email FROM users").unwrap().execute(&db, &mut file).unwrap();
+    assert_eq!(rows.len(), 22);
+    assert_eq!(rows[0], vec!["1", &username, "3", "alice@example.com"]);
+    assert_eq!(rows[1], vec!["2", "Bob", "46", "bobby@example.com"]);
+    for (email, expected) in [("bob@example.com", vec![]), ("bobby@example.com", vec![vec!["Bob"]])] {
+      let sql = format!("SELECT username FROM users WHERE email = '{}'", email);
+      let rows = Query::parse(&sql).unwrap().execute(&db, &mut file).unwrap();
+      assert_eq!(rows, expected);
+    }
+
+    if let Some(output) = sqlite3_output(
+      file.get_ref(),
+      "PRAGMA integrity_check; SELECT length(username), age FROM users WHERE id = 1;",
+    ) {
+      assert_eq!(output, "ok\n300|3\n");
+    }
+  }
+
+  #[test]
+  fn rewritten_rows_free_their_overflow_pages() {
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_DOCUMENTS).unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let sql = "UPDATE documents SET body = 'short' WHERE title = 'document 2'";
+    assert_eq!(update(&db, &mut file, sql).unwrap(), 1);
+    let sql = "DELETE FROM documents WHERE title = 'document 3'";
+    assert_eq!(delete(&db, &mut file, sql).unwrap(), 1);
+
+    // Each 10KB body spills onto two 4KB overflow pages, and each row has a leaf page of its
+    // own, which the delete leaves empty.
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    assert_eq!(db.header.num_freelist_pages, 5);
+    let rows = Query::parse("SELECT title, body FROM documents").unwrap().execute(&db, &mut file).unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[1], vec!["document 2", "short"]);
+
+    if let Some(output) = sqlite3_output(file.get_ref(), "PRAGMA integrity_check;") {
+      assert_eq!(output, "ok\n");
+    }
+  }
+
+  fn create_table(db: &mut Database, file: &mut std::io::Cursor<Vec<u8>>, sql: &str) -> crate::error::Result<()> {
+    match Query::parse(sql)? {
+      Query::Create(create) => db.create_table(file, &create),
+      query => panic!("not a create: {:?}", query),
+    }
+  }
+
+  #[test]
+  fn created_table_is_usable_immediately() {
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_INTERIOR).unwrap());
+    let mut db = Database::parse_header_and_schema(&mut file).unwrap();
+    let schema_cookie = db.header.schema_cookie;
+    create_table(&mut db, &mut file, "CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT);").unwrap();
+    assert_eq!(db.header.schema_cookie, schema_cookie + 1);
+    assert_eq!(db.schema.table_names(), ["notes", "users"]);
+
+    assert!(matches!(
+      create_table(&mut db, &mut file, "CREATE TABLE Notes (x)"),
+      Err(Error::AlreadyExists { kind, name }) if kind == "table" && name == "notes"
+    ));
+    assert!(matches!(
+      create_table(&mut db, &mut file, "CREATE TABLE IF NOT EXISTS idx_users_email (x)"),
+      Err(Error::AlreadyExists { kind, .. }) if kind == "index"
+    ));
+    create_table(&mut db, &mut file, "CREATE TABLE IF NOT EXISTS notes (x)").unwrap();
+    assert_eq!(db.header.schema_cookie, schema_cookie + 1);
+    for sql in ["CREATE TABLE tags (name TEXT UNIQUE)", "CREATE TABLE tags (name TEXT PRIMARY KEY)"] {
+      assert!(matches!(create_table(&mut db, &mut file, sql), Err(Error::UnsupportedSql(_))), "{}", sql);
+    }
+
+    assert_eq!(insert(&db, &mut file, "INSERT INTO notes (body) VALUES ('hello'), ('world')").unwrap(), 2);
+    let rows = Query::parse("SELECT id, body FROM notes").unwrap().execute(&db, &mut file).unwrap();
+    assert_eq!(rows, vec![vec!["1", "hello"], vec!["2", "world"]]);
+
+    if let Some(output) = sqlite3_output(
+      file.get_ref(),
+      "PRAGMA integrity_check; SELECT sql FROM sqlite_master WHERE name = 'notes'; SELECT * FROM notes;",
+    ) {
+      assert_eq!(
+        output,
+        "ok\nCREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT)\n1|hello\n2|world\n"
+      );
+    }
+  }
+
+  #[test]
+  fn quoted_names_are_never_keywords() {
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_INTERIOR).unwrap());
+    let mut db = Database::parse_header_and_schema(&mut file).unwrap();
+    let sql = "CREATE TABLE \"order\" (id INTEGER PRIMARY KEY, \"from\" TEXT, selection INTEGER, [where] TEXT)";
+    create_table(&mut db, &mut file, sql).unwrap();
+    let table = db.schema.table("order").unwrap();
+    assert_eq!(table.column_names, ["id", "from", "selection", "where"]);
+    assert_eq!(table.row_id_column, Some(0));
+
+    let sql = "INSERT INTO \"order\" (\"from\", selection, `where`) VALUES ('a', 1, 'x'), ('b', 2, 'y'), ('a', 3, 'z')";
+    assert_eq!(insert(&db, &mut file, sql).unwrap(), 3);
+    let query = Query::parse("UPDATE \"order\" SET \"where\" = 'w' WHERE selection = 2").unwrap();
+    match query {
+      Query::Update(update) => assert_eq!(db.update(&mut file, &update).unwrap(), 1),
+      query => panic!("not an update: {:?}", query),
+    }
+    // `selection` starts with SELECT, and `from` is only a keyword unquoted.
+    for (sql, expected) in [
+      ("SELECT \"from\", selection FROM \"order\"", "a,1,b,2,a,3"),
+      ("SELECT selection FROM \"order\" WHERE \"from\" = 'a' ORDER BY selection DESC", "3,1"),
+      ("SELECT [where] FROM [order] WHERE selection >= 2 ORDER BY `where`", "w,z"),
+      ("SELECT count(*) FROM \"order\" WHERE \"from\" = 'b'", "1"),
+    ] {
+      let rows = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+      assert_eq!(rows.concat().join(","), expected, "{}", sql);
+    }
+
+    if let Some(output) = sqlite3_output(file.get_ref(), "SELECT \"from\", \"where\" FROM \"order\" WHERE selection = 2;") {
+      assert_eq!(output, "b|w\n");
+    }
+  }
+
+  /// Copy `fixture` to a file of its own named after `name`, for tests that open it by path.
+  fn temp_copy(fixture: &str, name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("sqlite-rust-{}-{}.db", std::process::id(), name));
+    std::fs::copy(fixture, &path).unwrap();
+    path
+  }
+
+  #[test]
+  fn schema_is_reloaded_when_another_connection_writes() {
+    let path = temp_copy(TEST_DB_INTERIOR, "refresh");
+    let mut file = std::fs::File::open(&path).unwrap();
+    let mut db = Database::open(&path).unwrap();
+    // The schema is on page 1 alone, read once when opening and never again.
+    assert_eq!(db.table_pages_parsed(), 1);
+    let count = Query::parse("SELECT count(*) FROM users").unwrap();
+    for _ in 0..100 {
+      assert!(!db.refresh(&mut file).unwrap());
+      assert_eq!(count.execute(&db, &mut file).unwrap(), [["22"]]);
+    }
+    // Then the three pages of the users table, once each.
+    assert_eq!(db.table_pages_parsed(), 1 + 3);
+
+    let write = |db: &mut Database, sql: &str| {
+      let mut file = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+      match Query::parse(sql).unwrap() {
+        Query::Create(create) => db.create_table(&mut file, &create).unwrap(),
+        Query::Insert(insert) => assert_eq!(db.insert(&mut file, &insert).unwrap(), 1),
+        query => panic!("not a write: {:?}", query),
+      }
+    };
+    let mut other = Database::open(&path).unwrap();
+    write(&mut other, "CREATE TABLE notes (body)");
+    write(&mut other, "INSERT INTO users (username) VALUES ('Zed')");
+    assert!(db.schema.table("notes").is_err());
+    assert!(db.refresh(&mut file).unwrap());
+    assert!(db.schema.table("notes").is_ok());
+    // The cached pages of the users table were dropped along with the old schema.
+    assert_eq!(count.execute(&db, &mut file).unwrap(), [["23"]]);
+
+    // A write through the handle itself leaves it up to date.
+    write(&mut db, "INSERT INTO notes VALUES ('hello')");
+    assert!(!db.refresh(&mut file).unwrap());
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn pages_past_4gib_are_read_from_a_sparse_file() {
+    let path = temp_copy(TEST_DB_INTERIOR, "sparse");
+    let data = std::fs::read(TEST_DB_INTERIOR).unwrap();
+    let page_size = u16::from_be_bytes([data[16], data[17]]) as usize;
+    // A copy of the leaf page 4, placed where its offset only fits in 64 bits. The file is
+    // sparse, so only that page takes up space.
+    let far_page = (1usize << 32) / page_size + 4;
+    let mut file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+    file.seek(std::io::SeekFrom::Start((far_page as u64 - 1) * page_size as u64)).unwrap();
+    file.write_all(&data[3 * page_size..4 * page_size]).unwrap();
+    drop(file);
+
+    let (db, mut file) = open_fixture(path.to_str().unwrap());
+    let far_records = db.get_full_table(&mut file, far_page, &["username"], &[1]);
+    let records = db.get_full_table(&mut file, 4, &["username"], &[1]).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(format!("{:?}", far_records.unwrap()), format!("{:?}", records));
+    assert!(!records.is_empty());
+  }
+
+  #[test]
+  fn wal_mode_databases_with_a_wal_file_warn_or_fail() {
+    let path = temp_copy(TEST_DB_INTERIOR, "wal");
+    let mut data = std::fs::read(&path).unwrap();
+    data[18..20].copy_from_slice(&[2, 2]);
+    std::fs::write(&path, data).unwrap();
+    let wal_path = journal::wal_path(&path);
+
+    // Without a -wal file, or with an empty one, everything is in the main file.
+    let db = Database::open_with(&path, WalPolicy::Fail).unwrap();
+    assert_eq!(db.header.journal_mode(), "wal");
+    std::fs::write(&wal_path, b"").unwrap();
+    assert!(Database::open_with(&path, WalPolicy::Fail).is_ok());
+
+    std::fs::write(&wal_path, [0x37, 0x7f, 0x06, 0x82]).unwrap();
+    let strict = Database::open_with(&path, WalPolicy::Fail);
+    let lenient = Database::open_with(&path, WalPolicy::Warn);
+    std::fs::remove_file(&wal_path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert!(matches!(strict, Err(Error::WalNotSupported(p)) if p == wal_path.display().to_string()));
+    assert_eq!(lenient.unwrap().schema.table_names(), ["users"]);
+    assert_eq!(Database::parse_header_and_schema(std::fs::File::open(TEST_DB_INTERIOR).unwrap()).unwrap().header.journal_mode(), "rollback");
+  }
+
+  #[test]
+  fn rowid_is_selectable_with_or_without_an_alias() {
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_INTERIOR).unwrap());
+    let mut db = Database::parse_header_and_schema(&mut file).unwrap();
+    create_table(&mut db, &mut file, "CREATE TABLE tags (name TEXT)").unwrap();
+    create_table(&mut db, &mut file, "CREATE TABLE odd (rowid TEXT, oid TEXT)").unwrap();
+    insert(&db, &mut file, "INSERT INTO tags VALUES ('a'), ('b')").unwrap();
+    insert(&db, &mut file, "INSERT INTO odd VALUES ('x', 'y')").unwrap();
+
+    let query = Query::parse("SELECT rowid, name, _rowid_, OID FROM tags").unwrap();
+    assert_eq!(
+      query.execute(&db, &mut file).unwrap(),
+      vec![vec!["1", "a", "1", "1"], vec!["2", "b", "2", "2"]]
+    );
+    assert_eq!(&*query.query_rows(&db, &mut file).unwrap().column_names, ["rowid", "name", "_rowid_", "OID"]);
+
+    // The table's own columns hide the rowid, leaving only the third name for it.
+    let query = Query::parse("SELECT rowid, oid, _rowid_ FROM odd").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["x", "y", "1"]]);
+
+    // With an INTEGER PRIMARY KEY, the rowid is that column, and is named after it.
+    let query = Query::parse("SELECT rowid, username FROM users WHERE email = 'bob@example.com'").unwrap();
+    let result_set = query.query_rows(&db, &mut file).unwrap();
+    assert_eq!(&*result_set.column_names, ["id", "username"]);
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["2", "Bob"]]);
+    let query = Query::parse("SELECT rowid FROM users WHERE email = 'bob@example.com'").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["2"]]);
+  }
+
+  #[test]
+  fn only_the_integer_primary_key_reads_the_rowid() {
+    let (db, mut file) = open_fixture(TEST_DB_ROWID_ALIAS);
+
+    // The expected values are those sqlite3 returns. A column named id is just a column, while
+    // a key named otherwise, or declared as a table constraint, is the rowid.
+    for (sql, expected) in [
+      ("SELECT id, body FROM notes", vec![vec!["null", "untitled"], vec!["n1", "first"], vec!["null", "draft"]]),
+      ("SELECT body FROM notes WHERE id = 1", vec![]),
+      ("SELECT user_id, name FROM accounts", vec![vec!["7", "ann"], vec!["12", "bo"], vec!["40", "cy"]]),
+      ("SELECT name FROM accounts WHERE user_id > 10", vec![vec!["bo"], vec!["cy"]]),
+      ("SELECT k, v FROM keyed WHERE k = 200", vec![vec!["200", "y"]]),
+    ] {
+      let query = Query::parse(sql).unwrap();
+      assert_eq!(query.execute(&db, &mut file).unwrap(), expected, "{}", sql);
+    }
+    assert_eq!(
+      db.explain("SELECT name FROM accounts WHERE user_id = 12").unwrap().to_string(),
+      "SEARCH accounts USING INTEGER PRIMARY KEY (rowid=?)"
+    );
+    assert_eq!(db.explain("SELECT body FROM notes WHERE id = 1").unwrap().to_string(), "SCAN notes");
+  }
+
+  #[test]
+  fn empty_tables_have_no_rows() {
+    // The table empty has only its root leaf, with no cells, as has its index.
+    let data = std::fs::read(TEST_DB_EMPTY).unwrap();
+    let assert_empty = |data: Vec<u8>| {
+      let mut file = std::io::Cursor::new(data);
+      let db = Database::parse_header_and_schema(&mut file).unwrap();
+      assert_eq!(db.schema.table_names(), vec!["empty", "notes"]);
+      for (sql, expected) in [
+        ("SELECT * FROM empty", vec![]),
+        ("SELECT COUNT(*) FROM empty", vec![vec!["0"]]),
+        ("SELECT COUNT(*) FROM empty WHERE name = 'a'", vec![vec!["0"]]),
+        ("SELECT COUNT(*) FROM empty WHERE id > 0", vec![vec!["0"]]),
+        ("SELECT name FROM empty WHERE name = 'a'", vec![]),
+        ("SELECT * FROM empty WHERE id = 1", vec![]),
+        ("SELECT name FROM empty ORDER BY name", vec![]),
+        ("SELECT max(id) FROM empty", vec![vec!["null"]]),
+        ("SELECT min(name) FROM empty", vec![vec!["null"]]),
+        ("SELECT * FROM notes", vec![vec!["kept"]]),
+      ] {
+        let query = Query::parse(sql).unwrap();
+        assert_eq!(query.execute(&db, &mut file).unwrap(), expected, "{}", sql);
+      }
+      assert_eq!(db.check_integrity(&mut file).unwrap(), Vec::<String>::new());
+    };
+    assert_empty(data.clone());
+
+    // Deletes may leave an interior root with no cells, whose only child is an empty leaf: here
+    // the root of empty, page 2, moved to a new page 5 below it.
+    let page_size = 4096;
+    let mut data = data;
+    let leaf = data[page_size..2 * page_size].to_vec();
+    data.extend_from_slice(&leaf);
+    data[page_size..page_size + 12].copy_from_slice(&[0x05, 0, 0, 0, 0, 0x10, 0, 0, 0, 0, 0, 5]);
+    data[28..32].copy_from_slice(&5u32.to_be_bytes());
+    assert_empty(data);
+  }
+
+  #[test]
+  fn integer_primary_key_desc_is_stored_in_the_record() {
+    // events (id INTEGER PRIMARY KEY DESC, name TEXT), with ids 100, 50, 7 and -3 as rows 1 to 4.
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_PK_DESC).unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    assert_eq!(db.schema.table("events").unwrap().row_id_column, None);
+    let index = db.schema.indexes("events").next().unwrap();
+    assert_eq!((index.name.as_str(), index.sql.as_str()), ("sqlite_autoindex_events_1", ""));
+    assert_eq!((index.column_name.as_str(), index.descending, index.unique), ("id", true, true));
+
+    let query = Query::parse("SELECT rowid, id, name FROM events").unwrap();
+    assert_eq!(
+      query.execute(&db, &mut file).unwrap(),
+      vec![vec!["1", "100", "launch"], vec!["2", "50", "review"], vec!["3", "7", "draft"], vec!["4", "-3", "idea"]]
+    );
+    let query = Query::parse("SELECT rowid, name FROM events WHERE id = 7").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["3", "draft"]]);
+    assert_eq!(
+      db.explain("SELECT name FROM events WHERE id = 7").unwrap().to_string(),
+      "SEARCH events USING INDEX sqlite_autoindex_events_1"
+    );
+
+    // A new row gets the next rowid whatever its id, and its key goes in the index.
+    assert_eq!(insert(&db, &mut file, "INSERT INTO events (id, name) VALUES (60, 'ship')").unwrap(), 1);
+    let query = Query::parse("SELECT rowid, name FROM events WHERE id = 60").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["5", "ship"]]);
+    assert_eq!(db.check_integrity(&mut file).unwrap(), Vec::<String>::new());
+  }
+
+  #[test]
+  fn coalesce_replaces_nulls_in_select_list_and_where() {
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_INTERIOR).unwrap());
+    let mut db = Database::parse_header_and_schema(&mut file).unwrap();
+    create_table(&mut db, &mut file, "CREATE TABLE people (name TEXT, nickname TEXT, city TEXT)").unwrap();
+    insert(
+      &db,
+      &mut file,
+      "INSERT INTO people VALUES ('Ann', NULL, NULL), ('Bob', 'Bobby', NULL), ('Cy', NULL, 'Oslo')",
+    )
+    .unwrap();
+
+    let query = Query::parse("SELECT name, IFNULL(nickname, 'none'), coalesce(nickname, city, name) FROM people").unwrap();
+    assert_eq!(
+      query.execute(&db, &mut file).unwrap(),
+      vec![vec!["Ann", "none", "Ann"], vec!["Bob", "Bobby", "Bobby"], vec!["Cy", "none", "Oslo"]]
+    );
+    let query = Query::parse("SELECT name FROM people WHERE coalesce(nickname, city, '') = ''").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["Ann"]]);
+    let query = Query::parse("SELECT count(*) FROM people WHERE ifnull(city, 'x') != 'Oslo'").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["2"]]);
+  }
+
+  #[test]
+  fn where_conditions_with_nulls_use_three_valued_logic() {
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_INTERIOR).unwrap());
+    let mut db = Database::parse_header_and_schema(&mut file).unwrap();
+    create_table(&mut db, &mut file, "CREATE TABLE people (name TEXT, age INTEGER, city TEXT)").unwrap();
+    insert(
+      &db,
+      &mut file,
+      "INSERT INTO people VALUES ('Ann', 40, NULL), ('Bob', 25, 'Oslo'), ('Cy', 35, 'Oslo'), ('Di', NULL, 'Rome')",
+    )
+    .unwrap();
+
+    // The expected names are those sqlite3 returns.
+    for (condition, expected) in [
+      ("NOT (age > 30 AND city = 'Oslo')", "Bob,Di"),
+      ("NOT city = 'Oslo'", "Di"),
+      ("city = 'Rome' OR age > 30 AND city = 'Oslo'", "Cy,Di"),
+      ("(city = 'Rome' OR age > 30) AND city = 'Oslo'", "Cy"),
+      ("NOT (age < 30 OR city = 'Oslo')", ""),
+      ("age > 30 OR city = 'Rome'", "Ann,Cy,Di"),
+    ] {
+      let query = Query::parse(&format!("SELECT name FROM people WHERE {}", condition)).unwrap();
+      let names = query.execute(&db, &mut file).unwrap().concat().join(",");
+      assert_eq!(names, expected, "{}", condition);
+    }
+  }
+
+  #[test]
+  fn numbers_take_a_text_columns_affinity() {
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_INTERIOR).unwrap());
+    let mut db = Database::parse_header_and_schema(&mut file).unwrap();
+    insert(&db, &mut file, "INSERT INTO users (username, age, email) VALUES ('Num', 1, '42')").unwrap();
+    create_table(&mut db, &mut file, "CREATE TABLE codes (code VARCHAR(8) NOT NULL, price REAL)").unwrap();
+    insert(&db, &mut file, "INSERT INTO codes VALUES ('5', 1.5), ('05', 2.5)").unwrap();
+
+    // The email probe goes through its index.
+    assert_eq!(
+      db.explain("SELECT username FROM users WHERE email = 42").unwrap().to_string(),
+      "SEARCH users USING INDEX idx_users_email"
+    );
+    for (sql, expected) in [
+      ("SELECT username FROM users WHERE email = 42", vec![vec!["Num"]]),
+      ("SELECT code FROM codes WHERE code = 5", vec![vec!["5"]]),
+      ("SELECT code FROM codes WHERE code = 5.0", vec![]),
+      ("SELECT code FROM codes WHERE price = '2.5'", vec![vec!["05"]]),
+    ] {
+      let query = Query::parse(sql).unwrap();
+      assert_eq!(query.execute(&db, &mut file).unwrap(), expected, "{}", sql);
+    }
+  }
+
+  #[test]
+  fn integral_reals_stored_as_integers_read_as_reals() {
+    let (db, mut file) = open_fixture(TEST_DB_REALS);
+    for (sql, expected) in [
+      ("SELECT value FROM readings", vec![Some(7.0), Some(2.5), Some(-3.0), None, Some(12.0)]),
+      ("SELECT value FROM readings WHERE value = 7", vec![Some(7.0)]),
+      ("SELECT value FROM readings WHERE value < 0", vec![Some(-3.0)]),
+      ("SELECT value FROM readings WHERE label = 'twelve'", vec![Some(12.0)]),
+      ("SELECT max(value) FROM readings", vec![Some(12.0)]),
+      ("SELECT value FROM readings ORDER BY value DESC LIMIT 2", vec![Some(12.0), Some(7.0)]),
+    ] {
+      let result_set = Query::parse(sql).unwrap().query_rows(&db, &mut file).unwrap();
+      let values = result_set.rows.iter().map(|row| row.values()[0].clone()).collect::<Vec<_>>();
+      assert!(values.iter().all(|value| matches!(value, Value::Real(_) | Value::Null)), "{}: {:?}", sql, values);
+      assert_eq!(values.iter().map(Value::as_real).collect::<Vec<_>>(), expected, "{}", sql);
+    }
+  }
+
+  #[test]
+  fn values_of_different_types_follow_sqlite_order() {
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_INTERIOR).unwrap());
+    let mut db = Database::parse_header_and_schema(&mut file).unwrap();
+    create_table(&mut db, &mut file, "CREATE TABLE mixed (v)").unwrap();
+    insert(
+      &db,
+      &mut file,
+      "INSERT INTO mixed VALUES ('b'), (2), (NULL), (1.5), ('10'), (-3), (2.0), ('a')",
+    )
+    .unwrap();
+
+    // The expected values are those sqlite3 returns: NULL, then numbers, then text.
+    for (sql, expected) in [
+      ("SELECT v FROM mixed ORDER BY v", "null,-3,1.5,2,2.0,10,a,b"),
+      (
+        "SELECT typeof(v) FROM mixed ORDER BY v",
+        "null,integer,real,integer,real,text,text,text",
+      ),
+      ("SELECT v FROM mixed ORDER BY v DESC LIMIT 3", "b,a,10"),
+      ("SELECT v FROM mixed WHERE v > 1.5", "b,2,10,2.0,a"),
+      ("SELECT typeof(v) FROM mixed WHERE v = 2", "integer,real"),
+      ("SELECT MAX(v) FROM mixed", "b"),
+      ("SELECT MIN(v) FROM mixed", "-3"),
+    ] {
+      let query = Query::parse(sql).unwrap();
+      assert_eq!(query.execute(&db, &mut file).unwrap().concat().join(","), expected, "{}", sql);
+    }
+  }
+
+  #[test]
+  fn min_and_max_ignore_nulls() {
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_INTERIOR).unwrap());
+    let mut db = Database::parse_header_and_schema(&mut file).unwrap();
+    insert(&db, &mut file, "INSERT INTO users (username, age) VALUES ('Nobody', NULL), ('Noone', NULL)").unwrap();
+    create_table(&mut db, &mut file, "CREATE TABLE empty (name TEXT)").unwrap();
+
+    // NULL emails come first in the index, so the minimum is found past them.
+    for (sql, expected) in [
+      ("SELECT MIN(email) FROM users", "alice@example.com"),
+      ("SELECT MAX(email) FROM users", "dave@example.com"),
+      ("SELECT MAX(id) FROM users", "24"),
+      ("SELECT MIN(age) FROM users", "15"),
+      ("SELECT MIN(rowid) FROM empty", "null"),
+      ("SELECT MAX(name) FROM empty", "null"),
+    ] {
+      let query = Query::parse(sql).unwrap();
+      assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec![expected]], "{}", sql);
+    }
+  }
+
+  #[test]
+  fn collated_columns_compare_text_with_their_collation() {
+    let mut file = std::io::Cursor::new(std::fs::read(TEST_DB_NOCASE).unwrap());
+    let mut db = Database::parse_header_and_schema(&mut file).unwrap();
+    insert(&db, &mut file, "INSERT INTO people (name, code, tag) VALUES ('aLiCe_0', 'Y00', 't9  ')").unwrap();
+    assert!(matches!(
+      create_table(&mut db, &mut file, "CREATE TABLE words (word TEXT COLLATE FRENCH)"),
+      Err(Error::UnsupportedSql(message)) if message == "no such collation sequence: french"
+    ));
+
+    // Only the index on name compares as its column does, so code is scanned.
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    assert_eq!(
+      db.explain("SELECT id FROM people WHERE name = 'ALICE_0'").unwrap().to_string(),
+      "SEARCH people USING INDEX idx_people_name"
+    );
+    assert_eq!(
+      db.explain("SELECT id FROM people WHERE code = 'X01'").unwrap().to_string(),
+      "SCAN people"
+    );
+    // The expected values are those sqlite3 returns.
+    for (sql, expected) in [
+      ("SELECT id FROM people WHERE name = 'ALICE_0' ORDER BY id", "1,61"),
+      ("SELECT name FROM people WHERE name = 'alice_0' ORDER BY id", "Alice_0,aLiCe_0"),
+      ("SELECT id FROM people WHERE code = 'X00'", ""),
+      ("SELECT id FROM people WHERE tag = 't9'", "61"),
+      ("SELECT name FROM people WHERE name > 'zoe' ORDER BY name", "zoe_19,ZOE_39,ZOE_59"),
+      ("SELECT name FROM people ORDER BY name DESC LIMIT 2", "ZOE_59,ZOE_39"),
+      ("SELECT code FROM people ORDER BY code LIMIT 2", "X01,X03"),
+      ("SELECT MIN(name) FROM people", "Alice_0"),
+      ("SELECT MAX(name) FROM people", "ZOE_59"),
+    ] {
+      let query = Query::parse(sql).unwrap();
+      assert_eq!(query.execute(&db, &mut file).unwrap().concat().join(","), expected, "{}", sql);
+    }
+
+    if let Some(output) = sqlite3_output(file.get_ref(), "PRAGMA integrity_check;") {
+      assert_eq!(output, "ok\n");
+    }
+  }
+
+  #[test]
+  fn index_probes_compare_with_the_index_collation() {
+    let (db, mut file) = open_fixture(TEST_DB_NOCASE);
+    assert_eq!(
+      db.explain("SELECT id FROM people WHERE tag = 't1   '").unwrap().to_string(),
+      "SEARCH people USING INDEX idx_people_tag"
+    );
+    // The expected values are those sqlite3 returns. Trailing spaces don't count in the RTRIM
+    // index on tag, but leading ones do.
+    for (sql, expected) in [
+      ("SELECT id FROM people WHERE tag = 't1   ' AND id < 12 ORDER BY id", "2,5,8,11"),
+      ("SELECT count(*) FROM people WHERE tag = 't2'", "20"),
+      ("SELECT count(*) FROM people WHERE tag = ' t2'", "0"),
+      ("SELECT count(*) FROM people WHERE name = 'bOb_1'", "1"),
+    ] {
+      let query = Query::parse(sql).unwrap();
+      assert_eq!(query.execute(&db, &mut file).unwrap().concat().join(","), expected, "{}", sql);
+    }
+
+    // The NOCASE of the index on code comes from CREATE INDEX, not the column, and is what a
+    // probe of the index itself compares with.
+    let index = |name: &str| db.schema.objects.iter().filter_map(|o| o.as_index()).find(|i| i.name == name).unwrap();
+    assert_eq!(index("idx_people_code").collation, crate::record::Collation::NoCase);
+    assert_eq!(index("idx_people_tag").collation, crate::record::Collation::RTrim);
+    let row_ids = db.search_index(&mut file, index("idx_people_code").root_page, Value::Text("x01".to_string())).unwrap();
+    assert_eq!(row_ids, vec![2]);
+  }
+
+  #[test]
+  fn interrupted_write_is_rolled_back_on_open() {
+    let path = temp_copy(TEST_DB_INTERIOR, "interrupted");
+    let journal_path = journal::journal_path(&path);
+    let original = std::fs::read(&path).unwrap();
+
+    let db = Database::open(&path).unwrap();
+    let mut file = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+    db.begin().unwrap();
+    let rows = (0..50)
+      .map(|i| format!("('user{}', {}, 'user{}@example.com')", i, i, i))
+      .collect::<Vec<_>>()
+      .join(", ");
+    let sql = format!("INSERT INTO users (username, age, email) VALUES {}", rows);
+    match Query::parse(&sql).unwrap() {
+      Query::Insert(insert) => assert_eq!(db.insert(&mut file, &insert).unwrap(), 50),
+      query => panic!("not an insert: {:?}", query),
+    }
+
+    // Crash after the journal is written and part of the pages, including new ones, are.
+    let pages = db.batch.lock().unwrap().take().unwrap();
+    let page_size = db.header.page_size;
+    let database_size = original.len() / page_size;
+    let originals =
+      journal::original_pages(&mut file, page_size, database_size, pages.keys().copied()).unwrap();
+    journal::write_journal(&journal_path, page_size, database_size, &originals).unwrap();
+    for (page_index, data) in pages.iter().skip(pages.len() / 2) {
+      file.seek(std::io::SeekFrom::Start((page_size * (page_index - 1)) as u64)).unwrap();
+      file.write_all(data).unwrap();
+    }
+    drop(file);
+    assert!(std::fs::read(&path).unwrap() != original);
+
+    // sqlite3 rolls the journal back too.
+    let copy = temp_copy(path.to_str().unwrap(), "interrupted-copy");
+    std::fs::copy(&journal_path, journal::journal_path(&copy)).unwrap();
+    let output = std::process::Command::new("sqlite3")
+      .arg(&copy)
+      .arg("PRAGMA integrity_check; SELECT count(*) FROM users;")
+      .output();
+    if let Ok(output) = output {
+      assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n22\n");
+    }
+    std::fs::remove_file(&copy).unwrap();
+    let _ = std::fs::remove_file(journal::journal_path(&copy));
+
+    let db = Database::open(&path).unwrap();
+    assert!(!journal_path.exists());
+    assert!(std::fs::read(&path).unwrap() == original);
+    let mut file = std::fs::File::open(&path).unwrap();
+    let rows = Query::parse("SELECT COUNT(*) FROM users").unwrap().execute(&db, &mut file).unwrap();
+    assert_eq!(rows, vec![vec!["22"]]);
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn transaction_writes_are_committed_together() {
+    let path = temp_copy(TEST_DB_INTERIOR, "transaction");
+    let original = std::fs::read(&path).unwrap();
+    let mut db = Database::open(&path).unwrap();
+    let mut file = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+    let execute = |db: &mut Database, file: &mut std::fs::File, sql: &str| match Query::parse(sql).unwrap() {
+      Query::Create(create) => db.create_table(file, &create).map(|_| 0),
+      Query::Insert(insert) => db.insert(file, &insert),
+      Query::Delete(delete) => db.delete(file, &delete),
+      query => query.execute(db, file).map(|rows| rows.len()),
+    };
+
+    // Rolled back writes are seen by reads until then, but never reach the file.
+    db.begin().unwrap();
+    assert!(matches!(db.begin(), Err(Error::TransactionState(_))));
+    execute(&mut db, &mut file, "DELETE FROM users WHERE age = 105").unwrap();
+    execute(&mut db, &mut file, "CREATE TABLE notes (id integer primary key, body text)").unwrap();
+    assert_eq!(execute(&mut db, &mut file, "SELECT id FROM users").unwrap(), 4);
+    db.rollback(&mut file).unwrap();
+    assert!(std::fs::read(&path).unwrap() == original);
+    assert_eq!(execute(&mut db, &mut file, "SELECT id FROM users").unwrap(), 22);
+    assert_eq!(db.schema.table_names(), ["users"]);
+    assert!(matches!(db.commit(&mut file), Err(Error::TransactionState(_))));
+
+    db.begin().unwrap();
+    execute(&mut db, &mut file, "CREATE TABLE notes (id integer primary key, body text)").unwrap();
+    execute(&mut db, &mut file, "INSERT INTO notes (body) VALUES ('a'), ('b')").unwrap();
+    execute(&mut db, &mut file, "DELETE FROM users WHERE age = 105").unwrap();
+    assert!(std::fs::read(&path).unwrap() == original);
+    db.commit(&mut file).unwrap();
+    assert!(!journal::journal_path(&path).exists());
+
+    let mut db = Database::open(&path).unwrap();
+    assert_eq!(execute(&mut db, &mut file, "SELECT id FROM users").unwrap(), 4);
+    assert_eq!(execute(&mut db, &mut file, "SELECT body FROM notes").unwrap(), 2);
+    if let Some(output) = sqlite3_output(
+      &std::fs::read(&path).unwrap(),
+      "PRAGMA integrity_check; SELECT count(*) FROM notes;",
+    ) {
+      assert_eq!(output, "ok\n2\n");
+    }
+    std::fs::remove_file(&path).unwrap();
+  }
+
+  #[test]
+  fn created_database_is_empty_and_writable() {
+    for page_size in [512, 65536] {
+      let path = std::env::temp_dir().join(format!("sqlite-rust-{}-create-{}.db", std::process::id(), page_size));
+      let _ = std::fs::remove_file(&path);
+      let mut db = Database::create(&path, page_size).unwrap();
+      assert!(matches!(Database::create(&path, page_size), Err(Error::Io(_))));
+      assert_eq!(db.header.page_size, page_size);
+      assert_eq!(db.header.size_in_pages, 1);
+      assert_eq!(db.header.schema_format, 4);
+      assert_eq!(db.schema.table_count(), 0);
+      if let Some(output) = sqlite3_output(
+        &std::fs::read(&path).unwrap(),
+        "PRAGMA integrity_check; PRAGMA page_count; SELECT count(*) FROM sqlite_master;",
+      ) {
+        assert_eq!(output, "ok\n1\n0\n");
+      }
+
+      let mut file = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+      match Query::parse("CREATE TABLE notes (id integer primary key, body text)").unwrap() {
+        Query::Create(create) => db.create_table(&mut file, &create).unwrap(),
+        query => panic!("not a create: {:?}", query),
+      }
+      match Query::parse("INSERT INTO notes (body) VALUES ('a')").unwrap() {
+        Query::Insert(insert) => assert_eq!(db.insert(&mut file, &insert).unwrap(), 1),
+        query => panic!("not an insert: {:?}", query),
+      }
+
+      if let Some(output) = sqlite3_output(
+        &std::fs::read(&path).unwrap(),
+        "PRAGMA integrity_check; PRAGMA page_size; PRAGMA encoding; SELECT * FROM notes;",
+      ) {
+        assert_eq!(output, format!("ok\n{}\nUTF-8\n1|a\n", page_size));
+      }
+      std::fs::remove_file(&path).unwrap();
+    }
+    assert!(matches!(
+      Database::create(std::env::temp_dir().join("unused.db"), 1000),
+      Err(Error::UnsupportedSql(_))
+    ));
+  }
+
+  #[test]
+  fn descending_indexes_follow_the_schema_format() {
+    let mut file = std::io::Cursor::new(std::fs::read("tests/fixtures/desc_index.db").unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    assert_eq!(db.schema_format(), 4);
+    let run = |file: &mut std::io::Cursor<Vec<u8>>, sql: &str| Query::parse(sql).unwrap().execute(&db, file);
+    // Found by walking the table, for comparing with what the DESC indexes find.
+    let rows = run(&mut file, "SELECT id, score FROM scores").unwrap();
+    let ids_scoring = |score: &str| {
+      rows.iter().filter(|row| row[1] == score).map(|row| vec![row[0].clone()]).collect::<Vec<_>>()
+    };
+    assert_eq!(run(&mut file, "SELECT id FROM scores WHERE score = 37").unwrap(), ids_scoring("37"));
+    assert_eq!(run(&mut file, "SELECT min(score) FROM scores").unwrap(), [["0"]]);
+    assert_eq!(run(&mut file, "SELECT max(score) FROM scores").unwrap(), [["250"]]);
+    let scores = run(&mut file, "SELECT score FROM scores ORDER BY score").unwrap();
+    let score = |row: &Vec<String>| row[0].parse::<i64>().ok();
+    assert!(scores.windows(2).all(|w| score(&w[0]) <= score(&w[1])));
+    let names = run(&mut file, "SELECT id, name FROM scores WHERE name = 'PLAYER_3'").unwrap();
+    assert_eq!(
+      names,
+      [["3", "player_3"], ["181", "Player_3"], ["294", "player_3"], ["448", "Player_3"], ["585", "player_3"]]
+    );
+
+    // Entries are inserted and deleted in the index's order.
+    insert(&db, &mut file, "INSERT INTO scores (name, score) VALUES ('new', 37), ('newer', 300)").unwrap();
+    match Query::parse("DELETE FROM scores WHERE score = 120").unwrap() {
+      Query::Delete(delete) => db.delete(&mut file, &delete).unwrap(),
+      query => panic!("not a delete: {:?}", query),
+    };
+    assert_eq!(run(&mut file, "SELECT max(score) FROM scores").unwrap(), [["300"]]);
+    let count = run(&mut file, "SELECT count(*) FROM scores WHERE score = 37").unwrap();
+    assert_eq!(count, [[(ids_scoring("37").len() + 1).to_string()]]);
+    if let Some(output) = sqlite3_output(file.get_ref(), "PRAGMA integrity_check;") {
+      assert_eq!(output, "ok\n");
+    }
+
+    // Formats before 4 have no DESC indexes, so one sorted that way is corrupt. The file's
+    // other indexes are still used.
+    let mut file = std::io::Cursor::new(std::fs::read("tests/fixtures/legacy_format.db").unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    assert_eq!(db.schema_format(), 1);
+    let run = |file: &mut std::io::Cursor<Vec<u8>>, sql: &str| Query::parse(sql).unwrap().execute(&db, file);
+    assert_eq!(run(&mut file, "SELECT b FROM t WHERE a = 15").unwrap(), [["17"]]);
+    assert!(matches!(run(&mut file, "SELECT a FROM t WHERE b = 17"), Err(Error::Corrupt { .. })));
+    assert!(matches!(run(&mut file, "SELECT max(b) FROM t"), Err(Error::Corrupt { .. })));
+    assert!(matches!(insert(&db, &mut file, "INSERT INTO t VALUES (5, 5)"), Err(Error::Corrupt { .. })));
   }
 }
@@ -0,0 +1,309 @@
+//! Comparing two databases, for checking what a migration did: which tables, indexes and views
+//! were added, removed or changed, and for each table in both with the same columns, which rows
+//! were. Rows are matched on their rowid, reading both tables side by side in rowid order, so
+//! neither is held in memory. Tables without a rowid are matched on their primary key instead,
+//! each read whole in key order first.
+
+use std::{cmp::Ordering, collections::BTreeMap, io::Write};
+
+use crate::{
+  btree::BtreeVisitor,
+  database::{compare_keys, Database, ObjectSchema, TableSchema},
+  error::{Error, Result},
+  expr::to_sql_literal,
+  query::Query,
+  record::{Record, Value},
+};
+
+/// The kind and SQL of each table, index and view, by name.
+fn schema_objects(db: &Database) -> BTreeMap<(&'static str, String), &str> {
+  db.schema
+    .objects
+    .iter()
+    .filter_map(|object| match object {
+      ObjectSchema::Table(table) => Some((("table", table.name.to_ascii_lowercase()), table.sql.as_str())),
+      ObjectSchema::Index(index) => Some((("index", index.name.to_ascii_lowercase()), index.sql.as_str())),
+      ObjectSchema::View(view) => Some((("view", view.name.to_ascii_lowercase()), view.sql.as_str())),
+      ObjectSchema::Trigger(_) => None,
+    })
+    .collect()
+}
+
+/// Two values are the same if they're of the same storage class and equal, NULL being the same
+/// as NULL.
+fn same_value(a: &Value, b: &Value) -> bool {
+  a.type_name() == b.type_name() && a.sort_key() == b.sort_key()
+}
+
+/// The changes to each of `column_names` between two versions of a row, as `column old -> new`.
+fn changed_columns(column_names: &[&str], record_a: &Record, record_b: &Record) -> Vec<String> {
+  column_names
+    .iter()
+    .zip(record_a.values.iter().zip(record_b.values.iter()))
+    .filter(|(_, (value_a, value_b))| !same_value(value_a, value_b))
+    .map(|(name, (value_a, value_b))| format!("{} {} -> {}", name, to_sql_literal(value_a), to_sql_literal(value_b)))
+    .collect()
+}
+
+/// The values of a row of the stored `columns` of `table` as written in SQL, with the rowid in
+/// place of a rowid alias column.
+fn row_literal(table: &TableSchema, columns: &[usize], record: &Record) -> String {
+  let values = columns.iter().zip(record.values.iter()).map(|(i, value)| match record.row_id {
+    Some(row_id) if table.row_id_column == Some(*i) => row_id.to_string(),
+    _ => to_sql_literal(value),
+  });
+  format!("({})", values.collect::<Vec<_>>().join(", "))
+}
+
+/// Write the differences between database `a` and database `b` to `out`, one per line, returning
+/// how many there were.
+pub fn diff<R, S, W>(a: &Database, mut file_a: R, b: &Database, mut file_b: S, mut out: W) -> Result<usize>
+  where
+    R: std::io::Read + std::io::Seek,
+    S: std::io::Read + std::io::Seek,
+    W: Write,
+{
+  let mut differences = 0;
+  let objects_a = schema_objects(a);
+  let objects_b = schema_objects(b);
+  for ((kind, name), sql) in objects_a.iter() {
+    match objects_b.get(&(kind, name.clone())) {
+      None => writeln!(out, "- {} {}", kind, name)?,
+      Some(sql_b) if sql_b != sql => writeln!(out, "~ {} {}: {} -> {}", kind, name, sql, sql_b)?,
+      Some(_) => continue,
+    }
+    differences += 1;
+  }
+  for (kind, name) in objects_b.keys().filter(|key| !objects_a.contains_key(key)) {
+    writeln!(out, "+ {} {}", kind, name)?;
+    differences += 1;
+  }
+
+  for table_a in a.schema.objects.iter().filter_map(ObjectSchema::as_table) {
+    let Ok(table_b) = b.schema.table(&table_a.name) else {
+      continue;
+    };
+    if table_a.column_names != table_b.column_names || table_a.stored_columns().positions != table_b.stored_columns().positions {
+      writeln!(out, "{}: columns differ, rows not compared", table_a.name)?;
+      differences += 1;
+      continue;
+    }
+    // Tables without a rowid are only matched up if their keys are the same.
+    if (table_a.without_rowid || table_b.without_rowid) && table_a.sql != table_b.sql {
+      writeln!(out, "{}: WITHOUT ROWID, rows not compared", table_a.name)?;
+      differences += 1;
+      continue;
+    }
+    differences += match table_a.without_rowid {
+      true => diff_keyed_rows(a, &mut file_a, table_a, b, &mut file_b, table_b, &mut out)?,
+      false => diff_rows(a, &mut file_a, table_a, b, &mut file_b, table_b, &mut out)?,
+    };
+  }
+  Ok(differences)
+}
+
+/// Write the rows added, removed and changed between `table_a` of `a` and `table_b` of `b`,
+/// which have the same columns, returning how many there were.
+fn diff_rows<R, S, W>(
+  a: &Database,
+  file_a: R,
+  table_a: &TableSchema,
+  b: &Database,
+  file_b: S,
+  table_b: &TableSchema,
+  mut out: W,
+) -> Result<usize>
+  where
+    R: std::io::Read + std::io::Seek,
+    S: std::io::Read + std::io::Seek,
+    W: Write,
+{
+  let (stored_a, stored_b) = (table_a.stored_columns(), table_b.stored_columns());
+  let (columns, column_names) = (&stored_a.positions, &stored_a.names);
+  let mut rows_a = a
+    .scan_table(file_a, table_a.root_page, &stored_a.names, &stored_a.record_indices)
+    .with_defaults(&stored_a.defaults);
+  let mut rows_b = b
+    .scan_table(file_b, table_b.root_page, &stored_b.names, &stored_b.record_indices)
+    .with_defaults(&stored_b.defaults);
+
+  let mut differences = 0;
+  let (mut row_a, mut row_b) = (rows_a.next().transpose()?, rows_b.next().transpose()?);
+  loop {
+    // A table that has run out of rows is behind the other.
+    let order = match (&row_a, &row_b) {
+      (None, None) => break,
+      (Some(_), None) => Ordering::Less,
+      (None, Some(_)) => Ordering::Greater,
+      (Some(record_a), Some(record_b)) => record_a.row_id.cmp(&record_b.row_id),
+    };
+    match order {
+      Ordering::Less => {
+        let record_a = row_a.as_ref().unwrap();
+        writeln!(out, "{}: - row {} {}", table_a.name, record_a.row_id.unwrap(), row_literal(table_a, columns, record_a))?;
+        row_a = rows_a.next().transpose()?;
+        differences += 1;
+      }
+      Ordering::Greater => {
+        let record_b = row_b.as_ref().unwrap();
+        writeln!(out, "{}: + row {} {}", table_a.name, record_b.row_id.unwrap(), row_literal(table_b, columns, record_b))?;
+        row_b = rows_b.next().transpose()?;
+        differences += 1;
+      }
+      Ordering::Equal => {
+        let (record_a, record_b) = (row_a.as_ref().unwrap(), row_b.as_ref().unwrap());
+        let changes = changed_columns(column_names, record_a, record_b);
+        if !changes.is_empty() {
+          writeln!(out, "{}: ~ row {}: {}", table_a.name, record_a.row_id.unwrap(), changes.join(", "))?;
+          differences += 1;
+        }
+        row_a = rows_a.next().transpose()?;
+        row_b = rows_b.next().transpose()?;
+      }
+    }
+  }
+  Ok(differences)
+}
+
+/// The records of a b-tree, in key order.
+#[derive(Default)]
+struct Entries(Vec<Record>);
+
+impl BtreeVisitor for Entries {
+  fn leaf_cell(&mut self, _row_id: Option<i64>, record: &Record) {
+    self.0.push(record.clone());
+  }
+}
+
+/// Write the rows added, removed and changed between `table_a` of `a` and `table_b` of `b`,
+/// tables without a rowid declared the same way, returning how many there were. Their records
+/// hold the primary key first, then the other stored columns, and are matched on the key.
+fn diff_keyed_rows<R, S, W>(
+  a: &Database,
+  file_a: R,
+  table_a: &TableSchema,
+  b: &Database,
+  file_b: S,
+  table_b: &TableSchema,
+  mut out: W,
+) -> Result<usize>
+  where
+    R: std::io::Read + std::io::Seek,
+    S: std::io::Read + std::io::Seek,
+    W: Write,
+{
+  let Query::Create(create) = Query::parse(&table_a.sql)? else {
+    return Err(Error::UnsupportedSql(table_a.sql.clone()));
+  };
+  let stored = table_a.stored_columns();
+  let key = &create.primary_key;
+  let collations = key.iter().map(|i| table_a.column_collations[*i]).collect::<Vec<_>>();
+  // Where each stored column is in the records, and so where it's put back in table order from.
+  let record_order = key
+    .iter()
+    .copied()
+    .chain(stored.positions.iter().copied().filter(|i| !key.contains(i)))
+    .collect::<Vec<_>>();
+  let in_table_order = |record: &Record| Record {
+    row_id: None,
+    values: stored
+      .positions
+      .iter()
+      .zip(stored.defaults.iter())
+      .map(|(i, default)| {
+        let value = record_order.iter().position(|j| j == i).and_then(|j| record.values.get(j));
+        value.unwrap_or(default).clone()
+      })
+      .collect(),
+  };
+  let key_literal = |record: &Record| {
+    let values = record.values.iter().take(key.len()).map(to_sql_literal);
+    format!("({})", values.collect::<Vec<_>>().join(", "))
+  };
+
+  let (mut entries_a, mut entries_b) = (Entries::default(), Entries::default());
+  a.walk_btree(file_a, table_a.root_page, &mut entries_a)?;
+  b.walk_btree(file_b, table_b.root_page, &mut entries_b)?;
+  let (mut rows_a, mut rows_b) = (entries_a.0.into_iter().peekable(), entries_b.0.into_iter().peekable());
+
+  let mut differences = 0;
+  loop {
+    let order = match (rows_a.peek(), rows_b.peek()) {
+      (None, None) => break,
+      (Some(_), None) => Ordering::Less,
+      (None, Some(_)) => Ordering::Greater,
+      (Some(record_a), Some(record_b)) => {
+        let key_of = |record: &Record| record.values.iter().take(key.len()).cloned().collect::<Vec<_>>();
+        compare_keys(&key_of(record_a), &key_of(record_b), &collations, &create.primary_key_descending)
+      }
+    };
+    match order {
+      Ordering::Less => {
+        let record_a = rows_a.next().unwrap();
+        let row = row_literal(table_a, &stored.positions, &in_table_order(&record_a));
+        writeln!(out, "{}: - row {} {}", table_a.name, key_literal(&record_a), row)?;
+        differences += 1;
+      }
+      Ordering::Greater => {
+        let record_b = rows_b.next().unwrap();
+        let row = row_literal(table_b, &stored.positions, &in_table_order(&record_b));
+        writeln!(out, "{}: + row {} {}", table_a.name, key_literal(&record_b), row)?;
+        differences += 1;
+      }
+      Ordering::Equal => {
+        let (record_a, record_b) = (rows_a.next().unwrap(), rows_b.next().unwrap());
+        let changes = changed_columns(&stored.names, &in_table_order(&record_a), &in_table_order(&record_b));
+        if !changes.is_empty() {
+          writeln!(out, "{}: ~ row {}: {}", table_a.name, key_literal(&record_a), changes.join(", "))?;
+          differences += 1;
+        }
+      }
+    }
+  }
+  Ok(differences)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::query::Query;
+
+  #[test]
+  fn schema_and_row_changes_are_listed() {
+    let data = std::fs::read("tests/fixtures/interior.db").unwrap();
+    let a = Database::parse_header_and_schema(std::io::Cursor::new(&data)).unwrap();
+    let mut file_b = std::io::Cursor::new(data.clone());
+    let mut b = Database::parse_header_and_schema(&mut file_b).unwrap();
+    for sql in [
+      "UPDATE users SET age = 30, email = NULL WHERE id = 2",
+      "DELETE FROM users WHERE id = 3",
+      "INSERT INTO users (username, age) VALUES ('Zed', 1.5)",
+      "CREATE TABLE extra (x)",
+    ] {
+      match Query::parse(sql).unwrap() {
+        Query::Update(update) => assert_eq!(b.update(&mut file_b, &update).unwrap(), 1),
+        Query::Delete(delete) => assert_eq!(b.delete(&mut file_b, &delete).unwrap(), 1),
+        Query::Insert(insert) => assert_eq!(b.insert(&mut file_b, &insert).unwrap(), 1),
+        Query::Create(create) => b.create_table(&mut file_b, &create).unwrap(),
+        query => panic!("unexpected query: {:?}", query),
+      }
+    }
+
+    let mut out = Vec::new();
+    let differences = diff(&a, std::io::Cursor::new(&data), &b, &mut file_b, &mut out).unwrap();
+    assert_eq!(
+      String::from_utf8(out).unwrap(),
+      "+ table extra\n\
+       users: ~ row 2: age 45 -> 30, email 'bob@example.com' -> NULL\n\
+       users: - row 3 (3, 'Charlie', 15, 'charlie@example.com')\n\
+       users: + row 23 (23, 'Zed', 1.5, NULL)\n"
+    );
+    assert_eq!(differences, 4);
+
+    // The other way around, and against itself.
+    let mut out = Vec::new();
+    diff(&b, &mut file_b, &a, std::io::Cursor::new(&data), &mut out).unwrap();
+    assert!(String::from_utf8(out).unwrap().starts_with("- table extra\nusers: ~ row 2: age 30 -> 45"));
+    assert_eq!(diff(&a, std::io::Cursor::new(&data), &a, std::io::Cursor::new(&data), std::io::sink()).unwrap(), 0);
+  }
+}
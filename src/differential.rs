@@ -0,0 +1,330 @@
+//! Differential tests, running the same queries through this crate and through SQLite itself (by
+//! way of rusqlite) and comparing the results. Only built with the `differential` feature:
+//!
+//! ```sh
+//! cargo test --features differential differential
+//! ```
+
+use rusqlite::types::ValueRef as SqliteValue;
+
+use crate::{database::Database, query::Query, record::Value};
+
+const FIXTURES: [&str; 23] = [
+  "tests/fixtures/added_columns.db",
+  "tests/fixtures/blobs.db",
+  "tests/fixtures/companies.db",
+  "tests/fixtures/deep.db",
+  "tests/fixtures/desc_index.db",
+  "tests/fixtures/documents.db",
+  "tests/fixtures/duplicates.db",
+  "tests/fixtures/empty.db",
+  "tests/fixtures/generated.db",
+  "tests/fixtures/incremental_vacuum.db",
+  "tests/fixtures/interior.db",
+  "tests/fixtures/leaf.db",
+  "tests/fixtures/mixed_case.db",
+  "tests/fixtures/nocase.db",
+  "tests/fixtures/null_keys.db",
+  "tests/fixtures/pk_desc.db",
+  "tests/fixtures/products.db",
+  "tests/fixtures/reals.db",
+  "tests/fixtures/rowid_alias.db",
+  "tests/fixtures/schema.db",
+  "tests/fixtures/small_pages.db",
+  "tests/fixtures/unique_index.db",
+  "tests/fixtures/views.db",
+];
+
+/// A value written so that values compare equal exactly when SQLite would print them the same:
+/// reals with every digit, and text and blobs quoted, so `1`, `1.0` and `'1'` stay apart.
+fn normalize(value: &Value) -> String {
+  match value {
+    Value::Null => "NULL".to_string(),
+    Value::Integer(n) => n.to_string(),
+    Value::Real(f) => format!("{:?}", f),
+    Value::Text(s) => format!("'{}'", s),
+    Value::Blob(b) => format!("X'{}'", b.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+  }
+}
+
+fn normalize_sqlite(value: SqliteValue) -> String {
+  match value {
+    SqliteValue::Null => "NULL".to_string(),
+    SqliteValue::Integer(n) => n.to_string(),
+    SqliteValue::Real(f) => format!("{:?}", f),
+    SqliteValue::Text(s) => format!("'{}'", String::from_utf8_lossy(s)),
+    SqliteValue::Blob(b) => format!("X'{}'", b.iter().map(|b| format!("{:02x}", b)).collect::<String>()),
+  }
+}
+
+fn our_rows(path: &str, sql: &str) -> Vec<Vec<String>> {
+  let mut file = std::fs::File::open(path).unwrap();
+  let db = Database::parse_header_and_schema(&mut file).unwrap();
+  let result_set = Query::parse(sql)
+    .and_then(|query| query.query_rows(&db, &mut file))
+    .unwrap_or_else(|e| panic!("{}: {}: {}", path, sql, e));
+  result_set
+    .rows
+    .iter()
+    .map(|row| row.values().iter().map(normalize).collect())
+    .collect()
+}
+
+fn sqlite_rows(connection: &rusqlite::Connection, sql: &str) -> Vec<Vec<String>> {
+  let mut statement = connection.prepare(sql).unwrap();
+  let column_count = statement.column_count();
+  statement
+    .query_map([], |row| (0..column_count).map(|i| row.get_ref(i).map(normalize_sqlite)).collect())
+    .unwrap()
+    .collect::<Result<_, _>>()
+    .unwrap()
+}
+
+/// Run each of `queries` on the database at `path` with both this crate and SQLite, failing on
+/// the first whose results differ.
+fn assert_same_results(path: &str, queries: &[String]) {
+  let connection = rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY).unwrap();
+  for sql in queries {
+    let expected = sqlite_rows(&connection, sql);
+    let found = our_rows(path, sql);
+    assert!(
+      found == expected,
+      "{}: {}\n  sqlite: {:?}\n  ours:   {:?}",
+      path,
+      sql,
+      expected.iter().take(5).collect::<Vec<_>>(),
+      found.iter().take(5).collect::<Vec<_>>()
+    );
+  }
+}
+
+/// Queries covering every column of every table in `db`: all of it in rowid order, counted,
+/// sorted on each column, and each column's extremes.
+fn table_queries(db: &Database) -> Vec<String> {
+  let mut queries = Vec::new();
+  for table in db.schema.objects.iter().filter_map(|o| o.as_table()) {
+    let columns = table.column_names.join(", ");
+    queries.push(format!("SELECT rowid, {} FROM {}", columns, table.name));
+    queries.push(format!("SELECT count(*) FROM {}", table.name));
+    for column in table.column_names.iter() {
+      queries.push(format!("SELECT rowid, {} FROM {} ORDER BY {}, rowid", column, table.name, column));
+      queries.push(format!("SELECT min({}) FROM {}", column, table.name));
+      queries.push(format!("SELECT max({}) FROM {}", column, table.name));
+    }
+  }
+  queries
+}
+
+#[test]
+fn fixtures_read_the_same_as_with_sqlite() {
+  for path in FIXTURES {
+    let mut file = std::fs::File::open(path).unwrap();
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    assert_same_results(path, &table_queries(&db));
+  }
+}
+
+#[test]
+fn filters_on_fixtures_match_sqlite() {
+  let queries = [
+    "SELECT id, name FROM companies WHERE country = 'country_123'",
+    "SELECT count(*) FROM companies WHERE country > 'country_500'",
+    "SELECT username, age FROM users WHERE age > 30 AND email IS NOT NULL",
+    "SELECT username FROM users WHERE age >= 25 AND age <= 35 OR username LIKE 'D%'",
+    "SELECT email FROM users WHERE email = 'bob@example.com' ORDER BY email DESC",
+    "SELECT age, username FROM users ORDER BY age DESC, username LIMIT 3",
+    "SELECT id, name FROM people WHERE name = 'ALICE'",
+    "SELECT id, tag FROM people WHERE tag = 'x'",
+  ];
+  let fixtures = [
+    ("tests/fixtures/companies.db", &queries[..2]),
+    ("tests/fixtures/interior.db", &queries[2..6]),
+    ("tests/fixtures/nocase.db", &queries[6..]),
+  ];
+  for (path, queries) in fixtures {
+    assert_same_results(path, &queries.iter().map(|sql| sql.to_string()).collect::<Vec<_>>());
+  }
+}
+
+#[test]
+fn aggregates_orderings_and_limits_combine_as_in_sqlite() {
+  let columns = ["count(*)", "max(age)", "min(email)", "username", "email, age"];
+  let filters = ["", " WHERE age > 30", " WHERE email = 'bob@example.com'", " WHERE id = 3"];
+  let orders = ["", " ORDER BY email, id", " ORDER BY id DESC"];
+  let limits = ["", " LIMIT 0", " LIMIT 2", " LIMIT 3 OFFSET 2", " LIMIT 5 OFFSET 40"];
+  let mut queries = Vec::new();
+  for columns in columns {
+    for filter in filters {
+      for order in orders {
+        for limit in limits {
+          queries.push(format!("SELECT {} FROM users{}{}{}", columns, filter, order, limit));
+        }
+      }
+    }
+  }
+  assert_same_results("tests/fixtures/interior.db", &queries);
+}
+
+#[test]
+fn order_by_positions_sort_as_in_sqlite() {
+  let path = "tests/fixtures/interior.db";
+  let queries = [
+    "SELECT username, age FROM users ORDER BY 2",
+    "SELECT username, age FROM users ORDER BY 2 DESC, 1",
+    "SELECT age * 2, id FROM users ORDER BY 1 LIMIT 5",
+    "SELECT * FROM users ORDER BY 4, 1",
+    "SELECT email FROM users ORDER BY 1 DESC LIMIT 3",
+    "SELECT id FROM users ORDER BY 1.0, '1', id DESC",
+  ];
+  assert_same_results(path, &queries.iter().map(|sql| sql.to_string()).collect::<Vec<_>>());
+
+  // Positions outside the select list are rejected with the same message.
+  let connection = rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY).unwrap();
+  let mut file = std::fs::File::open(path).unwrap();
+  let db = Database::parse_header_and_schema(&mut file).unwrap();
+  for sql in ["SELECT username, age FROM users ORDER BY 3", "SELECT id FROM users ORDER BY 1, 0"] {
+    let expected = match connection.prepare(sql).err().unwrap() {
+      rusqlite::Error::SqlInputError { msg, .. } | rusqlite::Error::SqliteFailure(_, Some(msg)) => msg,
+      error => panic!("{}: {}", sql, error),
+    };
+    let found = Query::parse(sql).unwrap().query_rows(&db, &mut file).err().unwrap().to_string();
+    assert_eq!(found, expected, "{}", sql);
+  }
+}
+
+/// A xorshift generator, so the generated tables and queries are the same on every run.
+struct Random(u64);
+
+impl Random {
+  fn next(&mut self) -> u64 {
+    self.0 ^= self.0 << 13;
+    self.0 ^= self.0 >> 7;
+    self.0 ^= self.0 << 17;
+    self.0
+  }
+
+  fn below(&mut self, n: u64) -> u64 {
+    self.next() % n
+  }
+
+  /// A literal of a random storage class, with values clustered so that comparisons with the
+  /// table's values go both ways.
+  fn literal(&mut self) -> String {
+    match self.below(5) {
+      0 => "NULL".to_string(),
+      1 => (self.below(200) as i64 - 100).to_string(),
+      2 => format!("{:.2}", (self.below(20000) as f64 - 10000.0) / 100.0),
+      3 => format!("'{}'", self.below(200) as i64 - 100),
+      _ => format!("'{}'", ["apple", "Apple", "banana", "", "cherry pie", "z"][self.below(6) as usize]),
+    }
+  }
+}
+
+#[test]
+fn generated_tables_and_queries_match_sqlite() {
+  let mut random = Random(0x2545_f491_4f6c_dd1d);
+  // Declared types of each affinity, and none at all.
+  let columns = [("a", ""), ("b", "integer"), ("c", "text"), ("d", "real"), ("e", "numeric")];
+  for round in 0..4 {
+    let path = std::env::temp_dir().join(format!("sqlite-rust-differential-{}-{}.db", std::process::id(), round));
+    let _ = std::fs::remove_file(&path);
+    let connection = rusqlite::Connection::open(&path).unwrap();
+    let definitions = columns.iter().map(|(name, ty)| format!("{} {}", name, ty)).collect::<Vec<_>>();
+    connection.execute(&format!("CREATE TABLE r ({})", definitions.join(", ")), []).unwrap();
+    // Enough rows to need interior pages in some rounds.
+    connection.execute_batch("BEGIN").unwrap();
+    for _ in 0..50 + 300 * round {
+      let values = (0..columns.len()).map(|_| random.literal()).collect::<Vec<_>>();
+      connection.execute(&format!("INSERT INTO r VALUES ({})", values.join(", ")), []).unwrap();
+    }
+    connection.execute_batch("COMMIT").unwrap();
+    drop(connection);
+
+    let path = path.to_str().unwrap();
+    let names = columns.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+    let mut queries = vec![format!("SELECT rowid, {} FROM r", names.join(", "))];
+    let operators = ["=", "!=", "<", "<=", ">", ">=", "IS", "IS NOT"];
+    for _ in 0..40 {
+      let column = names[random.below(names.len() as u64) as usize];
+      let operator = operators[random.below(operators.len() as u64) as usize];
+      let literal = random.literal();
+      let other = names[random.below(names.len() as u64) as usize];
+      queries.push(match random.below(3) {
+        0 => format!("SELECT rowid, {} FROM r WHERE {} {} {}", column, column, operator, literal),
+        1 => {
+          let other_literal = random.literal();
+          format!(
+            "SELECT rowid, {}, {} FROM r WHERE {} {} {} AND {} > {}",
+            column, other, column, operator, literal, other, other_literal
+          )
+        }
+        _ => format!("SELECT rowid, {} FROM r WHERE {} {} {} ORDER BY {}, rowid", other, column, operator, literal, other),
+      });
+    }
+    assert_same_results(path, &queries);
+    std::fs::remove_file(path).unwrap();
+  }
+}
+
+impl Random {
+  /// A value of a random storage class, drawn from edges where the order across classes is easy
+  /// to get wrong: integers next to reals that round to them, equal integers and reals, and text
+  /// and blobs that differ only in case or length.
+  fn value(&mut self) -> Value {
+    const INTEGERS: [i64; 8] = [i64::MIN, -1, 0, 3, 1 << 53, (1 << 53) + 1, i64::MAX - 1, i64::MAX];
+    const REALS: [f64; 8] = [-1e300, -0.5, -0.0, 3.0, 9007199254740992.0, 9.223372036854776e18, 1e300, 2.5];
+    const TEXTS: [&str; 7] = ["", "a", "A", "ab", "10", "9", "é"];
+    match self.below(5) {
+      0 => Value::Null,
+      1 if self.below(2) == 0 => Value::Integer(INTEGERS[self.below(8) as usize]),
+      1 => Value::Integer(self.below(20) as i64 - 10),
+      2 if self.below(2) == 0 => Value::Real(REALS[self.below(8) as usize]),
+      2 => Value::Real((self.below(40) as f64 - 20.0) / 4.0),
+      3 => Value::Text(TEXTS[self.below(7) as usize].to_string()),
+      _ => Value::Blob((0..self.below(3)).map(|_| b"aA\0"[self.below(3) as usize]).collect()),
+    }
+  }
+}
+
+#[test]
+fn sort_keys_order_values_as_sqlite_order_by_does() {
+  let mut random = Random(0x9e37_79b9_7f4a_7c15);
+  let connection = rusqlite::Connection::open_in_memory().unwrap();
+  // A column without a declared type keeps each value's storage class as given.
+  connection.execute("CREATE TABLE v (id INTEGER PRIMARY KEY, x)", []).unwrap();
+  let values = (0..500).map(|_| random.value()).collect::<Vec<_>>();
+  for (id, value) in values.iter().enumerate() {
+    let value = match value {
+      Value::Null => rusqlite::types::Value::Null,
+      Value::Integer(n) => rusqlite::types::Value::Integer(*n),
+      Value::Real(f) => rusqlite::types::Value::Real(*f),
+      Value::Text(s) => rusqlite::types::Value::Text(s.clone()),
+      Value::Blob(b) => rusqlite::types::Value::Blob(b.clone()),
+    };
+    connection.execute("INSERT INTO v VALUES (?, ?)", rusqlite::params![id, value]).unwrap();
+  }
+
+  // Equal values keep their insertion order in both, as SQLite breaks the tie on id.
+  for descending in [false, true] {
+    let direction = if descending { "DESC" } else { "ASC" };
+    let mut statement = connection.prepare(&format!("SELECT id FROM v ORDER BY x {}, id", direction)).unwrap();
+    let expected = statement
+      .query_map([], |row| row.get::<_, usize>(0))
+      .unwrap()
+      .collect::<Result<Vec<_>, _>>()
+      .unwrap();
+    let mut found = (0..values.len()).collect::<Vec<_>>();
+    found.sort_by(|&a, &b| {
+      let ordering = values[a].sort_key().cmp(&values[b].sort_key());
+      if descending { ordering.reverse() } else { ordering }.then(a.cmp(&b))
+    });
+    let sorted = |ids: &[usize]| ids.iter().map(|&id| normalize(&values[id])).collect::<Vec<_>>();
+    assert!(
+      found == expected,
+      "ORDER BY x {}\n  sqlite: {:?}\n  ours:   {:?}",
+      direction,
+      sorted(&expected),
+      sorted(&found)
+    );
+  }
+}
@@ -16,6 +16,8 @@ impl<I> ParseError<I> for MyError<I> {
   }
 }
 
+/// A value read from the file that's outside the range its field allows, such as an unknown page
+/// or serial type.
 #[derive(Debug, Error)]
 pub struct InvalidValueError(pub String);
 
@@ -24,3 +26,67 @@ impl std::fmt::Display for InvalidValueError {
     write!(f, "{:?}", self)
   }
 }
+
+/// Errors returned by the database and query API.
+#[derive(Debug, Error)]
+pub enum Error {
+  #[error("no such table: {0}")]
+  TableNotFound(String),
+  /// A column that isn't in `table`, whose columns are `available`. Without a FROM clause, there
+  /// are none.
+  #[error("no such column: {column}{}", available_columns(.available))]
+  ColumnNotFound {
+    table: String,
+    column: String,
+    available: Vec<String>,
+  },
+  #[error("unsupported SQL: {0}")]
+  UnsupportedSql(String),
+  /// A VIRTUAL generated column read by a query, whose expression can't be computed here.
+  #[error("column {0} is a generated column and cannot be read by this tool")]
+  GeneratedColumn(String),
+  /// SQL that SQLite rejects as well, with the message it gives.
+  #[error("{0}")]
+  InvalidSql(String),
+  /// A file whose header isn't that of a SQLite database, or is too damaged to read.
+  #[error("not a SQLite database: {0}")]
+  NotADatabase(String),
+  /// A database in WAL mode whose `-wal` file holds changes that aren't in the main file yet.
+  #[error("database is in WAL mode and has uncheckpointed changes in {0}, which can't be read")]
+  WalNotSupported(String),
+  #[error("database disk image is malformed: page {page}: {reason}")]
+  Corrupt { page: usize, reason: String },
+  #[error("expected {expected} parameters, got {found}")]
+  ParameterCount { expected: usize, found: usize },
+  #[error("the schema changed since the statement was prepared")]
+  SchemaChanged,
+  #[error("{found} values for {expected} columns")]
+  ValueCount { expected: usize, found: usize },
+  #[error("{0}")]
+  Constraint(String),
+  #[error("{kind} {name} already exists")]
+  AlreadyExists { kind: String, name: String },
+  #[error("datatype mismatch")]
+  DatatypeMismatch,
+  #[error("{0}")]
+  TransactionState(String),
+  /// A query stopped by the progress handler.
+  #[error("query interrupted")]
+  Interrupted,
+  /// A CSV file that can't be imported, at the line where the trouble starts.
+  #[error("line {line}: {reason}")]
+  Csv { line: usize, reason: String },
+  #[error("page {page} is full")]
+  PageFull { page: usize },
+  #[error(transparent)]
+  Io(#[from] std::io::Error),
+}
+
+fn available_columns(available: &[String]) -> String {
+  match available {
+    [] => String::new(),
+    _ => format!(" (available: {})", available.join(", ")),
+  }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -7,6 +7,8 @@ use nom::{
 use crate::error::{InvalidValueError, MyError};
 
 pub const HEADER_SIZE: usize = 100;
+/// The sqlite release recorded as the last to have written a database created by this crate.
+const SQLITE_VERSION_NUMBER: u32 = 3046000;
 
 #[derive(Debug)]
 pub struct Header {
@@ -28,18 +30,20 @@ pub struct Header {
   pub application_id: u32,
   pub version_valid_for: u32,
   pub sqlite_version_number: u32,
+  /// Oddities that don't stop the database being read, like nonzero reserved bytes.
+  pub warnings: Vec<String>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum FormatVersion {
   Legacy = 1,
   WriteAheadLog = 2,
 }
 
-impl TryFrom<u16> for FormatVersion {
+impl TryFrom<u8> for FormatVersion {
   type Error = InvalidValueError;
 
-  fn try_from(value: u16) -> Result<Self, Self::Error> {
+  fn try_from(value: u8) -> Result<Self, Self::Error> {
     match value {
       1 => Ok(FormatVersion::Legacy),
       2 => Ok(FormatVersion::WriteAheadLog),
@@ -51,7 +55,7 @@ impl TryFrom<u16> for FormatVersion {
   }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum TextEncoding {
   Utf8 = 1,
   Utf16le = 2,
@@ -75,9 +79,87 @@ impl TryFrom<u32> for TextEncoding {
 }
 
 impl Header {
+  /// Whether the database is in WAL mode, so that recent changes may be in its `-wal` file
+  /// rather than in the database file itself.
+  pub fn is_wal(&self) -> bool {
+    matches!(self.write_version, FormatVersion::WriteAheadLog)
+      || matches!(self.read_version, FormatVersion::WriteAheadLog)
+  }
+
+  /// The journal mode as shown by `.dbinfo`: `wal`, or `rollback` for the legacy journal modes,
+  /// which the header doesn't tell apart.
+  pub fn journal_mode(&self) -> &'static str {
+    if self.is_wal() {
+      "wal"
+    } else {
+      "rollback"
+    }
+  }
+
+  /// The header of a new database made of an empty page 1, with pages of `page_size` bytes.
+  pub fn new(page_size: usize) -> Self {
+    Header {
+      page_size,
+      write_version: FormatVersion::Legacy,
+      read_version: FormatVersion::Legacy,
+      end_page_reserved_bytes: 0,
+      file_change_counter: 1,
+      size_in_pages: 1,
+      first_freelist_trunk_page: 0,
+      num_freelist_pages: 0,
+      schema_cookie: 0,
+      schema_format: 4,
+      default_page_cache_size: 0,
+      largest_root_btree_page: 0,
+      text_encoding: TextEncoding::Utf8,
+      user_version: 0,
+      incremental_vacuum_mode: false,
+      application_id: 0,
+      version_valid_for: 1,
+      sqlite_version_number: SQLITE_VERSION_NUMBER,
+      warnings: Vec::new(),
+    }
+  }
+
+  pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+    let mut header = [0; HEADER_SIZE];
+    header[..16].copy_from_slice(b"SQLite format 3\0");
+    // A page size of 65536 doesn't fit in two bytes, and is stored as 1.
+    let page_size = if self.page_size == 65536 { 1 } else { self.page_size as u16 };
+    header[16..18].copy_from_slice(&page_size.to_be_bytes());
+    header[18] = self.write_version as u8;
+    header[19] = self.read_version as u8;
+    header[20] = self.end_page_reserved_bytes as u8;
+    // The maximum and minimum embedded payload fractions, and the leaf payload fraction.
+    header[21..24].copy_from_slice(&[64, 32, 32]);
+    for (offset, value) in [
+      (24, self.file_change_counter as u32),
+      (28, self.size_in_pages as u32),
+      (32, self.first_freelist_trunk_page as u32),
+      (36, self.num_freelist_pages as u32),
+      (40, self.schema_cookie),
+      (44, self.schema_format),
+      (48, self.default_page_cache_size as u32),
+      (52, self.largest_root_btree_page as u32),
+      (56, self.text_encoding as u32),
+      (60, self.user_version),
+      (64, self.incremental_vacuum_mode as u32),
+      (68, self.application_id),
+      (92, self.version_valid_for),
+      (96, self.sqlite_version_number),
+    ] {
+      header[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+    }
+    header
+  }
+
   pub fn parse(input: &[u8]) -> IResult<&[u8], Self, MyError<&[u8]>> {
+    let invalid = |message: String| Error(MyError::InvalidValueError(InvalidValueError(message)));
+
     let (input, header_string) = take(16usize)(input)?;
-    assert_eq!(header_string, b"SQLite format 3\0");
+    if header_string != b"SQLite format 3\0" {
+      return Err(invalid("missing the \"SQLite format 3\" header string".to_string()));
+    }
 
     let (input, page_size) = {
       let (input, value) = be_u16(input)?;
@@ -107,12 +189,11 @@ impl Header {
 
     let (input, end_page_reserved_bytes) = u8(input)?;
 
-    let (input, max_embedded_payload_fraction) = u8(input)?;
-    assert_eq!(max_embedded_payload_fraction, 64);
-    let (input, min_embedded_payload_fraction) = u8(input)?;
-    assert_eq!(min_embedded_payload_fraction, 32);
-    let (input, leaf_payload_fraction) = u8(input)?;
-    assert_eq!(leaf_payload_fraction, 32);
+    // The payload fractions were meant to be tunable, but SQLite only accepts these values.
+    let (input, payload_fractions) = take(3usize)(input)?;
+    if payload_fractions != [64, 32, 32] {
+      return Err(invalid(format!("invalid payload fractions {:?}", payload_fractions)));
+    }
 
     let (input, file_change_counter) = be_u32(input)?;
     let (input, size_in_pages) = be_u32(input)?;
@@ -133,11 +214,15 @@ impl Header {
     let text_encoding = TextEncoding::try_from(text_encoding).map_err(|e| Error(MyError::from(e)))?;
     let (input, user_version) = be_u32(input)?;
     let (input, incremental_vacuum_mode) = be_u32(input)?;
-    assert!(incremental_vacuum_mode == 0 || incremental_vacuum_mode == 1);
+    // SQLite reads any nonzero value as true.
     let incremental_vacuum_mode = incremental_vacuum_mode != 0;
     let (input, application_id) = be_u32(input)?;
-    let (input, zeros) = take(20usize)(input)?;
-    assert!(zeros.iter().all(|b| *b == 0));
+    // Reserved for expansion, and zero in files written by SQLite, but harmless otherwise.
+    let mut warnings = Vec::new();
+    let (input, reserved) = take(20usize)(input)?;
+    if reserved.iter().any(|b| *b != 0) {
+      warnings.push("reserved header bytes 72..92 are not zero".to_string());
+    }
     let (input, version_valid_for) = be_u32(input)?;
     let (input, sqlite_version_number) = be_u32(input)?;
 
@@ -162,7 +247,38 @@ impl Header {
         application_id,
         version_valid_for,
         sqlite_version_number,
+        warnings,
       },
     ))
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::Header;
+  use crate::error::MyError;
+
+  #[test]
+  fn unusual_headers_are_errors_or_warnings() {
+    let bytes = Header::new(4096).to_bytes();
+    let (_, header) = Header::parse(&bytes).unwrap();
+    assert_eq!(header.page_size, 4096);
+    assert!(header.warnings.is_empty());
+
+    let mut reserved = bytes;
+    reserved[80] = 7;
+    let (_, header) = Header::parse(&reserved).unwrap();
+    assert_eq!(header.warnings, ["reserved header bytes 72..92 are not zero"]);
+
+    let mut payload_fraction = bytes;
+    payload_fraction[21] = 65;
+    assert!(matches!(
+      Header::parse(&payload_fraction),
+      Err(nom::Err::Error(MyError::InvalidValueError(e))) if e.0 == "invalid payload fractions [65, 32, 32]"
+    ));
+    let mut magic = bytes;
+    magic[..6].copy_from_slice(b"MySQL ");
+    assert!(Header::parse(&magic).is_err());
+    assert!(Header::parse(&bytes[..50]).is_err());
+  }
+}
@@ -0,0 +1,253 @@
+//! Loading a CSV file into a new table, the reverse of [`Database::export_csv`]. The first line
+//! names the columns, whose types are guessed from the rows after it, and every row is inserted
+//! in a single transaction, so a failed import leaves the database as it was.
+
+use crate::{
+  database::Database,
+  error::{Error, Result},
+  expr::Affinity,
+  output::quote_identifier,
+  query::{InsertQuery, Query},
+  record::Value,
+};
+
+/// How many rows are looked at to choose the type of each column.
+const SAMPLE_ROWS: usize = 100;
+
+/// What [`Database::import_csv`] did.
+#[derive(Debug)]
+pub struct ImportReport {
+  pub rows: usize,
+  /// The line of each row skipped for having the wrong number of fields.
+  pub skipped: Vec<usize>,
+}
+
+/// The fields of each line of `csv` with the line it starts on, a quoted field being able to
+/// span lines. An empty field that isn't quoted is `None`, which imports as NULL, so that NULL
+/// and empty text both survive an export and import.
+fn csv_records(csv: &str) -> Result<Vec<(usize, Vec<Option<String>>)>> {
+  let mut records = Vec::new();
+  let mut chars = csv.chars().peekable();
+  let mut line = 1;
+  while chars.peek().is_some() {
+    let start_line = line;
+    let mut fields = Vec::new();
+    let mut field = None::<String>;
+    loop {
+      match chars.next() {
+        Some('"') => {
+          let text = field.get_or_insert_with(String::new);
+          loop {
+            match chars.next() {
+              Some('"') if chars.peek() == Some(&'"') => text.push(chars.next().unwrap()),
+              Some('"') => break,
+              Some(c) => {
+                line += usize::from(c == '\n');
+                text.push(c);
+              }
+              None => {
+                return Err(Error::Csv {
+                  line: start_line,
+                  reason: "unterminated quoted field".to_string(),
+                })
+              }
+            }
+          }
+        }
+        Some(',') => fields.push(field.take()),
+        Some('\r') if chars.peek() == Some(&'\n') => {}
+        Some('\n') | None => {
+          line += 1;
+          fields.push(field.take());
+          break;
+        }
+        Some(c) => field.get_or_insert_with(String::new).push(c),
+      }
+    }
+    records.push((start_line, fields));
+  }
+  Ok(records)
+}
+
+/// The declared type of a column holding `values`: INTEGER or REAL if they're all numbers of
+/// that kind, TEXT otherwise or if they're all NULL.
+fn column_type<'a>(values: impl Iterator<Item = &'a str>) -> &'static str {
+  let mut column_type = None;
+  for value in values {
+    if value.parse::<i64>().is_ok() {
+      column_type = column_type.or(Some("INTEGER"));
+    } else if value.parse::<f64>().is_ok_and(f64::is_finite) {
+      column_type = Some("REAL");
+    } else {
+      return "TEXT";
+    }
+  }
+  column_type.unwrap_or("TEXT")
+}
+
+impl Database {
+  /// Create the table `table_name` from the CSV in `csv` and insert its rows, in one transaction.
+  /// A row with more or fewer fields than the header is skipped if `skip_malformed`, and
+  /// otherwise fails the import before anything is written.
+  pub fn import_csv<F, C>(&mut self, mut file: F, table_name: &str, csv: C, skip_malformed: bool) -> Result<ImportReport>
+    where
+      F: std::io::Read + std::io::Write + std::io::Seek,
+      C: std::io::Read,
+  {
+    let mut records = csv_records(&std::io::read_to_string(csv)?)?.into_iter();
+    let Some((_, header)) = records.next() else {
+      return Err(Error::Csv {
+        line: 1,
+        reason: "no header row".to_string(),
+      });
+    };
+    let column_names = header.into_iter().map(Option::unwrap_or_default).collect::<Vec<_>>();
+
+    let mut rows = Vec::new();
+    let mut skipped = Vec::new();
+    for (line, fields) in records {
+      if fields.len() == column_names.len() {
+        rows.push(fields);
+      } else if skip_malformed {
+        skipped.push(line);
+      } else {
+        return Err(Error::Csv {
+          line,
+          reason: format!("expected {} fields, found {}", column_names.len(), fields.len()),
+        });
+      }
+    }
+
+    let columns = column_names.iter().enumerate().map(|(i, name)| {
+      let sample = rows.iter().take(SAMPLE_ROWS).filter_map(|row| row[i].as_deref());
+      format!("{} {}", quote_identifier(name), column_type(sample))
+    });
+    let sql = format!("CREATE TABLE {} ({})", quote_identifier(table_name), columns.collect::<Vec<_>>().join(", "));
+    let Query::Create(create) = Query::parse(&sql)? else {
+      unreachable!();
+    };
+
+    self.begin()?;
+    let inserted = self.create_table(&mut file, &create).and_then(|()| {
+      let table = self.schema.table(table_name)?;
+      let rows = rows.into_iter().map(|row| {
+        let values = row.into_iter().zip(table.column_affinities.iter());
+        values
+          .map(|(field, affinity)| match (field, affinity) {
+            (None, _) => Value::Null,
+            // A REAL column stores whole numbers as reals too.
+            (Some(text), Affinity::Real) => match affinity.apply(&Value::Text(text)).into_owned() {
+              Value::Integer(n) => Value::Real(n as f64),
+              value => value,
+            },
+            (Some(text), _) => affinity.apply(&Value::Text(text)).into_owned(),
+          })
+          .collect()
+      });
+      let insert = InsertQuery {
+        table_name: table_name.to_string(),
+        column_names: None,
+        rows: rows.collect(),
+      };
+      self.insert(&mut file, &insert)
+    });
+    match inserted {
+      Ok(rows) => {
+        self.commit(&mut file)?;
+        Ok(ImportReport { rows, skipped })
+      }
+      Err(error) => {
+        self.rollback(&mut file)?;
+        Err(error)
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn import(csv: &str, skip_malformed: bool) -> (Database, std::io::Cursor<Vec<u8>>, Result<ImportReport>) {
+    let mut file = std::io::Cursor::new(std::fs::read("tests/fixtures/interior.db").unwrap());
+    let mut db = Database::parse_header_and_schema(&mut file).unwrap();
+    let report = db.import_csv(&mut file, "imported", csv.as_bytes(), skip_malformed);
+    (db, file, report)
+  }
+
+  #[test]
+  fn exported_tables_import_as_they_were() {
+    let file = std::fs::File::open("tests/fixtures/interior.db").unwrap();
+    let db = Database::parse_header_and_schema(&file).unwrap();
+    let mut csv = Vec::new();
+    db.export_csv(&file, "users", &mut csv).unwrap();
+    let users = db.get_full_table(&file, 2, &["id", "username", "age", "email"], &[0, 1, 2, 3]).unwrap();
+
+    let (db, mut file, report) = import(std::str::from_utf8(&csv).unwrap(), false);
+    let report = report.unwrap();
+    assert_eq!((report.rows, report.skipped.len()), (22, 0));
+    let table = db.schema.table("imported").unwrap();
+    assert_eq!(table.sql, "CREATE TABLE imported (id INTEGER, username TEXT, age INTEGER, email TEXT)");
+    let imported = db.get_full_table(&mut file, table.root_page, &["id", "username", "age", "email"], &[0, 1, 2, 3]).unwrap();
+    let values = |records: &[crate::record::Record]| format!("{:?}", records.iter().map(|r| &r.values).collect::<Vec<_>>());
+    // The rowid alias reads as NULL from the record of the original.
+    let mut users = users;
+    for user in users.iter_mut() {
+      user.values[0] = Value::Integer(user.row_id.unwrap());
+    }
+    assert_eq!(values(&imported), values(&users));
+  }
+
+  #[test]
+  fn column_types_are_guessed_from_the_rows() {
+    let csv = "n,x,\"a \"\"b\"\"\",empty\r\n1,1.5,\"multi\nline\",\n2,3,,\n";
+    let (db, mut file, report) = import(csv, false);
+    assert_eq!(report.unwrap().rows, 2);
+    let table = db.schema.table("imported").unwrap();
+    assert_eq!(table.sql, "CREATE TABLE imported (n INTEGER, x REAL, \"a \"\"b\"\"\" TEXT, empty TEXT)");
+    let rows = db.get_full_table(&mut file, table.root_page, &["n", "x", "a \"b\"", "empty"], &[0, 1, 2, 3]).unwrap();
+    assert_eq!(
+      format!("{:?}", rows.iter().map(|r| &r.values).collect::<Vec<_>>()),
+      format!(
+        "{:?}",
+        [
+          [Value::Integer(1), Value::Real(1.5), Value::Text("multi\nline".to_string()), Value::Null],
+          [Value::Integer(2), Value::Real(3.0), Value::Null, Value::Null],
+        ]
+      )
+    );
+  }
+
+  #[test]
+  fn malformed_rows_abort_or_are_skipped() {
+    let csv = "a,b\n1,2\n3\n4,5\n6,7,8\n";
+    let (db, _, report) = import(csv, false);
+    assert!(matches!(report, Err(Error::Csv { line: 3, .. })), "{:?}", report);
+    assert!(db.schema.table("imported").is_err());
+
+    let (db, file, report) = import(csv, true);
+    let report = report.unwrap();
+    assert_eq!((report.rows, report.skipped), (2, vec![3, 5]));
+
+    // What's written reads back in sqlite3, where it's installed.
+    let path = std::env::temp_dir().join(format!("sqlite-rust-{}-import.db", std::process::id()));
+    std::fs::write(&path, file.get_ref()).unwrap();
+    let output = std::process::Command::new("sqlite3")
+      .arg(&path)
+      .arg("PRAGMA integrity_check; SELECT count(*), sum(a), sum(b) FROM imported")
+      .output();
+    std::fs::remove_file(&path).unwrap();
+    if let Ok(output) = output {
+      assert_eq!(String::from_utf8(output.stdout).unwrap(), "ok\n2|5|7\n");
+    }
+    assert!(db.schema.table("imported").is_ok());
+  }
+
+  #[test]
+  fn unterminated_quotes_are_reported() {
+    let (_, _, report) = import("a\n1\n\"2\n3\n", false);
+    assert!(matches!(report, Err(Error::Csv { line: 3, .. })), "{:?}", report);
+    let (_, _, report) = import("", false);
+    assert!(matches!(report, Err(Error::Csv { line: 1, .. })), "{:?}", report);
+  }
+}
@@ -0,0 +1,149 @@
+use std::{
+  io::{Read, Seek, SeekFrom, Write},
+  path::{Path, PathBuf},
+};
+
+use crate::{error::Result, page::page_offset};
+
+/// Every rollback journal starts with these bytes.
+const MAGIC: [u8; 8] = [0xd9, 0xd5, 0x05, 0xf9, 0x20, 0xa1, 0x63, 0xd7];
+/// The journal header is padded to a sector, so that page records never share one with it.
+const SECTOR_SIZE: usize = 512;
+
+/// The rollback journal of the database at `path`, which sqlite looks for next to it.
+pub fn journal_path<P>(path: P) -> PathBuf
+  where
+    P: AsRef<Path>,
+{
+  let mut journal_path = path.as_ref().as_os_str().to_owned();
+  journal_path.push("-journal");
+  PathBuf::from(journal_path)
+}
+
+/// The write-ahead log of the database at `path`, in WAL mode. It isn't read, only looked for.
+pub fn wal_path<P>(path: P) -> PathBuf
+  where
+    P: AsRef<Path>,
+{
+  let mut wal_path = path.as_ref().as_os_str().to_owned();
+  wal_path.push("-wal");
+  PathBuf::from(wal_path)
+}
+
+/// Write a journal holding the original contents of the pages about to be overwritten, and sync
+/// it to disk. `database_size` is the size of the database in pages before the write, which it
+/// is truncated back to on rollback.
+pub fn write_journal(
+  path: &Path,
+  page_size: usize,
+  database_size: usize,
+  pages: &[(usize, Vec<u8>)],
+) -> Result<()> {
+  let nonce = std::time::SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .map(|duration| duration.subsec_nanos())
+    .unwrap_or_default();
+
+  let mut journal = Vec::with_capacity(SECTOR_SIZE + pages.len() * (page_size + 8));
+  journal.extend(MAGIC);
+  journal.extend((pages.len() as u32).to_be_bytes());
+  journal.extend(nonce.to_be_bytes());
+  journal.extend((database_size as u32).to_be_bytes());
+  journal.extend((SECTOR_SIZE as u32).to_be_bytes());
+  journal.extend((page_size as u32).to_be_bytes());
+  journal.resize(SECTOR_SIZE, 0);
+  for (page_index, data) in pages.iter() {
+    journal.extend((*page_index as u32).to_be_bytes());
+    journal.extend(data);
+    journal.extend(checksum(nonce, data).to_be_bytes());
+  }
+
+  let mut file = std::fs::File::create(path)?;
+  file.write_all(&journal)?;
+  file.sync_all()?;
+  Ok(())
+}
+
+/// Delete the journal once the write it protects is on disk, committing it.
+pub fn delete_journal(path: &Path) -> Result<()> {
+  match std::fs::remove_file(path) {
+    Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+    _ => Ok(()),
+  }
+}
+
+/// Restore the pages saved in the journal at `path`, if there is one, left behind by a write
+/// that didn't complete, then delete it. Returns whether anything was rolled back.
+///
+/// Records are replayed until one is incomplete or fails its checksum, which means the journal
+/// itself wasn't fully written, and so neither was any page of the database.
+pub fn rollback(file: &mut std::fs::File, path: &Path) -> Result<bool> {
+  let journal = match std::fs::read(path) {
+    Ok(journal) => journal,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+    Err(e) => return Err(e.into()),
+  };
+  if journal.len() < SECTOR_SIZE || journal[..8] != MAGIC {
+    delete_journal(path)?;
+    return Ok(false);
+  }
+
+  let page_count = read_u32(&journal, 8) as usize;
+  let nonce = read_u32(&journal, 12);
+  let database_size = read_u32(&journal, 16) as usize;
+  let sector_size = read_u32(&journal, 20) as usize;
+  let page_size = read_u32(&journal, 24) as usize;
+
+  let mut records = journal[sector_size.max(SECTOR_SIZE).min(journal.len())..].chunks_exact(page_size + 8);
+  for record in records.by_ref().take(page_count) {
+    let page_index = read_u32(record, 0) as usize;
+    let data = &record[4..4 + page_size];
+    if page_index == 0 || read_u32(record, 4 + page_size) != checksum(nonce, data) {
+      break;
+    }
+    file.seek(SeekFrom::Start(page_offset(page_size, page_index)?))?;
+    file.write_all(data)?;
+  }
+  file.set_len(page_size as u64 * database_size as u64)?;
+  file.sync_all()?;
+
+  delete_journal(path)?;
+  Ok(true)
+}
+
+/// Read the original contents of `pages` from `file`, skipping those past its end which didn't
+/// exist before the write.
+pub fn original_pages<F>(
+  mut file: F,
+  page_size: usize,
+  database_size: usize,
+  pages: impl Iterator<Item = usize>,
+) -> Result<Vec<(usize, Vec<u8>)>>
+  where
+    F: Read + Seek,
+{
+  let mut originals = Vec::new();
+  for page_index in pages.filter(|page_index| *page_index <= database_size) {
+    let mut data = vec![0; page_size];
+    file.seek(SeekFrom::Start(page_offset(page_size, page_index)?))?;
+    file.read_exact(&mut data)?;
+    originals.push((page_index, data));
+  }
+  Ok(originals)
+}
+
+/// sqlite's page record checksum: the nonce plus every 200th byte of the page, working down from
+/// 200 bytes before its end.
+fn checksum(nonce: u32, data: &[u8]) -> u32 {
+  let mut checksum = nonce;
+  let mut i = data.len() as isize - 200;
+  while i > 0 {
+    checksum = checksum.wrapping_add(data[i as usize] as u32);
+    i -= 200;
+  }
+  checksum
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+  u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
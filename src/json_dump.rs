@@ -0,0 +1,149 @@
+//! Dumping tables as JSON, an object with an array of rows for each table, behind the `json`
+//! feature. Rows are written as they're scanned, one per line so that a dump kept in version
+//! control diffs row by row.
+
+use std::io::Write;
+
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+use crate::{
+  database::{Database, ObjectSchema, TableSchema},
+  error::{Error, Result},
+  record::Value,
+  serialize::Base64Blobs,
+};
+
+/// A row as a JSON object from column name to value, with blobs as base64 strings as in
+/// [`Base64Blobs`].
+struct JsonRow<'a> {
+  column_names: &'a [&'a str],
+  values: &'a [Value],
+}
+
+impl Serialize for JsonRow<'_> {
+  fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+      S: Serializer,
+  {
+    let mut map = serializer.serialize_map(Some(self.values.len()))?;
+    for (column_name, value) in self.column_names.iter().zip(self.values) {
+      map.serialize_entry(column_name, &Base64Blobs(value))?;
+    }
+    map.end()
+  }
+}
+
+fn json_error(error: serde_json::Error) -> Error {
+  Error::Io(error.into())
+}
+
+impl Database {
+  /// Write the stored columns of every row of the tables named in `table_names`, or of every
+  /// table if it's empty, to `out` as a JSON object keyed by table name. Returns the number of
+  /// rows written.
+  pub fn dump_json<R, W>(&self, mut file: R, table_names: &[&str], mut out: W) -> Result<usize>
+    where
+      R: std::io::Read + std::io::Seek,
+      W: Write,
+  {
+    let tables = if table_names.is_empty() {
+      self.schema.objects.iter().filter_map(ObjectSchema::as_table).collect::<Vec<_>>()
+    } else {
+      table_names.iter().map(|name| self.schema.table(name)).collect::<Result<Vec<_>>>()?
+    };
+    if let Some(table) = tables.iter().find(|table| table.without_rowid) {
+      return Err(Error::UnsupportedSql(format!("dumping WITHOUT ROWID table {}", table.name)));
+    }
+
+    let mut row_count = 0;
+    write!(out, "{{")?;
+    for (i, table) in tables.iter().enumerate() {
+      write!(out, "{}\n  ", if i == 0 { "" } else { "," })?;
+      serde_json::to_writer(&mut out, &table.name).map_err(json_error)?;
+      write!(out, ": [")?;
+      let table_rows = self.dump_table_json(&mut file, table, &mut out)?;
+      write!(out, "{}]", if table_rows == 0 { "" } else { "\n  " })?;
+      row_count += table_rows;
+    }
+    writeln!(out, "{}}}", if tables.is_empty() { "" } else { "\n" })?;
+    out.flush()?;
+    Ok(row_count)
+  }
+
+  fn dump_table_json<R, W>(&self, file: R, table: &TableSchema, mut out: W) -> Result<usize>
+    where
+      R: std::io::Read + std::io::Seek,
+      W: Write,
+  {
+    let stored = table.stored_columns();
+    let row_id_position = table.row_id_column.and_then(|column| stored.positions.iter().position(|p| *p == column));
+    let rows = self.scan_table(file, table.root_page, &stored.names, &stored.record_indices);
+    let mut row_count = 0;
+    for record in rows.with_defaults(&stored.defaults) {
+      let mut record = record?;
+      if let Some(i) = row_id_position {
+        record.values[i] = Value::Integer(record.row_id.unwrap());
+      }
+      write!(out, "{}\n    ", if row_count == 0 { "" } else { "," })?;
+      let row = JsonRow {
+        column_names: &stored.names,
+        values: &record.values,
+      };
+      serde_json::to_writer(&mut out, &row).map_err(json_error)?;
+      row_count += 1;
+    }
+    Ok(row_count)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn dump(path: &str, table_names: &[&str]) -> (usize, String) {
+    let file = std::fs::File::open(path).unwrap();
+    let db = Database::parse_header_and_schema(&file).unwrap();
+    let mut out = Vec::new();
+    let row_count = db.dump_json(&file, table_names, &mut out).unwrap();
+    (row_count, String::from_utf8(out).unwrap())
+  }
+
+  #[test]
+  fn every_table_is_dumped_with_a_row_per_line() {
+    let (row_count, json) = dump("tests/fixtures/interior.db", &[]);
+    assert_eq!(row_count, 22);
+    assert_eq!(json.lines().count(), 22 + 4);
+    assert!(json.starts_with("{\n  \"users\": [\n    {\"id\":1,\"username\":\"Alice\",\"age\":29,\"email\":\"alice@example.com\"},\n"));
+    assert!(json.ends_with("}\n  ]\n}\n"));
+    let parsed: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed["users"].as_array().unwrap().len(), 22);
+  }
+
+  #[test]
+  fn values_are_written_as_in_the_json_result_mode() {
+    let mut file = std::io::Cursor::new(std::fs::read("tests/fixtures/interior.db").unwrap());
+    let mut db = Database::parse_header_and_schema(&mut file).unwrap();
+    let csv = "t,r,n\nhi,1.5,\nthere,2,\n";
+    db.import_csv(&mut file, "values", csv.as_bytes(), false).unwrap();
+    let mut out = Vec::new();
+    assert_eq!(db.dump_json(&mut file, &["values", "VALUES"], &mut out).unwrap(), 4);
+    let parsed: serde_json::Value = serde_json::from_slice(&out).unwrap();
+    assert_eq!(parsed["values"][0], serde_json::json!({"t": "hi", "r": 1.5, "n": null}));
+    assert_eq!(parsed["values"][1], serde_json::json!({"t": "there", "r": 2.0, "n": null}));
+
+    let blob = JsonRow {
+      column_names: &["b"],
+      values: &[Value::Blob(b"abc".to_vec())],
+    };
+    assert_eq!(serde_json::to_string(&blob).unwrap(), r#"{"b":"YWJj"}"#);
+  }
+
+  #[test]
+  fn unknown_tables_fail_before_anything_is_written() {
+    let file = std::fs::File::open("tests/fixtures/interior.db").unwrap();
+    let db = Database::parse_header_and_schema(&file).unwrap();
+    let mut out = Vec::new();
+    assert!(matches!(db.dump_json(&file, &["users", "nope"], &mut out), Err(Error::TableNotFound(_))));
+    assert!(out.is_empty());
+  }
+}
@@ -0,0 +1,34 @@
+//! A reader and writer of SQLite database files, behind the command line in `main.rs`.
+
+#[cfg(feature = "async")]
+pub mod async_database;
+pub mod btree;
+pub mod cell;
+pub mod database;
+pub mod diff;
+#[cfg(all(test, feature = "differential"))]
+mod differential;
+pub mod error;
+pub mod export;
+pub mod expr;
+pub mod header;
+pub mod import;
+#[cfg(feature = "json")]
+pub mod json_dump;
+pub mod integrity;
+pub mod journal;
+pub mod output;
+pub mod page;
+pub mod pagedump;
+pub mod pattern;
+pub mod pragma;
+pub mod query;
+pub mod record;
+pub mod row;
+mod sql;
+pub mod statement;
+pub mod stats;
+pub mod transaction;
+#[cfg(feature = "serde")]
+pub mod serialize;
+pub mod varint;
@@ -1,17 +1,32 @@
-use anyhow::{bail, Result};
-use query::Query;
+use std::{
+  ops::ControlFlow,
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+};
 
-mod cell;
-mod database;
-mod error;
-mod header;
-mod page;
-mod query;
-mod record;
-mod varint;
+use anyhow::{bail, Result};
+use sqlite_starter_rust::{database, diff, output, query::Query};
 
 fn main() -> Result<()> {
-  let args = std::env::args().collect::<Vec<_>>();
+  let mut args = std::env::args().collect::<Vec<_>>();
+  // `--batch` before the database path leaves just the command's output, for scripts.
+  let batch = args.get(1).is_some_and(|arg| arg == "--batch");
+  if batch {
+    args.remove(1);
+  }
+  // `diff <a> <b>` compares two databases, exiting with status 1 if they differ, as diff(1) does.
+  if args.get(1).is_some_and(|arg| arg == "diff") {
+    let (Some(path_a), Some(path_b)) = (args.get(2), args.get(3)) else {
+      bail!("Usage: diff <database path> <database path>");
+    };
+    let (file_a, file_b) = (std::fs::File::open(path_a)?, std::fs::File::open(path_b)?);
+    let db_a = database::Database::parse_header_and_schema(&file_a)?;
+    let db_b = database::Database::parse_header_and_schema(&file_b)?;
+    let differences = diff::diff(&db_a, &file_a, &db_b, &file_b, std::io::stdout().lock())?;
+    std::process::exit(if differences == 0 { 0 } else { 1 });
+  }
   match args.len() {
     0 | 1 => bail!("Missing <database path> and <command>"),
     2 => bail!("Missing <command>"),
@@ -19,32 +34,182 @@ fn main() -> Result<()> {
   }
 
   let mut file = std::fs::File::open(&args[1])?;
-  let mut db = database::Database::parse_header_and_schema(&mut file)?;
+  #[cfg(not(feature = "mmap"))]
+  let mut db = database::Database::open(&args[1])?;
+  #[cfg(feature = "mmap")]
+  let mut db = database::Database::open_mmap(&args[1])?;
+
+  // Ctrl-C stops the query in progress, which then fails with "query interrupted".
+  let interrupted = Arc::new(AtomicBool::new(false));
+  let handler_interrupted = interrupted.clone();
+  ctrlc::set_handler(move || handler_interrupted.store(true, Ordering::Relaxed))?;
+  db.set_progress_handler(database::DEFAULT_PROGRESS_PAGES, move || {
+    if interrupted.swap(false, Ordering::Relaxed) {
+      ControlFlow::Break(())
+    } else {
+      ControlFlow::Continue(())
+    }
+  });
 
-  let command = &args[2];
+  // `.mode insert [table]` before the command prints query results as INSERT statements.
+  let (insert_table, command) = match args[2].strip_prefix(".mode") {
+    Some(mode) => {
+      let mut words = mode.split_whitespace();
+      match words.next() {
+        Some("insert") => {}
+        Some(mode) => bail!("Unsupported output mode: {}", mode),
+        None => bail!("Missing output mode"),
+      }
+      let Some(command) = args.get(3) else {
+        bail!("Missing <command>");
+      };
+      (Some(words.next().map(str::to_owned)), command)
+    }
+    None => (None, &args[2]),
+  };
   match command.as_str() {
     ".dbinfo" => {
       println!("database page size: {}", db.header.page_size);
+      println!("database page count: {}", db.header.size_in_pages);
+      println!("schema format: {}", db.schema_format());
       println!("number of tables: {}", db.schema.table_count());
+      println!("journal mode: {}", db.header.journal_mode());
     }
     ".tables" => {
-      let tables = db.schema.table_names();
-      let tables_string = tables.join(" ");
-      println!("{}", tables_string);
+      // Like sqlite3, views are listed along with the tables, but SQLite's internal tables aren't.
+      let mut tables = db.schema.table_names();
+      tables.retain(|name| !name.to_ascii_lowercase().starts_with("sqlite_"));
+      tables.extend(db.schema.view_names());
+      tables.sort();
+      print!("{}", output::name_columns(&tables));
+    }
+    export if export.starts_with(".export ") => {
+      let mut arguments = export[".export ".len()..].split_whitespace();
+      let (Some(table_name), Some(path), None) = (arguments.next(), arguments.next(), arguments.next()) else {
+        bail!("Usage: .export <table> <path.csv>");
+      };
+      let out = std::io::BufWriter::new(std::fs::File::create(path)?);
+      let row_count = db.export_csv(&mut file, table_name, out)?;
+      println!("Exported {} rows to {}", row_count, path);
     }
-    query_str => {
-      let query = Query::parse(query_str)?;
-      let results = query.execute(&mut db, &mut file)?;
-      for row in results.iter() {
-        println!("{}", row.join("|"));
+    import if import.starts_with(".import ") => {
+      let mut arguments = import[".import ".len()..].split_whitespace().peekable();
+      let skip_malformed = arguments.next_if_eq(&"--skip-malformed").is_some();
+      let (Some(path), Some(table_name), None) = (arguments.next(), arguments.next(), arguments.next()) else {
+        bail!("Usage: .import [--skip-malformed] <path.csv> <table>");
+      };
+      let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&args[1])?;
+      let report = db.import_csv(&mut file, table_name, std::fs::File::open(path)?, skip_malformed)?;
+      for line in report.skipped.iter() {
+        eprintln!("{}: line {}: wrong number of fields, skipped", path, line);
       }
+      println!("Imported {} rows into {}", report.rows, table_name);
+    }
+    #[cfg(feature = "json")]
+    json_dump if json_dump.split_whitespace().next() == Some(".json_dump") => {
+      let table_names = json_dump.split_whitespace().skip(1).collect::<Vec<_>>();
+      let stdout = std::io::stdout().lock();
+      db.dump_json(&mut file, &table_names, std::io::BufWriter::new(stdout))?;
+    }
+    #[cfg(not(feature = "json"))]
+    json_dump if json_dump.split_whitespace().next() == Some(".json_dump") => {
+      bail!(".json_dump needs the json feature");
+    }
+    ".stats" => {
+      print!("{}", db.stats(&mut file)?);
     }
+    #[cfg(feature = "json")]
+    ".stats --json" => {
+      println!("{}", serde_json::to_string_pretty(&db.stats(&mut file)?)?);
+    }
+    #[cfg(not(feature = "json"))]
+    ".stats --json" => {
+      bail!(".stats --json needs the json feature");
+    }
+    pagedump if pagedump.starts_with(".pagedump") => {
+      let page = pagedump[".pagedump".len()..].trim();
+      let Ok(page) = page.parse::<usize>() else {
+        bail!("Usage: .pagedump <page number>");
+      };
+      print!("{}", db.debug_page(&mut file, page)?);
+    }
+    ".views" => {
+      print!("{}", output::name_columns(&db.schema.view_names()));
+    }
+    query_str if query_str.to_ascii_lowercase().starts_with("explain ") => {
+      let sql = query_str["explain ".len()..].trim_start();
+      let sql = match sql.get(.."query plan ".len()) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("query plan ") => &sql[prefix.len()..],
+        _ => sql,
+      };
+      println!("{}", db.explain(sql)?);
+    }
+    query_str => match Query::parse(query_str)? {
+      Query::Create(create) => {
+        let mut file = std::fs::OpenOptions::new()
+          .read(true)
+          .write(true)
+          .open(&args[1])?;
+        db.create_table(&mut file, &create)?;
+      }
+      Query::Insert(insert) => {
+        let mut file = std::fs::OpenOptions::new()
+          .read(true)
+          .write(true)
+          .open(&args[1])?;
+        db.insert(&mut file, &insert)?;
+      }
+      Query::Delete(delete) => {
+        let mut file = std::fs::OpenOptions::new()
+          .read(true)
+          .write(true)
+          .open(&args[1])?;
+        db.delete(&mut file, &delete)?;
+      }
+      Query::Update(update) => {
+        let mut file = std::fs::OpenOptions::new()
+          .read(true)
+          .write(true)
+          .open(&args[1])?;
+        db.update(&mut file, &update)?;
+      }
+      Query::Pragma(pragma) if pragma.value.is_some() => {
+        let mut file = std::fs::OpenOptions::new()
+          .read(true)
+          .write(true)
+          .open(&args[1])?;
+        pragma.assign(&mut db, &mut file)?;
+      }
+      query => {
+        if let Some(table_name) = insert_table {
+          let table_name = match (table_name, &query) {
+            (Some(table_name), _) => table_name,
+            (None, Query::Select(select)) => select.table_name.clone().unwrap_or_else(|| "table".to_string()),
+            (None, _) => "table".to_string(),
+          };
+          let result_set = query.query_rows(&db, &mut file)?;
+          for row in result_set.rows.iter() {
+            println!("{}", output::insert_statement(&table_name, &result_set.column_names, row.values()));
+          }
+        } else {
+          let result_set = query.query_rows(&db, &mut file)?;
+          for row in result_set.rows.iter() {
+            println!("{}", output::list_row(row.values()));
+          }
+        }
+      }
+    },
   }
 
-  eprintln!(
-      "Parsed {} table pages and {} index pages",
-      db.table_pages_parsed, db.index_pages_parsed
-  );
+  if !batch {
+    eprintln!(
+        "Parsed {} table pages, {} index pages and {} overflow pages, decoded {} records",
+        db.table_pages_parsed(), db.index_pages_parsed(), db.overflow_pages_parsed(), db.records_decoded()
+    );
+  }
 
   Ok(())
 }
@@ -0,0 +1,148 @@
+use std::borrow::Cow;
+
+use crate::{
+  expr::{to_sql_literal, to_text},
+  record::Value,
+};
+
+/// Keywords that can't name a table or column without quotes.
+const KEYWORDS: [&str; 24] = [
+  "and", "as", "by", "check", "create", "default", "delete", "from", "group", "in", "index", "insert", "into",
+  "is", "not", "null", "on", "or", "order", "primary", "select", "table", "values", "where",
+];
+
+/// `name` as it's written in SQL: as is if it's a plain identifier, or else double-quoted.
+pub fn quote_identifier(name: &str) -> Cow<'_, str> {
+  let plain = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+    && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    && !KEYWORDS.iter().any(|keyword| keyword.eq_ignore_ascii_case(name));
+  if plain {
+    Cow::Borrowed(name)
+  } else {
+    Cow::Owned(format!("\"{}\"", name.replace('"', "\"\"")))
+  }
+}
+
+/// An INSERT statement adding a row of `values` for `column_names` to `table_name`, which reads
+/// back as the same values, as printed by `.mode insert`.
+pub fn insert_statement(table_name: &str, column_names: &[String], values: &[Value]) -> String {
+  let columns = column_names.iter().map(|name| quote_identifier(name)).collect::<Vec<_>>();
+  let values = values.iter().map(to_sql_literal).collect::<Vec<_>>();
+  format!(
+    "INSERT INTO {}({}) VALUES({});",
+    quote_identifier(table_name),
+    columns.join(","),
+    values.join(",")
+  )
+}
+
+/// A result row as sqlite3 prints it in list mode: values separated by `|`, NULL as nothing, and
+/// reals with a decimal point.
+pub fn list_row(values: &[Value]) -> String {
+  values.iter().map(to_text).collect::<Vec<_>>().join("|")
+}
+
+/// A field of a CSV file, quoted as sqlite3 quotes it: whenever it holds anything but printable
+/// ASCII other than `"` and `,`, and when it's empty text, to tell it apart from NULL. Blobs are
+/// written in hexadecimal.
+pub fn csv_field(value: &Value) -> String {
+  let text = match value {
+    Value::Null => return String::new(),
+    Value::Blob(b) => return b.iter().map(|byte| format!("{:02x}", byte)).collect(),
+    value => to_text(value),
+  };
+  let needs_quotes = text.is_empty() || text.bytes().any(|byte| byte <= b' ' || byte >= 0x7f || byte == b'"' || byte == b',');
+  if needs_quotes {
+    format!("\"{}\"", text.replace('"', "\"\""))
+  } else {
+    text
+  }
+}
+
+/// A line of a CSV file holding `values`.
+pub fn csv_row(values: &[Value]) -> String {
+  values.iter().map(csv_field).collect::<Vec<_>>().join(",")
+}
+
+/// `names` laid out as sqlite3's `.tables` does: in columns filled top to bottom, each padded to
+/// the longest name, as many as fit in 80 characters.
+pub fn name_columns(names: &[&str]) -> String {
+  let width = names.iter().map(|name| name.chars().count()).max().unwrap_or(0);
+  let column_count = (80 / (width + 2)).max(1);
+  let row_count = names.len().div_ceil(column_count);
+  let mut output = String::new();
+  for row in 0..row_count {
+    for (i, name) in names.iter().enumerate().skip(row).step_by(row_count) {
+      let separator = if i < row_count { "" } else { "  " };
+      output += &format!("{}{:<width$}", separator, name, width = width);
+    }
+    output += "\n";
+  }
+  output
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{insert_statement, name_columns};
+  use crate::{database::Database, query::Query, record::Value};
+
+  #[test]
+  fn insert_statements_quote_values_and_names() {
+    let columns = ["id".to_string(), "count(*)".to_string(), "order".to_string(), "data".to_string()];
+    let values = [Value::Integer(1), Value::Text("it's".to_string()), Value::Null, Value::Blob(b"\0\x7f".to_vec())];
+    assert_eq!(
+      insert_statement("my table", &columns, &values),
+      "INSERT INTO \"my table\"(id,\"count(*)\",\"order\",data) VALUES(1,'it''s',NULL,X'007f');"
+    );
+    let reals = [Value::Real(1.0), Value::Real(1.5), Value::Real(1e20), Value::Real(f64::NEG_INFINITY)];
+    assert_eq!(
+      insert_statement("t", &columns, &reals),
+      "INSERT INTO t(id,\"count(*)\",\"order\",data) VALUES(1.0,1.5,1e20,-1e999);"
+    );
+  }
+
+  #[test]
+  fn names_are_laid_out_in_columns_like_sqlite3() {
+    assert_eq!(name_columns(&[]), "");
+    assert_eq!(name_columns(&["users"]), "users\n");
+    let long = "b".repeat(27);
+    let names = ["a", &long, "c", &"d".repeat(20), "e", "f", "v"];
+    assert_eq!(
+      name_columns(&names),
+      format!(
+        "{:<27}  {:<27}\n{}  {:<27}\n{:<27}  {:<27}\n{:<27}\n",
+        "a", "e", long, "f", "c", "v", "d".repeat(20)
+      )
+    );
+  }
+
+  #[test]
+  fn insert_statements_round_trip_through_sqlite3() {
+    let mut file = std::fs::File::open("tests/fixtures/interior.db").unwrap();
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let query = Query::parse("SELECT id, username, age, email FROM users WHERE age > 30").unwrap();
+    let result_set = query.query_rows(&db, &mut file).unwrap();
+    assert!(!result_set.rows.is_empty());
+
+    let mut script = "CREATE TABLE copied (id integer primary key, username text, age integer, email text);\n".to_string();
+    for row in result_set.rows.iter() {
+      script += &insert_statement("copied", &result_set.column_names, row.values());
+      script += "\n";
+    }
+    script += "SELECT count(*), sum(age), group_concat(username) FROM copied;\n";
+    // Without sqlite3 installed, there's nothing to compare with.
+    let Ok(output) = std::process::Command::new("sqlite3").arg(":memory:").arg(&script).output() else {
+      return;
+    };
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let query = Query::parse("SELECT username, age FROM users WHERE age > 30").unwrap();
+    let rows = query.execute(&db, &mut file).unwrap();
+    let usernames = rows.iter().map(|row| row[0].as_str()).collect::<Vec<_>>().join(",");
+    let age_sum = rows.iter().map(|row| row[1].parse::<i64>().unwrap()).sum::<i64>();
+    assert_eq!(
+      String::from_utf8(output.stdout).unwrap().trim(),
+      format!("{}|{}|{}", rows.len(), age_sum, usernames)
+    );
+  }
+}
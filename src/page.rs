@@ -4,7 +4,29 @@ use nom::{
   number::complete::{be_u16, be_u32, u8},
   IResult,
 };
-use crate::cell::Cell;
+use crate::{
+  cell::{Cell, CellPayload},
+  error::{Error, InvalidValueError},
+  varint::varint,
+};
+
+/// The byte offset of the (1-based) page `page_index` in a database of `page_size` byte pages.
+/// It is computed in 64 bits, as a database can outgrow 4GiB even where `usize` can't, and page
+/// numbers the format can't hold, which are 32 bits, are rejected.
+pub fn page_offset(page_size: usize, page_index: usize) -> crate::error::Result<u64> {
+  match u32::try_from(page_index) {
+    Ok(page_number) if page_number >= 1 => Ok((page_number as u64 - 1) * page_size as u64),
+    _ => Err(Error::Corrupt {
+      page: page_index,
+      reason: "page number out of range".to_string(),
+    }),
+  }
+}
+
+/// The parse error for bytes that can't be a valid page.
+fn invalid(input: &[u8]) -> nom::Err<nom::error::Error<&[u8]>> {
+  nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify))
+}
 
 pub struct Page {
   pub ty: PageType,
@@ -12,7 +34,6 @@ pub struct Page {
   pub rightmost_pointer: Option<usize>,
 }
 
-#[allow(dead_code)]
 #[derive(Debug)]
 pub enum PageType {
   LockByte,
@@ -22,7 +43,6 @@ pub enum PageType {
   PointerMap,
 }
 
-#[allow(dead_code)]
 #[derive(Debug)]
 pub enum FreelistPageType {
   Trunk,
@@ -37,20 +57,488 @@ pub enum BTreePageType {
   IndexLeaf,
 }
 
-impl TryFrom<u16> for PageType {
-  type Error = anyhow::Error;
+impl TryFrom<u8> for PageType {
+  type Error = InvalidValueError;
 
-  fn try_from(value: u16) -> Result<Self, Self::Error> {
+  fn try_from(value: u8) -> Result<Self, Self::Error> {
     match value {
       0x02 => Ok(PageType::BTree(BTreePageType::IndexInterior)),
       0x05 => Ok(PageType::BTree(BTreePageType::TableInterior)),
       0x0a => Ok(PageType::BTree(BTreePageType::IndexLeaf)),
       0x0d => Ok(PageType::BTree(BTreePageType::TableLeaf)),
-      _ => Err(anyhow::format_err!("unknown page type {}", value)),
+      _ => Err(InvalidValueError(format!("unknown page type {}", value))),
     }
   }
 }
 
+/// A b-tree page whose header and cell pointer array have been parsed, but whose cells are only
+/// decoded on demand. Cell pointers are stored in key order, so this allows binary searching a
+/// page without decoding every cell on it.
+pub struct RawPage<'input> {
+  pub ty: BTreePageType,
+  pub rightmost_pointer: Option<usize>,
+  data: &'input [u8],
+  page_start: usize,
+  first_freeblock: usize,
+  /// Where the header says the cell content area starts.
+  cell_content_offset: usize,
+  cell_offsets: Vec<usize>,
+  usable_page_size: usize,
+}
+
+impl<'input> RawPage<'input> {
+  pub fn parse(
+    input: &'input [u8],
+    is_first_page: bool,
+    usable_page_size: usize,
+  ) -> IResult<&'input [u8], Self> {
+    let page_input = input;
+    let (input, page_type) = u8(input)?;
+    let ty = match PageType::try_from(page_type) {
+      Ok(PageType::BTree(b_tree_page_type)) => b_tree_page_type,
+      _ => return Err(invalid(page_input)),
+    };
+
+    let (input, first_freeblock) = be_u16(input)?;
+    let (input, cell_count) = be_u16(input)?;
+    let (input, cell_content_offset) = be_u16(input)?;
+    let cell_content_offset = if cell_content_offset == 0 {
+      65536
+    } else {
+      cell_content_offset as usize
+    };
+
+    let (input, _num_fragmented_free_bytes) = u8(input)?;
+    let (input, rightmost_pointer) = if matches!(ty, BTreePageType::IndexInterior | BTreePageType::TableInterior) {
+      let (input, rightmost_pointer) = be_u32(input)?;
+      (input, Some(rightmost_pointer as usize))
+    } else {
+      (input, None)
+    };
+
+    let (input, cell_pointers) = count(be_u16, cell_count as usize)(input)?;
+
+    Ok((
+      input,
+      RawPage {
+        ty,
+        rightmost_pointer,
+        data: page_input,
+        // Cell offsets are relative to the start of the page, which for the first page includes
+        // the database header that isn't part of `page_input`.
+        page_start: if is_first_page { 100 } else { 0 },
+        first_freeblock: first_freeblock as usize,
+        cell_content_offset,
+        cell_offsets: cell_pointers.into_iter().map(|o| o as usize).collect(),
+        usable_page_size,
+      },
+    ))
+  }
+
+  pub fn cell_count(&self) -> usize {
+    self.cell_offsets.len()
+  }
+
+  /// The offset from the start of the page of the end of the header and cell pointers.
+  fn pointers_end(&self) -> usize {
+    let header_size = if self.rightmost_pointer.is_some() { 12 } else { 8 };
+    self.page_start + header_size + 2 * self.cell_count()
+  }
+
+  /// The offsets from the start of the page of the cell content area, which can't start before
+  /// the header and cell pointers end, and of the end of the usable part of the page.
+  fn content_area(&self) -> (usize, usize) {
+    (self.cell_content_offset.max(self.pointers_end()), self.usable_page_size)
+  }
+
+  /// The offset and size of each freeblock, in the order of the chain. The chain has to stay
+  /// within the cell content area and, as SQLite keeps it, in increasing order of offset without
+  /// overlapping, which also means it can't loop.
+  fn freeblocks(&self) -> Result<Vec<(usize, usize)>, String> {
+    let (content_start, end) = self.content_area();
+    let read_u16 = |offset: usize| u16::from_be_bytes([self.data[offset], self.data[offset + 1]]) as usize;
+    let mut freeblocks = Vec::new();
+    let (mut next, mut min_offset) = (self.first_freeblock, content_start);
+    while next != 0 {
+      if next < min_offset || next + 4 > end {
+        return Err(match freeblocks.last() {
+          Some((previous, _)) => format!("freeblock at offset {} out of order after {}", next, previous),
+          None => format!("freeblock offset {} out of range {}..{}", next, content_start, end),
+        });
+      }
+      let size = read_u16(next - self.page_start + 2);
+      if size < 4 || next + size > end {
+        return Err(format!("freeblock at offset {} extends off the end of the page", next));
+      }
+      freeblocks.push((next, size));
+      min_offset = next + size;
+      next = read_u16(next - self.page_start);
+    }
+    Ok(freeblocks)
+  }
+
+  /// Check that every cell pointer and freeblock is within the cell content area, returning what's
+  /// wrong if one isn't. This reads no cells, so a page that passes can still have cells that
+  /// overlap, which [`RawPage::check_cells`] finds.
+  pub fn check_pointers(&self) -> Result<(), String> {
+    let (content_start, end) = self.content_area();
+    if !(self.pointers_end()..=end).contains(&self.cell_content_offset) {
+      return Err(format!(
+        "cell content offset {} out of range {}..={}",
+        self.cell_content_offset,
+        self.pointers_end(),
+        end
+      ));
+    }
+    for (i, offset) in self.cell_offsets.iter().enumerate() {
+      if !(content_start..end).contains(offset) {
+        return Err(format!("cell {} offset {} out of range {}..{}", i, offset, content_start, end));
+      }
+    }
+    self.freeblocks().map(|_| ())
+  }
+
+  /// Check that each cell ends within the page, and that no two cells, or a cell and a
+  /// freeblock, take up the same bytes, returning what's wrong if they do. Unlike
+  /// [`RawPage::check_pointers`], this decodes the start of every cell.
+  pub fn check_cells(&self) -> Result<(), String> {
+    let (_, end) = self.content_area();
+    // The offset and size of each cell or freeblock, and which cell it is.
+    let mut extents = Vec::with_capacity(self.cell_count());
+    for i in 0..self.cell_count() {
+      let (_, bytes) = self.cell_bytes(i).map_err(|_| format!("cell {} is malformed", i))?;
+      if self.cell_offsets[i] + bytes.len() > end {
+        return Err(format!("cell {} extends off the end of the page", i));
+      }
+      extents.push((self.cell_offsets[i], bytes.len(), Some(i)));
+    }
+    extents.extend(self.freeblocks()?.into_iter().map(|(offset, size)| (offset, size, None)));
+    extents.sort_unstable();
+    for pair in extents.windows(2) {
+      let ((offset, size, cell), (next_offset, _, next_cell)) = (pair[0], pair[1]);
+      if offset + size <= next_offset {
+        continue;
+      }
+      return Err(match (cell, next_cell) {
+        (Some(cell), Some(next_cell)) => format!("cells {} and {} overlap", cell.min(next_cell), cell.max(next_cell)),
+        (Some(cell), None) | (None, Some(cell)) => format!("cell {} overlaps a freeblock", cell),
+        (None, None) => unreachable!("freeblocks() rejects overlapping blocks"),
+      });
+    }
+    Ok(())
+  }
+
+  /// The page from the start of the `i`-th cell on. A cell pointer into the database header of the
+  /// first page, or past the end of the page, is an error.
+  fn cell_input(&self, i: usize) -> Result<&'input [u8], nom::Err<nom::error::Error<&'input [u8]>>> {
+    let offset = self.cell_offsets[i].checked_sub(self.page_start).ok_or_else(|| invalid(self.data))?;
+    let (cell_input, _) = take(offset)(self.data)?;
+    Ok(cell_input)
+  }
+
+  /// Decode the `i`-th cell in key order.
+  pub fn cell(
+    &self,
+    i: usize,
+    column_names: &[&str],
+    column_indices: &[usize],
+  ) -> IResult<&'input [u8], Cell> {
+    let cell_input = self.cell_input(i)?;
+    Cell::parse(
+      cell_input,
+      self.ty,
+      self.usable_page_size,
+      column_names,
+      column_indices,
+    )
+  }
+
+  /// The payload of the `i`-th cell of a leaf or index page, borrowed from the page.
+  pub fn cell_payload(&self, i: usize) -> IResult<&'input [u8], CellPayload<'input>> {
+    let cell_input = self.cell_input(i)?;
+    Cell::parse_cell_payload(cell_input, self.ty, self.usable_page_size)
+  }
+
+  /// The bytes of the `i`-th cell as stored on the page, including the number of its first
+  /// overflow page if it has one.
+  pub fn cell_bytes(&self, i: usize) -> IResult<&'input [u8], &'input [u8]> {
+    let cell_input = self.cell_input(i)?;
+    let rest = match self.ty {
+      BTreePageType::TableInterior => {
+        let (rest, _left_child_pointer) = be_u32(cell_input)?;
+        varint(rest)?.0
+      }
+      _ => Cell::parse_cell_payload(cell_input, self.ty, self.usable_page_size)?.0,
+    };
+    let cell_len = cell_input.len() - rest.len();
+    Ok((rest, &cell_input[..cell_len]))
+  }
+
+  /// The rowid of the `i`-th cell of a table page, without decoding its record.
+  pub fn row_id(&self, i: usize) -> IResult<&'input [u8], i64> {
+    let cell_input = self.cell_input(i)?;
+    match self.ty {
+      BTreePageType::TableInterior => {
+        let (cell_input, _left_child_pointer) = be_u32(cell_input)?;
+        varint(cell_input)
+      }
+      BTreePageType::TableLeaf => {
+        let (cell_input, _payload_size) = varint(cell_input)?;
+        varint(cell_input)
+      }
+      _ => unreachable!(),
+    }
+  }
+
+  /// Binary search for the first cell for which `compare` (comparing that cell's key against the
+  /// probe) doesn't return `Ordering::Less`, or `cell_count()` if there is none.
+  pub fn lower_bound<F>(&self, mut compare: F) -> crate::error::Result<usize>
+    where
+      F: FnMut(usize) -> crate::error::Result<std::cmp::Ordering>,
+  {
+    let (mut low, mut high) = (0, self.cell_count());
+    while low < high {
+      let mid = low + (high - low) / 2;
+      if compare(mid)? == std::cmp::Ordering::Less {
+        low = mid + 1;
+      } else {
+        high = mid;
+      }
+    }
+    Ok(low)
+  }
+}
+
+/// A b-tree page being modified in place.
+pub struct PageMut<'a> {
+  data: &'a mut [u8],
+  /// Where the b-tree page header starts: after the database header on the first page.
+  header_offset: usize,
+  usable_page_size: usize,
+}
+
+impl<'a> PageMut<'a> {
+  pub fn new(data: &'a mut [u8], is_first_page: bool, usable_page_size: usize) -> Self {
+    PageMut {
+      data,
+      header_offset: if is_first_page { 100 } else { 0 },
+      usable_page_size,
+    }
+  }
+
+  /// Whether a page of type `ty` can hold `cells`.
+  pub fn fits(&self, ty: BTreePageType, cells: &[Vec<u8>]) -> bool {
+    let cells_size = cells.iter().map(|cell| cell.len() + 2).sum::<usize>();
+    self.header_offset + Self::header_size(ty) + cells_size <= self.usable_page_size
+  }
+
+  /// Replace the contents of the page with `cells`, in key order, returning false without
+  /// modifying the page if they don't fit.
+  pub fn rebuild(
+    &mut self,
+    ty: BTreePageType,
+    cells: &[Vec<u8>],
+    rightmost_pointer: Option<u32>,
+  ) -> bool {
+    if !self.fits(ty, cells) {
+      return false;
+    }
+
+    self.data[self.header_offset..self.usable_page_size].fill(0);
+    self.data[self.header_offset] = match ty {
+      BTreePageType::IndexInterior => 0x02,
+      BTreePageType::TableInterior => 0x05,
+      BTreePageType::IndexLeaf => 0x0a,
+      BTreePageType::TableLeaf => 0x0d,
+    };
+    if let Some(rightmost_pointer) = rightmost_pointer {
+      self.data[self.header_offset + 8..self.header_offset + 12]
+        .copy_from_slice(&rightmost_pointer.to_be_bytes());
+    }
+
+    let mut cell_offset = self.usable_page_size;
+    let pointers_offset = self.cell_pointers_offset();
+    for (i, cell) in cells.iter().enumerate() {
+      cell_offset -= cell.len();
+      self.data[cell_offset..cell_offset + cell.len()].copy_from_slice(cell);
+      self.write_u16(pointers_offset + 2 * i, cell_offset);
+    }
+    self.write_u16(self.header_offset + 3, cells.len());
+    self.write_u16(self.header_offset + 5, cell_offset % 65536);
+    true
+  }
+
+  pub fn rightmost_pointer(&self) -> Option<u32> {
+    if !self.is_interior() {
+      return None;
+    }
+    let offset = self.header_offset + 8;
+    Some(u32::from_be_bytes(self.data[offset..offset + 4].try_into().unwrap()))
+  }
+
+  pub fn set_rightmost_pointer(&mut self, rightmost_pointer: u32) {
+    assert!(self.rightmost_pointer().is_some());
+    let offset = self.header_offset + 8;
+    self.data[offset..offset + 4].copy_from_slice(&rightmost_pointer.to_be_bytes());
+  }
+
+  /// The left child pointer of the `i`-th cell of an interior page.
+  pub fn left_child_pointer(&self, i: usize) -> u32 {
+    let offset = self.read_u16(self.cell_pointers_offset() + 2 * i);
+    u32::from_be_bytes(self.data[offset..offset + 4].try_into().unwrap())
+  }
+
+  fn header_size(ty: BTreePageType) -> usize {
+    match ty {
+      BTreePageType::TableInterior | BTreePageType::IndexInterior => 12,
+      BTreePageType::TableLeaf | BTreePageType::IndexLeaf => 8,
+    }
+  }
+
+  /// Whether this is an interior page, which has a rightmost pointer in its header. The page was
+  /// either parsed as a b-tree page before, or is being rebuilt as one.
+  fn is_interior(&self) -> bool {
+    matches!(self.data[self.header_offset], 0x02 | 0x05)
+  }
+
+  pub fn cell_count(&self) -> usize {
+    self.read_u16(self.header_offset + 3)
+  }
+
+  fn cell_content_offset(&self) -> usize {
+    match self.read_u16(self.header_offset + 5) {
+      0 => 65536,
+      offset => offset,
+    }
+  }
+
+  fn cell_pointers_offset(&self) -> usize {
+    self.header_offset + if self.is_interior() { 12 } else { 8 }
+  }
+
+  /// The size of the unallocated region between the cell pointer array and the cell content.
+  /// Free blocks within the cell content area aren't reused.
+  pub fn free_space(&self) -> usize {
+    self.cell_content_offset() - (self.cell_pointers_offset() + 2 * self.cell_count())
+  }
+
+  /// Insert `cell` so that it becomes the `i`-th cell in key order, returning false without
+  /// modifying the page if it doesn't fit.
+  pub fn insert_cell(&mut self, i: usize, cell: &[u8]) -> bool {
+    let cell_count = self.cell_count();
+    assert!(i <= cell_count);
+    if cell.len() + 2 > self.free_space() {
+      return false;
+    }
+
+    let cell_offset = self.cell_content_offset() - cell.len();
+    self.data[cell_offset..cell_offset + cell.len()].copy_from_slice(cell);
+
+    let pointer = self.cell_pointers_offset() + 2 * i;
+    let pointers_end = self.cell_pointers_offset() + 2 * cell_count;
+    self.data.copy_within(pointer..pointers_end, pointer + 2);
+    self.write_u16(pointer, cell_offset);
+
+    self.write_u16(self.header_offset + 3, cell_count + 1);
+    // An offset of 65536 is stored as 0.
+    self.write_u16(self.header_offset + 5, cell_offset % 65536);
+    true
+  }
+
+  /// Remove the `i`-th cell, which is `cell_len` bytes long, returning its space to the free
+  /// block list.
+  pub fn remove_cell(&mut self, i: usize, cell_len: usize) {
+    let cell_count = self.cell_count();
+    assert!(i < cell_count);
+    let pointer = self.cell_pointers_offset() + 2 * i;
+    let pointers_end = self.cell_pointers_offset() + 2 * cell_count;
+    let cell_offset = self.read_u16(pointer);
+    self.data.copy_within(pointer + 2..pointers_end, pointer);
+    self.write_u16(pointers_end - 2, 0);
+    self.write_u16(self.header_offset + 3, cell_count - 1);
+    self.free(cell_offset, cell_len);
+  }
+
+  /// Overwrite the `i`-th cell, which is `cell_len` bytes long, with `cell`, returning false
+  /// without modifying the page if it doesn't fit. The space left over becomes a free block, so
+  /// it can't be less than the 4 bytes of a free block header.
+  pub fn replace_cell(&mut self, i: usize, cell: &[u8], cell_len: usize) -> bool {
+    assert!(i < self.cell_count());
+    if cell.len() > cell_len || (cell.len() < cell_len && cell.len() + 4 > cell_len) {
+      return false;
+    }
+    let cell_offset = self.read_u16(self.cell_pointers_offset() + 2 * i);
+    self.data[cell_offset..cell_offset + cell.len()].copy_from_slice(cell);
+    if cell.len() < cell_len {
+      self.free(cell_offset + cell.len(), cell_len - cell.len());
+    }
+    true
+  }
+
+  /// Add `len` bytes at `offset` to the free block list, merging adjacent blocks. Free space at
+  /// the start of the cell content area is returned to the unallocated region instead.
+  fn free(&mut self, offset: usize, len: usize) {
+    self.data[offset..offset + len].fill(0);
+
+    // Each free block starts with the offset of the next one and its own size.
+    let mut blocks = Vec::new();
+    let mut block = self.read_u16(self.header_offset + 1);
+    while block != 0 {
+      blocks.push((block, self.read_u16(block + 2)));
+      block = self.read_u16(block);
+    }
+    blocks.push((offset, len));
+    blocks.sort_unstable();
+
+    // sqlite requires at least 4 bytes between free blocks, so a fragment between two of them is
+    // merged into the first, and so is one before the first block and the cell content area.
+    let mut fragmented_bytes = self.data[self.header_offset + 7] as usize;
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(blocks.len());
+    for (offset, len) in blocks {
+      match merged.last_mut() {
+        Some(last) if last.0 + last.1 + 4 > offset => {
+          fragmented_bytes = fragmented_bytes.saturating_sub(offset - (last.0 + last.1));
+          last.1 = offset + len - last.0;
+        }
+        _ => merged.push((offset, len)),
+      }
+    }
+
+    let mut cell_content_offset = self.cell_content_offset();
+    if merged.first().is_some_and(|block| block.0 < cell_content_offset + 4) {
+      let (offset, len) = merged.remove(0);
+      fragmented_bytes = fragmented_bytes.saturating_sub(offset - cell_content_offset);
+      cell_content_offset = offset + len;
+      self.write_u16(self.header_offset + 5, cell_content_offset % 65536);
+    }
+
+    // A free block needs at least 4 bytes for its header, smaller gaps are counted as fragments.
+    merged.retain(|(_, len)| {
+      if *len < 4 {
+        fragmented_bytes += len;
+      }
+      *len >= 4
+    });
+    self.data[self.header_offset + 7] = fragmented_bytes as u8;
+
+    let mut next = self.header_offset + 1;
+    for (offset, len) in merged {
+      self.write_u16(next, offset);
+      self.write_u16(offset + 2, len);
+      next = offset;
+    }
+    self.write_u16(next, 0);
+  }
+
+  fn read_u16(&self, offset: usize) -> usize {
+    u16::from_be_bytes([self.data[offset], self.data[offset + 1]]) as usize
+  }
+
+  fn write_u16(&mut self, offset: usize, value: usize) {
+    self.data[offset..offset + 2].copy_from_slice(&(value as u16).to_be_bytes());
+  }
+}
+
 impl Page {
   pub fn parse<'input>(
     input: &'input [u8],
@@ -59,67 +547,210 @@ impl Page {
     column_indices: &[usize],
     usable_page_size: usize,
   ) -> IResult<&'input [u8], Self> {
-    let (input, page_type) = u8(input)?;
-    let page_type = PageType::try_from(page_type).expect("invalid page type");
-
-    let (input, cells, rightmost_pointer) = match &page_type {
-      PageType::BTree(b_tree_page_type) => {
-        let (input, _first_freelock) = be_u16(input)?;
-        let (input, cell_count) = be_u16(input)?;
-        let (input, cell_content_offset) = be_u16(input)?;
-        let _cell_content_offset = if cell_content_offset == 0 {
-          65536
-        } else {
-          cell_content_offset as usize
-        };
-
-        let (input, _num_fragmented_free_bytes) = u8(input)?;
-        let (input, rightmost_pointer) = if matches!(b_tree_page_type,BTreePageType::IndexInterior | BTreePageType::TableInterior) {
-          let (input, rightmost_pointer) = be_u32(input)?;
-          (input, Some(rightmost_pointer as usize))
-        } else {
-          (input, None)
-        };
-
-        let (input, mut cell_pointers) = count(be_u16, cell_count as usize)(input)?;
-        cell_pointers.sort_unstable();
-
-        let mut position = if is_first_page { 100 } else { 0 } + if matches!( b_tree_page_type, BTreePageType::IndexInterior | BTreePageType::TableInterior) {
-          12
-        } else {
-          8
-        } + cell_count as usize * 2;
-
-        let mut rest = input;
-        let mut cells = Vec::with_capacity(cell_count as usize);
-        for cell_offset in cell_pointers.iter() {
-          let (remainder, _) = take(*cell_offset as usize - position)(rest)?;
-          position = *cell_offset as usize;
-          rest = remainder;
-          let (remainder, cell) = Cell::parse(
-            rest,
-            *b_tree_page_type,
-            usable_page_size,
-            column_names,
-            column_indices,
-          )?;
-          cells.push(cell);
-          let cell_size = rest.len() - remainder.len();
-          rest = remainder;
-          position += cell_size;
-        }
-        (input, cells, rightmost_pointer)
-      }
-      _ => todo!("non b-tree page"),
-    };
+    let (input, raw_page) = RawPage::parse(input, is_first_page, usable_page_size)?;
+
+    let mut cells = Vec::with_capacity(raw_page.cell_count());
+    for i in 0..raw_page.cell_count() {
+      let (_, cell) = raw_page.cell(i, column_names, column_indices)?;
+      cells.push(cell);
+    }
 
     Ok((
       input,
       Page {
-        ty: page_type,
+        ty: PageType::BTree(raw_page.ty),
         cells,
-        rightmost_pointer,
+        rightmost_pointer: raw_page.rightmost_pointer,
       },
     ))
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::{page_offset, PageMut, RawPage};
+  use crate::error::Error;
+
+  #[test]
+  fn page_offsets_past_4gib_do_not_wrap() {
+    assert_eq!(page_offset(4096, 1).unwrap(), 0);
+    assert_eq!(page_offset(4096, 1 << 20).unwrap(), (1 << 32) - 4096);
+    assert_eq!(page_offset(4096, (1 << 20) + 1).unwrap(), 1 << 32);
+    // The largest database: 2^32 - 1 pages of 64KB.
+    assert_eq!(page_offset(65536, u32::MAX as usize).unwrap(), (u32::MAX as u64 - 1) << 16);
+    assert!(matches!(page_offset(4096, 0), Err(Error::Corrupt { page: 0, .. })));
+    #[cfg(target_pointer_width = "64")]
+    assert!(matches!(page_offset(4096, 1 << 32), Err(Error::Corrupt { .. })));
+  }
+
+  /// Build a 4KB table interior page with `cell_count` cells, cell `i` having key `10 * i`.
+  fn table_interior_page(cell_count: usize) -> Vec<u8> {
+    let mut page = vec![0u8; 4096];
+    page[0] = 0x05;
+    page[3..5].copy_from_slice(&(cell_count as u16).to_be_bytes());
+    page[8..12].copy_from_slice(&9999u32.to_be_bytes());
+
+    let mut content_offset = page.len();
+    for i in 0..cell_count {
+      let key = (10 * i) as u16;
+      let child = (i + 2) as u16;
+      // Left child pointer followed by a two byte varint key.
+      let cell = [
+        0,
+        0,
+        (child >> 8) as u8,
+        child as u8,
+        0x80 | (key >> 7) as u8,
+        (key & 0x7f) as u8,
+      ];
+      content_offset -= cell.len();
+      page[content_offset..content_offset + cell.len()].copy_from_slice(&cell);
+      page[12 + 2 * i..14 + 2 * i].copy_from_slice(&(content_offset as u16).to_be_bytes());
+    }
+    page[5..7].copy_from_slice(&(content_offset as u16).to_be_bytes());
+    page
+  }
+
+  #[test]
+  fn lower_bound_on_interior_page() {
+    let data = table_interior_page(400);
+    let (_, page) = RawPage::parse(&data, false, 4096).unwrap();
+    assert_eq!(page.cell_count(), 400);
+    assert_eq!(page.rightmost_pointer, Some(9999));
+
+    for (row_id, expected) in [(0, 0), (1, 1), (2000, 200), (2005, 201), (3990, 399), (4000, 400)] {
+      let mut comparisons = 0;
+      let i = page
+        .lower_bound(|i| {
+          comparisons += 1;
+          Ok(page.row_id(i).unwrap().1.cmp(&row_id))
+        })
+        .unwrap();
+      assert_eq!(i, expected);
+      assert!(comparisons <= 9, "{} comparisons for {}", comparisons, row_id);
+    }
+  }
+
+  #[test]
+  fn malformed_pages_are_errors() {
+    // Found by fuzzing: a page type that isn't a b-tree's, and a cell pointer into the database
+    // header on the first page, used to panic.
+    assert!(RawPage::parse(&[0x00; 8], false, 512).is_err());
+    let data = [0x0d, 0x00, 0x00, 0x00, 0x03, 0xcb, 0x00, 0x01, 0x4e, 0x00, 0x00, 0x00, 0x00, 0x00];
+    let (_, page) = RawPage::parse(&data, true, 512).unwrap();
+    assert_eq!(page.cell_count(), 3);
+    assert!(page.cell(1, &[], &[]).is_err());
+    assert!(page.cell_payload(1).is_err());
+    assert!(page.row_id(1).is_err());
+  }
+
+  #[test]
+  fn insert_cell_keeps_pointers_in_key_order() {
+    let mut data = vec![0u8; 512];
+    data[0] = 0x0d;
+    data[5..7].copy_from_slice(&512u16.to_be_bytes());
+
+    // Leaf cells holding a one byte payload: size, rowid, then a record with no columns.
+    let mut page = PageMut::new(&mut data, false, 512);
+    for (i, row_id) in [(0, 20u8), (0, 10), (2, 40), (2, 30)] {
+      assert!(page.insert_cell(i, &[1, row_id, 1]));
+    }
+    assert_eq!(page.cell_count(), 4);
+    assert_eq!(page.free_space(), 512 - 8 - 4 * 2 - 4 * 3);
+
+    let (_, raw_page) = RawPage::parse(&data, false, 512).unwrap();
+    let row_ids = (0..4).map(|i| raw_page.row_id(i).unwrap().1).collect::<Vec<_>>();
+    assert_eq!(row_ids, [10, 20, 30, 40]);
+
+    let mut page = PageMut::new(&mut data, false, 512);
+    let free_space = page.free_space();
+    assert!(!page.insert_cell(0, &vec![0; free_space - 1]));
+    assert_eq!(page.cell_count(), 4);
+    assert!(page.insert_cell(0, &vec![0; free_space - 2]));
+    assert_eq!(page.free_space(), 0);
+  }
+
+  #[test]
+  fn removed_cells_become_free_blocks() {
+    let mut data = vec![0u8; 512];
+    data[0] = 0x0d;
+    data[5..7].copy_from_slice(&512u16.to_be_bytes());
+
+    // Leaf cells with a 3 byte payload: size, rowid, then a record with one 1-byte integer.
+    let mut page = PageMut::new(&mut data, false, 512);
+    for (i, row_id) in [10u8, 20, 30, 40].into_iter().enumerate() {
+      assert!(page.insert_cell(i, &[3, row_id, 2, 1, row_id]));
+    }
+    // Cells are placed from the end of the page, so rowid 40 is at the start of the content.
+    page.remove_cell(3, 5);
+    assert_eq!(page.cell_content_offset(), 512 - 3 * 5);
+    page.remove_cell(1, 5);
+    page.remove_cell(0, 5);
+    assert_eq!(page.cell_count(), 1);
+    assert_eq!(page.free_space(), 512 - 8 - 2 - 3 * 5);
+    // Rowids 10 and 20 were adjacent, so they were merged into a single block.
+    assert_eq!(page.read_u16(1), 512 - 2 * 5);
+    assert_eq!(page.read_u16(512 - 2 * 5), 0);
+    assert_eq!(page.read_u16(512 - 2 * 5 + 2), 10);
+
+    // Freeing the space below the free block merges it all back into the unallocated region.
+    page.remove_cell(0, 5);
+    assert_eq!(page.read_u16(1), 0);
+    assert_eq!(page.cell_content_offset(), 512);
+
+    let (_, raw_page) = RawPage::parse(&data, false, 512).unwrap();
+    assert_eq!(raw_page.cell_count(), 0);
+  }
+
+  #[test]
+  fn fragments_between_free_blocks_are_merged() {
+    let mut data = vec![0u8; 512];
+    data[0] = 0x0d;
+    data[5..7].copy_from_slice(&512u16.to_be_bytes());
+    let mut page = PageMut::new(&mut data, false, 512);
+    for (i, len) in [5, 2, 5, 5].into_iter().enumerate() {
+      assert!(page.insert_cell(i, &vec![1; len]));
+    }
+    // The 2 byte cell at 505 is too small for a free block, so it's counted as fragmented.
+    page.remove_cell(1, 2);
+    assert_eq!(page.read_u16(1), 0);
+    assert_eq!(page.data[7], 2);
+
+    // sqlite rejects free blocks less than 4 bytes apart, so the fragment joins the blocks.
+    page.remove_cell(0, 5);
+    page.remove_cell(0, 5);
+    assert_eq!(page.read_u16(1), 500);
+    assert_eq!((page.read_u16(500), page.read_u16(502)), (0, 12));
+    assert_eq!(page.data[7], 0);
+  }
+
+  #[test]
+  fn cells_and_freeblocks_stay_within_the_page() {
+    let mut data = vec![0u8; 512];
+    data[0] = 0x0d;
+    data[5..7].copy_from_slice(&512u16.to_be_bytes());
+    let mut page = PageMut::new(&mut data, false, 512);
+    for (i, row_id) in [10u8, 20, 30, 40].into_iter().enumerate() {
+      assert!(page.insert_cell(i, &[3, row_id, 2, 1, row_id]));
+    }
+    page.remove_cell(1, 5);
+    let (_, raw_page) = RawPage::parse(&data, false, 512).unwrap();
+    assert_eq!(raw_page.freeblocks(), Ok(vec![(502, 5)]));
+    assert_eq!((raw_page.check_pointers(), raw_page.check_cells()), (Ok(()), Ok(())));
+
+    // Rowid 10's cell, the last on the page, runs into the bytes reserved at the end of it.
+    let (_, raw_page) = RawPage::parse(&data, false, 510).unwrap();
+    assert_eq!(raw_page.check_pointers(), Ok(()));
+    assert_eq!(raw_page.check_cells(), Err("cell 0 extends off the end of the page".to_string()));
+
+    // A freeblock pointing at itself, and one too small to hold its own header.
+    let mut corrupt = data.clone();
+    corrupt[502..504].copy_from_slice(&502u16.to_be_bytes());
+    let (_, raw_page) = RawPage::parse(&corrupt, false, 512).unwrap();
+    assert_eq!(raw_page.check_pointers(), Err("freeblock at offset 502 out of order after 502".to_string()));
+    let mut corrupt = data;
+    corrupt[504..506].copy_from_slice(&3u16.to_be_bytes());
+    let (_, raw_page) = RawPage::parse(&corrupt, false, 512).unwrap();
+    assert_eq!(raw_page.check_pointers(), Err("freeblock at offset 502 extends off the end of the page".to_string()));
+  }
+}
@@ -0,0 +1,307 @@
+//! A description of a page as stored, for `.pagedump`: its header, its cell pointers, and what
+//! can be made of each cell. Unlike the readers used by queries, it doesn't stop at the first
+//! thing it can't decode, but notes it against the cell and carries on.
+
+use std::fmt::Display;
+
+use nom::number::complete::be_u32;
+
+use crate::{
+  cell::{Cell, CellPayload},
+  database::Database,
+  error::{Error, Result},
+  expr::to_sql_literal,
+  header::HEADER_SIZE,
+  page::BTreePageType,
+  record::{RecordRaw, RecordType, Value},
+  varint::varint,
+};
+
+/// How many bytes of each cell's payload are shown.
+const PAYLOAD_PREFIX: usize = 16;
+
+/// A page's b-tree header and cells, as returned by [`Database::debug_page`].
+#[derive(Debug)]
+pub struct PageDebug {
+  pub page: usize,
+  /// The page type byte, which is only a b-tree page type for b-tree pages.
+  pub page_type: u8,
+  pub first_freeblock: u16,
+  pub cell_count: u16,
+  pub cell_content_offset: u16,
+  pub fragmented_free_bytes: u8,
+  pub rightmost_pointer: Option<u32>,
+  pub cell_pointers: Vec<u16>,
+  pub cells: Vec<CellDebug>,
+}
+
+/// One cell of a [`PageDebug`], with whatever could be decoded of it.
+#[derive(Debug, Default)]
+pub struct CellDebug {
+  /// The offset of the cell from the start of the page.
+  pub offset: usize,
+  pub left_child_pointer: Option<u32>,
+  pub payload_size: Option<usize>,
+  pub row_id: Option<i64>,
+  pub first_overflow_page: Option<usize>,
+  pub serial_types: Vec<i64>,
+  /// The record's values: a table row, or an index key followed by its rowid.
+  pub values: Option<Vec<Value>>,
+  /// The start of the payload stored on the page.
+  pub payload_prefix: Vec<u8>,
+  /// What stopped the cell from being decoded any further.
+  pub error: Option<String>,
+}
+
+fn page_type_name(page_type: u8) -> &'static str {
+  match page_type {
+    0x02 => "index interior",
+    0x05 => "table interior",
+    0x0a => "index leaf",
+    0x0d => "table leaf",
+    _ => "not a b-tree page",
+  }
+}
+
+impl Database {
+  /// Describe page `page_index` for debugging. Only reading the page can fail: anything wrong
+  /// with its contents is reported in the description.
+  pub fn debug_page<R>(&self, file: R, page_index: usize) -> Result<PageDebug>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    if page_index == 0 || page_index > self.header.size_in_pages {
+      return Err(Error::Corrupt {
+        page: page_index,
+        reason: "page number out of range".to_string(),
+      });
+    }
+    let page_data = self.read_page_data(file, page_index, RecordType::Table)?;
+    let data = &page_data[..self.usable_page_size()];
+    let header_offset = if page_index == 1 { HEADER_SIZE } else { 0 };
+    let header = &data[header_offset..];
+    let read_u16 = |offset: usize| u16::from_be_bytes([header[offset], header[offset + 1]]);
+
+    let page_type = header[0];
+    let ty = match page_type {
+      0x02 => BTreePageType::IndexInterior,
+      0x05 => BTreePageType::TableInterior,
+      0x0a => BTreePageType::IndexLeaf,
+      0x0d => BTreePageType::TableLeaf,
+      _ => {
+        return Ok(PageDebug {
+          page: page_index,
+          page_type,
+          first_freeblock: 0,
+          cell_count: 0,
+          cell_content_offset: 0,
+          fragmented_free_bytes: 0,
+          rightmost_pointer: None,
+          cell_pointers: Vec::new(),
+          cells: Vec::new(),
+        })
+      }
+    };
+    let interior = matches!(ty, BTreePageType::TableInterior | BTreePageType::IndexInterior);
+    let header_size = if interior { 12 } else { 8 };
+    let cell_count = read_u16(3);
+    // The pointer array is cut short where it would run off the page.
+    let pointer_count = (cell_count as usize).min((header.len() - header_size) / 2);
+    let cell_pointers = (0..pointer_count).map(|i| read_u16(header_size + 2 * i)).collect::<Vec<_>>();
+    let cells = cell_pointers
+      .iter()
+      .map(|offset| self.debug_cell(data, ty, *offset as usize, header_offset + header_size + 2 * pointer_count))
+      .collect();
+
+    Ok(PageDebug {
+      page: page_index,
+      page_type,
+      first_freeblock: read_u16(1),
+      cell_count,
+      cell_content_offset: read_u16(5),
+      fragmented_free_bytes: header[7],
+      rightmost_pointer: interior.then(|| u32::from_be_bytes(header[8..12].try_into().unwrap())),
+      cell_pointers,
+      cells,
+    })
+  }
+
+  /// Decode as much as possible of the cell at `offset` of a page of type `ty`, whose header and
+  /// cell pointers take up the page up to `content_start`.
+  fn debug_cell(&self, data: &[u8], ty: BTreePageType, offset: usize, content_start: usize) -> CellDebug {
+    let mut cell = CellDebug {
+      offset,
+      ..CellDebug::default()
+    };
+    if offset < content_start || offset >= data.len() {
+      cell.error = Some(format!("offset outside the cell content area ({}..{})", content_start, data.len()));
+      return cell;
+    }
+    let input = &data[offset..];
+
+    if matches!(ty, BTreePageType::TableInterior) {
+      match be_u32::<_, nom::error::Error<&[u8]>>(input).and_then(|(rest, pointer)| Ok((pointer, varint(rest)?.1))) {
+        Ok((left_child_pointer, key)) => {
+          cell.left_child_pointer = Some(left_child_pointer);
+          cell.row_id = Some(key);
+        }
+        Err(_) => cell.error = Some("cell runs past the end of the page".to_string()),
+      }
+      return cell;
+    }
+
+    match Cell::parse_cell_payload(input, ty, self.usable_page_size()) {
+      Ok((_, CellPayload::Local { left_child_pointer, row_id, payload })) => {
+        cell.left_child_pointer = left_child_pointer;
+        cell.row_id = row_id;
+        cell.payload_size = Some(payload.len());
+        debug_record(cell, payload)
+      }
+      Ok((_, CellPayload::Overflow(overflow))) => {
+        cell.left_child_pointer = overflow.left_child_pointer;
+        cell.row_id = overflow.row_id;
+        cell.payload_size = Some(overflow.payload_size);
+        cell.first_overflow_page = Some(overflow.first_overflow_page);
+        debug_record(cell, &overflow.local_payload)
+      }
+      Err(_) => {
+        cell.error = Some("cell runs past the end of the page".to_string());
+        cell
+      }
+    }
+  }
+}
+
+/// Fill in the serial types, values and payload prefix of `cell` from `payload`, which is only
+/// the start of the record if the cell overflows.
+fn debug_record(mut cell: CellDebug, payload: &[u8]) -> CellDebug {
+  cell.payload_prefix = payload[..payload.len().min(PAYLOAD_PREFIX)].to_vec();
+
+  let mut serial_types = Vec::new();
+  let header = varint(payload).ok().and_then(|(mut rest, header_size)| {
+    let header_end = usize::try_from(header_size).ok().filter(|size| *size <= payload.len())?;
+    while payload.len() - rest.len() < header_end {
+      let (remainder, serial_type) = varint(rest).ok()?;
+      serial_types.push(serial_type);
+      rest = remainder;
+    }
+    Some(())
+  });
+  cell.serial_types = serial_types;
+  if header.is_none() {
+    cell.error = Some("record header runs past the payload".to_string());
+    return cell;
+  }
+
+  match (RecordRaw::parse(payload), cell.first_overflow_page) {
+    (Ok((_, record)), _) => cell.values = Some(record.to_record(None).values),
+    (Err(_), Some(first_overflow_page)) => {
+      cell.error = Some(format!("the rest of the record is on overflow page {}", first_overflow_page))
+    }
+    (Err(_), None) => cell.error = Some("failed to decode record values".to_string()),
+  }
+  cell
+}
+
+impl Display for PageDebug {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    writeln!(f, "page {}: {} (type 0x{:02x})", self.page, page_type_name(self.page_type), self.page_type)?;
+    if page_type_name(self.page_type) == "not a b-tree page" {
+      return Ok(());
+    }
+    writeln!(f, "first freeblock: {}", self.first_freeblock)?;
+    writeln!(f, "cell count: {}", self.cell_count)?;
+    writeln!(f, "cell content offset: {}", self.cell_content_offset)?;
+    writeln!(f, "fragmented free bytes: {}", self.fragmented_free_bytes)?;
+    if let Some(rightmost_pointer) = self.rightmost_pointer {
+      writeln!(f, "rightmost pointer: {}", rightmost_pointer)?;
+    }
+    let pointers = self.cell_pointers.iter().map(|offset| offset.to_string()).collect::<Vec<_>>();
+    writeln!(f, "cell pointers: {}", pointers.join(" "))?;
+
+    for (i, cell) in self.cells.iter().enumerate() {
+      let mut fields = vec![format!("cell {} at {}", i, cell.offset)];
+      if let Some(left_child_pointer) = cell.left_child_pointer {
+        fields.push(format!("left child {}", left_child_pointer));
+      }
+      if let Some(row_id) = cell.row_id {
+        fields.push(format!("rowid {}", row_id));
+      }
+      if let Some(payload_size) = cell.payload_size {
+        fields.push(format!("payload {} bytes", payload_size));
+      }
+      if let Some(first_overflow_page) = cell.first_overflow_page {
+        fields.push(format!("overflow page {}", first_overflow_page));
+      }
+      writeln!(f, "{}", fields.join(", "))?;
+      if cell.payload_size.is_some() {
+        let serial_types = cell.serial_types.iter().map(|ty| ty.to_string()).collect::<Vec<_>>();
+        writeln!(f, "  serial types: {}", serial_types.join(" "))?;
+      }
+      if let Some(values) = &cell.values {
+        let values = values.iter().map(to_sql_literal).collect::<Vec<_>>();
+        writeln!(f, "  values: {}", values.join(", "))?;
+      }
+      if !cell.payload_prefix.is_empty() {
+        let bytes = cell.payload_prefix.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>();
+        let more = if cell.payload_size.unwrap_or(0) > cell.payload_prefix.len() { " ..." } else { "" };
+        writeln!(f, "  bytes: {}{}", bytes.join(" "), more)?;
+      }
+      if let Some(error) = &cell.error {
+        writeln!(f, "  error: {}", error)?;
+      }
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::database::Database;
+
+  #[test]
+  fn pages_are_described_cell_by_cell() {
+    let mut data = std::fs::read("tests/fixtures/interior.db").unwrap();
+    let db = Database::parse_header_and_schema(std::io::Cursor::new(&data)).unwrap();
+    let page = db.debug_page(std::io::Cursor::new(&data), 2).unwrap();
+    assert_eq!((page.rightmost_pointer, page.cells.len()), (Some(5), 1));
+    assert_eq!((page.cells[0].left_child_pointer, page.cells[0].row_id), (Some(4), Some(15)));
+
+    let page = db.debug_page(std::io::Cursor::new(&data), 4).unwrap();
+    assert_eq!(page.cell_count, 15);
+    let cell = &page.cells[1];
+    assert_eq!((cell.row_id, cell.payload_size), (Some(2), Some(24)));
+    assert_eq!(cell.serial_types, [0, 19, 1, 43]);
+    assert_eq!(
+      page.to_string().lines().skip(10).take(4).collect::<Vec<_>>(),
+      [
+        "cell 1 at 456, rowid 2, payload 24 bytes",
+        "  serial types: 0 19 1 43",
+        "  values: NULL, 'Bob', 45, 'bob@example.com'",
+        "  bytes: 05 00 13 01 2b 42 6f 62 2d 62 6f 62 40 65 78 61 ...",
+      ]
+    );
+    assert!(db.debug_page(std::io::Cursor::new(&data), 0).is_err());
+    assert!(db.debug_page(std::io::Cursor::new(&data), db.header.size_in_pages + 1).is_err());
+
+    // A pointer into the page header, and a record header claiming a serial type for a text
+    // longer than the payload, are noted against their cells while the others decode as usual.
+    let page_start = 3 * db.header.page_size;
+    data[page_start + 8..page_start + 10].copy_from_slice(&4u16.to_be_bytes());
+    data[page_start + 456 + 6] = 0x7f;
+    let db = Database::parse_header_and_schema(std::io::Cursor::new(&data)).unwrap();
+    let page = db.debug_page(std::io::Cursor::new(&data), 4).unwrap();
+    assert_eq!(page.cells[0].error.as_deref(), Some("offset outside the cell content area (38..512)"));
+    assert_eq!(page.cells[1].serial_types, [0, 19, 1, 127]);
+    assert_eq!(page.cells[1].error.as_deref(), Some("failed to decode record values"));
+    assert!(page.cells[2].values.is_some() && page.cells[2].error.is_none());
+
+    // Only the start of an overflowing record is on the page.
+    let data = std::fs::read("tests/fixtures/documents.db").unwrap();
+    let db = Database::parse_header_and_schema(std::io::Cursor::new(&data)).unwrap();
+    let cell = &db.debug_page(std::io::Cursor::new(&data), 7).unwrap().cells[0];
+    assert_eq!((cell.payload_size, cell.first_overflow_page), (Some(10256), Some(3)));
+    assert_eq!(cell.serial_types, [0, 33, 20492]);
+    assert_eq!(cell.error.as_deref(), Some("the rest of the record is on overflow page 3"));
+  }
+}
@@ -0,0 +1,198 @@
+/// The wildcards of a LIKE or GLOB pattern, and whether it ignores case.
+struct Syntax {
+  match_all: char,
+  match_one: char,
+  /// Whether `[...]` matches one character from a set.
+  has_sets: bool,
+  /// Whether ASCII letters match regardless of case.
+  ignore_case: bool,
+  /// The character making the one after it match itself, even a wildcard or the escape itself.
+  escape: Option<char>,
+}
+
+const LIKE: Syntax = Syntax {
+  match_all: '%',
+  match_one: '_',
+  has_sets: false,
+  ignore_case: true,
+  escape: None,
+};
+
+const GLOB: Syntax = Syntax {
+  match_all: '*',
+  match_one: '?',
+  has_sets: true,
+  ignore_case: false,
+  escape: None,
+};
+
+/// Whether `text` matches the LIKE `pattern`, where `%` matches any run of characters and `_`
+/// any one, ignoring the case of ASCII letters.
+pub fn like(pattern: &str, text: &str) -> bool {
+  matches(&chars(pattern), &chars(text), &LIKE)
+}
+
+/// Like [`like`], with `escape` making the character after it match only itself. A pattern
+/// ending in `escape` matches nothing.
+pub fn like_escaped(pattern: &str, text: &str, escape: char) -> bool {
+  let syntax = Syntax {
+    escape: Some(escape),
+    ..LIKE
+  };
+  matches(&chars(pattern), &chars(text), &syntax)
+}
+
+/// Whether `text` matches the GLOB `pattern`, where `*` matches any run of characters, `?` any
+/// one, and `[...]` any one in the set, which may hold ranges like `a-z` and is negated by a
+/// leading `^`. Case matters.
+pub fn glob(pattern: &str, text: &str) -> bool {
+  matches(&chars(pattern), &chars(text), &GLOB)
+}
+
+fn chars(s: &str) -> Vec<char> {
+  s.chars().collect()
+}
+
+fn matches(pattern: &[char], text: &[char], syntax: &Syntax) -> bool {
+  let mut t = 0;
+  let mut p = 0;
+  while p < pattern.len() {
+    let c = pattern[p];
+    if Some(c) == syntax.escape {
+      let Some(&escaped) = pattern.get(p + 1) else {
+        return false;
+      };
+      if t == text.len() || !(escaped == text[t] || (syntax.ignore_case && escaped.eq_ignore_ascii_case(&text[t]))) {
+        return false;
+      }
+      p += 2;
+      t += 1;
+      continue;
+    }
+    if c == syntax.match_all {
+      // A run of wildcards matches as many characters as it has single-character ones, and
+      // then any number more, so try every split of the rest of the text.
+      while p < pattern.len()
+        && Some(pattern[p]) != syntax.escape
+        && (pattern[p] == syntax.match_all || pattern[p] == syntax.match_one)
+      {
+        if pattern[p] == syntax.match_one {
+          if t == text.len() {
+            return false;
+          }
+          t += 1;
+        }
+        p += 1;
+      }
+      return (t..=text.len()).any(|start| matches(&pattern[p..], &text[start..], syntax));
+    }
+
+    if t == text.len() {
+      return false;
+    }
+    if c == syntax.match_one {
+      p += 1;
+    } else if c == '[' && syntax.has_sets {
+      match match_set(&pattern[p + 1..], text[t]) {
+        Some((true, set_len)) => p += 1 + set_len,
+        _ => return false,
+      }
+    } else if c == text[t] || (syntax.ignore_case && c.eq_ignore_ascii_case(&text[t])) {
+      p += 1;
+    } else {
+      return false;
+    }
+    t += 1;
+  }
+  t == text.len()
+}
+
+/// Match `c` against the set starting just after a `[`, returning whether it's in the set and
+/// the length of the rest of the set including its `]`, or `None` if the set isn't closed.
+fn match_set(set: &[char], c: char) -> Option<(bool, usize)> {
+  let mut i = 0;
+  let negated = set.first() == Some(&'^');
+  if negated {
+    i += 1;
+  }
+  let mut found = false;
+  // A `]` straight after the opening `[` (or `[^`) is a member rather than the end.
+  let mut first = true;
+  loop {
+    let member = *set.get(i)?;
+    if member == ']' && !first {
+      return Some((found != negated, i + 1));
+    }
+    first = false;
+    match (set.get(i + 1), set.get(i + 2)) {
+      (Some('-'), Some(&end)) if end != ']' => {
+        found |= (member..=end).contains(&c);
+        i += 3;
+      }
+      _ => {
+        found |= member == c;
+        i += 1;
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{glob, like, like_escaped};
+
+  #[test]
+  fn like_ignores_case_and_glob_does_not() {
+    assert!(like("dav%", "Dave"));
+    assert!(!glob("dav*", "Dave"));
+    assert!(glob("Dav*", "Dave"));
+    assert!(like("_a_e", "Dave"));
+    assert!(glob("?a?e", "Dave"));
+    assert!(!like("_a_", "Dave"));
+    assert!(like("%", ""));
+    assert!(like("%a%%", "banana"));
+    assert!(!like("%x%", "banana"));
+    assert!(like("a_%", "ab"));
+    assert!(!like("a_%", "a"));
+    // Each syntax's wildcards are plain characters to the other.
+    assert!(!like("a*", "ab"));
+    assert!(glob("100%", "100%"));
+    assert!(!glob("100%", "1000"));
+  }
+
+  #[test]
+  fn glob_sets() {
+    assert!(glob("[A-C]*", "Bob"));
+    assert!(!glob("[A-C]*", "Dave"));
+    assert!(glob("[^A-C]*", "Dave"));
+    assert!(glob("x[]]y", "x]y"));
+    assert!(glob("x[^]]y", "x-y"));
+    assert!(glob("[a-]", "-"));
+    assert!(glob("[0-9][0-9]", "42"));
+    assert!(!glob("[0-9]", "42"));
+    assert!(!glob("[abc", "a"));
+    assert!(!like("[a]", "a"));
+    assert!(like("[a]", "[A]"));
+  }
+
+  #[test]
+  fn like_escapes_match_themselves() {
+    // The expected results are those of sqlite3.
+    assert!(like_escaped("50\\%", "50%", '\\'));
+    assert!(!like_escaped("50\\%", "50x", '\\'));
+    assert!(like_escaped("50\\%%", "50% off", '\\'));
+    assert!(like_escaped("a!_b", "a_b", '!'));
+    assert!(!like_escaped("a!_b", "axb", '!'));
+    assert!(like_escaped("a\\\\b", "a\\b", '\\'));
+    // An escaped character that isn't a wildcard is matched as itself, still ignoring case.
+    assert!(like_escaped("\\a\\b", "AB", '\\'));
+    // A pattern ending in the escape character matches nothing.
+    assert!(!like_escaped("a\\", "a\\", '\\'));
+    assert!(!like_escaped("a\\", "a", '\\'));
+    // A wildcard can be the escape character, and then isn't a wildcard.
+    assert!(like_escaped("%%", "%", '%'));
+    assert!(!like_escaped("%%", "x", '%'));
+    assert!(like_escaped("a%%", "a%", '%'));
+    assert!(!like_escaped("a%", "ab", '%'));
+  }
+}
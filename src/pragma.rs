@@ -0,0 +1,333 @@
+use std::sync::Arc;
+
+use crate::{
+  database::{automatic_index_key, Database, ObjectSchema},
+  error::{Error, Result},
+  header::TextEncoding,
+  query::{parse_identifier, parse_literal, strip_keyword, CreateQuery, Query},
+  record::{Record, Value},
+  row::{ResultSet, Row},
+};
+
+/// A PRAGMA reading the schema or the header, the read-only subset tools probe a database with.
+#[derive(Debug)]
+pub struct PragmaQuery {
+  pub name: String,
+  /// The table or index named in parentheses, for the pragmas about one.
+  pub argument: Option<String>,
+  /// The value assigned with `= value`, for the pragmas that can be set.
+  pub value: Option<i32>,
+}
+
+/// The pragmas that can be set, each a 32-bit integer in the header.
+const SETTABLE_PRAGMAS: [&str; 2] = ["user_version", "application_id"];
+
+impl PragmaQuery {
+  /// Parse `PRAGMA [main.]name [(argument)]`, or `PRAGMA [main.]name = value` for one of
+  /// [`SETTABLE_PRAGMAS`].
+  pub fn parse(query_str: &str) -> Result<Self> {
+    let unsupported = || Error::UnsupportedSql(query_str.to_owned());
+    let body = strip_keyword(query_str.trim().trim_end_matches(';').trim_end(), "pragma").ok_or_else(unsupported)?;
+    let body = match body.get(.."main.".len()) {
+      Some(prefix) if prefix.eq_ignore_ascii_case("main.") => &body[prefix.len()..],
+      _ => body,
+    };
+    let (name, rest) = parse_identifier(body).ok_or_else(unsupported)?;
+
+    let rest = rest.trim();
+    if let Some(value) = rest.strip_prefix('=') {
+      if !SETTABLE_PRAGMAS.iter().any(|pragma| name.eq_ignore_ascii_case(pragma)) {
+        return Err(unsupported());
+      }
+      // Like SQLite, only the low 32 bits of the value are kept.
+      let value = match parse_literal(value.trim()) {
+        Some((Value::Integer(n), "")) => n as i32,
+        _ => return Err(unsupported()),
+      };
+      return Ok(PragmaQuery {
+        name: name.to_owned(),
+        argument: None,
+        value: Some(value),
+      });
+    }
+    let argument = if rest.is_empty() {
+      None
+    } else {
+      let argument = rest
+        .strip_prefix('(')
+        .and_then(|rest| rest.strip_suffix(')'))
+        .ok_or_else(unsupported)?
+        .trim();
+      // The name may be given as a string as well as an identifier.
+      let argument = match parse_literal(argument) {
+        Some((Value::Text(text), "")) => text,
+        _ => match parse_identifier(argument) {
+          Some((name, "")) => name.to_owned(),
+          _ => return Err(unsupported()),
+        },
+      };
+      Some(argument)
+    };
+
+    Ok(PragmaQuery {
+      name: name.to_owned(),
+      argument,
+      value: None,
+    })
+  }
+
+  /// Run the pragma. Like SQLite, an unknown pragma, or one about a table or index that doesn't
+  /// exist, returns no rows rather than failing.
+  pub fn execute<R>(&self, db: &Database, file: R) -> Result<ResultSet>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    if self.value.is_some() {
+      return Err(Error::UnsupportedSql(format!("setting PRAGMA {} in a query", self.name)));
+    }
+    let header = &db.header;
+    let (column_names, rows): (&[&str], _) = match self.name.to_ascii_lowercase().as_str() {
+      "table_info" => (&["cid", "name", "type", "notnull", "dflt_value", "pk"], self.table_info(db)?),
+      "index_list" => (&["seq", "name", "unique", "origin", "partial"], self.index_list(db)?),
+      "index_info" => (&["seqno", "cid", "name"], self.index_info(db)?),
+      "page_size" => (&["page_size"], vec![vec![Value::Integer(header.page_size as i64)]]),
+      "encoding" => {
+        let encoding = match header.text_encoding {
+          TextEncoding::Utf8 => "UTF-8",
+          TextEncoding::Utf16le => "UTF-16le",
+          TextEncoding::Utf16be => "UTF-16be",
+        };
+        (&["encoding"], vec![vec![Value::Text(encoding.to_owned())]])
+      }
+      "user_version" => (&["user_version"], vec![vec![Value::Integer(db.user_version().into())]]),
+      "application_id" => (&["application_id"], vec![vec![Value::Integer(db.application_id().into())]]),
+      "freelist_count" => (&["freelist_count"], vec![vec![Value::Integer(header.num_freelist_pages as i64)]]),
+      "integrity_check" => {
+        let mut problems = db.check_integrity(file)?;
+        if problems.is_empty() {
+          problems.push("ok".to_owned());
+        }
+        (&["integrity_check"], problems.into_iter().map(|problem| vec![Value::Text(problem)]).collect())
+      }
+      _ => (&[], Vec::new()),
+    };
+
+    let column_names: Arc<[String]> = column_names.iter().map(|name| name.to_string()).collect();
+    let rows = rows
+      .into_iter()
+      .map(|values| Row::new(column_names.clone(), Record { row_id: None, values }))
+      .collect();
+    Ok(ResultSet { column_names, rows })
+  }
+
+  /// Run a pragma assigning a value, writing it to the header.
+  pub fn assign<F>(&self, db: &mut Database, file: F) -> Result<()>
+    where
+      F: std::io::Read + std::io::Write + std::io::Seek,
+  {
+    let value = self
+      .value
+      .ok_or_else(|| Error::UnsupportedSql(format!("PRAGMA {} without a value", self.name)))?;
+    match self.name.to_ascii_lowercase().as_str() {
+      "user_version" => db.set_user_version(file, value),
+      "application_id" => db.set_application_id(file, value),
+      _ => Err(Error::UnsupportedSql(format!("setting PRAGMA {}", self.name))),
+    }
+  }
+
+  /// A row for each column of the table: its position, name, declared type, whether it is NOT
+  /// NULL, its default, and its position in the primary key, counting from 1, or 0.
+  fn table_info(&self, db: &Database) -> Result<Vec<Vec<Value>>> {
+    let Some(table) = self.argument.as_ref().and_then(|name| db.schema.table(name).ok()) else {
+      return Ok(Vec::new());
+    };
+    let query = Query::parse(&table.sql)?;
+    let create = as_create(&query, &table.sql)?;
+
+    // Generated columns are left out, as SQLite does, and the cid counts only the rest.
+    let mut rows = Vec::new();
+    for (i, name) in create.column_names.iter().enumerate() {
+      if create.column_generated[i].is_some() {
+        continue;
+      }
+      let pk = create.primary_key.iter().position(|&key| key == i).map_or(0, |i| i + 1);
+      rows.push(vec![
+        Value::Integer(rows.len() as i64),
+        Value::Text(name.clone()),
+        Value::Text(type_name(&create.column_types[i])),
+        Value::Integer(create.column_not_null[i].into()),
+        create.column_defaults[i].clone().map_or(Value::Null, Value::Text),
+        Value::Integer(pk as i64),
+      ]);
+    }
+    Ok(rows)
+  }
+
+  /// A row for each index on the table, most recently created first.
+  fn index_list(&self, db: &Database) -> Result<Vec<Vec<Value>>> {
+    let Some(table) = self.argument.as_ref().and_then(|name| db.schema.table(name).ok()) else {
+      return Ok(Vec::new());
+    };
+
+    let mut rows = Vec::new();
+    for (seq, index) in db.schema.indexes(&table.name).collect::<Vec<_>>().into_iter().rev().enumerate() {
+      let query = db.schema.index_query(index)?;
+      let create = as_create(&query, &index.sql)?;
+      // An index without SQL is one SQLite made for a PRIMARY KEY or UNIQUE constraint. None are
+      // partial.
+      let origin = match index.sql.is_empty() {
+        true if automatic_index_key(&index.name, table)?.is_primary_key => "pk",
+        true => "u",
+        false => "c",
+      };
+      rows.push(vec![
+        Value::Integer(seq as i64),
+        Value::Text(index.name.clone()),
+        Value::Integer(create.unique.into()),
+        Value::Text(origin.to_owned()),
+        Value::Integer(0),
+      ]);
+    }
+    Ok(rows)
+  }
+
+  /// A row for each key column of the index: its position in the key and in the table, where
+  /// -1 is the rowid, and its name.
+  fn index_info(&self, db: &Database) -> Result<Vec<Vec<Value>>> {
+    let index = self.argument.as_ref().and_then(|name| {
+      db.schema
+        .objects
+        .iter()
+        .filter_map(ObjectSchema::as_index)
+        .find(|index| index.name.eq_ignore_ascii_case(name))
+    });
+    let Some(index) = index else {
+      return Ok(Vec::new());
+    };
+    let table = db.schema.table(&index.table_name)?;
+    let query = db.schema.index_query(index)?;
+    let create = as_create(&query, &index.sql)?;
+
+    let mut rows = Vec::new();
+    for (seqno, name) in create.column_names.iter().enumerate() {
+      let cid = table.column_names.iter().position(|column| column.eq_ignore_ascii_case(name));
+      rows.push(vec![
+        Value::Integer(seqno as i64),
+        Value::Integer(cid.map_or(-1, |cid| cid as i64)),
+        Value::Text(cid.map_or(name.as_str(), |cid| &table.column_names[cid]).to_owned()),
+      ]);
+    }
+    Ok(rows)
+  }
+}
+
+fn as_create<'a>(query: &'a Query, sql: &str) -> Result<&'a CreateQuery> {
+  query.as_create().ok_or_else(|| Error::UnsupportedSql(sql.to_owned()))
+}
+
+/// A declared type as SQLite reports it, with the standard type names in upper case.
+fn type_name(declared: &str) -> String {
+  const STANDARD_TYPES: [&str; 6] = ["any", "blob", "int", "integer", "real", "text"];
+  if STANDARD_TYPES.iter().any(|t| declared.eq_ignore_ascii_case(t)) {
+    declared.to_ascii_uppercase()
+  } else {
+    declared.to_owned()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::PragmaQuery;
+  use crate::{database::Database, error::Error, query::Query};
+
+  fn open(path: &str) -> (Database, std::fs::File) {
+    let file = std::fs::File::open(path).unwrap();
+    let db = Database::parse_header_and_schema(&file).unwrap();
+    (db, file)
+  }
+
+  #[test]
+  fn pragmas_describe_the_schema_and_header() {
+    let (db, mut file) = open("tests/fixtures/interior.db");
+    // The expected rows are those sqlite3 returns, with NULL shown as null.
+    for (sql, expected) in [
+      (
+        "PRAGMA table_info(users)",
+        vec![
+          vec!["0", "id", "INTEGER", "0", "null", "1"],
+          vec!["1", "username", "TEXT", "0", "null", "0"],
+          vec!["2", "age", "INTEGER", "0", "null", "0"],
+          vec!["3", "email", "TEXT", "0", "null", "0"],
+        ],
+      ),
+      ("pragma index_list('USERS');", vec![vec!["0", "idx_users_email", "0", "c", "0"]]),
+      ("PRAGMA main.index_info(\"idx_users_email\")", vec![vec!["0", "3", "email"]]),
+      ("PRAGMA page_size", vec![vec!["512"]]),
+      ("PRAGMA encoding", vec![vec!["UTF-8"]]),
+      ("PRAGMA user_version", vec![vec!["0"]]),
+      ("PRAGMA application_id", vec![vec!["0"]]),
+      ("PRAGMA freelist_count", vec![vec!["0"]]),
+      ("PRAGMA table_info(nope)", vec![]),
+      ("PRAGMA index_info(nope)", vec![]),
+      ("PRAGMA nonsense", vec![]),
+    ] {
+      let query = Query::parse(sql).unwrap();
+      assert_eq!(query.execute(&db, &mut file).unwrap(), expected, "{}", sql);
+    }
+
+    let result_set = Query::parse("PRAGMA page_size").unwrap().query_rows(&db, &mut file).unwrap();
+    assert_eq!(&*result_set.column_names, ["page_size"]);
+    assert!(matches!(PragmaQuery::parse("PRAGMA page_size = 1024"), Err(Error::UnsupportedSql(_))));
+  }
+
+  #[test]
+  fn table_info_reports_types_defaults_and_keys_as_written() {
+    // CREATE TABLE t (a varchar(10) not null default 'x y', b My Type default (1+2), c,
+    //   d int default -5, e text collate nocase default null, primary key (d, a))
+    let (db, mut file) = open("tests/fixtures/table_info.db");
+
+    let query = Query::parse("PRAGMA table_info(t)").unwrap();
+    assert_eq!(
+      query.execute(&db, &mut file).unwrap(),
+      vec![
+        vec!["0", "a", "varchar(10)", "1", "'x y'", "2"],
+        vec!["1", "b", "My Type", "0", "1+2", "0"],
+        vec!["2", "c", "", "0", "null", "0"],
+        vec!["3", "d", "INT", "0", "-5", "1"],
+        vec!["4", "e", "TEXT", "0", "null", "0"],
+      ]
+    );
+  }
+
+  #[test]
+  fn user_version_and_application_id_are_written_to_the_header() {
+    let path = std::env::temp_dir().join(format!("sqlite-rust-{}-user-version.db", std::process::id()));
+    std::fs::copy("tests/fixtures/interior.db", &path).unwrap();
+    let mut file = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+    let mut db = Database::parse_header_and_schema(&mut file).unwrap();
+    let change_counter = db.header.file_change_counter;
+    for sql in ["PRAGMA user_version = 5", "pragma main.APPLICATION_ID=-2;"] {
+      match Query::parse(sql).unwrap() {
+        Query::Pragma(pragma) => pragma.assign(&mut db, &mut file).unwrap(),
+        query => panic!("not a pragma: {:?}", query),
+      }
+    }
+    assert_eq!((db.user_version(), db.application_id()), (5, -2));
+    assert_eq!(db.header.file_change_counter, change_counter + 2);
+    let query = Query::parse("PRAGMA application_id").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), [["-2"]]);
+    assert!(matches!(PragmaQuery::parse("PRAGMA user_version = 'x'"), Err(Error::UnsupportedSql(_))));
+
+    db.set_user_version(&mut file, -7).unwrap();
+    if let Ok(output) = std::process::Command::new("sqlite3")
+      .arg(&path)
+      .arg("PRAGMA user_version; PRAGMA application_id; PRAGMA integrity_check; PRAGMA user_version = 9;")
+      .output()
+    {
+      assert_eq!(String::from_utf8_lossy(&output.stdout), "-7\n-2\nok\n");
+      let (db, _) = open(path.to_str().unwrap());
+      assert_eq!(db.user_version(), 9);
+    }
+    std::fs::remove_file(&path).unwrap();
+  }
+}
@@ -1,35 +1,368 @@
+use std::{borrow::Cow, sync::Arc};
+
 use crate::{
-  database::{Database, ObjectSchema},
-  record::Value,
+  database::{Database, IndexStats, ObjectSchema, TableSchema},
+  error::{Error, Result},
+  expr::{self, Affinity, Expr},
+  pattern,
+  pragma::PragmaQuery,
+  record::{Collation, Record, RecordRef, Value, ValueRef},
+  row::{ResultSet, Row},
+  sql::{self, Statement, TableOption},
 };
 
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum Query {
   Select(SelectQuery),
   Create(CreateQuery),
+  Insert(InsertQuery),
+  Delete(DeleteQuery),
+  Update(UpdateQuery),
+  Pragma(PragmaQuery),
 }
 
 #[derive(Debug)]
 pub struct SelectQuery {
-  pub table_name: String,
+  /// The table in the FROM clause, or `None` without one, when the select list is computed once
+  /// from constants.
+  pub table_name: Option<String>,
   pub columns: Vec<Column>,
-  pub filter: Option<Filter>,
+  /// The conditions ANDed together in the WHERE clause, which every returned row matches.
+  pub filters: Vec<Condition>,
+  /// The ORDER BY terms, most significant first. Rows come in rowid order without any.
+  pub order_by: Vec<OrderBy>,
+  pub limit: Option<usize>,
+  /// How many result rows to skip before the LIMIT counts any, given with OFFSET after it.
+  pub offset: Option<usize>,
+}
+
+/// An ORDER BY term: the expression rows are sorted on, and in which direction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderBy {
+  pub expr: Expr,
+  pub descending: bool,
+  /// The collation comparing text, the column's when `expr` is one. Set on resolving.
+  pub collation: Collation,
+}
+
+impl OrderBy {
+  /// The position in the select list a term that is just an integer sorts on, counting from 1.
+  /// Any other constant sorts on nothing.
+  fn position(&self) -> Option<i64> {
+    match self.expr {
+      Expr::Literal(Value::Integer(position)) => Some(position),
+      _ => None,
+    }
+  }
 }
 
-#[derive(Debug, PartialEq)]
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Column {
+  /// `*`, every column of the table in order, which [`SelectQuery::resolve`] expands into them.
+  All,
   Count,
   ColumnName(String),
+  /// Any other expression, named by its text in the select list.
+  Expr { expr: Expr, name: String },
+  /// An aggregate of an expression over the matching rows, named by its text in the select list.
+  Aggregate {
+    function: Aggregate,
+    expr: Expr,
+    name: String,
+  },
 }
 
-#[derive(Debug)]
+/// An aggregate function of one argument, which ignores NULLs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+  Min,
+  Max,
+  /// `count(expr)`, the number of non-NULL values; `count(*)` is [`Column::Count`].
+  Count,
+}
+
+impl Aggregate {
+  fn from_name(name: &str) -> Option<Aggregate> {
+    match name.to_ascii_lowercase().as_str() {
+      "min" => Some(Aggregate::Min),
+      "max" => Some(Aggregate::Max),
+      "count" => Some(Aggregate::Count),
+      _ => None,
+    }
+  }
+
+  fn name(&self) -> &'static str {
+    match self {
+      Aggregate::Min => "MIN",
+      Aggregate::Max => "MAX",
+      Aggregate::Count => "COUNT",
+    }
+  }
+
+  /// Parse a select list `item` that is a call to an aggregate function.
+  fn parse(item: &str) -> Option<(Aggregate, Expr)> {
+    let (name, args) = item.split_once('(')?;
+    let function = Aggregate::from_name(name.trim())?;
+    match Expr::parse(args.strip_suffix(')')?.trim()) {
+      Some((expr, "")) => Some((function, expr)),
+      _ => None,
+    }
+  }
+
+  /// Fold `value` into the aggregate so far, `None` until a non-NULL value is seen. Text is
+  /// compared with `collation`.
+  fn step(&self, aggregate: Option<Value>, value: Value, collation: Collation) -> Option<Value> {
+    if matches!(value, Value::Null) {
+      return aggregate;
+    }
+    match aggregate {
+      Some(Value::Integer(count)) if *self == Aggregate::Count => Some(Value::Integer(count + 1)),
+      None if *self == Aggregate::Count => Some(Value::Integer(1)),
+      Some(aggregate) => {
+        let ordering = value.as_value_ref().collated_cmp(&aggregate.as_value_ref(), collation);
+        let replaces = match self {
+          Aggregate::Min => ordering.is_lt(),
+          Aggregate::Max => ordering.is_gt(),
+          Aggregate::Count => unreachable!("counts are integers"),
+        };
+        Some(if replaces { value } else { aggregate })
+      }
+      None => Some(value),
+    }
+  }
+
+  /// The result of the aggregate folded by [`Aggregate::step`]: NULL without any values, but for
+  /// a count, which is 0.
+  fn finish(&self, aggregate: Option<Value>) -> Value {
+    match (self, aggregate) {
+      (_, Some(value)) => value,
+      (Aggregate::Count, None) => Value::Integer(0),
+      (_, None) => Value::Null,
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
 pub struct Filter {
-  pub column_name: String,
+  /// The column or expression compared with the value.
+  pub left: Expr,
+  pub op: Comparison,
   pub column_value: Value,
+  /// Whether the value is a `?` placeholder, bound when a [`Statement`](crate::statement::Statement)
+  /// is executed.
+  pub is_parameter: bool,
+}
+
+/// A boolean condition of a WHERE clause, made of comparisons `F`.
+#[derive(Debug, Clone)]
+pub enum Condition<F = Filter> {
+  Comparison(F),
+  Not(Box<Condition<F>>),
+  And(Box<Condition<F>>, Box<Condition<F>>),
+  Or(Box<Condition<F>>, Box<Condition<F>>),
+}
+
+/// The value of a condition in SQL's three-valued logic, where a comparison with NULL is neither
+/// true nor false. Only rows for which the WHERE clause is true are returned.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Truth {
+  True,
+  False,
+  Unknown,
+}
+
+/// The operator of a `column op value` condition.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+  Eq,
+  Ne,
+  Lt,
+  Le,
+  Gt,
+  Ge,
+  /// `LIKE`, with `%` and `_` wildcards, ignoring case. The ESCAPE character, if there is one,
+  /// makes the character after it match itself.
+  Like { escape: Option<char> },
+  /// `GLOB`, with `*`, `?` and `[...]` wildcards, respecting case.
+  Glob,
+  /// `IS`, like `=` but true for two NULLs and false for one, never unknown.
+  Is,
+  /// `IS NOT`, the negation of `IS`.
+  IsNot,
+}
+
+impl Comparison {
+  /// Parse the operator at the start of `input`.
+  fn parse(input: &str) -> Option<(Self, &str)> {
+    [
+      ("==", Comparison::Eq),
+      ("=", Comparison::Eq),
+      ("!=", Comparison::Ne),
+      ("<>", Comparison::Ne),
+      ("<=", Comparison::Le),
+      (">=", Comparison::Ge),
+      ("<", Comparison::Lt),
+      (">", Comparison::Gt),
+    ]
+    .into_iter()
+    .find_map(|(symbol, op)| input.strip_prefix(symbol).map(|rest| (op, rest)))
+    .or_else(|| {
+      [("like", Comparison::Like { escape: None }), ("glob", Comparison::Glob)]
+        .into_iter()
+        .find_map(|(keyword, op)| strip_keyword(input, keyword).map(|rest| (op, rest)))
+    })
+    .or_else(|| {
+      let rest = strip_keyword(input, "is")?;
+      match strip_keyword(rest, "not") {
+        Some(rest) => Some((Comparison::IsNot, rest)),
+        None => Some((Comparison::Is, rest)),
+      }
+    })
+  }
+
+  /// Whether `left op right` holds, comparing text with `collation`: unknown if either side is
+  /// NULL, unless the operator is IS or IS NOT, and otherwise following SQLite's sort order, even
+  /// across types. Patterns ignore the collation, and numbers match them as their text.
+  pub fn test(self, left: ValueRef, right: ValueRef, collation: Collation) -> Truth {
+    use std::cmp::Ordering::*;
+    if let Comparison::Is | Comparison::IsNot = self {
+      let is = match (left, right) {
+        (ValueRef::Null, ValueRef::Null) => true,
+        (ValueRef::Null, _) | (_, ValueRef::Null) => false,
+        _ => left.sql_cmp(&right, collation) == Some(Equal),
+      };
+      return (is == (self == Comparison::Is)).into();
+    }
+    let Some(ordering) = left.sql_cmp(&right, collation) else {
+      return Truth::Unknown;
+    };
+    let holds = match self {
+      Comparison::Eq => ordering == Equal,
+      Comparison::Ne => ordering != Equal,
+      Comparison::Lt => ordering == Less,
+      Comparison::Le => ordering != Greater,
+      Comparison::Gt => ordering == Greater,
+      Comparison::Ge => ordering != Less,
+      Comparison::Is | Comparison::IsNot => unreachable!("IS is tested before comparing"),
+      Comparison::Like { .. } | Comparison::Glob => {
+        fn text(value: ValueRef<'_>) -> Cow<'_, str> {
+          match value {
+            ValueRef::Text(s) => Cow::Borrowed(s),
+            ValueRef::Blob(b) => String::from_utf8_lossy(b),
+            value => Cow::Owned(expr::to_text(&value.to_value())),
+          }
+        }
+        match self {
+          Comparison::Like { escape: None } => pattern::like(&text(right), &text(left)),
+          Comparison::Like { escape: Some(escape) } => pattern::like_escaped(&text(right), &text(left), escape),
+          _ => pattern::glob(&text(right), &text(left)),
+        }
+      }
+    };
+    holds.into()
+  }
+}
+
+impl<F> Condition<F> {
+  /// The comparisons in the condition, in order of appearance.
+  pub fn comparisons(&self) -> Vec<&F> {
+    match self {
+      Condition::Comparison(comparison) => vec![comparison],
+      Condition::Not(condition) => condition.comparisons(),
+      Condition::And(left, right) | Condition::Or(left, right) => {
+        let mut comparisons = left.comparisons();
+        comparisons.extend(right.comparisons());
+        comparisons
+      }
+    }
+  }
+
+  /// The same condition with each comparison replaced by `f` of it, called in order of
+  /// appearance.
+  pub fn map<G>(&self, f: &mut dyn FnMut(&F) -> G) -> Condition<G> {
+    match self {
+      Condition::Comparison(comparison) => Condition::Comparison(f(comparison)),
+      Condition::Not(condition) => Condition::Not(Box::new(condition.map(f))),
+      Condition::And(left, right) => {
+        let left = left.map(f);
+        Condition::And(Box::new(left), Box::new(right.map(f)))
+      }
+      Condition::Or(left, right) => {
+        let left = left.map(f);
+        Condition::Or(Box::new(left), Box::new(right.map(f)))
+      }
+    }
+  }
+
+  /// Evaluate the condition with `test` giving the truth of each comparison. The right side of
+  /// AND and OR isn't evaluated when the left side decides the result.
+  pub fn evaluate(&self, test: &mut dyn FnMut(&F) -> Truth) -> Truth {
+    match self {
+      Condition::Comparison(comparison) => test(comparison),
+      Condition::Not(condition) => !condition.evaluate(test),
+      Condition::And(left, right) => match left.evaluate(test) {
+        Truth::False => Truth::False,
+        left => left.and(right.evaluate(test)),
+      },
+      Condition::Or(left, right) => match left.evaluate(test) {
+        Truth::True => Truth::True,
+        left => left.or(right.evaluate(test)),
+      },
+    }
+  }
+
+  /// The comparison, if the condition is just one.
+  pub fn as_comparison(&self) -> Option<&F> {
+    match self {
+      Condition::Comparison(comparison) => Some(comparison),
+      _ => None,
+    }
+  }
+}
+
+impl Truth {
+  pub fn and(self, other: Truth) -> Truth {
+    match (self, other) {
+      (Truth::False, _) | (_, Truth::False) => Truth::False,
+      (Truth::True, Truth::True) => Truth::True,
+      _ => Truth::Unknown,
+    }
+  }
+
+  pub fn or(self, other: Truth) -> Truth {
+    match (self, other) {
+      (Truth::True, _) | (_, Truth::True) => Truth::True,
+      (Truth::False, Truth::False) => Truth::False,
+      _ => Truth::Unknown,
+    }
+  }
+}
+
+impl std::ops::Not for Truth {
+  type Output = Truth;
+
+  fn not(self) -> Truth {
+    match self {
+      Truth::True => Truth::False,
+      Truth::False => Truth::True,
+      Truth::Unknown => Truth::Unknown,
+    }
+  }
+}
+
+impl From<bool> for Truth {
+  fn from(value: bool) -> Self {
+    if value {
+      Truth::True
+    } else {
+      Truth::False
+    }
+  }
 }
 
 impl Column {
-  #[allow(dead_code)]
   pub fn as_name(&self) -> Option<&str> {
     match self {
       Column::ColumnName(s) => Some(s),
@@ -40,76 +373,143 @@ impl Column {
 
 #[derive(Debug)]
 pub struct CreateQuery {
+  pub name: String,
+  pub is_index: bool,
+  /// Whether an index is declared UNIQUE.
+  pub unique: bool,
+  pub if_not_exists: bool,
+  /// Whether a table is declared WITHOUT ROWID, so its rows are stored in an index b-tree keyed
+  /// on the primary key.
+  pub without_rowid: bool,
+  /// Whether a table is declared STRICT, so every value must be of its column's declared type.
+  pub strict: bool,
+  /// The statement as stored in the schema table.
+  pub sql: String,
   pub column_names: Vec<String>,
+  /// The affinity of each column, from its declared type.
+  pub column_affinities: Vec<Affinity>,
+  /// The collation each column's COLLATE clause names, if it has one.
+  pub column_collations: Vec<Option<Collation>>,
+  /// The declared type of each column as written, empty if it has none.
+  pub column_types: Vec<String>,
+  /// Whether each column is declared NOT NULL.
+  pub column_not_null: Vec<bool>,
+  /// For an index, whether each key column is sorted DESC.
+  pub column_descending: Vec<bool>,
+  /// The text of each column's DEFAULT expression as written, if it has one.
+  pub column_defaults: Vec<Option<String>>,
+  /// The value each column's DEFAULT clause gives it.
+  pub column_default_values: Vec<Option<DefaultValue>>,
+  /// The text of each column's CHECK expression, if it has one.
+  pub column_checks: Vec<Option<String>>,
+  /// The text of each CHECK expression of the table as a whole.
+  pub table_checks: Vec<String>,
+  /// The parent key each column's REFERENCES clause names, if it has one.
+  pub column_references: Vec<Option<ForeignKey>>,
+  /// How each generated column is computed, or `None` for an ordinary one.
+  pub column_generated: Vec<Option<GeneratedColumn>>,
+  /// The positions of the PRIMARY KEY columns, in key order.
+  pub primary_key: Vec<usize>,
+  /// Whether each PRIMARY KEY column is sorted DESC, which orders the b-tree of a table without
+  /// a rowid.
+  pub primary_key_descending: Vec<bool>,
+  /// The `INTEGER PRIMARY KEY` column, whose value is the rowid rather than being stored in the
+  /// record. Declaring it `INTEGER PRIMARY KEY DESC` stores it like any other column instead.
+  pub row_id_column: Option<usize>,
+  /// The PRIMARY KEY and UNIQUE constraints of a table that SQLite keeps an index for, the nth
+  /// being named `sqlite_autoindex_<table>_<n>`.
+  pub key_constraints: Vec<KeyConstraint>,
 }
 
-impl Query {
-  pub fn parse(query_str: &str) -> anyhow::Result<Self> {
-    if query_str.to_ascii_lowercase().starts_with("select") {
-      let mut parts = query_str.split_whitespace().peekable();
-      assert_eq!(
-        parts.next().map(|s| s.to_ascii_lowercase()),
-        Some("select".into())
-      );
+/// The columns of a PRIMARY KEY or UNIQUE constraint, in key order, and whether each is sorted
+/// DESC.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyConstraint {
+  pub columns: Vec<usize>,
+  pub descending: Vec<bool>,
+  pub is_primary_key: bool,
+}
 
-      let mut columns = Vec::new();
-      for next_token in parts.by_ref() {
-        if next_token == "," || next_token.to_ascii_lowercase() == "from" {
-          break;
-        } else if next_token.to_ascii_lowercase().contains("count(") {
-          columns.push(Column::Count);
-        } else {
-          let column_name = next_token.trim_start_matches(',').trim_end_matches(',');
-          columns.push(Column::ColumnName(column_name.to_owned()))
-        }
-      }
-
-      let table_name = parts.next().unwrap().to_ascii_lowercase();
-      let mut filter = None;
-      if parts.next().map(|s| s.to_ascii_lowercase()) == Some("where".into()) {
-        let column_name = parts.next().unwrap().to_ascii_lowercase();
-        assert_eq!(parts.next(), Some("="));
-
-        let column_value = if parts.peek().unwrap().starts_with('\'') {
-          let mut text = String::new();
-          loop {
-            let next_part = parts.next().unwrap();
-            text.push_str(next_part.trim_matches('\''));
-            if next_part.ends_with('\'') {
-              break;
-            }
-            text.push(' ');
-          }
-          Value::Text(text)
-        } else {
-          todo!()
-        };
+/// What a DEFAULT clause gives a column a row has no value for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DefaultValue {
+  /// A literal: NULL, TRUE or FALSE, a number with its sign, a string or a blob.
+  Literal(Value),
+  CurrentTime,
+  CurrentDate,
+  CurrentTimestamp,
+  /// Any other expression, as written inside its parentheses.
+  Expr(String),
+}
 
-        filter = Some(Filter {
-          column_name,
-          column_value,
-        });
-      }
+/// The table and columns a REFERENCES clause names. Without columns, it's the parent table's
+/// primary key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKey {
+  pub table: String,
+  pub columns: Vec<String>,
+}
 
-      Ok(Query::Select(SelectQuery {
-        table_name,
-        columns,
-        filter,
-      }))
-    } else if query_str.to_ascii_lowercase().starts_with("create") {
-      let (_, columns_info) = query_str.split_once('(').unwrap();
-      let columns_info = columns_info.strip_suffix(')').unwrap();
-      let columns = columns_info.split(',');
+/// The expression of a `GENERATED ALWAYS AS (...)` column, as written, and whether its values are
+/// STORED in the record or, for a VIRTUAL column, computed when read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedColumn {
+  pub expr: String,
+  pub stored: bool,
+}
 
-      let mut column_names = Vec::new();
-      for column_info in columns {
-        let column_name = column_info.split_whitespace().next().unwrap();
-        column_names.push(column_name.to_owned());
-      }
+#[derive(Debug)]
+pub struct DeleteQuery {
+  pub table_name: String,
+  /// Only rows matching every filter are deleted, or every row without any.
+  pub filters: Vec<Condition>,
+}
 
-      Ok(Query::Create(CreateQuery { column_names }))
-    } else {
-      Err(anyhow::format_err!("unsupported or invalid query type"))
+#[derive(Debug)]
+pub struct UpdateQuery {
+  pub table_name: String,
+  /// The new value of each column being set, in the order they are written.
+  pub assignments: Vec<(String, Value)>,
+  /// Only rows matching every filter are updated, or every row without any.
+  pub filters: Vec<Condition>,
+}
+
+#[derive(Debug)]
+pub struct InsertQuery {
+  pub table_name: String,
+  /// The columns given values by each row, or every column in table order if omitted.
+  pub column_names: Option<Vec<String>>,
+  pub rows: Vec<Vec<Value>>,
+}
+
+impl Query {
+  pub fn parse(query_str: &str) -> Result<Self> {
+    check_hex_literals(query_str)?;
+    if query_str.to_ascii_lowercase().starts_with("pragma") {
+      return Ok(Query::Pragma(PragmaQuery::parse(query_str)?));
+    }
+    let unsupported = || Error::UnsupportedSql(query_str.to_owned());
+    match sql::parse(query_str).ok_or_else(unsupported)? {
+      Statement::Select(select) => Ok(Query::Select(SelectQuery::from_clauses(query_str, select)?)),
+      Statement::Create(create) => Ok(Query::Create(CreateQuery::from_clauses(query_str, create)?)),
+      Statement::Insert(insert) => Ok(Query::Insert(InsertQuery {
+        table_name: insert.table_name.to_owned(),
+        column_names: insert.column_names.map(|names| names.into_iter().map(str::to_owned).collect()),
+        rows: insert.rows,
+      })),
+      Statement::Delete(delete) => Ok(Query::Delete(DeleteQuery {
+        table_name: delete.table_name.to_owned(),
+        filters: parse_where(delete.condition).ok_or_else(unsupported)?,
+      })),
+      Statement::Update(update) => Ok(Query::Update(UpdateQuery {
+        table_name: update.table_name.to_owned(),
+        assignments: update
+          .assignments
+          .into_iter()
+          .map(|(column_name, value)| (column_name.to_owned(), value))
+          .collect(),
+        filters: parse_where(update.condition).ok_or_else(unsupported)?,
+      })),
     }
   }
 
@@ -120,121 +520,3178 @@ impl Query {
     }
   }
 
-  pub fn execute<R>(&self, db: &mut Database, mut file: R) -> anyhow::Result<Vec<Vec<String>>> where R: std::io::Read + std::io::Seek,
+  /// Run the query, returning each value of its result rows as displayed.
+  #[cfg(test)]
+  pub fn execute<R>(&self, db: &Database, file: R) -> Result<Vec<Vec<String>>> where R: std::io::Read + std::io::Seek,
+  {
+    let result_set = self.query_rows(db, file)?;
+    Ok(
+      result_set
+        .rows
+        .iter()
+        .map(|row| row.values().iter().map(|value| value.to_string()).collect())
+        .collect(),
+    )
+  }
+
+  /// Run the query, returning its result rows with their output column names.
+  pub fn query_rows<R>(&self, db: &Database, file: R) -> Result<ResultSet> where R: std::io::Read + std::io::Seek,
   {
     match self {
       Query::Select(select) => {
-        if select.columns.iter().any(|c| matches!(c, Column::Count)) && select.columns.len() != 1 {
-          anyhow::bail!("count() queries with more than one select column not supported");
+        let parameter_count = select.parameter_count();
+        if parameter_count != 0 {
+          return Err(Error::ParameterCount {
+            expected: parameter_count,
+            found: 0,
+          });
         }
-
-        let table_root_page = db.schema.table_root_page(&select.table_name)?;
-        let table_column_names = db
-          .schema
-          .objects
-          .iter()
-          .find(|o| { matches!(o, ObjectSchema::Table(_)) && o.as_table().unwrap().root_page == table_root_page })
-          .map(|o| o.as_table().unwrap().column_names.clone())
-          .unwrap();
-
-        let is_count_query = select.columns.iter().any(|c| matches!(c, Column::Count));
-        let mut select_column_names = if is_count_query {
-          vec!["id".to_string()]
-        } else {
-          select.columns.iter().map(|c| c.as_name().unwrap().to_owned()).collect::<Vec<_>>()
+        select.resolve(db)?.execute(db, file, &[])
+      }
+      Query::Pragma(pragma) => pragma.execute(db, file),
+      // Writes need the file opened for writing, so go through Database::insert and the like.
+      Query::Create(_) | Query::Insert(_) | Query::Delete(_) | Query::Update(_) => {
+        let statement = match self {
+          Query::Create(_) => "CREATE",
+          Query::Insert(_) => "INSERT",
+          Query::Delete(_) => "DELETE",
+          _ => "UPDATE",
         };
-        if let Some(filter_column_name) = select.filter.as_ref().map(|f| f.column_name.clone()){
-          if !select_column_names.contains(&filter_column_name) {
-            select_column_names.push(filter_column_name);
-          }
-        }
+        Err(Error::UnsupportedSql(format!("{} in a query that only reads", statement)))
+      }
+    }
+  }
+}
 
-        let mut column_names = Vec::new();
-        let mut column_indices = Vec::new();
-        let mut filter_column_index = None;
-        for (i, column_name) in table_column_names.iter().enumerate() {
-          if select_column_names.contains(column_name) {
-            column_names.push(column_name.as_str());
-            column_indices.push(i);
-            if let Some(filter_column_name) =
-              select.filter.as_ref().map(|f| f.column_name.clone())
-            {
-              if filter_column_name == *column_name {
-                filter_column_index = Some(column_names.len() - 1);
-              }
-            }
-          }
+impl SelectQuery {
+  /// Build the query from the clauses of `query_str`.
+  fn from_clauses(query_str: &str, select: sql::Select) -> Result<Self> {
+    let unsupported = || Error::UnsupportedSql(query_str.to_owned());
+
+    // Each item of the select list is its own output column, in list order.
+    let mut columns = Vec::new();
+    for item in select.items {
+      if let Some((function, expr)) = Aggregate::parse(item) {
+        columns.push(Column::Aggregate {
+          function,
+          expr,
+          name: item.to_owned(),
+        });
+        continue;
+      }
+      if is_count_star(item) {
+        columns.push(Column::Count);
+        continue;
+      }
+      if item == "*" {
+        columns.push(Column::All);
+        continue;
+      }
+      match Expr::parse(item) {
+        Some((Expr::Column(column_name), "")) => columns.push(Column::ColumnName(column_name)),
+        Some((expr, "")) => columns.push(Column::Expr {
+          expr,
+          name: item.to_owned(),
+        }),
+        _ => return Err(unsupported()),
+      }
+    }
+
+    let filters = match select.condition {
+      Some(condition) => match parse_conditions(condition) {
+        Some((filters, "")) => filters,
+        _ => return Err(unsupported()),
+      },
+      None => Vec::new(),
+    };
+
+    let order_by = select
+      .order_by
+      .into_iter()
+      .map(|(expr, descending)| match Expr::parse(expr) {
+        Some((expr, "")) => Ok(OrderBy {
+          expr,
+          descending,
+          collation: Collation::Binary,
+        }),
+        _ => Err(unsupported()),
+      })
+      .collect::<Result<_>>()?;
+
+    let (mut limit, mut offset) = (None, None);
+    if let Some((limit_str, offset_str)) = select.limit {
+      let count = |clause: &str, count_str: &str| {
+        count_str
+          .parse::<i64>()
+          .map_err(|_| Error::UnsupportedSql(format!("invalid {} value: {}", clause, count_str)))
+      };
+      // As in SQLite, a negative LIMIT is no limit at all, and a negative OFFSET skips nothing.
+      limit = usize::try_from(count("LIMIT", limit_str)?).ok();
+      offset = offset_str
+        .map(|offset_str| count("OFFSET", offset_str).map(|offset| offset.max(0) as usize))
+        .transpose()?;
+    }
+
+    Ok(SelectQuery {
+      table_name: select.table_name.map(str::to_owned),
+      columns,
+      filters,
+      order_by,
+      limit,
+      offset,
+    })
+  }
+}
+
+impl CreateQuery {
+  /// Build the query from the clauses of `query_str`, reading each column definition and table
+  /// constraint.
+  fn from_clauses(query_str: &str, create: sql::Create) -> Result<Self> {
+    let unsupported = || Error::UnsupportedSql(query_str.to_owned());
+    let sql = query_str.trim().trim_end_matches(';').trim_end();
+    let sql::Create {
+      unique,
+      is_index,
+      if_not_exists,
+      name,
+      ..
+    } = create;
+    if is_index && !create.options.is_empty() {
+      return Err(unsupported());
+    }
+    let without_rowid = create.options.contains(&TableOption::WithoutRowid);
+    let strict = create.options.contains(&TableOption::Strict);
+
+    let mut column_names = Vec::new();
+    let mut column_affinities = Vec::new();
+    let mut column_collations = Vec::new();
+    let mut row_id_column = None;
+    let mut type_names = Vec::new();
+    let mut column_types = Vec::new();
+    let mut column_not_null = Vec::new();
+    let mut column_descending = Vec::new();
+    let mut column_defaults = Vec::new();
+    let mut column_default_values = Vec::new();
+    let mut column_checks = Vec::new();
+    let mut table_checks = Vec::new();
+    let mut column_references = Vec::new();
+    let mut column_generated = Vec::new();
+    let mut primary_key = Vec::new();
+    let mut key_columns = None;
+    // The columns of each PRIMARY KEY and UNIQUE constraint, in order, with whether each is
+    // sorted DESC, and whether it's the primary key.
+    let mut keys = Vec::new();
+    let key_of = |key_columns: &[&str]| {
+      key_columns
+        .iter()
+        .filter_map(|key_column| {
+          let (key_column, rest) = parse_identifier(key_column)?;
+          let descending = rest.split_whitespace().last().is_some_and(|w| w.eq_ignore_ascii_case("desc"));
+          Some((key_column.to_owned(), descending))
+        })
+        .collect::<Vec<_>>()
+    };
+    for column_info in create.definitions {
+      let (column_name, rest) = parse_identifier(column_info).ok_or_else(unsupported)?;
+      let quoted = column_info.starts_with(['"', '`', '[']);
+      if !quoted && TABLE_CONSTRAINTS.iter().any(|c| column_name.eq_ignore_ascii_case(c)) {
+        // PRIMARY KEY and UNIQUE constraints may alias the rowid or be indexed, and a CHECK
+        // stops writes to the table.
+        let lowercase = column_info.to_ascii_lowercase();
+        if let Some((_, columns)) = lowercase.split_once("primary key") {
+          let columns = columns.trim().strip_prefix('(').and_then(|c| c.strip_suffix(')'));
+          key_columns = columns.map(|c| split_list(c).into_iter().map(str::to_owned).collect::<Vec<_>>());
+          keys.extend(key_columns.as_ref().map(|columns: &Vec<String>| {
+            (key_of(&columns.iter().map(String::as_str).collect::<Vec<_>>()), true)
+          }));
+        } else if let Some(i) = find_keyword(column_info, "unique") {
+          let rest = strip_keyword(&column_info[i..], "unique").unwrap_or_default();
+          let (columns, _) = parenthesized(rest).ok_or_else(unsupported)?;
+          keys.push((key_of(&split_list(columns)), false));
+        } else if let Some(i) = find_keyword(column_info, "check") {
+          let rest = strip_keyword(&column_info[i..], "check").unwrap_or_default();
+          table_checks.push(parenthesized(rest).ok_or_else(unsupported)?.0.to_owned());
+        }
+        continue;
+      }
+      let words = rest.split_whitespace();
+      let written_type = words.clone().collect::<Vec<_>>();
+      let column_type = words.map(|w| w.to_ascii_lowercase()).collect::<Vec<_>>();
+      // The declared type is the words before the first constraint.
+      let type_len = column_type
+        .iter()
+        .take_while(|w| !COLUMN_CONSTRAINTS.contains(&w.as_str()))
+        .count();
+      let written_type = declared_type(&written_type[..type_len].join(" "));
+      let type_name = written_type.to_ascii_lowercase();
+      column_types.push(written_type);
+      column_not_null.push(column_type.windows(2).any(|w| w[0] == "not" && w[1] == "null"));
+      column_descending.push(is_index && column_type.last().map(String::as_str) == Some("desc"));
+      let default = find_keyword(column_info, "default")
+        .map(|i| default_clause(strip_keyword(&column_info[i..], "default").unwrap_or_default()));
+      column_defaults.push(default.map(|default| unparenthesized(default).to_owned()));
+      column_default_values.push(default.map(DefaultValue::parse));
+      column_checks.push(match find_keyword(column_info, "check") {
+        Some(i) => {
+          let rest = strip_keyword(&column_info[i..], "check").unwrap_or_default();
+          Some(parenthesized(rest).ok_or_else(unsupported)?.0.to_owned())
+        }
+        None => None,
+      });
+      column_references.push(match find_keyword(column_info, "references") {
+        Some(i) => {
+          let rest = strip_keyword(&column_info[i..], "references").unwrap_or_default();
+          let (table, rest) = parse_identifier(rest).ok_or_else(unsupported)?;
+          let columns = match parenthesized(rest) {
+            Some((columns, _)) => split_list(columns)
+              .into_iter()
+              .map(|column| column.trim_matches(|c| matches!(c, '"' | '`' | '[' | ']')).to_owned())
+              .collect(),
+            None => Vec::new(),
+          };
+          Some(ForeignKey {
+            table: table.to_owned(),
+            columns,
+          })
+        }
+        None => None,
+      });
+      // GENERATED ALWAYS is optional before AS, and a generated column is VIRTUAL by default.
+      column_generated.push(match find_keyword(column_info, "as") {
+        Some(i) => {
+          let rest = strip_keyword(&column_info[i..], "as").unwrap_or_default();
+          let (expr, rest) = parenthesized(rest).ok_or_else(unsupported)?;
+          Some(GeneratedColumn {
+            expr: expr.trim().to_owned(),
+            stored: strip_keyword(rest.trim_start(), "stored").is_some(),
+          })
+        }
+        None => None,
+      });
+      // Only a column declared exactly INTEGER PRIMARY KEY aliases the rowid, and not if the
+      // key is DESC, a quirk SQLite keeps for compatibility.
+      let constraints = &column_type[type_len..];
+      let key = constraints.windows(2).position(|w| w[0] == "primary" && w[1] == "key");
+      if let Some(key) = key {
+        primary_key = vec![column_names.len()];
+        let order = constraints.get(key + 2).filter(|order| *order == "asc" || *order == "desc");
+        if type_name == "integer" && order.map(String::as_str) != Some("desc") {
+          row_id_column = Some(column_names.len());
+        }
+        keys.push((vec![(column_name.to_owned(), order.is_some_and(|order| order == "desc"))], true));
+      }
+      if constraints.iter().any(|w| w == "unique") {
+        keys.push((vec![(column_name.to_owned(), false)], false));
+      }
+      let collation = match column_type.iter().position(|w| w == "collate") {
+        Some(i) => {
+          let name = column_type.get(i + 1).ok_or_else(unsupported)?;
+          let collation = Collation::from_name(name)
+            .ok_or_else(|| Error::UnsupportedSql(format!("no such collation sequence: {}", name)))?;
+          Some(collation)
         }
+        None => None,
+      };
+      column_names.push(column_name.to_owned());
+      column_affinities.push(Affinity::from_type_name(&type_name));
+      column_collations.push(collation);
+      type_names.push(type_name);
+    }
+    if let Some(key_columns) = key_columns {
+      // An ASC or DESC in a table constraint doesn't change which columns are in the key.
+      primary_key = key_columns
+        .iter()
+        .filter_map(|key_column| {
+          let (key_column, _) = parse_identifier(key_column)?;
+          column_names.iter().position(|c| c.eq_ignore_ascii_case(key_column))
+        })
+        .collect();
+      // Nor does it stop a single column aliasing the rowid.
+      row_id_column = match primary_key[..] {
+        [key] if type_names[key] == "integer" => Some(key),
+        _ => None,
+      };
+    }
+    // A table without a rowid has nothing for its key to alias.
+    if without_rowid {
+      row_id_column = None;
+    }
+    let primary_key_descending = keys
+      .iter()
+      .find(|(_, is_primary_key)| *is_primary_key)
+      .map(|(key, _)| key.iter().map(|(_, descending)| *descending).collect())
+      .unwrap_or_default();
+    // Every key but one aliasing the rowid, or keying a table without one, gets an index,
+    // unless an earlier key has the same columns.
+    let mut key_constraints = Vec::<KeyConstraint>::new();
+    for (key, is_primary_key) in keys {
+      if is_primary_key && (row_id_column.is_some() || without_rowid) {
+        continue;
+      }
+      let (columns, descending) = key
+        .into_iter()
+        .filter_map(|(key_column, descending)| {
+          Some((column_names.iter().position(|c| c.eq_ignore_ascii_case(&key_column))?, descending))
+        })
+        .unzip::<_, _, Vec<_>, Vec<_>>();
+      if !key_constraints.iter().any(|constraint| constraint.columns == columns) {
+        key_constraints.push(KeyConstraint {
+          columns,
+          descending,
+          is_primary_key,
+        });
+      }
+    }
 
-        let mut need_to_filter = true;
-        let records = if let Some(filter) = select.filter.as_ref() {
-          let mut index = None;
-          for object in db.schema.objects.iter() {
-            if let ObjectSchema::Index(idx) = object {
-              if idx.column_name == filter.column_name {
-                index = Some(idx);
-              }
-            }
-          }
-          if let Some(index) = index {
-            let row_ids = db.search_index(
-              &mut file,
-              index.root_page,
-              filter.column_value.clone(),
-            )?;
-            need_to_filter = false;
-            let table_root_page = db.schema.table_root_page(&select.table_name)?;
-            db.get_by_row_ids(
-              file,
-              table_root_page,
-              &row_ids,
-              &column_names,
-              &column_indices,
-            )?
-          } else {
-            db.get_full_table(file, table_root_page, &column_names, &column_indices)?
-          }
-        } else {
-          db.get_full_table(file, table_root_page, &column_names, &column_indices)?
-        };
+    Ok(CreateQuery {
+      name: name.to_owned(),
+      is_index,
+      unique,
+      if_not_exists,
+      without_rowid,
+      strict,
+      sql: sql.to_owned(),
+      column_names,
+      column_affinities,
+      column_collations,
+      column_types,
+      column_not_null,
+      column_descending,
+      column_defaults,
+      column_default_values,
+      column_checks,
+      table_checks,
+      column_references,
+      column_generated,
+      primary_key,
+      primary_key_descending,
+      row_id_column,
+      key_constraints,
+    })
+  }
+}
 
-        let mut results = Vec::new();
-        let mut result_count = 0;
-        for record in records.iter() {
-          if need_to_filter {
-            if let Some(filter) = select.filter.as_ref() {
-              let value = &record.values[filter_column_index.unwrap()];
-              if *value != filter.column_value {
-                continue;
-              }
-            }
-          }
+/// `n` written as an ordinal, as in `1st` or `12th`.
+fn ordinal(n: usize) -> String {
+  let suffix = match (n % 10, n % 100) {
+    (_, 11..=13) => "th",
+    (1, _) => "st",
+    (2, _) => "nd",
+    (3, _) => "rd",
+    _ => "th",
+  };
+  format!("{}{}", n, suffix)
+}
 
-          result_count += 1;
-          if !is_count_query {
-            let mut row = Vec::new();
-            for column in select.columns.iter() {
-              #[allow(clippy::single_match)]
-              match column {
-                Column::ColumnName(column_name) => {
-                  let record_index =
-                    column_names.iter().position(|c| c == column_name).unwrap();
-                  row.push(record.values[record_index].to_string());
-                }
-                _ => {}
-              }
-            }
-            results.push(row);
-          }
+/// Whether the select list `item` is `count(*)`, in any case and spacing.
+fn is_count_star(item: &str) -> bool {
+  let item = item.chars().filter(|c| !c.is_whitespace()).collect::<String>();
+  item.eq_ignore_ascii_case("count(*)")
+}
+
+/// Split `input` at the commas outside parentheses and string literals, trimming each item.
+fn split_list(input: &str) -> Vec<&str> {
+  let mut items = Vec::new();
+  let mut depth = 0;
+  let mut in_string = false;
+  let mut item_start = 0;
+  for (i, c) in input.char_indices() {
+    match c {
+      // A doubled quote inside a string closes and reopens it, which comes to the same thing.
+      '\'' => in_string = !in_string,
+      '(' if !in_string => depth += 1,
+      ')' if !in_string => depth -= 1,
+      ',' if !in_string && depth == 0 => {
+        items.push(input[item_start..i].trim());
+        item_start = i + 1;
+      }
+      _ => {}
+    }
+  }
+  items.push(input[item_start..].trim());
+  items
+}
+
+/// A column's declared type as SQLite stores it: a quoted type is unquoted, dropping anything
+/// after the closing quote, so `"varying character"(30)` is declared `varying character`.
+fn declared_type(written: &str) -> String {
+  let close = match written.chars().next() {
+    Some(quote @ ('"' | '\'' | '`')) => quote,
+    Some('[') => ']',
+    _ => return written.to_owned(),
+  };
+  let mut declared = String::new();
+  let mut chars = written[1..].chars().peekable();
+  while let Some(c) = chars.next() {
+    if c != close {
+      declared.push(c);
+    } else if close != ']' && chars.peek() == Some(&close) {
+      // A quote inside the name is escaped by doubling it.
+      declared.push(c);
+      chars.next();
+    } else {
+      break;
+    }
+  }
+  declared
+}
+
+/// The keywords starting a table constraint, in place of a column definition.
+const TABLE_CONSTRAINTS: [&str; 5] = ["constraint", "primary", "unique", "check", "foreign"];
+
+/// The keywords starting a column constraint, which ends the column's declared type.
+const COLUMN_CONSTRAINTS: [&str; 11] = [
+  "constraint", "primary", "not", "null", "unique", "check", "default", "collate", "references",
+  "generated", "as",
+];
+
+/// Fail on a hexadecimal literal outside quotes that is too big for 64 bits, which SQLite rejects
+/// rather than reading it as a real, like a decimal one.
+fn check_hex_literals(sql: &str) -> Result<()> {
+  let mut quote = None;
+  let mut after_word = false;
+  for (i, c) in sql.char_indices() {
+    match (quote, c) {
+      (Some(q), c) if c == q => quote = None,
+      (Some(_), _) => {}
+      (None, '\'' | '"' | '`') => quote = Some(c),
+      (None, '[') => quote = Some(']'),
+      (None, '0') if !after_word && sql[i + 1..].starts_with(['x', 'X']) => {
+        let digits = &sql[i + 2..];
+        let end = digits.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(digits.len());
+        if digits[..end].trim_start_matches('0').len() > 16 {
+          return Err(Error::InvalidSql(format!("hex literal too big: {}", &sql[i..i + 2 + end])));
         }
+      }
+      _ => {}
+    }
+    after_word = c.is_alphanumeric() || c == '_';
+  }
+  Ok(())
+}
+
+/// The expression of a DEFAULT clause at the start of `input`, as written: up to the next column
+/// constraint.
+fn default_clause(input: &str) -> &str {
+  // The expression itself may be NULL, so the search for the next constraint starts after it.
+  let first = if strip_keyword(input, "null").is_some() { "null".len() } else { 0 };
+  let end = COLUMN_CONSTRAINTS
+    .iter()
+    .filter_map(|keyword| find_keyword(&input[first..], keyword))
+    .min()
+    .map_or(input.len(), |end| first + end);
+  input[..end].trim()
+}
+
+/// `expr` without the parentheses around it, if it has them.
+fn unparenthesized(expr: &str) -> &str {
+  expr
+    .strip_prefix('(')
+    .and_then(|expr| expr.strip_suffix(')'))
+    .unwrap_or(expr)
+    .trim()
+}
+
+impl DefaultValue {
+  /// Parse the expression of a DEFAULT clause. Anything that isn't a literal or one of the
+  /// CURRENT_ keywords is kept as written.
+  fn parse(written: &str) -> Self {
+    if let Some(expr) = written.strip_prefix('(').and_then(|expr| expr.strip_suffix(')')) {
+      return DefaultValue::Expr(expr.trim().to_owned());
+    }
+    let keyword = written.to_ascii_lowercase();
+    match keyword.as_str() {
+      "current_time" => return DefaultValue::CurrentTime,
+      "current_date" => return DefaultValue::CurrentDate,
+      "current_timestamp" => return DefaultValue::CurrentTimestamp,
+      "true" => return DefaultValue::Literal(Value::Integer(1)),
+      "false" => return DefaultValue::Literal(Value::Integer(0)),
+      _ => {}
+    }
+    if let Some(hex) = keyword.strip_prefix("x'").and_then(|hex| hex.strip_suffix('\'')) {
+      let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect::<Option<Vec<_>>>();
+      if let Some(bytes) = bytes {
+        return DefaultValue::Literal(Value::Blob(bytes));
+      }
+    }
+    // A sign may be separated from its number by spaces.
+    let literal = match written.strip_prefix(['+', '-']) {
+      Some(number) => format!("{}{}", &written[..1], number.trim_start()),
+      None => written.to_owned(),
+    };
+    match parse_literal(&literal) {
+      Some((value, "")) => DefaultValue::Literal(value),
+      _ => DefaultValue::Expr(written.to_owned()),
+    }
+  }
 
-        if is_count_query {
-          results.push(vec![result_count.to_string()]);
+  /// The value the default gives every row, unless it's a time, or an expression that isn't
+  /// constant or can't be evaluated.
+  pub fn constant(&self) -> Option<Value> {
+    match self {
+      DefaultValue::Literal(value) => Some(value.clone()),
+      DefaultValue::Expr(written) => match Expr::parse(written) {
+        Some((expr, rest)) if rest.trim().is_empty() && expr.column_names().is_empty() => {
+          let no_columns = Record {
+            row_id: None,
+            values: Vec::new(),
+          };
+          Some(expr.evaluate(&no_columns))
         }
+        _ => None,
+      },
+      DefaultValue::CurrentTime | DefaultValue::CurrentDate | DefaultValue::CurrentTimestamp => None,
+    }
+  }
+}
+
+/// The text inside the parentheses at the start of `input`, with the rest of the input after
+/// the closing one.
+fn parenthesized(input: &str) -> Option<(&str, &str)> {
+  let inner = input.trim_start().strip_prefix('(')?;
+  let mut depth = 0;
+  let mut quote = None;
+  for (i, c) in inner.char_indices() {
+    match (quote, c) {
+      (Some(q), c) if c == q => quote = None,
+      (Some(_), _) => {}
+      (None, '\'' | '"' | '`') => quote = Some(c),
+      (None, '[') => quote = Some(']'),
+      (None, '(') => depth += 1,
+      (None, ')') if depth == 0 => return Some((&inner[..i], &inner[i + 1..])),
+      (None, ')') => depth -= 1,
+      _ => {}
+    }
+  }
+  None
+}
+
+/// The offset of the first case-insensitive `keyword` in `input` that stands as a word of its
+/// own, outside of quotes and parentheses.
+fn find_keyword(input: &str, keyword: &str) -> Option<usize> {
+  let mut depth = 0;
+  let mut quote = None;
+  let mut after_word = false;
+  for (i, c) in input.char_indices() {
+    match (quote, c) {
+      (Some(q), c) if c == q => quote = None,
+      (Some(_), _) => {}
+      (None, '\'' | '"' | '`') => quote = Some(c),
+      (None, '[') => quote = Some(']'),
+      (None, '(') => depth += 1,
+      (None, ')') => depth -= 1,
+      (None, _) if depth == 0 && !after_word && strip_keyword(&input[i..], keyword).is_some() => {
+        return Some(i);
+      }
+      _ => {}
+    }
+    after_word = c.is_alphanumeric() || c == '_';
+  }
+  None
+}
 
-        Ok(results)
+/// Parse a name at the start of `input`, either a bare word or one quoted with double quotes,
+/// backticks or square brackets, returning it unquoted with the rest of the input.
+pub(crate) fn parse_identifier(input: &str) -> Option<(&str, &str)> {
+  let close = match input.chars().next()? {
+    '"' => '"',
+    '`' => '`',
+    '[' => ']',
+    _ => {
+      let end = input
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(input.len());
+      return (end > 0).then(|| input.split_at(end));
+    }
+  };
+  let (name, rest) = input[1..].split_once(close)?;
+  Some((name, rest))
+}
+
+/// Strip a leading case-insensitive `keyword` followed by whitespace or the end of the input.
+pub(crate) fn strip_keyword<'a>(input: &'a str, keyword: &str) -> Option<&'a str> {
+  let prefix = input.get(..keyword.len())?;
+  let rest = &input[keyword.len()..];
+  if prefix.eq_ignore_ascii_case(keyword) && !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_') {
+    Some(rest.trim_start())
+  } else {
+    None
+  }
+}
+
+/// Parse a NULL, number or single-quoted string literal at the start of `input`. Numbers may be
+/// written in hexadecimal, or with an exponent.
+pub(crate) fn parse_literal(input: &str) -> Option<(Value, &str)> {
+  if let Some(rest) = input.strip_prefix('\'') {
+    // A quote inside the string is escaped by doubling it.
+    let mut text = String::new();
+    let mut chars = rest.char_indices();
+    while let Some((i, c)) = chars.next() {
+      if c != '\'' {
+        text.push(c);
+      } else if rest[i + 1..].starts_with('\'') {
+        text.push('\'');
+        chars.next();
+      } else {
+        return Some((Value::Text(text), &rest[i + 1..]));
       }
-      _ => todo!("non select query"),
+    }
+    return None;
+  }
+  if let Some(rest) = strip_keyword(input, "null") {
+    return Some((Value::Null, rest));
+  }
+
+  // A sign only starts the number, so that in `2-1` it is an operator.
+  let sign = usize::from(input.starts_with(['+', '-']));
+  if let Some(digits) = input[sign..].strip_prefix("0x").or_else(|| input[sign..].strip_prefix("0X")) {
+    let end = digits.find(|c: char| !c.is_ascii_hexdigit()).unwrap_or(digits.len());
+    // As in SQLite, the digits are the bits of a 64-bit two's complement integer.
+    let n = u64::from_str_radix(&digits[..end], 16).ok()? as i64;
+    let n = if input.starts_with('-') { n.wrapping_neg() } else { n };
+    return Some((Value::Integer(n), &digits[end..]));
+  }
+  if input.starts_with(char::is_whitespace) {
+    return None;
+  }
+  let (number, _) = expr::numeric_prefix(input);
+  if number.is_empty() {
+    return None;
+  }
+  let end = number.len();
+  let value = match number.parse::<i64>() {
+    Ok(n) => Value::Integer(n),
+    Err(_) => Value::Real(number.parse::<f64>().ok()?),
+  };
+  Some((value, &input[end..]))
+}
+
+/// Parse the condition of an optional WHERE clause, returning `None` if it doesn't make up the
+/// whole clause. `?` placeholders are only supported in select queries.
+fn parse_where(condition: Option<&str>) -> Option<Vec<Condition>> {
+  let Some(condition) = condition else {
+    return Some(Vec::new());
+  };
+  let (filters, rest) = parse_conditions(condition)?;
+  let has_parameter = filters
+    .iter()
+    .any(|filter| filter.comparisons().iter().any(|comparison| comparison.is_parameter));
+  if !rest.is_empty() || has_parameter {
+    return None;
+  }
+  Some(filters)
+}
+
+/// Parse a WHERE condition, returning the conditions ANDed together at its top level with the
+/// rest of the input. Comparisons are combined with NOT, AND and OR, binding in that order, and
+/// parentheses.
+fn parse_conditions(input: &str) -> Option<(Vec<Condition>, &str)> {
+  let (condition, rest) = parse_or(input)?;
+  let mut conjuncts = Vec::new();
+  let mut conditions = vec![condition];
+  while let Some(condition) = conditions.pop() {
+    match condition {
+      Condition::And(left, right) => conditions.extend([*right, *left]),
+      condition => conjuncts.push(condition),
+    }
+  }
+  Some((conjuncts, rest))
+}
+
+fn parse_or(input: &str) -> Option<(Condition, &str)> {
+  let (mut condition, mut rest) = parse_and(input)?;
+  while let Some(remainder) = strip_keyword(rest, "or") {
+    let (right, remainder) = parse_and(remainder)?;
+    condition = Condition::Or(Box::new(condition), Box::new(right));
+    rest = remainder;
+  }
+  Some((condition, rest))
+}
+
+fn parse_and(input: &str) -> Option<(Condition, &str)> {
+  let (mut condition, mut rest) = parse_not(input)?;
+  while let Some(remainder) = strip_keyword(rest, "and") {
+    let (right, remainder) = parse_not(remainder)?;
+    condition = Condition::And(Box::new(condition), Box::new(right));
+    rest = remainder;
+  }
+  Some((condition, rest))
+}
+
+fn parse_not(input: &str) -> Option<(Condition, &str)> {
+  let input = input.trim_start();
+  if let Some(rest) = strip_keyword(input, "not") {
+    let (condition, rest) = parse_not(rest)?;
+    return Some((Condition::Not(Box::new(condition)), rest));
+  }
+  // Parentheses group conditions, unless they are around an expression compared instead.
+  let grouped = input
+    .strip_prefix('(')
+    .and_then(parse_or)
+    .and_then(|(condition, rest)| Some((condition, rest.trim_start().strip_prefix(')')?)));
+  if let Some((condition, rest)) = grouped {
+    return Some((condition, rest.trim_start()));
+  }
+  let (filter, rest) = parse_comparison(input)?;
+  Some((Condition::Comparison(filter), rest))
+}
+
+/// Parse an `expression op value` comparison, where the value is a literal or a `?` placeholder.
+fn parse_comparison(input: &str) -> Option<(Filter, &str)> {
+  let (left, rest) = Expr::parse(input)?;
+  let (mut op, rest) = Comparison::parse(rest.trim_start())?;
+  let rest = rest.trim_start();
+  let (column_value, is_parameter, mut rest) = match rest.strip_prefix('?') {
+    Some(rest) => (Value::Null, true, rest),
+    None => {
+      let (value, rest) = parse_literal(rest)?;
+      (value, false, rest)
+    }
+  };
+  // The ESCAPE character of a LIKE must be exactly one character.
+  if let Comparison::Like { escape } = &mut op {
+    if let Some(remainder) = strip_keyword(rest.trim_start(), "escape") {
+      let (Value::Text(text), remainder) = parse_literal(remainder)? else {
+        return None;
+      };
+      let mut chars = text.chars();
+      *escape = Some(chars.next()?);
+      if chars.next().is_some() {
+        return None;
+      }
+      rest = remainder;
+    }
+  }
+  let filter = Filter {
+    left,
+    op,
+    column_value,
+    is_parameter,
+  };
+  Some((filter, rest.trim_start()))
+}
+
+/// How a select query reads the database, chosen by [`ResolvedSelect::plan`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryPlan {
+  /// Compute the single row of a select without a FROM clause, if the WHERE clause holds.
+  ConstantRow,
+  /// Count the rows of a table from the cell counts of its leaf pages, without decoding them.
+  CountRows { table: String, root_page: usize },
+  /// Visit every row of a table, keeping those matching the conditions on the `filters` columns.
+  FullScan {
+    table: String,
+    root_page: usize,
+    filters: Vec<String>,
+    /// The index on a filtered column that could have been used instead, if the scan is
+    /// estimated to read fewer pages.
+    rejected_index: Option<RejectedIndex>,
+  },
+  /// Look up rows directly by their rowid in the table b-tree, then check the conditions on the
+  /// `residual_filters` columns on the fetched rows.
+  RowidSeek {
+    table: String,
+    root_page: usize,
+    /// The rowids to look up. Empty until the filter value is known, e.g. when it's a `?`.
+    ids: Vec<i64>,
+    residual_filters: Vec<String>,
+  },
+  /// Find the matching rowids in an index, then fetch those rows from the table b-tree. The
+  /// conditions on the `residual_filters` columns, which the index can't answer, are checked on
+  /// the fetched rows.
+  IndexSeek {
+    table: String,
+    root_page: usize,
+    index: String,
+    index_root_page: usize,
+    residual_filters: Vec<String>,
+  },
+  /// Walk an index in key order, or in reverse if `descending`, and fetch each entry's row from
+  /// the table b-tree, so rows come out in ORDER BY order without sorting. The conditions on the
+  /// `filters` columns are checked on the fetched rows.
+  IndexOrderScan {
+    table: String,
+    root_page: usize,
+    index: String,
+    index_root_page: usize,
+    descending: bool,
+    filters: Vec<String>,
+  },
+  /// Find the smallest or largest rowid by descending the edge of the table b-tree.
+  RowidEdge {
+    table: String,
+    root_page: usize,
+    aggregate: Aggregate,
+  },
+  /// Find the smallest or largest non-NULL key of an index by descending its edge.
+  IndexEdge {
+    table: String,
+    index: String,
+    index_root_page: usize,
+    aggregate: Aggregate,
+  },
+  /// Answer the query from index entries alone, as it only needs the indexed column.
+  CoveringIndexScan {
+    table: String,
+    index: String,
+    index_root_page: usize,
+  },
+  /// Count the matching entries of an index, without reading the table.
+  IndexCount {
+    table: String,
+    index: String,
+    index_root_page: usize,
+  },
+}
+
+/// An index passed over for a table scan, with the pages each was estimated to read, from the
+/// index's [`IndexStats`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedIndex {
+  pub index: String,
+  pub lookup_cost: usize,
+  pub scan_cost: usize,
+}
+
+/// What happens to the records a [`QueryPlan`] reads to make the result rows, in order: sorting
+/// them, folding them into one row, then skipping the OFFSET and keeping the LIMIT. How
+/// aggregates, ORDER BY, LIMIT and OFFSET combine is decided by [`ResolvedSelect::pipeline`]
+/// alone.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+  /// Whether the records are sorted on the ORDER BY terms, unless they're read in that order.
+  pub sort: bool,
+  /// Whether the records are folded into a single row, by COUNT(*) or another aggregate.
+  pub single_row: bool,
+  pub offset: usize,
+  pub limit: Option<usize>,
+}
+
+impl Pipeline {
+  /// The result rows among `rows`, which come sorted and folded already: those after the
+  /// OFFSET, up to the LIMIT. Taking from a streaming scan stops it before reading the pages it
+  /// no longer needs.
+  fn window<I>(&self, rows: I) -> impl Iterator<Item = I::Item>
+    where
+      I: Iterator,
+  {
+    rows.skip(self.offset).take(self.limit.unwrap_or(usize::MAX))
+  }
+}
+
+impl std::fmt::Display for QueryPlan {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      QueryPlan::ConstantRow => write!(f, "SCAN CONSTANT ROW"),
+      QueryPlan::CountRows { table, .. } => write!(f, "SCAN {} USING LEAF CELL COUNTS", table),
+      QueryPlan::FullScan { table, .. } => write!(f, "SCAN {}", table),
+      QueryPlan::RowidSeek {
+        table,
+        residual_filters,
+        ..
+      } => {
+        write!(f, "SEARCH {} USING INTEGER PRIMARY KEY (rowid=?)", table)?;
+        write_residual_filters(f, residual_filters)
+      }
+      QueryPlan::IndexSeek {
+        table,
+        index,
+        residual_filters,
+        ..
+      } => {
+        write!(f, "SEARCH {} USING INDEX {}", table, index)?;
+        write_residual_filters(f, residual_filters)
+      }
+      QueryPlan::IndexOrderScan {
+        table, index, filters, ..
+      } => {
+        write!(f, "SCAN {} USING INDEX {} (ORDER BY satisfied by index)", table, index)?;
+        write_residual_filters(f, filters)
+      }
+      QueryPlan::RowidEdge {
+        table, aggregate, ..
+      } => write!(f, "SEARCH {} USING INTEGER PRIMARY KEY FOR {}", table, aggregate.name()),
+      QueryPlan::IndexEdge {
+        table,
+        index,
+        aggregate,
+        ..
+      } => write!(f, "SEARCH {} USING COVERING INDEX {} FOR {}", table, index, aggregate.name()),
+      QueryPlan::CoveringIndexScan { table, index, .. } => {
+        write!(f, "SEARCH {} USING COVERING INDEX {}", table, index)
+      }
+      QueryPlan::IndexCount { table, index, .. } => {
+        write!(f, "SEARCH {} USING COVERING INDEX {} FOR COUNT", table, index)
+      }
+    }
+  }
+}
+
+fn write_residual_filters(f: &mut std::fmt::Formatter<'_>, columns: &[String]) -> std::fmt::Result {
+  if !columns.is_empty() {
+    write!(f, " FILTER {}", columns.join(", "))?;
+  }
+  Ok(())
+}
+
+/// A select query resolved against the schema: the b-trees it reads and the columns it decodes.
+#[derive(Debug, Clone)]
+pub struct ResolvedSelect {
+  pub table_name: String,
+  pub table_root_page: usize,
+  /// Whether there's no FROM clause, so the select list is computed once, over no columns.
+  pub is_constant: bool,
+  /// Name and root page of the index on the seek filter's column, if there is one.
+  pub index: Option<(String, usize)>,
+  /// What `ANALYZE` recorded about `index`, to weigh looking rows up in it against a scan.
+  pub index_stats: Option<IndexStats>,
+  /// The conditions ANDed together in the WHERE clause.
+  pub filters: Vec<Condition<ResolvedFilter>>,
+  /// Position among `filters` of the equality comparison used to look rows up by rowid or in
+  /// `index`, if there is one. The other filters are checked on the rows it finds.
+  pub seek_filter: Option<usize>,
+  /// The columns decoded from each record, in table order, with their position in the record.
+  pub column_names: Vec<String>,
+  pub column_indices: Vec<usize>,
+  /// Positions among `column_names` of the columns with REAL affinity, whose integral values
+  /// SQLite stores as integers and reads back as reals.
+  pub real_columns: Vec<usize>,
+  /// The default of each of `column_names`, which a record written before ALTER TABLE added the
+  /// column has in place of a value.
+  pub column_defaults: Vec<Value>,
+  /// The expression computing each output column from a decoded record, for non-count queries.
+  pub output_columns: Vec<Expr>,
+  pub output_column_names: Arc<[String]>,
+  pub is_count_query: bool,
+  /// The ORDER BY terms, computing their keys from a decoded record.
+  pub order_by: Vec<OrderBy>,
+  /// Name and root page of an index whose key order is the ORDER BY order, if there is one.
+  pub order_index: Option<(String, usize)>,
+  /// Whether the ORDER BY is just the rowid, ascending, which a table scan already follows.
+  pub ordered_by_row_id: bool,
+  /// The aggregate computing the only output column from `output_columns`, if there is one.
+  pub aggregate: Option<Aggregate>,
+  /// Name and root page of an index on the aggregated column, whose edge holds its minimum and
+  /// maximum.
+  pub aggregate_index: Option<(String, usize)>,
+  /// Whether the aggregate is of the rowid, found at the edge of the table b-tree.
+  pub aggregate_of_row_id: bool,
+  /// The collation comparing text aggregated, the column's when the argument is one.
+  pub aggregate_collation: Collation,
+  pub limit: Option<usize>,
+  pub offset: usize,
+}
+
+/// A WHERE comparison resolved against the columns decoded by a [`ResolvedSelect`].
+#[derive(Debug, Clone)]
+pub struct ResolvedFilter {
+  /// The column or expression compared, as written.
+  pub name: String,
+  pub left: Expr,
+  /// Position among `column_names` of the compared column, when `left` is just a column, so it
+  /// can be compared without copying it out of the page.
+  pub column_index: Option<usize>,
+  pub op: Comparison,
+  /// The value compared against, unless it is bound to the `parameter`th `?` placeholder.
+  pub value: Value,
+  pub parameter: Option<usize>,
+  /// The affinity of the compared column, applied to the value before comparing. Pattern
+  /// matches and comparisons on other expressions have none.
+  pub affinity: Option<Affinity>,
+  /// The collation comparing text, the column's when `left` is one.
+  pub collation: Collation,
+}
+
+impl ResolvedFilter {
+  /// The value compared against, or `None` if it's a placeholder missing from `params`. A
+  /// literal already has the column's affinity applied, a bound value gets it here.
+  fn value<'a>(&'a self, params: &'a [Value]) -> Option<Cow<'a, Value>> {
+    match (self.parameter, self.affinity) {
+      (Some(parameter), Some(affinity)) => Some(affinity.apply(params.get(parameter)?)),
+      (Some(parameter), None) => params.get(parameter).map(Cow::Borrowed),
+      (None, _) => Some(Cow::Borrowed(&self.value)),
+    }
+  }
+
+  /// Whether the comparison holds for `record` with `params` bound to the placeholders.
+  fn matches(&self, record: &Record, params: &[Value]) -> Truth {
+    let value = self.value(params).expect("parameters are bound");
+    match self.column_index {
+      Some(i) => self.op.test(record.values[i].as_value_ref(), value.as_value_ref(), self.collation),
+      None => self.op.test(self.left.evaluate(record).as_value_ref(), value.as_value_ref(), self.collation),
+    }
+  }
+
+  /// Like [`ResolvedFilter::matches`], but only copying the record out of the page if the
+  /// comparison is on an expression rather than a column or the rowid.
+  fn matches_ref(&self, record: &RecordRef, params: &[Value]) -> Truth {
+    let left = match (self.column_index, &self.left) {
+      (Some(i), _) => record.values[i],
+      (None, Expr::RowId) => ValueRef::Integer(record.row_id.expect("table records have a rowid")),
+      (None, _) => return self.matches(&record.to_record(), params),
+    };
+    let value = self.value(params).expect("parameters are bound");
+    self.op.test(left, value.as_value_ref(), self.collation)
+  }
+}
+
+impl SelectQuery {
+  /// The number of `?` placeholders, which are bound in order of appearance.
+  pub fn parameter_count(&self) -> usize {
+    self
+      .filters
+      .iter()
+      .flat_map(Condition::comparisons)
+      .filter(|filter| filter.is_parameter)
+      .count()
+  }
+
+  pub fn resolve(&self, db: &Database) -> Result<ResolvedSelect> {
+    if self.columns.iter().any(|c| matches!(c, Column::Count)) && self.columns.len() != 1 {
+      return Err(Error::UnsupportedSql(
+        "count() queries with more than one select column".to_string(),
+      ));
+    }
+    if self.columns.iter().any(|c| matches!(c, Column::Aggregate { .. })) && self.columns.len() != 1 {
+      return Err(Error::UnsupportedSql(
+        "aggregate queries with more than one select column".to_string(),
+      ));
+    }
+
+    // The table is named as in its CREATE statement from here on, whatever case the query uses.
+    // Without a FROM clause, it's a table of no columns, so naming any is an error.
+    let constant_table;
+    let table = match &self.table_name {
+      Some(table_name) => db.schema.table(table_name)?,
+      None => {
+        constant_table = TableSchema {
+          name: String::new(),
+          root_page: 0,
+          sql: String::new(),
+          column_names: Vec::new(),
+          column_affinities: Vec::new(),
+          column_collations: Vec::new(),
+          row_id_column: None,
+          column_defaults: Vec::new(),
+          column_not_null: Vec::new(),
+          checked: false,
+          column_generated: Vec::new(),
+          without_rowid: false,
+          strict: false,
+        };
+        &constant_table
+      }
+    };
+    if table.without_rowid {
+      return Err(Error::UnsupportedSql(format!("reading WITHOUT ROWID table {}", table.name)));
+    }
+    if self.columns.contains(&Column::All) {
+      if self.table_name.is_none() {
+        return Err(Error::UnsupportedSql("SELECT * without a FROM clause".to_string()));
+      }
+      let columns = self
+        .columns
+        .iter()
+        .flat_map(|column| match column {
+          Column::All => table.column_names.iter().map(|c| Column::ColumnName(c.clone())).collect(),
+          column => vec![column.clone()],
+        })
+        .collect();
+      let select = SelectQuery {
+        table_name: self.table_name.clone(),
+        columns,
+        filters: self.filters.clone(),
+        order_by: self.order_by.clone(),
+        limit: self.limit,
+        offset: self.offset,
+      };
+      return select.resolve(db);
+    }
+    if self.order_by.iter().any(|term| term.position().is_some()) {
+      let select = SelectQuery {
+        table_name: self.table_name.clone(),
+        columns: self.columns.clone(),
+        filters: self.filters.clone(),
+        order_by: self.order_by_positions()?,
+        limit: self.limit,
+        offset: self.offset,
+      };
+      return select.resolve(db);
+    }
+    // VIRTUAL generated columns aren't stored, so each reference to one computes it instead.
+    if table.column_generated.iter().flatten().any(|generated| !generated.stored) {
+      return self.expand_virtual_columns(table)?.resolve_table(db, table);
+    }
+    self.resolve_table(db, table)
+  }
+
+  /// The ORDER BY terms with each integer among them, a 1-based position in the select list,
+  /// replaced by the column or expression there. A count or aggregate is a single row, with
+  /// nothing to sort, so a term naming it is left out.
+  fn order_by_positions(&self) -> Result<Vec<OrderBy>> {
+    let mut order_by = Vec::new();
+    for (i, term) in self.order_by.iter().enumerate() {
+      let Some(position) = term.position() else {
+        order_by.push(term.clone());
+        continue;
+      };
+      let column = usize::try_from(position)
+        .ok()
+        .and_then(|position| self.columns.get(position.checked_sub(1)?))
+        .ok_or_else(|| {
+          Error::InvalidSql(format!(
+            "{} ORDER BY term out of range - should be between 1 and {}",
+            ordinal(i + 1),
+            self.columns.len()
+          ))
+        })?;
+      let expr = match column {
+        Column::All => unreachable!("`*` is expanded before resolving"),
+        Column::Count | Column::Aggregate { .. } => continue,
+        Column::ColumnName(column_name) => Expr::Column(column_name.clone()),
+        Column::Expr { expr, .. } => expr.clone(),
+      };
+      order_by.push(OrderBy { expr, ..term.clone() });
+    }
+    Ok(order_by)
+  }
+
+  /// The query with each reference to a VIRTUAL generated column of `table` replaced by the
+  /// column's expression. Its affinity is applied as a CAST for REAL and TEXT columns, which
+  /// converts numbers the same way.
+  fn expand_virtual_columns(&self, table: &TableSchema) -> Result<SelectQuery> {
+    let column_exprs = self
+      .columns
+      .iter()
+      .filter_map(|column| match column {
+        Column::All => unreachable!("`*` is expanded before resolving"),
+        Column::Count => None,
+        Column::ColumnName(column_name) => Some(Expr::Column(column_name.clone())),
+        Column::Expr { expr, .. } | Column::Aggregate { expr, .. } => Some(expr.clone()),
+      })
+      .collect::<Vec<_>>();
+    let mut pending = column_exprs
+      .iter()
+      .chain(self.filters.iter().flat_map(Condition::comparisons).map(|f| &f.left))
+      .chain(self.order_by.iter().map(|term| &term.expr))
+      .flat_map(Expr::column_names)
+      .map(str::to_owned)
+      .collect::<Vec<_>>();
+    // Only the columns the query reads are parsed, including those their expressions read.
+    let mut virtual_columns = Vec::<(String, Expr)>::new();
+    while let Some(column_name) = pending.pop() {
+      let Some(i) = table.column_names.iter().position(|c| c.eq_ignore_ascii_case(&column_name)) else {
+        continue;
+      };
+      let Some(generated) = table.column_generated[i].as_ref().filter(|generated| !generated.stored) else {
+        continue;
+      };
+      if virtual_columns.iter().any(|(c, _)| c.eq_ignore_ascii_case(&column_name)) {
+        continue;
+      }
+      let expr = match Expr::parse(&generated.expr) {
+        Some((expr, rest)) if rest.trim().is_empty() => expr,
+        _ => return Err(Error::GeneratedColumn(column_name)),
+      };
+      pending.extend(expr.column_names().into_iter().map(str::to_owned));
+      let expr = match table.column_affinities[i] {
+        affinity @ (Affinity::Real | Affinity::Text) => Expr::Cast(Box::new(expr), affinity),
+        _ => expr,
+      };
+      virtual_columns.push((column_name, expr));
+    }
+
+    fn expand(expr: &Expr, virtual_columns: &[(String, Expr)]) -> Expr {
+      expr.resolve(&|column_name| match virtual_columns.iter().find(|(c, _)| c.eq_ignore_ascii_case(column_name)) {
+        Some((_, expr)) => expand(expr, virtual_columns),
+        None => Expr::Column(column_name.to_owned()),
+      })
+    }
+    let expand = |expr: &Expr| expand(expr, &virtual_columns);
+    let columns = self
+      .columns
+      .iter()
+      .map(|column| match column {
+        Column::All => unreachable!("`*` is expanded before resolving"),
+        Column::Count => Column::Count,
+        Column::ColumnName(column_name) if virtual_columns.iter().any(|(c, _)| c.eq_ignore_ascii_case(column_name)) => {
+          Column::Expr {
+            expr: expand(&Expr::Column(column_name.clone())),
+            name: column_name.clone(),
+          }
+        }
+        Column::ColumnName(column_name) => Column::ColumnName(column_name.clone()),
+        Column::Expr { expr, name } => Column::Expr {
+          expr: expand(expr),
+          name: name.clone(),
+        },
+        Column::Aggregate { function, expr, name } => Column::Aggregate {
+          function: *function,
+          expr: expand(expr),
+          name: name.clone(),
+        },
+      })
+      .collect();
+    Ok(SelectQuery {
+      table_name: self.table_name.clone(),
+      columns,
+      filters: self
+        .filters
+        .iter()
+        .map(|condition| {
+          condition.map(&mut |filter| Filter {
+            left: expand(&filter.left),
+            ..filter.clone()
+          })
+        })
+        .collect(),
+      order_by: self
+        .order_by
+        .iter()
+        .map(|term| OrderBy {
+          expr: expand(&term.expr),
+          ..term.clone()
+        })
+        .collect(),
+      limit: self.limit,
+      offset: self.offset,
+    })
+  }
+
+  /// Resolve the query against `table`, the one it reads from.
+  fn resolve_table(&self, db: &Database, table: &TableSchema) -> Result<ResolvedSelect> {
+    let table_root_page = table.root_page;
+    let table_column_names = table.column_names.clone();
+    // A table's own column named like the rowid hides it, as in SQLite.
+    let is_row_id = |column_name: &str| {
+      ["rowid", "_rowid_", "oid"].iter().any(|name| name.eq_ignore_ascii_case(column_name))
+        && self.table_name.is_some()
+        && !table_column_names.iter().any(|c| c.eq_ignore_ascii_case(column_name))
+    };
+    // The INTEGER PRIMARY KEY column is stored as NULL, and read from the rowid instead.
+    let row_id_column = table.row_id_column.map(|i| table_column_names[i].as_str());
+    let reads_row_id =
+      |column_name: &str| is_row_id(column_name) || row_id_column.is_some_and(|c| c.eq_ignore_ascii_case(column_name));
+
+    let is_count_query = self.columns.iter().any(|c| matches!(c, Column::Count));
+    let is_aggregate_query = self.columns.iter().any(|c| matches!(c, Column::Aggregate { .. }));
+    let output_column_names: Arc<[String]> = self
+      .columns
+      .iter()
+      .map(|column| match column {
+        Column::All => unreachable!("`*` is expanded before resolving"),
+        Column::Count => "count(*)".to_string(),
+        // The rowid is named after the INTEGER PRIMARY KEY column aliasing it, if there is one.
+        Column::ColumnName(column_name) if is_row_id(column_name) => match table.row_id_column {
+          Some(row_id_column) => table_column_names[row_id_column].clone(),
+          None => column_name.clone(),
+        },
+        Column::ColumnName(column_name) => column_name.clone(),
+        Column::Expr { name, .. } | Column::Aggregate { name, .. } => name.clone(),
+      })
+      .collect();
+
+    let output_exprs = self
+      .columns
+      .iter()
+      .filter_map(|column| match column {
+        Column::All => unreachable!("`*` is expanded before resolving"),
+        Column::Count => None,
+        Column::ColumnName(column_name) => Some(Expr::Column(column_name.clone())),
+        Column::Expr { expr, .. } | Column::Aggregate { expr, .. } => Some(expr.clone()),
+      })
+      .collect::<Vec<_>>();
+    let referenced_columns = output_exprs
+      .iter()
+      .chain(self.filters.iter().flat_map(Condition::comparisons).map(|f| &f.left))
+      .chain(self.order_by.iter().map(|term| &term.expr))
+      .flat_map(Expr::column_names)
+      .filter(|column_name| !reads_row_id(column_name))
+      .collect::<Vec<_>>();
+    for column_name in referenced_columns.iter() {
+      if !table_column_names.iter().any(|c| c.eq_ignore_ascii_case(column_name)) {
+        return Err(Error::ColumnNotFound {
+          table: table.name.clone(),
+          column: column_name.to_string(),
+          available: table_column_names,
+        });
+      }
+    }
+
+    let mut column_names = Vec::new();
+    let mut column_indices = Vec::new();
+    let mut column_defaults = Vec::new();
+    for (i, column_name) in table_column_names.iter().enumerate() {
+      if referenced_columns.iter().any(|c| c.eq_ignore_ascii_case(column_name)) {
+        // Columns after a VIRTUAL one are a place further forward in the record.
+        let virtual_columns = table.column_generated[..i].iter().flatten().filter(|g| !g.stored).count();
+        column_names.push(column_name.clone());
+        column_indices.push(i - virtual_columns);
+        let default = table.column_defaults[i].as_ref().and_then(DefaultValue::constant);
+        column_defaults.push(default.unwrap_or(Value::Null));
+      }
+    }
+
+    // Each column is decoded once in table order, and every reference to it, including repeated
+    // ones, reads it from its position among them, so the output follows the select list.
+    let position = |column_name: &str| column_names.iter().position(|c| c.eq_ignore_ascii_case(column_name));
+    let resolve_column = |column_name: &str| match position(column_name) {
+      Some(i) => Expr::ColumnAt(i),
+      None => Expr::RowId,
+    };
+    let output_columns = output_exprs.iter().map(|expr| expr.resolve(&resolve_column)).collect();
+
+    let mut parameter_count = 0;
+    // A value compared with a column takes on the column's affinity, the rowid's being INTEGER.
+    let table_position =
+      |column_name: &str| table_column_names.iter().position(|c| c.eq_ignore_ascii_case(column_name));
+    let column_affinity = |column_name: &str| match table_position(column_name) {
+      Some(i) => table.column_affinities[i],
+      None => Affinity::Integer,
+    };
+    // Text compared with a column, sorted on it or aggregated uses the column's collation.
+    let column_collation = |column_name: &str| match table_position(column_name) {
+      Some(i) => table.column_collations[i],
+      None => Collation::Binary,
+    };
+    let mut resolve_filter = |filter: &Filter| {
+      let affinity = match filter.op {
+        Comparison::Like { .. } | Comparison::Glob => None,
+        _ => filter.left.as_column().map(column_affinity),
+      };
+      ResolvedFilter {
+        name: filter.left.to_string(),
+        left: filter.left.resolve(&resolve_column),
+        column_index: filter
+          .left
+          .as_column()
+          .and_then(position),
+        op: filter.op,
+        value: match affinity {
+          Some(affinity) => affinity.apply(&filter.column_value).into_owned(),
+          None => filter.column_value.clone(),
+        },
+        parameter: filter.is_parameter.then(|| {
+          parameter_count += 1;
+          parameter_count - 1
+        }),
+        affinity,
+        collation: filter.left.as_column().map_or(Collation::Binary, column_collation),
+      }
+    };
+    let filters = self
+      .filters
+      .iter()
+      .map(|condition| condition.map(&mut resolve_filter))
+      .collect::<Vec<_>>();
+
+    // Rows are looked up by an equality on the rowid if there is one, or else on the first
+    // indexed column. Only a comparison ANDed with the rest of the WHERE clause can be used.
+    let equalities = || {
+      filters
+        .iter()
+        .enumerate()
+        .filter_map(|(i, condition)| Some((i, condition.as_comparison()?)))
+        .filter(|(_, filter)| filter.op == Comparison::Eq)
+    };
+    // An index only orders and finds values of its column if it compares them the same way.
+    let index_on = |column_name: &str, collation: Collation| {
+      db.schema.objects.iter().find_map(|object| match object {
+        ObjectSchema::Index(idx)
+          if idx.table_name.eq_ignore_ascii_case(&table.name)
+            && idx.column_name.eq_ignore_ascii_case(column_name)
+            && idx.collation == collation =>
+        {
+          Some((idx.name.clone(), idx.root_page))
+        }
+        _ => None,
+      })
+    };
+    let mut index = None;
+    let mut seek_filter = equalities()
+      .find(|(_, filter)| filter.left == Expr::RowId)
+      .map(|(i, _)| i);
+    if seek_filter.is_none() {
+      for (i, filter) in equalities() {
+        let Some(column_name) = filter.column_index.map(|j| column_names[j].as_str()) else {
+          continue;
+        };
+        index = index_on(column_name, filter.collation);
+        if index.is_some() {
+          seek_filter = Some(i);
+          break;
+        }
+      }
+    }
+
+    let index_stats = index.as_ref().and_then(|(name, _)| {
+      db.schema
+        .objects
+        .iter()
+        .filter_map(ObjectSchema::as_index)
+        .find(|idx| idx.name == *name)
+        .and_then(|idx| idx.stats.clone())
+    });
+
+    // Rows already come in the order of a single ORDER BY term on the rowid, ascending, or on an
+    // indexed column, walking the index. A count query has a single row, so needs no order.
+    let mut order_index = None;
+    let mut ordered_by_row_id = false;
+    if let [term] = self.order_by.as_slice() {
+      match term.expr.as_column() {
+        Some(column_name) if reads_row_id(column_name) => {
+          ordered_by_row_id = !term.descending;
+        }
+        Some(column_name) if !is_count_query && !is_aggregate_query => {
+          order_index = index_on(column_name, column_collation(column_name));
+        }
+        _ => {}
+      }
+    }
+    // The minimum or maximum of the rowid, or of an indexed column, is at the edge of its b-tree.
+    let mut aggregate = None;
+    let mut aggregate_index = None;
+    let mut aggregate_of_row_id = false;
+    let mut aggregate_collation = Collation::Binary;
+    if let [Column::Aggregate { function, expr, .. }] = self.columns.as_slice() {
+      aggregate = Some(*function);
+      // The edges of a b-tree hold a minimum or maximum, but a count needs every row.
+      aggregate_collation = expr.as_column().map_or(Collation::Binary, column_collation);
+      match expr.as_column().filter(|_| *function != Aggregate::Count) {
+        Some(column_name) if reads_row_id(column_name) => {
+          aggregate_of_row_id = true;
+        }
+        Some(column_name) => {
+          aggregate_index = index_on(column_name, column_collation(column_name));
+        }
+        None => {}
+      }
+    }
+
+    let order_by = self
+      .order_by
+      .iter()
+      .map(|term| OrderBy {
+        expr: term.expr.resolve(&resolve_column),
+        descending: term.descending,
+        collation: term.expr.as_column().map_or(Collation::Binary, column_collation),
+      })
+      .collect();
+
+    let real_columns = column_names
+      .iter()
+      .enumerate()
+      .filter(|(_, column_name)| column_affinity(column_name) == Affinity::Real)
+      .map(|(position, _)| position)
+      .collect();
+
+    Ok(ResolvedSelect {
+      table_name: table.name.clone(),
+      table_root_page,
+      is_constant: self.table_name.is_none(),
+      index,
+      index_stats,
+      filters,
+      seek_filter,
+      column_names,
+      column_indices,
+      real_columns,
+      column_defaults,
+      output_columns,
+      output_column_names,
+      is_count_query,
+      order_by,
+      order_index,
+      ordered_by_row_id,
+      aggregate,
+      aggregate_index,
+      aggregate_of_row_id,
+      aggregate_collation,
+      limit: self.limit,
+      offset: self.offset.unwrap_or(0),
+    })
+  }
+}
+
+impl ResolvedSelect {
+  /// `record` with the columns it has no value for, being added since it was written, set to
+  /// their defaults.
+  fn with_defaults(&self, mut record: Record) -> Record {
+    if let Some(missing) = self.column_defaults.get(record.values.len()..) {
+      record.values.extend_from_slice(missing);
+    }
+    record
+  }
+
+  /// Choose how to read the matching rows, with `params` bound to the placeholders. Without a
+  /// value, e.g. for a `?` that isn't bound yet, the plan is the same but a rowid lookup has no
+  /// ids.
+  pub fn plan(&self, params: &[Value]) -> QueryPlan {
+    if self.is_constant {
+      return QueryPlan::ConstantRow;
+    }
+    let table = self.table_name.clone();
+    let root_page = self.table_root_page;
+    let seek_filter = match self.seek_filter {
+      Some(seek_filter) => self.filters[seek_filter].as_comparison().expect("seek filter is a comparison"),
+      None if self.filters.is_empty() && self.is_count_query => {
+        return QueryPlan::CountRows { table, root_page }
+      }
+      None if self.filters.is_empty() && self.aggregate_of_row_id => {
+        return QueryPlan::RowidEdge {
+          table,
+          root_page,
+          aggregate: self.aggregate.expect("aggregate query"),
+        }
+      }
+      None if self.filters.is_empty() && self.aggregate_index.is_some() => {
+        let (index, index_root_page) = self.aggregate_index.clone().unwrap();
+        return QueryPlan::IndexEdge {
+          table,
+          index,
+          index_root_page,
+          aggregate: self.aggregate.expect("aggregate query"),
+        };
+      }
+      None => {
+        let filters = self.filter_names();
+        return match self.order_index.clone() {
+          Some((index, index_root_page)) => QueryPlan::IndexOrderScan {
+            table,
+            root_page,
+            index,
+            index_root_page,
+            descending: self.order_by[0].descending,
+            filters,
+          },
+          None => QueryPlan::FullScan {
+            table,
+            root_page,
+            filters,
+            rejected_index: None,
+          },
+        };
+      }
+    };
+    let residual_filters = self
+      .filters
+      .iter()
+      .enumerate()
+      .filter(|(i, _)| Some(*i) != self.seek_filter)
+      .flat_map(|(_, condition)| condition.comparisons())
+      .map(|f| f.name.clone())
+      .collect::<Vec<_>>();
+
+    if seek_filter.left == Expr::RowId {
+      let ids = match seek_filter.value(params).as_deref() {
+        Some(Value::Integer(id)) => vec![*id],
+        _ => Vec::new(),
+      };
+      return QueryPlan::RowidSeek {
+        table,
+        root_page,
+        ids,
+        residual_filters,
+      };
+    }
+
+    let (index, index_root_page) = self.index.clone().expect("seek filter column is indexed");
+    if residual_filters.is_empty() && self.is_count_query {
+      QueryPlan::IndexCount {
+        table,
+        index,
+        index_root_page,
+      }
+    } else if residual_filters.is_empty()
+      && self.column_names.len() == 1
+      && seek_filter.collation == Collation::Binary
+    {
+      // Under another collation, matching entries may hold text other than the filter value.
+      QueryPlan::CoveringIndexScan {
+        table,
+        index,
+        index_root_page,
+      }
+    } else if let Some(stats) = self.index_stats.as_ref().filter(|stats| stats.scan_cost() < stats.lookup_cost()) {
+      // So many rows share the key that fetching them one by one costs more than reading them all.
+      QueryPlan::FullScan {
+        table,
+        root_page,
+        filters: self.filter_names(),
+        rejected_index: Some(RejectedIndex {
+          index,
+          lookup_cost: stats.lookup_cost(),
+          scan_cost: stats.scan_cost(),
+        }),
+      }
+    } else {
+      QueryPlan::IndexSeek {
+        table,
+        root_page,
+        index,
+        index_root_page,
+        residual_filters,
+      }
+    }
+  }
+
+  /// How the records read by `plan` become the result rows. An aggregate or count has a single
+  /// row, so needs no sort, but is still subject to the LIMIT and OFFSET, applied after it as
+  /// after any sort.
+  pub fn pipeline(&self, plan: &QueryPlan) -> Pipeline {
+    // A table scan or rowid lookup reads rows in rowid order, and an index order scan in the
+    // index's. Any other order means sorting every matching row before applying the LIMIT.
+    let in_order = match plan {
+      QueryPlan::ConstantRow | QueryPlan::IndexOrderScan { .. } => true,
+      QueryPlan::FullScan { .. } | QueryPlan::RowidSeek { .. } => self.ordered_by_row_id,
+      _ => false,
+    };
+    let single_row = self.is_count_query || self.aggregate.is_some();
+    Pipeline {
+      sort: !self.order_by.is_empty() && !single_row && !in_order,
+      single_row,
+      offset: self.offset,
+      limit: self.limit,
+    }
+  }
+
+  /// The columns, or expressions, compared in the WHERE clause.
+  fn filter_names(&self) -> Vec<String> {
+    self.filters.iter().flat_map(Condition::comparisons).map(|f| f.name.clone()).collect()
+  }
+
+  /// Run the query with `params` bound to its placeholders, in order.
+  pub fn execute<R>(&self, db: &Database, mut file: R, params: &[Value]) -> Result<ResultSet> where R: std::io::Read + std::io::Seek,
+  {
+    let value_row = |value: Value| {
+      Row::new(
+        self.output_column_names.clone(),
+        Record {
+          row_id: None,
+          values: vec![value],
+        },
+      )
+    };
+    let count_row = |count: usize| value_row(Value::Integer(count as i64));
+
+    let seek_value = self.seek_filter.map(|i| {
+      let seek_filter = self.filters[i].as_comparison().expect("seek filter is a comparison");
+      seek_filter.value(params).expect("parameters are bound").into_owned()
+    });
+    // The conditions the seek doesn't answer, checked on each row it finds.
+    let residual_filters = self
+      .filters
+      .iter()
+      .enumerate()
+      .filter(|(i, _)| Some(*i) != self.seek_filter)
+      .map(|(_, condition)| condition)
+      .collect::<Vec<_>>();
+    let matches_residual_filters = |record: &Record| {
+      residual_filters.iter().all(|condition| {
+        condition.evaluate(&mut |filter| filter.matches(record, params)) == Truth::True
+      })
+    };
+
+    let column_names = self.column_names.iter().map(String::as_str).collect::<Vec<_>>();
+    let column_indices = &self.column_indices;
+    let plan = self.plan(params);
+    let pipeline = self.pipeline(&plan);
+    let single_row_result = |row: Row| ResultSet {
+      rows: pipeline.window(std::iter::once(row)).collect(),
+      column_names: self.output_column_names.clone(),
+    };
+    let records: Box<dyn Iterator<Item = Result<Record>>> = match plan {
+      QueryPlan::ConstantRow => {
+        let record = Record {
+          row_id: None,
+          values: Vec::new(),
+        };
+        Box::new(std::iter::once(record).filter(matches_residual_filters).map(Ok))
+      }
+      QueryPlan::CountRows { root_page, .. } => {
+        // Every leaf cell is a row, so the records themselves never need decoding.
+        let count = db.count_rows(file, root_page)?;
+        return Ok(single_row_result(count_row(count)));
+      }
+      QueryPlan::RowidEdge {
+        root_page, aggregate, ..
+      } => {
+        let row_id = db.edge_row_id(file, root_page, aggregate == Aggregate::Max)?;
+        return Ok(single_row_result(value_row(row_id.map_or(Value::Null, Value::Integer))));
+      }
+      QueryPlan::IndexEdge {
+        index_root_page,
+        aggregate,
+        ..
+      } => {
+        // NULLs sort first in an index and are ignored, so the minimum may be past some.
+        let mut index_scan = db.scan_index(index_root_page, aggregate == Aggregate::Max)?;
+        let mut value = Value::Null;
+        while let Some((key, _)) = index_scan.next_entry(&mut file)? {
+          if !matches!(key, Value::Null) {
+            value = key;
+            break;
+          }
+        }
+        if let (Value::Integer(n), Expr::ColumnAt(i)) = (&value, &self.output_columns[0]) {
+          if self.real_columns.contains(i) {
+            value = Value::Real(*n as f64);
+          }
+        }
+        return Ok(single_row_result(value_row(value)));
+      }
+      QueryPlan::IndexCount {
+        index_root_page, ..
+      } => {
+        let seek_value = seek_value.expect("index seek has a value");
+        let row_ids = db.search_index(&mut file, index_root_page, seek_value.clone())?;
+        // Each row is counted once only because the rowids are distinct.
+        debug_assert!(row_ids.windows(2).all(|pair| pair[0] < pair[1]));
+        return Ok(single_row_result(count_row(row_ids.len())));
+      }
+      QueryPlan::FullScan {
+        root_page, filters: filter_columns, ..
+      } if filter_columns.is_empty() => {
+        Box::new(
+          db.scan_table(file, root_page, &column_names, column_indices)
+            .with_defaults(&self.column_defaults),
+        )
+      }
+      QueryPlan::FullScan { root_page, .. } => {
+        // The filters run on records borrowed from the page, so rows they reject are never
+        // copied out.
+        Box::new(
+          db.scan_table(file, root_page, &column_names, column_indices)
+            .with_defaults(&self.column_defaults)
+            .with_filter(move |record| {
+              self.filters.iter().all(|condition| {
+                condition.evaluate(&mut |filter| filter.matches_ref(record, params)) == Truth::True
+              })
+            }),
+        )
+      }
+      QueryPlan::IndexOrderScan {
+        root_page,
+        index_root_page,
+        descending,
+        ..
+      } => {
+        // Each row is fetched as its index entry is reached, so a LIMIT stops the walk early.
+        let mut index_scan = db.scan_index(index_root_page, descending)?;
+        let column_names = &column_names;
+        Box::new(std::iter::from_fn(move || loop {
+          let row_id = match index_scan.next_row_id(&mut file) {
+            Ok(row_id) => row_id?,
+            Err(e) => return Some(Err(e)),
+          };
+          match db.get_by_row_ids(&mut file, root_page, &[row_id], column_names, column_indices) {
+            Ok(records) => {
+              let mut records = records.into_iter().map(|record| self.with_defaults(record));
+              if let Some(record) = records.find(matches_residual_filters) {
+                return Some(Ok(record));
+              }
+            }
+            Err(e) => return Some(Err(e)),
+          }
+        }))
+      }
+      QueryPlan::RowidSeek { root_page, ids, .. } => {
+        let records = db.get_by_row_ids(file, root_page, &ids, &column_names, column_indices)?;
+        let records = records.into_iter().map(|record| self.with_defaults(record));
+        Box::new(records.filter(matches_residual_filters).map(Ok))
+      }
+      QueryPlan::IndexSeek {
+        root_page,
+        index_root_page,
+        ..
+      } => {
+        let seek_value = seek_value.expect("index seek has a value");
+        let row_ids = db.search_index(&mut file, index_root_page, seek_value.clone())?;
+        let records = db.get_by_row_ids(
+          file,
+          root_page,
+          &row_ids,
+          &column_names,
+          column_indices,
+        )?;
+        let records = records.into_iter().map(|record| self.with_defaults(record));
+        Box::new(records.filter(matches_residual_filters).map(Ok))
+      }
+      QueryPlan::CoveringIndexScan {
+        index_root_page, ..
+      } => {
+        // Every matching entry holds the filter value, which is the only column needed.
+        let seek_value = seek_value.expect("index seek has a value");
+        let row_ids = db.search_index(&mut file, index_root_page, seek_value.clone())?;
+        debug_assert!(row_ids.windows(2).all(|pair| pair[0] < pair[1]));
+        Box::new(row_ids.into_iter().map(move |row_id| {
+          Ok(Record {
+            row_id: Some(row_id),
+            values: vec![seek_value.clone()],
+          })
+        }))
+      }
+    };
+
+    let records: Box<dyn Iterator<Item = Result<Record>>> = if self.real_columns.is_empty() {
+      records
+    } else {
+      Box::new(records.map(|record| {
+        let mut record = record?;
+        for i in self.real_columns.iter() {
+          if let Value::Integer(n) = record.values[*i] {
+            record.values[*i] = Value::Real(n as f64);
+          }
+        }
+        Ok(record)
+      }))
+    };
+
+    let records = if pipeline.sort {
+      let mut keyed_records = records
+        .map(|record| {
+          let record = record?;
+          let keys = self.order_by.iter().map(|term| term.expr.evaluate(&record)).collect::<Vec<_>>();
+          Ok((keys, record))
+        })
+        .collect::<Result<Vec<_>>>()?;
+      // The sort is stable, so rows with equal keys stay in the order they were read.
+      keyed_records.sort_by(|(keys1, _), (keys2, _)| {
+        self
+          .order_by
+          .iter()
+          .zip(keys1.iter().zip(keys2))
+          .map(|(term, (key1, key2))| {
+            let ordering = key1.as_value_ref().collated_cmp(&key2.as_value_ref(), term.collation);
+            if term.descending { ordering.reverse() } else { ordering }
+          })
+          .find(|ordering| ordering.is_ne())
+          .unwrap_or(std::cmp::Ordering::Equal)
+      });
+      Box::new(keyed_records.into_iter().map(|(_, record)| Ok(record)))
+    } else {
+      records
+    };
+
+    if pipeline.single_row {
+      let row = match self.aggregate {
+        Some(aggregate) => {
+          let mut value = None;
+          for record in records {
+            value = aggregate.step(value, self.output_columns[0].evaluate(&record?), self.aggregate_collation);
+          }
+          value_row(aggregate.finish(value))
+        }
+        None => {
+          let mut count = 0;
+          for record in records {
+            record?;
+            count += 1;
+          }
+          count_row(count)
+        }
+      };
+      return Ok(single_row_result(row));
+    }
+
+    let mut results = Vec::new();
+    for record in pipeline.window(records) {
+      let record = record?;
+      let values = self
+        .output_columns
+        .iter()
+        .map(|expr| expr.evaluate(&record))
+        .collect();
+      results.push(Row::new(
+        self.output_column_names.clone(),
+        Record {
+          row_id: record.row_id,
+          values,
+        },
+      ));
+    }
+
+    Ok(ResultSet {
+      column_names: self.output_column_names.clone(),
+      rows: results,
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    Condition, DefaultValue, ForeignKey, GeneratedColumn, Pipeline, Query, QueryPlan, RejectedIndex, ResolvedSelect, Truth,
+  };
+  use crate::expr::{Affinity, Expr};
+  use crate::{database::Database, error::Error, record::{Collation, Value}, statement::Statement};
+
+  fn open_interior() -> (Database, std::fs::File) {
+    let file = std::fs::File::open("tests/fixtures/interior.db").unwrap();
+    let db = Database::parse_header_and_schema(&file).unwrap();
+    (db, file)
+  }
+
+  #[test]
+  fn plan_chooses_index_on_filter_column() {
+    let (db, _) = open_interior();
+    let table = "users".to_string();
+
+    assert_eq!(
+      db.explain("SELECT username FROM users WHERE email = 'bob@example.com'").unwrap(),
+      QueryPlan::IndexSeek {
+        table: table.clone(),
+        root_page: 2,
+        index: "idx_users_email".to_string(),
+        index_root_page: 3,
+        residual_filters: Vec::new(),
+      }
+    );
+    assert_eq!(
+      db.explain("SELECT email FROM users WHERE email = 'bob@example.com'").unwrap(),
+      QueryPlan::CoveringIndexScan {
+        table: table.clone(),
+        index: "idx_users_email".to_string(),
+        index_root_page: 3,
+      }
+    );
+    assert_eq!(
+      db.explain("SELECT email FROM users WHERE username = 'Bob'").unwrap(),
+      QueryPlan::FullScan {
+        table: table.clone(),
+        root_page: 2,
+        filters: vec!["username".to_string()],
+        rejected_index: None,
+      }
+    );
+    assert_eq!(
+      db.explain("SELECT username FROM users WHERE id = ?").unwrap(),
+      QueryPlan::RowidSeek {
+        table: table.clone(),
+        root_page: 2,
+        ids: Vec::new(),
+        residual_filters: Vec::new(),
+      }
+    );
+    assert_eq!(
+      db.explain("SELECT COUNT(*) FROM users").unwrap(),
+      QueryPlan::CountRows { table: table.clone(), root_page: 2 }
+    );
+    assert_eq!(
+      db.explain("SELECT COUNT(*) FROM users WHERE email = 'dave@example.com'").unwrap(),
+      QueryPlan::IndexCount {
+        table,
+        index: "idx_users_email".to_string(),
+        index_root_page: 3,
+      }
+    );
+  }
+
+  #[test]
+  fn index_count_reads_no_table_pages() {
+    let (db, mut file) = open_interior();
+    let table_pages_parsed = db.table_pages_parsed();
+    let query = Query::parse("SELECT COUNT(*) FROM users WHERE email = 'dave@example.com'").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["1"]]);
+    let query = Query::parse("SELECT COUNT(*) FROM users WHERE email = 'nobody@example.com'").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["0"]]);
+    assert_eq!(db.table_pages_parsed(), table_pages_parsed);
+    assert!(db.index_pages_parsed() > 0);
+  }
+
+  #[test]
+  fn plan_weighs_index_lookup_against_scan() {
+    // 2000 accounts on 14 leaf pages, half of them active, as sqlite_stat1 records.
+    let mut file = std::fs::File::open("tests/fixtures/analyzed.db").unwrap();
+    let db = Database::parse_header_and_schema(&file).unwrap();
+    let table = "accounts".to_string();
+
+    // Fetching 1000 rows one at a time reads far more pages than the whole table.
+    assert_eq!(
+      db.explain("SELECT email FROM accounts WHERE active = 1").unwrap(),
+      QueryPlan::FullScan {
+        table: table.clone(),
+        root_page: 2,
+        filters: vec!["active".to_string()],
+        rejected_index: Some(RejectedIndex {
+          index: "idx_accounts_active".to_string(),
+          lookup_cost: 2 + 1000 * 2,
+          scan_cost: 14,
+        }),
+      }
+    );
+    let query = Query::parse("SELECT email FROM accounts WHERE active = 1").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap().len(), 1000);
+
+    // A unique key matches a single row.
+    let plan = db.explain("SELECT active FROM accounts WHERE email = 'user7@example.com'").unwrap();
+    assert_eq!(plan.to_string(), "SEARCH accounts USING INDEX idx_accounts_email");
+    let query = Query::parse("SELECT active FROM accounts WHERE email = 'user7@example.com'").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["1"]]);
+
+    // Answering from the index alone reads no table pages, however many entries match.
+    let plan = db.explain("SELECT COUNT(*) FROM accounts WHERE active = 0").unwrap();
+    assert_eq!(plan.to_string(), "SEARCH accounts USING COVERING INDEX idx_accounts_active FOR COUNT");
+  }
+
+  #[test]
+  fn aggregates_order_by_limit_and_offset_combine() {
+    let (db, mut file) = open_interior();
+    // Each query with the rows sqlite3 returns for it, joined by `|`.
+    let cases = [
+      // A count or aggregate is a single row: ORDER BY has nothing to sort, but LIMIT and
+      // OFFSET still apply to it.
+      ("SELECT COUNT(*) FROM users ORDER BY age LIMIT 1", "22"),
+      ("SELECT COUNT(*) FROM users LIMIT 0", ""),
+      ("SELECT COUNT(*) FROM users LIMIT 1 OFFSET 1", ""),
+      ("SELECT COUNT(*) FROM users WHERE age > 30 ORDER BY username LIMIT 5", "19"),
+      ("SELECT COUNT(*) FROM users WHERE email = 'bob@example.com' LIMIT 1 OFFSET 0", "1"),
+      ("SELECT max(age) FROM users ORDER BY username LIMIT 3", "105"),
+      ("SELECT max(age) FROM users WHERE age < 40 ORDER BY age DESC", "29"),
+      ("SELECT max(id) FROM users LIMIT 0", ""),
+      ("SELECT min(email) FROM users LIMIT 1 OFFSET 1", ""),
+      ("SELECT 1 + 1 ORDER BY 1 LIMIT 5", "2"),
+      ("SELECT 1 + 1 LIMIT 1 OFFSET 1", ""),
+      // Otherwise rows are sorted first, then the OFFSET skipped and the LIMIT taken.
+      ("SELECT username FROM users WHERE age > 30 ORDER BY age LIMIT 5", "Bob|Dave|Dave2|Dave3|Dave4"),
+      ("SELECT username FROM users WHERE age > 30 ORDER BY age LIMIT 2 OFFSET 3", "Dave3|Dave4"),
+      ("SELECT email FROM users ORDER BY email LIMIT 2 OFFSET 2", "celestino@example.com|charlie@example.com"),
+      ("SELECT username FROM users LIMIT 2 OFFSET 20", "Dave18|Celestino"),
+      ("SELECT username FROM users LIMIT 3 OFFSET 30", ""),
+      ("SELECT username FROM users WHERE id = 2 LIMIT 1 OFFSET 1", ""),
+      ("SELECT id FROM users WHERE id > 18 LIMIT -1", "19|20|21|22"),
+      ("SELECT id FROM users WHERE id > 18 LIMIT -5 OFFSET 2", "21|22"),
+      ("SELECT id FROM users LIMIT 2 OFFSET -1", "1|2"),
+    ];
+    for (sql, expected) in cases {
+      let rows = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+      assert_eq!(rows.iter().map(|row| row.join(",")).collect::<Vec<_>>().join("|"), expected, "{}", sql);
+    }
+
+    let sql = "SELECT max(age) FROM users ORDER BY username LIMIT 3 OFFSET 1";
+    let Query::Select(select) = Query::parse(sql).unwrap() else {
+      panic!("not a select query");
+    };
+    let resolved = select.resolve(&db).unwrap();
+    assert_eq!(
+      resolved.pipeline(&resolved.plan(&[])),
+      Pipeline {
+        sort: false,
+        single_row: true,
+        offset: 1,
+        limit: Some(3),
+      }
+    );
+    assert!(matches!(
+      Query::parse("SELECT username FROM users LIMIT 1 OFFSET x"),
+      Err(Error::UnsupportedSql(message)) if message == "invalid OFFSET value: x"
+    ));
+  }
+
+  #[test]
+  fn min_and_max_descend_the_btree_edge() {
+    let (db, mut file) = open_interior();
+    let plan = db.explain("SELECT MAX(id) FROM users").unwrap();
+    assert_eq!(plan.to_string(), "SEARCH users USING INTEGER PRIMARY KEY FOR MAX");
+    let plan = db.explain("SELECT min(email) FROM users").unwrap();
+    assert_eq!(plan.to_string(), "SEARCH users USING COVERING INDEX idx_users_email FOR MIN");
+    let plan = db.explain("SELECT max(email) FROM users WHERE age > 30").unwrap();
+    assert_eq!(plan.to_string(), "SCAN users");
+
+    // Both b-trees are an interior page over two leaves, so a descent reads two of their pages.
+    let pages_read = |db: &Database| (db.table_pages_parsed(), db.index_pages_parsed());
+    let before = pages_read(&db);
+    let query = Query::parse("SELECT MAX(id) FROM users").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["22"]]);
+    assert_eq!(pages_read(&db), (before.0 + 2, before.1));
+    let (db, mut file) = open_interior();
+    let before = pages_read(&db);
+    let query = Query::parse("SELECT MAX(email) FROM users").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["dave@example.com"]]);
+    assert_eq!(pages_read(&db), (before.0, before.1 + 2));
+
+    let query = Query::parse("SELECT MIN(rowid) FROM users").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["1"]]);
+    let query = Query::parse("SELECT MIN(email) FROM users").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["alice@example.com"]]);
+
+    // Without an index the rows are scanned, and any WHERE clause is applied first.
+    let query = Query::parse("SELECT max(age) FROM users").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["105"]]);
+    let query = Query::parse("SELECT min(username) FROM users WHERE age > 30").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["Bob"]]);
+    let query = Query::parse("SELECT max(email) FROM users WHERE age > 1000").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["null"]]);
+  }
+
+  #[test]
+  fn plans_return_the_same_rows() {
+    let (db, mut file) = open_interior();
+
+    let statement = Statement::prepare(&db, "SELECT username FROM users WHERE id = ?").unwrap();
+    let result_set = statement.execute(&db, &mut file, &[Value::Integer(2)]).unwrap();
+    assert_eq!(result_set.rows.len(), 1);
+    assert_eq!(result_set.rows[0].get::<String>(0).unwrap(), "Bob");
+
+    let statement = Statement::prepare(&db, "SELECT email FROM users WHERE email = ?").unwrap();
+    let result_set = statement
+      .execute(&db, &mut file, &[Value::Text("bob@example.com".to_string())])
+      .unwrap();
+    assert_eq!(result_set.rows.len(), 1);
+    assert_eq!(result_set.rows[0].row_id(), Some(2));
+    assert_eq!(result_set.rows[0].get::<String>(0).unwrap(), "bob@example.com");
+  }
+
+  #[test]
+  fn order_by_index_matches_sorted_rows() {
+    let (db, mut file) = open_interior();
+    let plan = db.explain("SELECT username FROM users WHERE age > 30 ORDER BY email DESC LIMIT 3").unwrap();
+    assert_eq!(
+      plan.to_string(),
+      "SCAN users USING INDEX idx_users_email (ORDER BY satisfied by index) FILTER age"
+    );
+    // An equality seek finds few rows, so they're sorted instead.
+    let plan = db.explain("SELECT username FROM users WHERE email = 'bob@example.com' ORDER BY email").unwrap();
+    assert_eq!(plan.to_string(), "SEARCH users USING INDEX idx_users_email");
+
+    for sql in [
+      "SELECT username, email FROM users ORDER BY email",
+      "SELECT username, email FROM users ORDER BY email DESC",
+      "SELECT username FROM users WHERE age > 30 ORDER BY email LIMIT 3",
+      "SELECT email FROM users ORDER BY email DESC LIMIT 5",
+    ] {
+      let Query::Select(select) = Query::parse(sql).unwrap() else {
+        panic!("not a select query");
+      };
+      let resolved = select.resolve(&db).unwrap();
+      assert!(matches!(resolved.plan(&[]), QueryPlan::IndexOrderScan { .. }), "{}", sql);
+      let sorted = ResolvedSelect {
+        order_index: None,
+        ..resolved.clone()
+      };
+      assert!(matches!(sorted.plan(&[]), QueryPlan::FullScan { .. }), "{}", sql);
+
+      let rows = |resolved: &ResolvedSelect, file: &mut std::fs::File| {
+        let result_set = resolved.execute(&db, file, &[]).unwrap();
+        result_set
+          .rows
+          .iter()
+          .map(|row| (row.row_id(), row.get::<String>(0).unwrap()))
+          .collect::<Vec<_>>()
+      };
+      let index_rows = rows(&resolved, &mut file);
+      assert!(!index_rows.is_empty(), "{}", sql);
+      assert_eq!(index_rows, rows(&sorted, &mut file), "{}", sql);
+    }
+  }
+
+  #[test]
+  fn order_by_sorts_on_every_term() {
+    let (db, mut file) = open_interior();
+    let query = Query::parse("SELECT age, username FROM users ORDER BY age DESC, username DESC LIMIT 3").unwrap();
+    assert_eq!(
+      query.execute(&db, &mut file).unwrap(),
+      vec![vec!["105", "Dave9"], vec!["105", "Dave8"], vec!["105", "Dave7"]]
+    );
+    let query = Query::parse("SELECT username FROM users WHERE age < 40 ORDER BY age").unwrap();
+    assert_eq!(
+      query.execute(&db, &mut file).unwrap(),
+      vec![vec!["Charlie"], vec!["Celestino"], vec!["Alice"]]
+    );
+  }
+
+  #[test]
+  fn order_by_integers_are_select_list_positions() {
+    let (db, mut file) = open_interior();
+    let query = Query::parse("SELECT username, age FROM users ORDER BY 2 DESC, 1 DESC LIMIT 3").unwrap();
+    assert_eq!(
+      query.execute(&db, &mut file).unwrap(),
+      vec![vec!["Dave9", "105"], vec!["Dave8", "105"], vec!["Dave7", "105"]]
+    );
+    assert_eq!(
+      db.explain("SELECT email FROM users ORDER BY 1").unwrap(),
+      db.explain("SELECT email FROM users ORDER BY email").unwrap()
+    );
+    let query = Query::parse("SELECT max(age) FROM users ORDER BY 1").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["105"]]);
+
+    // The messages are sqlite3's.
+    for (sql, message) in [
+      ("SELECT username, age FROM users ORDER BY 5", "1st ORDER BY term out of range - should be between 1 and 2"),
+      ("SELECT username FROM users ORDER BY 0", "1st ORDER BY term out of range - should be between 1 and 1"),
+      ("SELECT * FROM users ORDER BY 1, 2, 5", "3rd ORDER BY term out of range - should be between 1 and 4"),
+      ("SELECT count(*) FROM users ORDER BY 2", "1st ORDER BY term out of range - should be between 1 and 1"),
+    ] {
+      let error = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap_err();
+      assert_eq!(error.to_string(), message, "{}", sql);
+    }
+    assert_eq!(super::ordinal(12), "12th");
+    assert_eq!(super::ordinal(22), "22nd");
+  }
+
+  #[test]
+  fn residual_filters_are_checked_on_seek_results() {
+    let (db, mut file) = open_interior();
+    let plan = db.explain("SELECT username FROM users WHERE email = 'bob@example.com' AND age > 30").unwrap();
+    assert_eq!(plan.to_string(), "SEARCH users USING INDEX idx_users_email FILTER age");
+    let plan = db.explain("SELECT username FROM users WHERE age < 30 AND id = 2").unwrap();
+    assert_eq!(plan.to_string(), "SEARCH users USING INTEGER PRIMARY KEY (rowid=?) FILTER age");
+    let plan = db.explain("SELECT COUNT(*) FROM users WHERE email = 'bob@example.com' AND email != 'x'").unwrap();
+    assert_eq!(plan.to_string(), "SEARCH users USING INDEX idx_users_email FILTER email");
+    // An equality ORed with another condition doesn't limit the rows to those it matches.
+    let plan = db.explain("SELECT username FROM users WHERE email = 'bob@example.com' OR age > 100").unwrap();
+    assert_eq!(plan.to_string(), "SCAN users");
+
+    // The index finds Bob either way, but only the first query's age condition holds for him.
+    let query = Query::parse("SELECT username FROM users WHERE email = 'bob@example.com' AND age > 30").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["Bob"]]);
+    let query = Query::parse("SELECT username FROM users WHERE email = 'bob@example.com' AND age > 50").unwrap();
+    assert!(query.execute(&db, &mut file).unwrap().is_empty());
+    let query = Query::parse("SELECT username FROM users WHERE age < 30 AND id = 2").unwrap();
+    assert!(query.execute(&db, &mut file).unwrap().is_empty());
+
+    let query = Query::parse("SELECT username FROM users WHERE age >= 45 AND age < 105").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["Bob"]]);
+
+    let statement = Statement::prepare(&db, "SELECT username FROM users WHERE email = ? AND age <= ?").unwrap();
+    assert_eq!(statement.parameter_count(), 2);
+    let email = Value::Text("bob@example.com".to_string());
+    let result_set = statement.execute(&db, &mut file, &[email.clone(), Value::Integer(45)]).unwrap();
+    assert_eq!(result_set.rows.len(), 1);
+    let result_set = statement.execute(&db, &mut file, &[email, Value::Integer(44)]).unwrap();
+    assert!(result_set.rows.is_empty());
+  }
+
+  #[test]
+  fn writes_cannot_be_queried() {
+    let (db, mut file) = open_interior();
+    let query = Query::parse("DELETE FROM users WHERE id = 2").unwrap();
+    let message = "unsupported SQL: DELETE in a query that only reads";
+    assert_eq!(query.query_rows(&db, &mut file).unwrap_err().to_string(), message);
+    assert_eq!(query.execute(&db, &mut file).unwrap_err().to_string(), message);
+    assert_eq!(Query::parse("SELECT COUNT(*) FROM users").unwrap().execute(&db, &mut file).unwrap(), vec![vec!["22"]]);
+  }
+
+  #[test]
+  fn projection_follows_the_select_list() {
+    let (db, mut file) = open_interior();
+    let query = Query::parse("SELECT username, id, username FROM users WHERE id = 2").unwrap();
+    let result_set = query.query_rows(&db, &mut file).unwrap();
+    assert_eq!(&*result_set.column_names, ["username", "id", "username"]);
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["Bob", "2", "Bob"]]);
+
+    for sql in [
+      "SELECT age, username FROM users WHERE id = 2",
+      "SELECT age,username FROM users WHERE id = 2",
+      "select age , username from users where id = 2",
+    ] {
+      let query = Query::parse(sql).unwrap();
+      assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["45", "Bob"]], "{}", sql);
+    }
+    assert!(Query::parse("SELECT age, FROM users").is_err());
+  }
+
+  #[test]
+  fn functions_in_select_list_and_where() {
+    let (db, mut file) = open_interior();
+    let query = Query::parse(
+      "SELECT username, typeof(age), length(email), substr(email, -11), CAST(age AS TEXT) FROM users \
+       WHERE length(username) > 4 AND substr(username, 1, 4) = 'Dave' LIMIT 2",
+    )
+    .unwrap();
+    let result_set = query.query_rows(&db, &mut file).unwrap();
+    assert_eq!(
+      &*result_set.column_names,
+      ["username", "typeof(age)", "length(email)", "substr(email, -11)", "CAST(age AS TEXT)"]
+    );
+    assert_eq!(
+      query.execute(&db, &mut file).unwrap(),
+      vec![
+        vec!["Dave2", "integer", "17", "example.com", "105"],
+        vec!["Dave3", "integer", "17", "example.com", "105"],
+      ]
+    );
+
+    let query = Query::parse("SELECT COUNT(*) FROM users WHERE CAST(age AS TEXT) = '105'").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["18"]]);
+    assert_eq!(
+      db.explain("SELECT id FROM users WHERE email = 'bob@example.com' AND length(username) = 3").unwrap().to_string(),
+      "SEARCH users USING INDEX idx_users_email FILTER length(username)"
+    );
+    assert!(matches!(
+      Query::parse("SELECT length(nosuch) FROM users").unwrap().execute(&db, &mut file),
+      Err(crate::error::Error::ColumnNotFound { .. })
+    ));
+  }
+
+  #[test]
+  fn unknown_columns_name_the_available_ones() {
+    let (db, mut file) = open_interior();
+    for sql in [
+      "SELECT nmae FROM users",
+      "SELECT username, nmae FROM users",
+      "SELECT username FROM users WHERE nmae = 'Bob'",
+      "SELECT username FROM users WHERE age > 30 OR NOT nmae = 'Bob'",
+      "SELECT username FROM users ORDER BY nmae",
+      "SELECT MAX(nmae) FROM users",
+      "SELECT count(nmae) FROM users",
+    ] {
+      let error = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap_err();
+      assert_eq!(
+        error.to_string(),
+        "no such column: nmae (available: id, username, age, email)",
+        "{}",
+        sql
+      );
+    }
+  }
+
+  #[test]
+  fn text_literals_take_a_numeric_columns_affinity() {
+    let (db, mut file) = open_interior();
+    let query = Query::parse("SELECT count(*) FROM users WHERE age = '105'").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["18"]]);
+    let query = Query::parse("SELECT username FROM users WHERE id = ' 3 '").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["Charlie"]]);
+    let statement = Statement::prepare(&db, "SELECT username FROM users WHERE age < ?").unwrap();
+    let result_set = statement.execute(&db, &mut file, &[Value::Text("26".to_string())]).unwrap();
+    assert_eq!(result_set.rows.len(), 2);
+    // Text that isn't entirely a number stays text, which is never equal to a number.
+    let query = Query::parse("SELECT count(*) FROM users WHERE age = '105x'").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["0"]]);
+    // A pattern is matched against the column's text, whatever its affinity.
+    let query = Query::parse("SELECT count(*) FROM users WHERE age LIKE '10_'").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["18"]]);
+  }
+
+  #[test]
+  fn integer_literals_in_where() {
+    // The queries of the old context/ engine's tests, with the same expected output.
+    for (fixture, sql, expected) in [
+      ("tests/fixtures/leaf.db", "SELECT username FROM users WHERE age = 105;", "Dave"),
+      ("tests/fixtures/leaf.db", "SELECT COUNT(*) FROM users WHERE age = 105;", "1"),
+      ("tests/fixtures/interior.db", "SELECT username FROM users WHERE age = 25;", "Celestino"),
+      ("tests/fixtures/interior.db", "SELECT COUNT(*) FROM users WHERE age = 105;", "18"),
+      ("tests/fixtures/interior.db", "SELECT username FROM users WHERE age = +25", "Celestino"),
+      ("tests/fixtures/interior.db", "SELECT COUNT(*) FROM users WHERE age > -1", "22"),
+      // Too big for an integer, so it is read as a real.
+      ("tests/fixtures/interior.db", "SELECT COUNT(*) FROM users WHERE age < 9223372036854775808", "22"),
+    ] {
+      let mut file = std::fs::File::open(fixture).unwrap();
+      let db = Database::parse_header_and_schema(&file).unwrap();
+      let rows = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+      assert_eq!(rows.concat().join("\n"), expected, "{}", sql);
+    }
+
+    let (db, mut file) = open_interior();
+    let rows = Query::parse("SELECT id, username FROM users WHERE age = 105")
+      .unwrap()
+      .execute(&db, &mut file)
+      .unwrap();
+    assert_eq!(rows.len(), 18);
+    assert_eq!(rows[0], vec!["4", "Dave"]);
+    assert!(Query::parse("SELECT username FROM users WHERE age = 1-2").is_err());
+  }
+
+  #[test]
+  fn queries_read_the_same_from_a_file_and_from_memory() {
+    // The rest of the old context/ engine's tests, run on the file and on a copy in memory.
+    let duplicates = (151..=650).map(|id| id.to_string()).collect::<Vec<_>>().join("\n");
+    let interior = ["Alice|29", "Bob|45", "Charlie|15"]
+      .into_iter()
+      .map(str::to_string)
+      .chain(std::iter::once("Dave|105".to_string()))
+      .chain((2..=18).map(|i| format!("Dave{}|105", i)))
+      .chain(std::iter::once("Celestino|25".to_string()))
+      .collect::<Vec<_>>()
+      .join("\n");
+    for (fixture, sql, expected) in [
+      ("tests/fixtures/leaf.db", "SELECT id, username, age FROM users;", "1|Alice|29\n2|Bob|45\n3|Charlie|15\n4|Dave|105"),
+      ("tests/fixtures/leaf.db", "SELECT COUNT(*) FROM users;", "4"),
+      ("tests/fixtures/interior.db", "SELECT username, age FROM users;", &interior),
+      ("tests/fixtures/interior.db", "SELECT COUNT(*) FROM users;", "22"),
+      ("tests/fixtures/interior.db", "SELECT id FROM users WHERE email = 'dave@example.com';", "4"),
+      ("tests/fixtures/interior.db", "SELECT id FROM users WHERE email = 'dave18@example.com';", "21"),
+      // Rows 151..=650 share one email, spread over more than twenty index leaf pages.
+      ("tests/fixtures/duplicates.db", "SELECT id FROM users WHERE email = 'dup@example.com';", &duplicates),
+    ] {
+      let data = std::fs::read(fixture).unwrap();
+      let mut file = std::fs::File::open(fixture).unwrap();
+      let mut memory = std::io::Cursor::new(&data[..]);
+      let db = Database::parse_header_and_schema(&file).unwrap();
+      let query = Query::parse(sql).unwrap();
+      for rows in [query.execute(&db, &mut file).unwrap(), query.execute(&db, &mut memory).unwrap()] {
+        let rows = rows.iter().map(|row| row.join("|")).collect::<Vec<_>>();
+        assert_eq!(rows.join("\n"), expected, "{}", sql);
+      }
+      let error = Query::parse("SELECT name FROM non_existent_table;").unwrap().execute(&db, &mut memory);
+      assert_eq!(error.unwrap_err().to_string(), "no such table: non_existent_table");
+    }
+
+    // Only the lookups by email, on the first and last leaves of its index, can use one.
+    let (db, _) = open_interior();
+    for (sql, plan) in [
+      ("SELECT id, username FROM users WHERE age = 105;", "SCAN users"),
+      ("SELECT username FROM users WHERE email = 'dave@example.com';", "SEARCH users USING INDEX idx_users_email"),
+      ("SELECT username FROM users WHERE email = 'dave18@example.com';", "SEARCH users USING INDEX idx_users_email"),
+    ] {
+      assert_eq!(db.explain(sql).unwrap().to_string(), plan, "{}", sql);
+    }
+  }
+
+  #[test]
+  fn parse_the_old_context_engine_statements() {
+    // The statements the old context/ engine's peg grammar was tested on.
+    let select = |sql: &str| match Query::parse(sql).unwrap() {
+      Query::Select(select) => select,
+      query => panic!("not a select: {:?}", query),
+    };
+    let query = select("SELECT id, name FROM users");
+    assert_eq!(format!("{:?}", query.columns), r#"[ColumnName("id"), ColumnName("name")]"#);
+    assert_eq!((query.table_name.as_deref(), query.filters.len()), (Some("users"), 0));
+    assert!(matches!(select("SELECT COUNT(*) FROM users").columns[..], [super::Column::Count]));
+    for (sql, column, value) in [
+      ("SELECT id FROM users WHERE name = 'Alice'", "name", "Alice"),
+      ("SELECT id, name FROM superheroes WHERE eye_color = 'Pink Eyes'", "eye_color", "Pink Eyes"),
+    ] {
+      let filter = select(sql).filters[0].as_comparison().unwrap().clone();
+      assert_eq!(filter.left, Expr::Column(column.to_string()), "{}", sql);
+      assert_eq!(filter.op, super::Comparison::Eq, "{}", sql);
+      assert_eq!(filter.column_value, Value::Text(value.to_string()), "{}", sql);
+    }
+    assert!(Query::parse("SELECT FROM users").is_err());
+    assert!(Query::parse("SELECT id FROM users WHERE name 'Alice'").is_err());
+
+    // The name, then each column with its declared type and whether it's NOT NULL.
+    for (sql, name, if_not_exists, columns, row_id_column) in [
+      (
+        "CREATE TABLE apples (id INTEGER, name TEXT, color TEXT)",
+        "apples",
+        false,
+        "id INTEGER, name TEXT, color TEXT",
+        None,
+      ),
+      (
+        "CREATE TABLE apples\n(\n  id INTEGER,\n  name TEXT,\n  color TEXT\n)",
+        "apples",
+        false,
+        "id INTEGER, name TEXT, color TEXT",
+        None,
+      ),
+      (
+        "CREATE TABLE apples (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT, color TEXT)",
+        "apples",
+        false,
+        "id INTEGER, name TEXT, color TEXT",
+        Some(0),
+      ),
+      (
+        "CREATE TABLE IF NOT EXISTS \"superheroes\" (id integer primary key autoincrement, name text not null, \
+         eye_color text, hair_color text, appearance_count integer, first_appearance text, \
+         first_appearance_year text);",
+        "superheroes",
+        true,
+        "id integer, name text not null, eye_color text, hair_color text, appearance_count integer, \
+         first_appearance text, first_appearance_year text",
+        Some(0),
+      ),
+      (
+        "CREATE TABLE companies (id integer primary key autoincrement, name text, \"size range\" text)",
+        "companies",
+        false,
+        "id integer, name text, size range text",
+        Some(0),
+      ),
+    ] {
+      let query = Query::parse(sql).unwrap();
+      let create = query.as_create().unwrap();
+      let found = (0..create.column_names.len())
+        .map(|i| {
+          let not_null = if create.column_not_null[i] { " not null" } else { "" };
+          format!("{} {}{}", create.column_names[i], create.column_types[i], not_null)
+        })
+        .collect::<Vec<_>>();
+      assert_eq!((create.name.as_str(), create.is_index, create.if_not_exists), (name, false, if_not_exists));
+      assert_eq!(found.join(", "), columns, "{}", sql);
+      assert_eq!(create.row_id_column, row_id_column, "{}", sql);
+    }
+
+    for (sql, name, unique, column) in [
+      ("CREATE UNIQUE INDEX idx_users_email ON users (email);", "idx_users_email", true, "email"),
+      ("CREATE INDEX idx_companies_country on companies (country)", "idx_companies_country", false, "country"),
+    ] {
+      let query = Query::parse(sql).unwrap();
+      let create = query.as_create().unwrap();
+      assert_eq!((create.name.as_str(), create.is_index, create.unique), (name, true, unique), "{}", sql);
+      assert_eq!(create.column_names, [column], "{}", sql);
+    }
+  }
+
+  #[test]
+  fn index_probes_take_the_affinity_of_the_indexed_column() {
+    // A quoted number probing an INTEGER index is the number, as are numbers with spaces around
+    // them or written as reals. Text that isn't a number matches nothing.
+    let mut file = std::fs::File::open("tests/fixtures/desc_index.db").unwrap();
+    let db = Database::parse_header_and_schema(&file).unwrap();
+    for (sql, expected) in [
+      ("SELECT id FROM scores WHERE score = '74'", vec!["2", "253", "504"]),
+      ("SELECT id FROM scores WHERE score = '74.0'", vec!["2", "253", "504"]),
+      ("SELECT id FROM scores WHERE score = ' 74 '", vec!["2", "253", "504"]),
+      ("SELECT count(*) FROM scores WHERE score = '74'", vec!["3"]),
+      ("SELECT score, typeof(score) FROM scores WHERE score = '74' LIMIT 1", vec!["74|integer"]),
+      ("SELECT id FROM scores WHERE score = 'abc'", vec![]),
+    ] {
+      assert!(db.explain(sql).unwrap().to_string().contains("INDEX scores_score"), "{}", sql);
+      let query = Query::parse(sql).unwrap();
+      let mut rows = query.execute(&db, &mut file).unwrap().iter().map(|row| row.join("|")).collect::<Vec<_>>();
+      rows.sort_by_key(|row| row.parse::<i64>().unwrap_or(0));
+      assert_eq!(rows, expected, "{}", sql);
+    }
+    // Probing the index directly converts the key the same way.
+    let row_ids = db.search_index(&mut file, 3, Value::Text("74".to_string())).unwrap();
+    assert_eq!(row_ids.len(), 3);
+
+    // A bare number probing a TEXT index is its text, which finds the text '105' and not '105.0'.
+    let mut file = std::io::Cursor::new(std::fs::read("tests/fixtures/interior.db").unwrap());
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let insert = Query::parse("INSERT INTO users (username, email) VALUES ('a', '105'), ('b', '105.0'), ('c', 'x')").unwrap();
+    let Query::Insert(insert) = insert else {
+      unreachable!();
+    };
+    db.insert(&mut file, &insert).unwrap();
+    for (sql, expected) in [
+      ("SELECT id FROM users WHERE email = 105", vec!["23"]),
+      ("SELECT id FROM users WHERE email = 105.0", vec!["24"]),
+      ("SELECT id FROM users WHERE email = '105'", vec!["23"]),
+      ("SELECT count(*) FROM users WHERE email = 105", vec!["1"]),
+      ("SELECT id FROM users WHERE email = 106", vec![]),
+    ] {
+      assert!(db.explain(sql).unwrap().to_string().contains("INDEX idx_users_email"), "{}", sql);
+      let query = Query::parse(sql).unwrap();
+      let rows = query.execute(&db, &mut file).unwrap().iter().map(|row| row.join("|")).collect::<Vec<_>>();
+      assert_eq!(rows, expected, "{}", sql);
+    }
+  }
+
+  #[test]
+  fn null_index_keys_never_match_a_probe() {
+    // A third of the rows have a NULL score, whose index entries come first and fill the first
+    // leaf pages of the index. The expected rows are those sqlite3 returns, with NULL as null.
+    let mut file = std::fs::File::open("tests/fixtures/null_keys.db").unwrap();
+    let db = Database::parse_header_and_schema(&file).unwrap();
+    for (sql, expected) in [
+      ("SELECT id FROM items WHERE score = 1", "1 101 151 251"),
+      ("SELECT count(*) FROM items WHERE score = 0", "4"),
+      ("SELECT id FROM items WHERE score = NULL", ""),
+      ("SELECT count(*) FROM items WHERE score = NULL", "0"),
+      ("SELECT count(*) FROM items WHERE score IS NULL", "100"),
+      ("SELECT count(*) FROM items WHERE score > 45", "16"),
+      ("SELECT count(*) FROM items WHERE score < 2", "8"),
+      ("SELECT min(score) FROM items", "0"),
+      ("SELECT max(score) FROM items", "49"),
+      ("SELECT id, score FROM items ORDER BY score LIMIT 3", "3|null 6|null 9|null"),
+      ("SELECT id, score FROM items ORDER BY score DESC LIMIT 3", "299|49 199|49 149|49"),
+    ] {
+      let rows = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+      let rows = rows.iter().map(|row| row.join("|")).collect::<Vec<_>>();
+      assert_eq!(rows.join(" "), expected, "{}", sql);
+    }
+    let index_root_page = db.schema.objects.iter().filter_map(|o| o.as_index()).next().unwrap().root_page;
+    assert!(db.search_index(&mut file, index_root_page, Value::Null).unwrap().is_empty());
+  }
+
+  #[test]
+  fn count_of_an_expression_skips_nulls() {
+    // The expected counts are those sqlite3 returns; a third of the scores are NULL.
+    let mut file = std::fs::File::open("tests/fixtures/null_keys.db").unwrap();
+    let db = Database::parse_header_and_schema(&file).unwrap();
+    for (sql, expected) in [
+      ("SELECT count(*) FROM items", "300"),
+      ("SELECT COUNT( * ) FROM items", "300"),
+      ("SELECT count(score) FROM items", "200"),
+      ("SELECT COUNT(score) FROM items WHERE score < 2", "8"),
+      ("SELECT count(score) FROM items WHERE score IS NULL", "0"),
+      ("SELECT count(score + 1) FROM items WHERE id < 10", "6"),
+      ("SELECT count(id) FROM items", "300"),
+      ("SELECT count(score) FROM items WHERE id = 1000", "0"),
+    ] {
+      let rows = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+      assert_eq!(rows, vec![vec![expected]], "{}", sql);
+    }
+  }
+
+  #[test]
+  fn rows_matching_several_or_arms_are_returned_once() {
+    let (db, mut file) = open_interior();
+    // The expected rows are those sqlite3 returns, in rowid order.
+    for (sql, expected) in [
+      ("SELECT id FROM users WHERE email = 'dave@example.com' OR id = 4", "4"),
+      ("SELECT id FROM users WHERE id = 4 OR id = 2 OR email = 'bob@example.com'", "2 4"),
+      ("SELECT count(*) FROM users WHERE email = 'bob@example.com' OR username = 'Bob'", "1"),
+      ("SELECT id FROM users WHERE (age > 100 OR id = 4) AND (id = 4 OR email = 'dave@example.com')", "4"),
+    ] {
+      let rows = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+      assert_eq!(rows.iter().map(|row| row.join("|")).collect::<Vec<_>>().join(" "), expected, "{}", sql);
+    }
+
+    // The rowid lists of two lookups joined, as an OR of them would, fetch each row once.
+    let bob = db.search_index(&mut file, 3, Value::Text("bob@example.com".to_string())).unwrap();
+    let dave = db.search_index(&mut file, 3, Value::Text("dave@example.com".to_string())).unwrap();
+    let row_ids = [bob.as_slice(), &dave, &[4, 2]].concat();
+    let records = db.get_by_row_ids(&mut file, 2, &row_ids, &["id"], &[0]).unwrap();
+    assert_eq!(records.iter().map(|record| record.row_id.unwrap()).collect::<Vec<_>>(), [2, 4]);
+  }
+
+  #[test]
+  fn hex_and_exponent_literals() {
+    let (db, mut file) = open_interior();
+    // Each query returns the same as the one with the literals written in decimal.
+    for (sql, decimal_sql) in [
+      ("SELECT COUNT(*) FROM users WHERE age > 0x14", "SELECT COUNT(*) FROM users WHERE age > 20"),
+      ("SELECT username FROM users WHERE age = 0X19", "SELECT username FROM users WHERE age = 25"),
+      ("SELECT username FROM users WHERE age = 2.5e1", "SELECT username FROM users WHERE age = 25"),
+      ("SELECT COUNT(*) FROM users WHERE age < 1E2", "SELECT COUNT(*) FROM users WHERE age < 100.0"),
+      ("SELECT id FROM users WHERE id = 0x1", "SELECT id FROM users WHERE id = 1"),
+      (
+        "SELECT 0x1A, 1.5e3, -0x10, 0xffffffffffffffff, 1e3, 15e-1, .5, 0x0000000000000000001",
+        "SELECT 26, 1500.0, -16, -1, 1000.0, 1.5, 0.5, 1",
+      ),
+      ("SELECT typeof(1e3), typeof(0x10), 0x10 + 1e1", "SELECT 'real', 'integer', 26.0"),
+    ] {
+      let rows = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+      let decimal_rows = Query::parse(decimal_sql).unwrap().execute(&db, &mut file).unwrap();
+      assert_eq!(rows, decimal_rows, "{}", sql);
+      assert!(!rows.is_empty());
+    }
+
+    for sql in ["SELECT 0x1ffffffffffffffff", "SELECT id FROM users WHERE age = 0x10000000000000000"] {
+      let error = Query::parse(sql).unwrap_err();
+      assert!(matches!(&error, Error::InvalidSql(_)), "{}", sql);
+      assert!(error.to_string().starts_with("hex literal too big: 0x1"), "{}", error);
+    }
+    // Quoted, it's just text.
+    let query = Query::parse("SELECT '0x1ffffffffffffffff'").unwrap();
+    assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec!["0x1ffffffffffffffff"]]);
+  }
+
+  #[test]
+  fn select_without_from_computes_one_row() {
+    let (db, mut file) = open_interior();
+    // The expected rows are those sqlite3 returns, with NULL shown as null.
+    for (sql, expected) in [
+      ("SELECT 1", vec![vec!["1"]]),
+      ("select 'hello', 2+2;", vec![vec!["hello", "4"]]),
+      ("SELECT 2 * 3 + 4, 2 + 3 * 4, 10 - 2 - 3, -5+2", vec![vec!["10", "14", "5", "-3"]]),
+      ("SELECT typeof(1 + 1.0), length('abc') * 2", vec![vec!["real", "6"]]),
+      ("SELECT count(*)", vec![vec!["1"]]),
+      ("SELECT max(3)", vec![vec!["3"]]),
+      ("SELECT 1 WHERE 1 = 2", vec![]),
+      ("SELECT 1 WHERE 1 = 1", vec![vec!["1"]]),
+      ("SELECT 1 LIMIT 0", vec![]),
+    ] {
+      let query = Query::parse(sql).unwrap();
+      assert_eq!(query.execute(&db, &mut file).unwrap(), expected, "{}", sql);
+    }
+    let result_set = Query::parse("SELECT 'hello', 2+2").unwrap().query_rows(&db, &mut file).unwrap();
+    assert_eq!(&*result_set.column_names, ["'hello'", "2+2"]);
+    assert_eq!(db.explain("SELECT 1").unwrap().to_string(), "SCAN CONSTANT ROW");
+
+    for sql in ["SELECT username", "SELECT 1 + age", "SELECT rowid", "SELECT 1 ORDER BY id"] {
+      let result = Query::parse(sql).unwrap().execute(&db, &mut file);
+      assert!(matches!(&result, Err(Error::ColumnNotFound { available, .. }) if available.is_empty()), "{}", sql);
+    }
+    let error = Query::parse("SELECT x").unwrap().execute(&db, &mut file).unwrap_err();
+    assert_eq!(error.to_string(), "no such column: x");
+
+    // Arithmetic works with a FROM clause too, in parentheses as well as conditions are.
+    let query = Query::parse("SELECT username, age * 2 FROM users WHERE (age + 1) * 2 = 52 OR (id = 1)").unwrap();
+    assert_eq!(
+      query.execute(&db, &mut file).unwrap(),
+      vec![vec!["Alice", "58"], vec!["Celestino", "50"]]
+    );
+  }
+
+  #[test]
+  fn integer_columns_compare_with_real_literals() {
+    let (db, mut file) = open_interior();
+    // The expected counts are those sqlite3 returns.
+    for (condition, expected) in [("age = 105.0", "18"), ("age > 104.5", "18"), ("age <= 25.0", "2"), ("age != 105.5", "22")] {
+      let query = Query::parse(&format!("SELECT count(*) FROM users WHERE {}", condition)).unwrap();
+      assert_eq!(query.execute(&db, &mut file).unwrap(), vec![vec![expected]], "{}", condition);
+    }
+  }
+
+  #[test]
+  fn like_ignores_case_and_glob_does_not() {
+    let (db, mut file) = open_interior();
+    let mut count = |sql: &str| Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+    assert_eq!(count("SELECT COUNT(*) FROM users WHERE username LIKE 'dav%'"), vec![vec!["18"]]);
+    assert_eq!(count("SELECT COUNT(*) FROM users WHERE username GLOB 'dav*'"), vec![vec!["0"]]);
+    assert_eq!(count("SELECT COUNT(*) FROM users WHERE username glob 'Dav*'"), vec![vec!["18"]]);
+    assert_eq!(count("SELECT COUNT(*) FROM users WHERE age LIKE '10_'"), vec![vec!["18"]]);
+
+    let query = Query::parse("SELECT username FROM users WHERE username GLOB '[A-C]*'").unwrap();
+    assert_eq!(
+      query.execute(&db, &mut file).unwrap(),
+      vec![vec!["Alice"], vec!["Bob"], vec!["Charlie"], vec!["Celestino"]]
+    );
+  }
+
+  #[test]
+  fn like_escape_makes_wildcards_literal() {
+    let (db, mut file) = open_interior();
+    // The expected results are those sqlite3 returns.
+    for (sql, expected) in [
+      ("SELECT COUNT(*) FROM users WHERE email LIKE '%_%'", "22"),
+      ("SELECT COUNT(*) FROM users WHERE email LIKE '%!_%' ESCAPE '!'", "0"),
+      ("SELECT COUNT(*) FROM users WHERE email like '%!@%' escape '!'", "22"),
+      ("SELECT COUNT(*) FROM users WHERE username LIKE 'd!av%' ESCAPE '!' AND age = 105", "18"),
+      ("SELECT 1 WHERE '50%' LIKE '50\\%' ESCAPE '\\'", "1"),
+      ("SELECT 1 WHERE '50x' LIKE '50\\%' ESCAPE '\\'", ""),
+      ("SELECT 1 WHERE 'a\\' LIKE 'a\\' ESCAPE '\\'", ""),
+    ] {
+      let rows = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+      assert_eq!(rows.concat().join(","), expected, "{}", sql);
+    }
+
+    for escape in ["''", "'ab'", "1"] {
+      let sql = format!("SELECT 1 WHERE 'a' LIKE 'a' ESCAPE {}", escape);
+      assert!(matches!(Query::parse(&sql), Err(Error::UnsupportedSql(_))), "{}", sql);
+    }
+    // Only LIKE has an ESCAPE clause.
+    assert!(Query::parse("SELECT 1 WHERE 'a' GLOB 'a' ESCAPE '!'").is_err());
+  }
+
+  #[test]
+  fn is_not_includes_nulls_unlike_not_equal() {
+    let mut file = std::fs::File::open("tests/fixtures/rowid_alias.db").unwrap();
+    let db = Database::parse_header_and_schema(&file).unwrap();
+    // The expected rows are those sqlite3 returns. Each pair contrasts IS with the comparison
+    // that is unknown for NULLs.
+    for (sql, expected) in [
+      ("SELECT body FROM notes WHERE id != 'n1'", ""),
+      ("SELECT body FROM notes WHERE id IS NOT 'n1'", "untitled,draft"),
+      ("SELECT body FROM notes WHERE id = 'n1'", "first"),
+      ("SELECT body FROM notes WHERE id is 'n1'", "first"),
+      ("SELECT body FROM notes WHERE id = NULL", ""),
+      ("SELECT body FROM notes WHERE id IS NULL", "untitled,draft"),
+      ("SELECT body FROM notes WHERE NOT id != NULL", ""),
+      ("SELECT body FROM notes WHERE id IS NOT NULL", "first"),
+      ("SELECT 1 WHERE NULL IS NULL", "1"),
+      ("SELECT 1 WHERE NULL IS 5", ""),
+      ("SELECT 1 WHERE NOT (NULL != 5)", ""),
+      ("SELECT 1 WHERE NOT (NULL IS 5)", "1"),
+    ] {
+      let rows = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+      assert_eq!(rows.concat().join(","), expected, "{}", sql);
+    }
+  }
+
+  #[test]
+  fn three_valued_logic() {
+    use Truth::*;
+    let all = [True, False, Unknown];
+    let and = all.map(|a| all.map(|b| a.and(b)));
+    assert_eq!(and, [[True, False, Unknown], [False, False, False], [Unknown, False, Unknown]]);
+    let or = all.map(|a| all.map(|b| a.or(b)));
+    assert_eq!(or, [[True, True, True], [True, False, Unknown], [True, Unknown, Unknown]]);
+    assert_eq!(all.map(|a| !a), [False, True, Unknown]);
+
+    // The right side isn't evaluated when the left decides the result.
+    let comparison = |truth| Box::new(Condition::Comparison(truth));
+    let mut evaluated = 0;
+    let mut test = |truth: &Truth| {
+      evaluated += 1;
+      *truth
+    };
+    assert_eq!(Condition::And(comparison(False), comparison(Unknown)).evaluate(&mut test), False);
+    assert_eq!(Condition::Or(comparison(True), comparison(Unknown)).evaluate(&mut test), True);
+    assert_eq!(Condition::Or(comparison(Unknown), comparison(False)).evaluate(&mut test), Unknown);
+    assert_eq!(Condition::Not(comparison(Unknown)).evaluate(&mut test), Unknown);
+    assert_eq!(evaluated, 5);
+  }
+
+  #[test]
+  fn parse_where_precedence() {
+    let select = |sql: &str| match Query::parse(sql).unwrap() {
+      Query::Select(select) => format!("{:?}", select.filters.iter().map(|c| c.map(&mut |f| f.left.to_string())).collect::<Vec<_>>()),
+      query => panic!("not a select: {:?}", query),
+    };
+    assert_eq!(
+      select("SELECT a FROM t WHERE a = 1 OR b = 2 AND NOT c = 3"),
+      r#"[Or(Comparison("a"), And(Comparison("b"), Not(Comparison("c"))))]"#
+    );
+    // The conditions ANDed at the top level are kept apart, for the planner.
+    assert_eq!(
+      select("SELECT a FROM t WHERE (a = 1 OR b = 2) AND NOT (c = 3 AND d = 4) and e = 5"),
+      r#"[Or(Comparison("a"), Comparison("b")), Not(And(Comparison("c"), Comparison("d"))), Comparison("e")]"#
+    );
+    assert!(Query::parse("SELECT a FROM t WHERE (a = 1").is_err());
+    assert!(Query::parse("SELECT a FROM t WHERE a = 1 OR").is_err());
+  }
+
+  #[test]
+  fn parse_select_splits_on_punctuation_not_whitespace() {
+    let select = |sql: &str| match Query::parse(sql).unwrap() {
+      Query::Select(select) => select,
+      query => panic!("not a select: {:?}", query),
+    };
+
+    let query = select("SELECT id,name FROM users");
+    assert_eq!(query.columns.len(), 2);
+    assert_eq!(query.table_name.as_deref(), Some("users"));
+    let query = select("SELECT count(*)FROM\n\"users\";");
+    assert!(matches!(query.columns[..], [super::Column::Count]));
+    assert_eq!(query.table_name.as_deref(), Some("users"));
+    assert_eq!(select("SELECT from_date FROM [events]").table_name.as_deref(), Some("events"));
+    assert_eq!(select("SELECT 'x FROM y', a FROM t").columns.len(), 2);
+
+    // String literals keep their exact text, whatever is next to their quotes.
+    for (sql, value) in [
+      ("SELECT a FROM t WHERE city = 'New  York  City'", "New  York  City"),
+      ("SELECT a FROM t WHERE city='Rome,Italy'", "Rome,Italy"),
+      ("SELECT a FROM t WHERE city = 'it''s (here)' ;", "it's (here)"),
+    ] {
+      let filter = select(sql).filters[0].as_comparison().unwrap().column_value.clone();
+      assert_eq!(filter, Value::Text(value.to_string()), "{}", sql);
+    }
+
+    let (db, mut file) = open_interior();
+    let rows = Query::parse("SELECT id,username FROM users WHERE username='Bob'").unwrap().execute(&db, &mut file);
+    assert_eq!(rows.unwrap(), vec![vec!["2", "Bob"]]);
+    assert!(Query::parse("SELECT id FROM").is_err());
+    assert!(Query::parse("SELECT id, FROM users").is_err());
+  }
+
+  #[test]
+  fn parse_insert() {
+    let query = Query::parse(
+      "INSERT INTO users (id, username, age) VALUES (23, 'O''Brien, Zoe', -31), (NULL,'x', 2.5);",
+    )
+    .unwrap();
+    let insert = match query {
+      Query::Insert(insert) => insert,
+      query => panic!("not an insert: {:?}", query),
+    };
+    assert_eq!(insert.table_name, "users");
+    assert_eq!(
+      insert.column_names,
+      Some(vec!["id".to_string(), "username".to_string(), "age".to_string()])
+    );
+    // NULL never compares equal, so compare the debug output.
+    assert_eq!(
+      format!("{:?}", insert.rows),
+      r#"[[Integer(23), Text("O'Brien, Zoe"), Integer(-31)], [Null, Text("x"), Real(2.5)]]"#
+    );
+
+    match Query::parse("insert into t values('a')").unwrap() {
+      Query::Insert(insert) => assert!(insert.column_names.is_none()),
+      query => panic!("not an insert: {:?}", query),
+    }
+    assert!(Query::parse("INSERT INTO users VALUES (1, 'a'").is_err());
+    assert!(Query::parse("INSERT INTO users VALUES (1) garbage").is_err());
+  }
+
+  #[test]
+  fn parse_update() {
+    let query = Query::parse("UPDATE users SET age = 30, username = 'A=B, C' WHERE username = 'Alice';").unwrap();
+    let update = match query {
+      Query::Update(update) => update,
+      query => panic!("not an update: {:?}", query),
+    };
+    assert_eq!(update.table_name, "users");
+    assert_eq!(
+      format!("{:?}", update.assignments),
+      r#"[("age", Integer(30)), ("username", Text("A=B, C"))]"#
+    );
+    assert_eq!(update.filters.len(), 1);
+    let filter = update.filters[0].as_comparison().unwrap();
+    assert_eq!(filter.left, Expr::Column("username".to_string()));
+    assert_eq!(filter.column_value, Value::Text("Alice".to_string()));
+
+    match Query::parse("update t set a = null").unwrap() {
+      Query::Update(update) => assert!(update.filters.is_empty()),
+      query => panic!("not an update: {:?}", query),
+    }
+    assert!(Query::parse("UPDATE users age = 30").is_err());
+    assert!(Query::parse("UPDATE users SET age = 30 garbage").is_err());
+  }
+
+  #[test]
+  fn parse_create_finds_the_rowid_alias() {
+    // Checked against which of these tables sqlite3 stores the key of as the rowid.
+    for (sql, row_id_column) in [
+      ("CREATE TABLE t (id INTEGER PRIMARY KEY, name TEXT)", Some(0)),
+      ("CREATE TABLE t (name TEXT, user_id integer primary key autoincrement)", Some(1)),
+      ("CREATE TABLE t (id TEXT, name TEXT)", None),
+      ("CREATE TABLE t (id INT PRIMARY KEY, name TEXT)", None),
+      ("CREATE TABLE t (id INTEGER PRIMARY KEY DESC, name TEXT)", None),
+      ("CREATE TABLE t (id INTEGER, name TEXT, PRIMARY KEY (id))", Some(0)),
+      ("CREATE TABLE t (id INTEGER, name TEXT, PRIMARY KEY (id DESC))", Some(0)),
+      ("CREATE TABLE t (a INTEGER, b INTEGER, PRIMARY KEY (a, b))", None),
+      ("CREATE TABLE t (a INTEGER, b TEXT, CONSTRAINT pk PRIMARY KEY (b))", None),
+    ] {
+      let query = Query::parse(sql).unwrap();
+      let create = query.as_create().unwrap();
+      assert_eq!(create.row_id_column, row_id_column, "{}", sql);
+      assert_eq!(create.column_names.len(), 2, "{}", sql);
+    }
+  }
+
+  #[test]
+  fn parse_create_lists_keys_sqlite_indexes() {
+    // The columns of each sqlite_autoindex_t_<n> in order, as sqlite3's PRAGMA index_xinfo gives
+    // them, DESC ones marked.
+    for (sql, keys) in [
+      ("CREATE TABLE t (id INTEGER PRIMARY KEY DESC, name TEXT)", vec!["pk id desc"]),
+      ("CREATE TABLE t (id INTEGER PRIMARY KEY ASC, name TEXT)", vec![]),
+      ("CREATE TABLE t (a UNIQUE, b TEXT PRIMARY KEY, c, UNIQUE (c, a), UNIQUE (b))", vec!["a", "pk b", "c a"]),
+      ("CREATE TABLE t (x INTEGER PRIMARY KEY, y UNIQUE)", vec!["y"]),
+      ("CREATE TABLE t (p, q, PRIMARY KEY (q DESC))", vec!["pk q desc"]),
+      ("CREATE TABLE t (id INTEGER, name TEXT, PRIMARY KEY (id DESC))", vec![]),
+      ("CREATE TABLE t (a, b, PRIMARY KEY (a, b)) WITHOUT ROWID", vec![]),
+    ] {
+      let query = Query::parse(sql).unwrap();
+      let create = query.as_create().unwrap();
+      let found = create
+        .key_constraints
+        .iter()
+        .map(|key| {
+          let columns = key.columns.iter().zip(key.descending.iter()).map(|(i, descending)| {
+            format!("{}{}", create.column_names[*i], if *descending { " desc" } else { "" })
+          });
+          let pk = key.is_primary_key.then_some("pk".to_string());
+          pk.into_iter().chain(columns).collect::<Vec<_>>().join(" ")
+        })
+        .collect::<Vec<_>>();
+      assert_eq!(found, keys, "{}", sql);
+    }
+    // A table without a rowid keeps the order of its key for its own b-tree instead.
+    let query = Query::parse("CREATE TABLE t (a, b, PRIMARY KEY (b DESC, a)) WITHOUT ROWID").unwrap();
+    let create = query.as_create().unwrap();
+    assert_eq!((&create.primary_key[..], &create.primary_key_descending[..]), (&[1, 0][..], &[true, false][..]));
+  }
+
+  #[test]
+  fn parse_create_keeps_declared_types_and_derives_affinities() {
+    // The types and affinities sqlite3 reports for these columns, with PRAGMA table_info and by
+    // the storage class of a value inserted as text.
+    let columns = [
+      ("VARCHAR(255)", "VARCHAR(255)", Affinity::Text),
+      ("NUMERIC", "NUMERIC", Affinity::Numeric),
+      ("DECIMAL(10,2)", "DECIMAL(10,2)", Affinity::Numeric),
+      ("decimal( 10 , 5 ) default 0", "decimal( 10 , 5 )", Affinity::Numeric),
+      ("BOOLEAN", "BOOLEAN", Affinity::Numeric),
+      ("DATETIME", "DATETIME", Affinity::Numeric),
+      ("BLOB", "BLOB", Affinity::Blob),
+      ("", "", Affinity::Blob),
+      ("DOUBLE PRECISION", "DOUBLE PRECISION", Affinity::Real),
+      ("UNSIGNED BIG INT", "UNSIGNED BIG INT", Affinity::Integer),
+      ("NVARCHAR(100) NOT NULL", "NVARCHAR(100)", Affinity::Text),
+      ("CHARACTER(20)", "CHARACTER(20)", Affinity::Text),
+      ("FLOATING POINT", "FLOATING POINT", Affinity::Integer),
+      ("\"varying character\"(30)", "varying character", Affinity::Text),
+      ("[big int] not null", "big int", Affinity::Integer),
+      ("\"var\"\"char\"(5)", "var\"char", Affinity::Text),
+    ];
+    let definitions = columns
+      .iter()
+      .enumerate()
+      .map(|(i, (written, _, _))| format!("c{} {}", i, written))
+      .collect::<Vec<_>>();
+    let query = Query::parse(&format!("CREATE TABLE t ({})", definitions.join(", "))).unwrap();
+    let create = query.as_create().unwrap();
+    for (i, (written, declared, affinity)) in columns.into_iter().enumerate() {
+      assert_eq!(create.column_types[i], declared, "{}", written);
+      assert_eq!(create.column_affinities[i], affinity, "{}", written);
+    }
+
+    let query = Query::parse("CREATE TABLE t (id \"integer\" primary key, name 'text')").unwrap();
+    assert_eq!(query.as_create().unwrap().row_id_column, Some(0));
+  }
+
+  #[test]
+  fn parse_create_reads_table_options() {
+    for (sql, without_rowid, strict) in [
+      ("CREATE TABLE t (id INTEGER PRIMARY KEY, b TEXT)", false, false),
+      ("CREATE TABLE t (id INTEGER PRIMARY KEY, b TEXT) STRICT", false, true),
+      ("CREATE TABLE t (id INTEGER PRIMARY KEY, b TEXT) without  rowid", true, false),
+      ("CREATE TABLE t (id INTEGER PRIMARY KEY, b TEXT) WITHOUT ROWID, STRICT;", true, true),
+      ("CREATE TABLE t (id INTEGER PRIMARY KEY, b TEXT)strict , Without Rowid", true, true),
+    ] {
+      let query = Query::parse(sql).unwrap();
+      let create = query.as_create().unwrap();
+      assert_eq!((create.without_rowid, create.strict), (without_rowid, strict), "{}", sql);
+      assert_eq!(create.column_names, ["id", "b"], "{}", sql);
+      // The key of a table without a rowid doesn't alias one.
+      assert_eq!(create.row_id_column, (!without_rowid).then_some(0), "{}", sql);
+    }
+    for sql in [
+      "CREATE TABLE t (a) STRICT,",
+      "CREATE TABLE t (a) TEMPORARY",
+      "CREATE TABLE t (a) WITHOUT",
+      "CREATE INDEX i ON t (a) STRICT",
+    ] {
+      assert!(matches!(Query::parse(sql), Err(Error::UnsupportedSql(_))), "{}", sql);
+    }
+  }
+
+  #[test]
+  fn parse_create_reads_default_values() {
+    let literal = |value| Some(DefaultValue::Literal(value));
+    let columns = [
+      ("-1", literal(Value::Integer(-1))),
+      ("- 2.5", literal(Value::Real(-2.5))),
+      ("+3", literal(Value::Integer(3))),
+      ("0.5", literal(Value::Real(0.5))),
+      ("1e3", literal(Value::Real(1000.0))),
+      ("0x10", literal(Value::Integer(16))),
+      ("-9223372036854775808", literal(Value::Integer(i64::MIN))),
+      ("NULL", literal(Value::Null)),
+      ("true", literal(Value::Integer(1))),
+      ("'it''s' not null", literal(Value::Text("it's".to_owned()))),
+      ("x'0a41'", literal(Value::Blob(b"\nA".to_vec()))),
+      ("CURRENT_TIMESTAMP", Some(DefaultValue::CurrentTimestamp)),
+      ("current_date", Some(DefaultValue::CurrentDate)),
+      ("(datetime('now'))", Some(DefaultValue::Expr("datetime('now')".to_owned()))),
+      ("(1 + 2) collate binary", Some(DefaultValue::Expr("1 + 2".to_owned()))),
+    ];
+    let definitions = columns
+      .iter()
+      .enumerate()
+      .map(|(i, (written, _))| format!("c{} default {}", i, written))
+      .chain(["n".to_owned()])
+      .collect::<Vec<_>>();
+    let query = Query::parse(&format!("CREATE TABLE t ({})", definitions.join(", "))).unwrap();
+    let create = query.as_create().unwrap();
+    for (i, (written, default)) in columns.into_iter().enumerate() {
+      // Compared as debug output, since NULL isn't equal to NULL.
+      assert_eq!(format!("{:?}", create.column_default_values[i]), format!("{:?}", default), "{}", written);
+    }
+    assert!(create.column_default_values.last().unwrap().is_none());
+
+    // Only literals, and expressions of them, are the same for every row.
+    assert_eq!(create.column_default_values[14].as_ref().unwrap().constant(), Some(Value::Integer(3)));
+    assert_eq!(create.column_default_values[13].as_ref().unwrap().constant(), None);
+    assert_eq!(create.column_default_values[11].as_ref().unwrap().constant(), None);
+  }
+
+  #[test]
+  fn parse_create_reads_references_clauses() {
+    let query = Query::parse(
+      "CREATE TABLE t (a INTEGER REFERENCES users(id) ON DELETE CASCADE, b text references \"Group\" (\"id\", [k]), c)",
+    )
+    .unwrap();
+    let create = query.as_create().unwrap();
+    let parent = |table: &str, columns: &[&str]| ForeignKey {
+      table: table.to_owned(),
+      columns: columns.iter().map(|c| c.to_string()).collect(),
+    };
+    assert_eq!(
+      create.column_references,
+      [Some(parent("users", &["id"])), Some(parent("Group", &["id", "k"])), None]
+    );
+    assert_eq!(create.column_types, ["INTEGER", "text", ""]);
+  }
+
+  #[test]
+  fn parse_create_reads_check_clauses() {
+    let query = Query::parse(
+      "CREATE TABLE t (price REAL CHECK (price >= 0), qty integer not null check(qty > 0 and (qty < 10)), n)",
+    )
+    .unwrap();
+    let create = query.as_create().unwrap();
+    assert_eq!(
+      create.column_checks,
+      [Some("price >= 0".to_owned()), Some("qty > 0 and (qty < 10)".to_owned()), None]
+    );
+    assert_eq!(create.column_not_null, [false, true, false]);
+    assert!(create.table_checks.is_empty());
+    assert!(Query::parse("CREATE TABLE t (price REAL CHECK (price >= 0)").is_err());
+
+    let query = Query::parse("CREATE TABLE t (lo, hi, CONSTRAINT ordered CHECK (lo <= hi), UNIQUE (lo))").unwrap();
+    let create = query.as_create().unwrap();
+    assert_eq!(create.column_checks, [None, None]);
+    assert_eq!(create.table_checks, ["lo <= hi"]);
+  }
+
+  #[test]
+  fn parse_create_reads_collate_clauses() {
+    let query = Query::parse("CREATE TABLE t (name TEXT COLLATE NOCASE, code text collate rtrim not null, n)").unwrap();
+    let create = query.as_create().unwrap();
+    assert_eq!(create.column_collations, [Some(Collation::NoCase), Some(Collation::RTrim), None]);
+    assert_eq!(create.column_types, ["TEXT", "text", ""]);
+    assert!(Query::parse("CREATE TABLE t (name TEXT COLLATE klingon)").is_err());
+  }
+
+  #[test]
+  fn parse_create_reads_generated_columns() {
+    let query = Query::parse(
+      "CREATE TABLE t (a, b, total REAL GENERATED ALWAYS AS (a + b) STORED, c text as (substr(a, 1, 2)) virtual, \
+       d generated always as ((a * 2)) not null)",
+    )
+    .unwrap();
+    let create = query.as_create().unwrap();
+    let generated = |expr: &str, stored| {
+      Some(GeneratedColumn {
+        expr: expr.to_owned(),
+        stored,
+      })
+    };
+    assert_eq!(
+      create.column_generated,
+      [
+        None,
+        None,
+        generated("a + b", true),
+        generated("substr(a, 1, 2)", false),
+        generated("(a * 2)", false)
+      ]
+    );
+    assert_eq!(create.column_types, ["", "", "REAL", "text", ""]);
+    assert_eq!(create.column_not_null, [false, false, false, false, true]);
+  }
+
+  #[test]
+  fn virtual_generated_columns_are_computed_rather_than_read() {
+    let mut file = std::fs::File::open("tests/fixtures/generated.db").unwrap();
+    let db = Database::parse_header_and_schema(&file).unwrap();
+    // The expected rows are those sqlite3 returns. `code`, `half` and `size` are VIRTUAL, so
+    // `note` comes straight after `total` in the record, and `size` reads `code` in turn.
+    for (sql, expected) in [
+      ("SELECT note, price FROM orders", "first,2.5,Second,10.5,null,0.5,third order,3.25"),
+      ("SELECT code, half, size FROM orders WHERE id = 4", "thi,1.625,5"),
+      ("SELECT id FROM orders WHERE code = 'Sec'", "2"),
+      ("SELECT size FROM orders ORDER BY size DESC", "7,5,4,null"),
+      ("SELECT max(half) FROM orders", "5.25"),
+      ("SELECT name FROM users WHERE name = 'BOB'", "bob"),
+    ] {
+      let rows = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap();
+      assert_eq!(rows.concat().join(","), expected, "{}", sql);
+    }
+    assert!(db.schema.writable_table("orders").is_err());
+  }
+
+  #[test]
+  fn generated_columns_that_cannot_be_computed_are_an_error() {
+    // events (id, payload, kind AS (json_extract(payload, '$.kind')) VIRTUAL, note), where note
+    // is the third value of each record.
+    let mut file = std::fs::File::open("tests/fixtures/opaque_generated.db").unwrap();
+    let db = Database::parse_header_and_schema(&file).unwrap();
+    let rows = Query::parse("SELECT id, note FROM events WHERE note = 'second'").unwrap().execute(&db, &mut file);
+    assert_eq!(rows.unwrap(), vec![vec!["2", "second"]]);
+
+    for sql in [
+      "SELECT kind FROM events",
+      "SELECT note FROM events WHERE kind = 'view'",
+      "SELECT note FROM events ORDER BY kind",
+      "SELECT * FROM events",
+    ] {
+      let error = Query::parse(sql).unwrap().execute(&db, &mut file).unwrap_err();
+      assert_eq!(error.to_string(), "column kind is a generated column and cannot be read by this tool", "{}", sql);
     }
   }
 }
@@ -1,13 +1,25 @@
 use std::fmt::Display;
 use nom::{bytes::complete::take, number::complete::i8, IResult};
 
-use crate::varint::varint;
+use crate::{
+  error::InvalidValueError,
+  varint::{varint, write_varint},
+};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Record {
+  /// The rowid of the cell this record was read from, for table b-tree records.
+  pub row_id: Option<i64>,
   pub values: Vec<Value>,
 }
 
+/// A record whose values borrow from the page (or reassembled payload) it was decoded from.
+#[derive(Debug)]
+pub struct RecordRef<'a> {
+  pub row_id: Option<i64>,
+  pub values: Vec<ValueRef<'a>>,
+}
+
 #[derive(Debug)]
 pub enum ColumnType {
   Null,
@@ -24,69 +36,215 @@ pub enum ColumnType {
   Text(usize),
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Clone)]
 pub enum Value {
   Null,
   Integer(i64),
   Real(f64),
   Text(String),
-  Blob(String),
+  Blob(Vec<u8>),
 }
 
-impl PartialOrd for Value {
-  fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+/// A value borrowing its text and blob data from the page it was decoded from, so that rows can
+/// be filtered without copying them.
+#[derive(Debug, Clone, Copy)]
+pub enum ValueRef<'a> {
+  Null,
+  Integer(i64),
+  Real(f64),
+  Text(&'a str),
+  Blob(&'a [u8]),
+}
+
+/// Compare an integer with a real exactly, as SQLite does, rather than by converting the integer
+/// to a real, which loses precision beyond 2^53. NaN is unordered.
+fn compare_integer_real(n: i64, f: f64) -> Option<std::cmp::Ordering> {
+  use std::cmp::Ordering::*;
+  if f.is_nan() {
+    return None;
+  }
+  // Reals outside the i64 range are beyond every integer. 2^63 itself is one past i64::MAX.
+  if f < i64::MIN as f64 {
+    return Some(Greater);
+  }
+  if f >= -(i64::MIN as f64) {
+    return Some(Less);
+  }
+  // The integer part of a real in range is exact, so only its fraction can break a tie.
+  Some(n.cmp(&(f as i64)).then(0.0.partial_cmp(&f.fract())?))
+}
+
+/// How text is compared, as chosen by a COLLATE clause on a column or index.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Collation {
+  /// Bytewise.
+  #[default]
+  Binary,
+  /// Bytewise after folding ASCII letters to lower case.
+  NoCase,
+  /// Bytewise, ignoring trailing spaces.
+  RTrim,
+}
+
+impl Collation {
+  pub fn from_name(name: &str) -> Option<Self> {
+    match name.to_ascii_lowercase().as_str() {
+      "binary" => Some(Collation::Binary),
+      "nocase" => Some(Collation::NoCase),
+      "rtrim" => Some(Collation::RTrim),
+      _ => None,
+    }
+  }
+
+  fn compare(self, s1: &str, s2: &str) -> std::cmp::Ordering {
     match self {
-      Value::Null => None,
-      Value::Integer(n1) => match other {
-        Value::Integer(n2) => n1.partial_cmp(n2),
-        _ => None,
-      },
-      Value::Real(f1) => match other {
-        Value::Real(f2) => f1.partial_cmp(f2),
-        _ => None,
-      },
-      Value::Text(s1) => match other {
-        Value::Text(s2) => s1.partial_cmp(s2),
-        Value::Blob(s2) => s1.partial_cmp(s2),
-        _ => None,
-      },
-      Value::Blob(s1) => match other {
-        Value::Text(s2) => s1.partial_cmp(s2),
-        Value::Blob(s2) => s1.partial_cmp(s2),
-        _ => None,
-      },
+      Collation::Binary => s1.as_bytes().cmp(s2.as_bytes()),
+      Collation::NoCase => {
+        let fold = |s: &str| s.bytes().map(|b| b.to_ascii_lowercase()).collect::<Vec<_>>();
+        fold(s1).cmp(&fold(s2))
+      }
+      Collation::RTrim => s1.trim_end_matches(' ').as_bytes().cmp(s2.trim_end_matches(' ').as_bytes()),
     }
   }
 }
 
-impl PartialEq for Value {
+impl PartialOrd for ValueRef<'_> {
+  /// Compare values as SQL does with the BINARY collation, see [`ValueRef::sql_cmp`].
+  fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    self.sql_cmp(other, Collation::Binary)
+  }
+}
+
+impl PartialEq for ValueRef<'_> {
+  /// SQL equality, under which NULL equals nothing. [`SortKey`] compares NULLs as equal.
   fn eq(&self, other: &Self) -> bool {
+    self.partial_cmp(other) == Some(std::cmp::Ordering::Equal)
+  }
+}
+
+impl PartialEq<Value> for ValueRef<'_> {
+  fn eq(&self, other: &Value) -> bool {
+    *self == other.as_value_ref()
+  }
+}
+
+impl ValueRef<'_> {
+  /// Compare values in SQLite's sort order: NULL first, then numbers compared numerically
+  /// whether integer or real, then text, then blobs. Text and blobs compare bytewise, as with
+  /// the BINARY collation.
+  pub fn total_cmp(&self, other: &Self) -> std::cmp::Ordering {
+    self.collated_cmp(other, Collation::Binary)
+  }
+
+  /// Compare values as SQL does: NULL is unordered, even with NULL, and other values follow
+  /// [`ValueRef::collated_cmp`], so a number is less than any text, after affinity is applied.
+  pub fn sql_cmp(&self, other: &Self, collation: Collation) -> Option<std::cmp::Ordering> {
+    if matches!(self, ValueRef::Null) || matches!(other, ValueRef::Null) {
+      return None;
+    }
+    Some(self.collated_cmp(other, collation))
+  }
+
+  /// Like [`ValueRef::total_cmp`], but comparing text with `collation`.
+  pub fn collated_cmp(&self, other: &Self, collation: Collation) -> std::cmp::Ordering {
+    use std::cmp::Ordering::{Greater, Less};
+    fn class(value: &ValueRef) -> u8 {
+      match value {
+        ValueRef::Null => 0,
+        ValueRef::Integer(_) | ValueRef::Real(_) => 1,
+        ValueRef::Text(_) => 2,
+        ValueRef::Blob(_) => 3,
+      }
+    }
+
+    match (self, other) {
+      (ValueRef::Integer(n1), ValueRef::Integer(n2)) => n1.cmp(n2),
+      // Only NaN is unordered with an integer, and it sorts after all of them.
+      (ValueRef::Integer(n), ValueRef::Real(f)) => compare_integer_real(*n, *f).unwrap_or(Less),
+      (ValueRef::Real(f), ValueRef::Integer(n)) => compare_integer_real(*n, *f).map_or(Greater, |o| o.reverse()),
+      // -0.0 equals 0.0, as it does the integer 0, and NaN sorts after every other number.
+      (ValueRef::Real(f1), ValueRef::Real(f2)) => f1.partial_cmp(f2).unwrap_or_else(|| f1.is_nan().cmp(&f2.is_nan())),
+      (ValueRef::Text(s1), ValueRef::Text(s2)) => collation.compare(s1, s2),
+      (ValueRef::Blob(b1), ValueRef::Blob(b2)) => b1.cmp(b2),
+      _ => class(self).cmp(&class(other)),
+    }
+  }
+
+  pub fn to_value(self) -> Value {
     match self {
-      Value::Null => false,
-      Value::Integer(n1) => match other {
-        Value::Integer(n2) => n1 == n2,
-        _ => false,
-      },
-      Value::Real(f1) => match other {
-        Value::Real(f2) => f1 == f2,
-        _ => false,
-      },
-      Value::Text(s1) => match other {
-        Value::Text(s2) => s1 == s2,
-        Value::Blob(s2) => s1 == s2,
-        _ => false,
-      },
-      Value::Blob(s1) => match other {
-        Value::Text(s2) => s1 == s2,
-        Value::Blob(s2) => s1 == s2,
-        _ => false,
-      },
+      ValueRef::Null => Value::Null,
+      ValueRef::Integer(n) => Value::Integer(n),
+      ValueRef::Real(f) => Value::Real(f),
+      ValueRef::Text(s) => Value::Text(s.to_owned()),
+      ValueRef::Blob(b) => Value::Blob(b.to_vec()),
     }
   }
 }
 
+impl PartialOrd for Value {
+  fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    self.as_value_ref().partial_cmp(&other.as_value_ref())
+  }
+}
+
+impl PartialEq for Value {
+  fn eq(&self, other: &Self) -> bool {
+    self.as_value_ref() == other.as_value_ref()
+  }
+}
+
+/// A value ordered by [`ValueRef::total_cmp`], for sorting, searching index keys, and grouping.
+/// Unlike the SQL comparison of values, it is a total order, under which NULL equals NULL.
+#[derive(Debug, Clone, Copy)]
+pub struct SortKey<'a>(pub ValueRef<'a>);
+
+impl Ord for SortKey<'_> {
+  fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+    self.0.total_cmp(&other.0)
+  }
+}
+
+impl PartialOrd for SortKey<'_> {
+  fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl PartialEq for SortKey<'_> {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other).is_eq()
+  }
+}
+
+impl Eq for SortKey<'_> {}
+
 impl Value {
+  pub fn as_value_ref(&self) -> ValueRef<'_> {
+    match self {
+      Value::Null => ValueRef::Null,
+      Value::Integer(n) => ValueRef::Integer(*n),
+      Value::Real(f) => ValueRef::Real(*f),
+      Value::Text(s) => ValueRef::Text(s),
+      Value::Blob(b) => ValueRef::Blob(b),
+    }
+  }
+
+  /// The value as a key in SQLite's sort order.
+  pub fn sort_key(&self) -> SortKey<'_> {
+    SortKey(self.as_value_ref())
+  }
+
+  /// The name of this value's storage class, as returned by SQLite's `typeof()`.
+  pub fn type_name(&self) -> &'static str {
+    match self {
+      Value::Null => "null",
+      Value::Integer(_) => "integer",
+      Value::Real(_) => "real",
+      Value::Text(_) => "text",
+      Value::Blob(_) => "blob",
+    }
+  }
+
   pub fn as_integer(&self) -> Option<i64> {
     match self {
       Value::Integer(n) => Some(*n),
@@ -94,7 +252,6 @@ impl Value {
     }
   }
 
-  #[allow(dead_code)]
   pub fn as_real(&self) -> Option<f64> {
     match self {
       Value::Real(f) => Some(*f),
@@ -109,10 +266,9 @@ impl Value {
     }
   }
 
-  #[allow(dead_code)]
-  pub fn as_blob(&self) -> Option<&str> {
+  pub fn as_blob(&self) -> Option<&[u8]> {
     match self {
-      Value::Blob(s) => Some(s),
+      Value::Blob(b) => Some(b),
       _ => None,
     }
   }
@@ -123,8 +279,9 @@ impl Display for Value {
     let str = match self {
       Value::Null => "null".into(),
       Value::Integer(n) => n.to_string(),
-      Value::Real(f) => f.to_string(),
-      Value::Blob(s) => s.to_owned(),
+      Value::Real(f) => crate::expr::format_real(*f),
+      // As sqlite3 prints a blob: its bytes as they are, here as far as they're UTF-8.
+      Value::Blob(b) => String::from_utf8_lossy(b).into_owned(),
       Value::Text(s) => s.to_owned(),
     };
     write!(f1, "{}", str)
@@ -132,7 +289,7 @@ impl Display for Value {
 }
 
 impl ColumnType {
-  #[allow(dead_code)]
+  /// The number of bytes taken up by a value of this type in the record body.
   fn size(&self) -> usize {
     match self {
       ColumnType::Null => 0,
@@ -148,10 +305,38 @@ impl ColumnType {
       ColumnType::Blob(size) | ColumnType::Text(size) => *size,
     }
   }
+
+  /// Decode a value of this type from the start of `input`, returning the rest of it.
+  fn decode<'a>(&self, input: &'a [u8]) -> IResult<&'a [u8], ValueRef<'a>> {
+    let invalid = |input| nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify));
+    let (rest, bytes) = take(self.size())(input)?;
+    let value = match self {
+      ColumnType::Null => ValueRef::Null,
+      ColumnType::I8 => ValueRef::Integer(i8(bytes)?.1 as i64),
+      ColumnType::I16 => ValueRef::Integer(i16::from_be_bytes([bytes[0], bytes[1]]) as i64),
+      ColumnType::I24 => {
+        let sign = if bytes[0] & 0x80 != 0 { 0xff } else { 0 };
+        ValueRef::Integer(i32::from_be_bytes([sign, bytes[0], bytes[1], bytes[2]]) as i64)
+      }
+      ColumnType::I32 => ValueRef::Integer(i32::from_be_bytes(bytes.try_into().unwrap()) as i64),
+      ColumnType::I48 => {
+        let mut value = [if bytes[0] & 0x80 != 0 { 0xff } else { 0 }; 8];
+        value[2..].copy_from_slice(bytes);
+        ValueRef::Integer(i64::from_be_bytes(value))
+      }
+      ColumnType::I64 => ValueRef::Integer(i64::from_be_bytes(bytes.try_into().unwrap())),
+      ColumnType::F64 => ValueRef::Real(f64::from_be_bytes(bytes.try_into().unwrap())),
+      ColumnType::Zero => ValueRef::Integer(0),
+      ColumnType::One => ValueRef::Integer(1),
+      ColumnType::Blob(_) => ValueRef::Blob(bytes),
+      ColumnType::Text(_) => ValueRef::Text(std::str::from_utf8(bytes).map_err(|_| invalid(bytes))?),
+    };
+    Ok((rest, value))
+  }
 }
 
 impl TryFrom<i64> for ColumnType {
-  type Error = anyhow::Error;
+  type Error = InvalidValueError;
 
   fn try_from(value: i64) -> Result<Self, Self::Error> {
     match value {
@@ -165,7 +350,8 @@ impl TryFrom<i64> for ColumnType {
       7 => Ok(ColumnType::F64),
       8 => Ok(ColumnType::Zero),
       9 => Ok(ColumnType::One),
-      10 | 11 => Err(anyhow::format_err!("invalid column type")),
+      10 | 11 => Err(InvalidValueError(format!("reserved serial type {}", value))),
+      value if value < 0 => Err(InvalidValueError(format!("invalid serial type {}", value))),
       value => {
         if value % 2 == 0 {
           Ok(ColumnType::Blob(((value - 12) / 2) as usize))
@@ -177,129 +363,446 @@ impl TryFrom<i64> for ColumnType {
   }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RecordType {
   Table,
   Index,
 }
 
-impl Record {
-  pub fn parse<'input>(
-    input: &'input [u8],
+/// A column of a record as stored, for showing how a value is encoded as well as what it is.
+#[derive(Debug, Clone, Copy)]
+pub struct RawColumn<'a> {
+  /// The serial type code from the record header, e.g. 1 for an 8-bit integer.
+  pub serial_type: i64,
+  /// The bytes of the value in the record body, empty for NULL, 0 and 1.
+  pub bytes: &'a [u8],
+  pub value: ValueRef<'a>,
+}
+
+/// A record decoded with the serial type and bytes of each of its columns, as returned by
+/// [`RecordRaw::parse`]. Queries decode with [`RecordRef::parse`] instead, which keeps only the
+/// values they ask for.
+#[derive(Debug)]
+pub struct RecordRaw<'a> {
+  /// The size of the record header, including the varint holding it.
+  pub header_size: usize,
+  pub columns: Vec<RawColumn<'a>>,
+}
+
+/// The size of a record header, and the serial type code and type of each column.
+type RecordHeader = (usize, Vec<(i64, ColumnType)>);
+
+/// The header of the record at the start of `input`, returning the input from the start of the
+/// record body.
+fn record_header(input: &[u8]) -> IResult<&[u8], RecordHeader> {
+  let invalid = |input| nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Verify));
+  let mut header_bytes_read = 0;
+  let before_input_len = input.len();
+  let (input, header_size) = varint(input)?;
+  let header_size = header_size as usize;
+  header_bytes_read += before_input_len - input.len();
+  if header_size > before_input_len {
+    // Only part of the payload is available, and it doesn't even cover the header.
+    return Err(nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Eof)));
+  }
+
+  let mut rest = input;
+  let mut column_types = Vec::new();
+  while header_bytes_read < header_size {
+    let (remainder, serial_type) = varint(rest)?;
+    header_bytes_read += rest.len() - remainder.len();
+    rest = remainder;
+    let column_type = ColumnType::try_from(serial_type).map_err(|_| invalid(rest))?;
+    column_types.push((serial_type, column_type));
+  }
+  Ok((rest, (header_size, column_types)))
+}
+
+impl<'a> RecordRaw<'a> {
+  /// Decode every column of the record in `input`, keeping each one's serial type and bytes.
+  pub fn parse(input: &'a [u8]) -> IResult<&'a [u8], Self> {
+    let (mut rest, (header_size, column_types)) = record_header(input)?;
+    let mut columns = Vec::with_capacity(column_types.len());
+    for (serial_type, column_type) in column_types {
+      let (remainder, value) = column_type.decode(rest)?;
+      columns.push(RawColumn {
+        serial_type,
+        bytes: &rest[..rest.len() - remainder.len()],
+        value,
+      });
+      rest = remainder;
+    }
+    Ok((rest, RecordRaw { header_size, columns }))
+  }
+
+  pub fn to_record(&self, row_id: Option<i64>) -> Record {
+    Record {
+      row_id,
+      values: self.columns.iter().map(|column| column.value.to_value()).collect(),
+    }
+  }
+}
+
+impl<'a> RecordRef<'a> {
+  pub fn parse(
+    input: &'a [u8],
+    row_id: Option<i64>,
     column_names: &[&str],
     column_indices: &[usize],
-    record_type: RecordType,
-  ) -> IResult<&'input [u8], Self> {
-    let (input, row_id) = if record_type == RecordType::Table {
-      let (input, row_id) = varint(input)?;
-      (input, Some(row_id))
-    } else {
-      (input, None)
+  ) -> IResult<&'a [u8], Self> {
+    let (mut rest, (_, column_types)) = record_header(input)?;
+
+    // Columns after the last requested one are never decoded, so the returned input points just
+    // past that column rather than at the end of the record.
+    let last_column = match column_indices.iter().max() {
+      Some(last_column) => *last_column,
+      None => return Ok((rest, RecordRef { row_id, values: Vec::new() })),
     };
 
-    let mut header_bytes_read = 0;
-    let before_input_len = input.len();
-    let (input, header_size) = varint(input)?;
-    let header_size = header_size as usize;
-    header_bytes_read += before_input_len - input.len();
-
-    let mut rest = input;
-    let mut column_types = Vec::new();
-    while header_bytes_read < header_size {
-      let (remainder, column_type) = varint(rest)?;
-      header_bytes_read += rest.len() - remainder.len();
+    let mut values = Vec::with_capacity(column_names.len());
+    for (i, (_, column_type)) in column_types.iter().enumerate().take(last_column + 1) {
+      if !column_indices.contains(&i) {
+        let (remainder, _) = take(column_type.size())(rest)?;
+        rest = remainder;
+        continue;
+      }
+      let (remainder, value) = column_type.decode(rest)?;
       rest = remainder;
-      let column_type = ColumnType::try_from(column_type).expect("invalid column type");
-      column_types.push(column_type);
+      values.push(value);
     }
 
-    let mut values = Vec::with_capacity(column_names.len());
-    for (i, column_type) in column_types.iter().enumerate() {
-      let to_include = column_indices.contains(&i);
-      let is_row_id_alias = if to_include {
-        column_names[column_indices.iter().position(|j| i == *j).unwrap()] == "id"
-      } else {
-        false
-      };
+    Ok((rest, RecordRef { row_id, values }))
+  }
 
-      match column_type {
-        ColumnType::Null => {
-          if to_include {
-            if record_type == RecordType::Table && is_row_id_alias {
-              values.push(Value::Integer(row_id.unwrap()));
-            } else {
-              values.push(Value::Null);
-            }
-          }
-        }
-        ColumnType::I8 => {
-          let (remainder, value) = i8(rest)?;
-          rest = remainder;
-          if to_include {
-            values.push(Value::Integer(value as i64));
-          }
+  pub fn to_record(&self) -> Record {
+    Record {
+      row_id: self.row_id,
+      values: self.values.iter().map(|v| v.to_value()).collect(),
+    }
+  }
+}
+
+impl Record {
+  /// Encode the values in the record format: a header of serial types followed by the values.
+  /// The rowid isn't part of the record, it is stored in the cell.
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut serial_types = Vec::new();
+    let mut body = Vec::new();
+    for value in self.values.iter() {
+      let serial_type = match value {
+        Value::Null => 0,
+        Value::Integer(n) => {
+          // The smallest of the 1, 2, 3, 4, 6 and 8 byte big-endian encodings holding `n`.
+          let (serial_type, size) = match *n {
+            -0x80..=0x7f => (1, 1),
+            -0x8000..=0x7fff => (2, 2),
+            -0x80_0000..=0x7f_ffff => (3, 3),
+            -0x8000_0000..=0x7fff_ffff => (4, 4),
+            -0x8000_0000_0000..=0x7fff_ffff_ffff => (5, 6),
+            _ => (6, 8),
+          };
+          body.extend_from_slice(&n.to_be_bytes()[8 - size..]);
+          serial_type
         }
-        ColumnType::I16 => {
-          let (remainder, bytes) = take(2usize)(rest)?;
-          rest = remainder;
-          if to_include {
-            values.push(Value::Integer(
-              i16::from_be_bytes([bytes[0], bytes[1]]) as i64
-            ));
-          }
+        Value::Real(f) => {
+          body.extend_from_slice(&f.to_be_bytes());
+          7
         }
-        ColumnType::I24 => {
-          let (remainder, bytes) = take(3usize)(rest)?;
-          rest = remainder;
-          if to_include {
-            values.push(Value::Integer(i32::from_be_bytes([
-              0, bytes[0], bytes[1], bytes[2],
-            ]) as i64));
-          }
+        Value::Text(s) => {
+          body.extend_from_slice(s.as_bytes());
+          13 + 2 * s.len() as i64
         }
-        ColumnType::I32 => {
-          let (remainder, bytes) = take(4usize)(rest)?;
-          rest = remainder;
-          if to_include {
-            values.push(Value::Integer(i32::from_be_bytes([
-              bytes[0], bytes[1], bytes[2], bytes[3],
-            ]) as i64));
-          }
+        Value::Blob(b) => {
+          body.extend_from_slice(b);
+          12 + 2 * b.len() as i64
         }
-        ColumnType::I48 => todo!("i48 column"),
-        ColumnType::I64 => todo!("i64 column"),
-        ColumnType::F64 => todo!("f64 column"),
-        ColumnType::Zero => {
-          if to_include {
-            values.push(Value::Integer(0i64));
-          }
+      };
+      write_varint(serial_type, &mut serial_types);
+    }
+
+    // The header size includes the varint holding it, which can make it one byte longer.
+    let mut header_size = serial_types.len() + 1;
+    let mut header_size_bytes = Vec::new();
+    loop {
+      header_size_bytes.clear();
+      write_varint(header_size as i64, &mut header_size_bytes);
+      if header_size_bytes.len() + serial_types.len() == header_size {
+        break;
+      }
+      header_size = header_size_bytes.len() + serial_types.len();
+    }
+
+    let mut bytes = header_size_bytes;
+    bytes.extend(serial_types);
+    bytes.extend(body);
+    bytes
+  }
+
+  pub fn parse<'input>(
+    input: &'input [u8],
+    row_id: Option<i64>,
+    column_names: &[&str],
+    column_indices: &[usize],
+  ) -> IResult<&'input [u8], Self> {
+    let (rest, record) = RecordRef::parse(input, row_id, column_names, column_indices)?;
+    Ok((rest, record.to_record()))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{ColumnType, Record, RecordRaw, RecordRef, Value, ValueRef};
+
+  #[test]
+  fn trailing_columns_are_not_decoded() {
+    // Header: size, i8, 10KB blob, f64.
+    let mut input = vec![0x05, 0x01, 0xa0, 0x1c, 0x07, 0x2a];
+    input.resize(input.len() + 10240, b'x');
+    input.extend(1.5f64.to_be_bytes());
+
+    let (rest, record) = Record::parse(&input, Some(1), &["n"], &[0]).unwrap();
+    assert_eq!(record.values, vec![Value::Integer(42)]);
+    assert_eq!(rest.len(), 10240 + 8);
+
+    // Even a truncated payload decodes as long as it covers the requested columns.
+    let (_, record) = Record::parse(&input[..8], Some(1), &["n"], &[0]).unwrap();
+    assert_eq!(record.values, vec![Value::Integer(42)]);
+    assert!(Record::parse(&input[..8], Some(1), &["b"], &[1]).is_err());
+  }
+
+  #[test]
+  fn malformed_records_are_errors() {
+    // Each of these, found by fuzzing, used to panic.
+    let columns = [("a", 0), ("b", 1), ("c", 2)];
+    let (names, indices): (Vec<_>, Vec<_>) = columns.into_iter().unzip();
+    // A header size that's cut off.
+    assert!(Record::parse(&[0xff], None, &names, &indices).is_err());
+    // Serial type 11, which is reserved.
+    assert!(Record::parse(&[0x04, 0x0b, 0xf6, 0x2e], None, &names, &indices).is_err());
+    // Text that isn't UTF-8.
+    assert!(Record::parse(&[0x03, 0x13, 0x31, 0xff, 0x6d, 0x6d], None, &names, &indices).is_err());
+  }
+
+  #[test]
+  fn serial_types_outside_the_format_are_invalid() {
+    for (serial_type, message) in [
+      (10, "reserved serial type 10"),
+      (11, "reserved serial type 11"),
+      (-1, "invalid serial type -1"),
+    ] {
+      assert_eq!(ColumnType::try_from(serial_type).unwrap_err().0, message);
+    }
+  }
+
+  #[test]
+  fn blobs_are_any_bytes() {
+    // A blob that isn't UTF-8, as sqlite3 writes x'010080'.
+    let input = [0x04, 0x00, 0x0d, 0x12, 0x01, 0x00, 0x80];
+    let (_, record) = Record::parse(&input, None, &["a", "b", "c"], &[0, 1, 2]).unwrap();
+    assert_eq!(record.values[2].as_blob(), Some(&[0x01, 0x00, 0x80][..]));
+
+    let blobs = [vec![], vec![0xff, 0x00], (0..=255).collect::<Vec<u8>>(), vec![0xc3; 300]];
+    let record = Record {
+      row_id: None,
+      values: blobs.iter().map(|blob| Value::Blob(blob.clone())).collect(),
+    };
+    let bytes = record.to_bytes();
+    let (rest, parsed) = Record::parse(&bytes, None, &["a", "b", "c", "d"], &[0, 1, 2, 3]).unwrap();
+    assert!(rest.is_empty());
+    for (value, blob) in parsed.values.iter().zip(blobs.iter()) {
+      assert_eq!(value.as_blob(), Some(blob.as_slice()));
+    }
+  }
+
+  #[test]
+  fn to_bytes_round_trips() {
+    let record = Record {
+      row_id: Some(7),
+      values: vec![
+        Value::Null,
+        Value::Integer(-3),
+        Value::Integer(1000),
+        Value::Integer(1 << 40),
+        Value::Integer(i64::MIN),
+        Value::Real(2.5),
+        Value::Text("Zoe".to_string()),
+        Value::Text("x".repeat(100)),
+      ],
+    };
+    let bytes = record.to_bytes();
+    // Header size, then one serial type per value with the long text's taking two bytes.
+    assert_eq!(&bytes[..8], &[10, 0, 1, 2, 5, 6, 7, 19]);
+
+    let names = ["a", "b", "c", "d", "e", "f", "g", "h"];
+    let (rest, parsed) =
+      Record::parse(&bytes, Some(7), &names, &[0, 1, 2, 3, 4, 5, 6, 7]).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(parsed.values.len(), record.values.len());
+    for (parsed, value) in parsed.values.iter().zip(record.values.iter()).skip(1) {
+      assert_eq!(parsed, value);
+    }
+  }
+
+  #[test]
+  fn raw_records_keep_how_each_value_is_stored() {
+    // 7 stored as an I8 and again as an I32, which SQLite never writes but other writers may,
+    // then 1, a real and text.
+    let mut input = vec![0x06, 0x01, 0x04, 0x09, 0x07, 0x13, 0x07, 0x00, 0x00, 0x00, 0x07];
+    input.extend(2.5f64.to_be_bytes());
+    input.extend(b"abc\xee");
+    let (rest, record) = RecordRaw::parse(&input).unwrap();
+    assert_eq!(rest, [0xee]);
+    assert_eq!(record.header_size, 6);
+    let columns = record.columns.iter().map(|column| (column.serial_type, column.bytes)).collect::<Vec<_>>();
+    assert_eq!(
+      columns,
+      [(1, &[0x07][..]), (4, &[0, 0, 0, 0x07]), (9, &[]), (7, &[0x40, 0x04, 0, 0, 0, 0, 0, 0]), (19, b"abc")]
+    );
+    let (_, decoded) = Record::parse(&input, Some(3), &[], &[0, 1, 2, 3, 4]).unwrap();
+    assert_eq!(format!("{:?}", record.to_record(Some(3))), format!("{:?}", decoded));
+
+    // A record header can be read without its body, but not decoded.
+    assert!(RecordRaw::parse(&input[..8]).is_err());
+    assert!(RecordRaw::parse(&[0x03, 0x13, 0x31, 0xff, 0x6d, 0x6d]).is_err());
+  }
+
+  #[test]
+  fn numbers_compare_across_serial_types() {
+    use std::cmp::Ordering::{Equal, Greater, Less};
+
+    // A value stored with each integer serial type, I8 to I64, and then F64.
+    for (value, serial_type) in [
+      (Value::Integer(-100), 1),
+      (Value::Integer(1000), 2),
+      (Value::Integer(-100_000), 3),
+      (Value::Integer(1 << 30), 4),
+      (Value::Integer(-(1 << 40)), 5),
+      (Value::Integer(1 << 60), 6),
+      (Value::Real(2.5), 7),
+    ] {
+      let bytes = Record {
+        row_id: None,
+        values: vec![value.clone()],
+      }
+      .to_bytes();
+      assert_eq!(bytes[1], serial_type);
+      let (_, record) = RecordRef::parse(&bytes, None, &["n"], &[0]).unwrap();
+      let stored = record.values[0];
+
+      // Literals equal to the value and on either side of it, as integers and as reals. The
+      // reals are exact, so only the comparison itself can go wrong.
+      let (literals, f) = match value {
+        Value::Integer(n) => {
+          let integers = [(n, Equal), (n + 1, Less), (n - 1, Greater)];
+          (integers.map(|(n, ordering)| (Value::Integer(n), ordering)), n as f64)
         }
-        ColumnType::One => {
-          if to_include {
-            values.push(Value::Integer(0i64));
-          }
+        Value::Real(f) => {
+          let integers = [(2, Greater), (3, Less), (2, Greater)];
+          (integers.map(|(n, ordering)| (Value::Integer(n), ordering)), f)
         }
-        ColumnType::Blob(size) => {
-          let (remainder, bytes) = take(*size)(rest)?;
-          rest = remainder;
-          if to_include {
-            values.push(Value::Blob(
-              std::str::from_utf8(bytes).expect("non utf-8 text").to_owned(),
-            ));
-          }
+        _ => unreachable!(),
+      };
+      let step = f.abs().max(1.0);
+      let reals = [(f, Equal), (f + step, Less), (f - step, Greater)].map(|(f, ordering)| (Value::Real(f), ordering));
+      for (literal, ordering) in literals.into_iter().chain(reals) {
+        let literal = literal.as_value_ref();
+        assert_eq!(stored.partial_cmp(&literal), Some(ordering), "{:?} vs {:?}", stored, literal);
+        assert_eq!(literal.partial_cmp(&stored), Some(ordering.reverse()), "{:?} vs {:?}", literal, stored);
+        assert_eq!(stored == literal, ordering == Equal, "{:?} vs {:?}", stored, literal);
+      }
+    }
+
+    // Converting the integer to a real would round it to the real it's compared with.
+    let big = ValueRef::Integer((1 << 53) + 1);
+    assert_eq!(big.partial_cmp(&ValueRef::Real((1u64 << 53) as f64)), Some(Greater));
+    assert_ne!(big, ValueRef::Real((1u64 << 53) as f64));
+    assert_eq!(ValueRef::Integer(i64::MAX).partial_cmp(&ValueRef::Real(i64::MAX as f64)), Some(Less));
+    assert_eq!(ValueRef::Integer(-3).partial_cmp(&ValueRef::Real(-2.5)), Some(Less));
+    assert_eq!(Value::Integer((1 << 53) + 1).sort_key().cmp(&Value::Real((1u64 << 53) as f64).sort_key()), Greater);
+  }
+
+  #[test]
+  fn collations_compare_text() {
+    use super::Collation;
+    use std::cmp::Ordering::{Equal, Less};
+
+    let text = ValueRef::Text;
+    for (collation, a, b, ordering) in [
+      (Collation::Binary, "ABC", "abc", Less),
+      (Collation::NoCase, "ABC", "abc", Equal),
+      // NOCASE folds to lower case, so "_" sorts before letters of either case, as in sqlite3.
+      (Collation::NoCase, "a_", "AB", Less),
+      // Only ASCII letters are folded.
+      (Collation::NoCase, "Ä", "ä", Less),
+      (Collation::RTrim, "abc  ", "abc", Equal),
+      (Collation::RTrim, " abc", "abc", Less),
+    ] {
+      assert_eq!(text(a).collated_cmp(&text(b), collation), ordering, "{} vs {} with {:?}", a, b, collation);
+    }
+    // Collations only apply to text, and NULL stays unordered.
+    assert_eq!(ValueRef::Blob(b"ABC").collated_cmp(&ValueRef::Blob(b"abc"), Collation::NoCase), Less);
+    assert_eq!(ValueRef::Null.sql_cmp(&text("a"), Collation::NoCase), None);
+  }
+
+  #[test]
+  fn sort_key_is_a_total_order() {
+    use super::SortKey;
+    use std::cmp::Ordering;
+
+    // A fixed xorshift sequence, so failures reproduce.
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    let mut next = move || {
+      state ^= state << 13;
+      state ^= state >> 7;
+      state ^= state << 17;
+      state
+    };
+    // Numbers are small multiples of a half, so comparing them as reals is exact and can serve
+    // as the reference order, along with the storage class and the bytes of text and blobs.
+    let mut random_value = || match next() % 5 {
+      0 => Value::Null,
+      1 => Value::Integer((next() % 41) as i64 - 20),
+      2 => Value::Real(((next() % 81) as i64 - 40) as f64 / 2.0),
+      3 => Value::Text(["", "a", "ab", "b", "B", "10"][(next() % 6) as usize].to_string()),
+      _ => Value::Blob([&b""[..], b"a", b"b"][(next() % 3) as usize].to_vec()),
+    };
+    fn reference_cmp(a: &Value, b: &Value) -> Ordering {
+      fn key(value: &Value) -> (u8, f64, &[u8]) {
+        match value {
+          Value::Null => (0, 0.0, &[]),
+          Value::Integer(n) => (1, *n as f64, &[]),
+          Value::Real(f) => (1, *f, &[]),
+          Value::Text(s) => (2, 0.0, s.as_bytes()),
+          Value::Blob(b) => (3, 0.0, b.as_slice()),
         }
-        ColumnType::Text(size) => {
-          let (remainder, bytes) = take(*size)(rest)?;
-          rest = remainder;
-          if to_include {
-            values.push(Value::Text(
-              std::str::from_utf8(bytes).expect("non utf-8 text").to_owned(),
-            ));
-          }
+      }
+      let (a, b) = (key(a), key(b));
+      a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)).then(a.2.cmp(b.2))
+    }
+
+    let values = (0..1000).map(|_| random_value()).collect::<Vec<_>>();
+    for a in values.iter() {
+      for b in values.iter().take(200) {
+        let ordering = a.sort_key().cmp(&b.sort_key());
+        assert_eq!(ordering, reference_cmp(a, b), "{:?} vs {:?}", a, b);
+        assert_eq!(b.sort_key().cmp(&a.sort_key()), ordering.reverse());
+        // SQL comparisons agree with the sort order, except that NULL is unordered.
+        let sql_ordering = a.partial_cmp(b);
+        match (a, b) {
+          (Value::Null, _) | (_, Value::Null) => assert_eq!(sql_ordering, None),
+          _ => assert_eq!(sql_ordering, Some(ordering)),
         }
+        assert_eq!(a == b, sql_ordering == Some(Ordering::Equal));
       }
     }
 
-    Ok((rest, Record { values }))
+    // Sorting by the key leaves every value no greater than the next, which needs transitivity.
+    let mut sorted = values.iter().map(Value::sort_key).collect::<Vec<SortKey>>();
+    sorted.sort();
+    assert!(sorted.windows(2).all(|pair| pair[0] <= pair[1]));
+    assert_eq!(Value::Null.sort_key(), Value::Null.sort_key());
+    assert_eq!(Value::Real(-0.0).sort_key(), Value::Real(0.0).sort_key());
+    assert_ne!(Value::Null, Value::Null);
+    assert_ne!(Value::Text("a".to_string()), Value::Blob(b"a".to_vec()));
   }
 }
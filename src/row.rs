@@ -0,0 +1,219 @@
+use std::sync::Arc;
+
+use thiserror::Error;
+
+use crate::record::{Record, Value};
+
+/// A row of a query result, giving typed access to its values by position or by output column
+/// name.
+#[derive(Debug, Clone)]
+pub struct Row {
+  column_names: Arc<[String]>,
+  record: Record,
+}
+
+/// The rows returned by a query, together with its output column names.
+#[derive(Debug, Clone)]
+pub struct ResultSet {
+  pub column_names: Arc<[String]>,
+  pub rows: Vec<Row>,
+}
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum RowError {
+  #[error("column {column} is {found}, expected {expected}")]
+  WrongType {
+    expected: &'static str,
+    found: &'static str,
+    column: String,
+  },
+  #[error("no such column: {0}")]
+  ColumnNotFound(String),
+  #[error("column index {index} out of range for a row of {len} columns")]
+  IndexOutOfRange { index: usize, len: usize },
+}
+
+/// A Rust type a [`Value`] can be read as.
+pub trait FromValue: Sized {
+  /// The SQLite storage class this type is read from, for [`RowError::WrongType`].
+  const TYPE_NAME: &'static str;
+
+  fn from_value(value: &Value) -> Option<Self>;
+}
+
+impl FromValue for i64 {
+  const TYPE_NAME: &'static str = "integer";
+
+  fn from_value(value: &Value) -> Option<Self> {
+    value.as_integer()
+  }
+}
+
+impl FromValue for f64 {
+  const TYPE_NAME: &'static str = "real";
+
+  fn from_value(value: &Value) -> Option<Self> {
+    match value {
+      Value::Real(f) => Some(*f),
+      Value::Integer(n) => Some(*n as f64),
+      _ => None,
+    }
+  }
+}
+
+impl FromValue for String {
+  const TYPE_NAME: &'static str = "text";
+
+  fn from_value(value: &Value) -> Option<Self> {
+    value.as_text().map(str::to_owned)
+  }
+}
+
+impl FromValue for Vec<u8> {
+  const TYPE_NAME: &'static str = "blob";
+
+  fn from_value(value: &Value) -> Option<Self> {
+    value.as_blob().map(<[u8]>::to_vec)
+  }
+}
+
+/// NULL is read as `None`, anything else as `T`.
+impl<T> FromValue for Option<T>
+  where
+    T: FromValue,
+{
+  const TYPE_NAME: &'static str = T::TYPE_NAME;
+
+  fn from_value(value: &Value) -> Option<Self> {
+    match value {
+      Value::Null => Some(None),
+      value => T::from_value(value).map(Some),
+    }
+  }
+}
+
+impl Row {
+  pub fn new(column_names: Arc<[String]>, record: Record) -> Self {
+    assert_eq!(column_names.len(), record.values.len());
+    Row {
+      column_names,
+      record,
+    }
+  }
+
+  pub fn column_names(&self) -> &[String] {
+    &self.column_names
+  }
+
+  pub fn values(&self) -> &[Value] {
+    &self.record.values
+  }
+
+  pub fn row_id(&self) -> Option<i64> {
+    self.record.row_id
+  }
+
+  pub fn get<T>(&self, index: usize) -> Result<T, RowError>
+    where
+      T: FromValue,
+  {
+    let value = self
+      .record
+      .values
+      .get(index)
+      .ok_or(RowError::IndexOutOfRange {
+        index,
+        len: self.record.values.len(),
+      })?;
+    T::from_value(value).ok_or_else(|| RowError::WrongType {
+      expected: T::TYPE_NAME,
+      found: value.type_name(),
+      column: self.column_names[index].clone(),
+    })
+  }
+
+  /// Like [`Row::get`], looking the column up by its (case-insensitive) output name.
+  pub fn get_by_name<T>(&self, name: &str) -> Result<T, RowError>
+    where
+      T: FromValue,
+  {
+    let index = self
+      .column_names
+      .iter()
+      .position(|column_name| column_name.eq_ignore_ascii_case(name))
+      .ok_or_else(|| RowError::ColumnNotFound(name.to_owned()))?;
+    self.get(index)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Row, RowError};
+  use crate::{
+    database::Database,
+    query::Query,
+    record::{Record, Value},
+  };
+
+  fn row() -> Row {
+    Row::new(
+      vec!["name".to_string(), "age".to_string(), "score".to_string()].into(),
+      Record {
+        row_id: Some(1),
+        values: vec![Value::Text("Alice".to_string()), Value::Integer(29), Value::Null],
+      },
+    )
+  }
+
+  #[test]
+  fn typed_access() {
+    let row = row();
+    assert_eq!(row.get::<String>(0), Ok("Alice".to_string()));
+    assert_eq!(row.get::<i64>(1), Ok(29));
+    assert_eq!(row.get::<f64>(1), Ok(29.0));
+    assert_eq!(row.get_by_name::<i64>("AGE"), Ok(29));
+    assert_eq!(row.get::<Option<f64>>(2), Ok(None));
+    assert_eq!(row.get::<Option<i64>>(1), Ok(Some(29)));
+  }
+
+  #[test]
+  fn mismatches_are_errors() {
+    let row = row();
+    assert_eq!(
+      row.get::<i64>(0),
+      Err(RowError::WrongType {
+        expected: "integer",
+        found: "text",
+        column: "name".to_string(),
+      })
+    );
+    assert_eq!(
+      row.get::<f64>(2),
+      Err(RowError::WrongType {
+        expected: "real",
+        found: "null",
+        column: "score".to_string(),
+      })
+    );
+    assert_eq!(
+      row.get_by_name::<i64>("email"),
+      Err(RowError::ColumnNotFound("email".to_string()))
+    );
+    assert_eq!(row.get::<i64>(3), Err(RowError::IndexOutOfRange { index: 3, len: 3 }));
+  }
+
+  #[test]
+  fn query_rows_are_named_by_output_column() {
+    let mut file = std::fs::File::open("tests/fixtures/leaf.db").unwrap();
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+    let query = Query::parse("SELECT username, age FROM users").unwrap();
+    let rows = query.query_rows(&db, &mut file).unwrap().rows;
+    assert_eq!(rows.len(), 4);
+    assert_eq!(rows[3].get_by_name::<String>("username"), Ok("Dave".to_string()));
+    assert_eq!(rows[3].get_by_name::<i64>("age"), Ok(105));
+
+    let query = Query::parse("SELECT COUNT(*) FROM users").unwrap();
+    let rows = query.query_rows(&db, &mut file).unwrap().rows;
+    assert_eq!(rows[0].get::<i64>(0), Ok(4));
+  }
+}
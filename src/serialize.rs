@@ -0,0 +1,239 @@
+//! `Serialize` implementations for values and query results, behind the `serde` feature.
+//!
+//! Blobs are serialized as bytes, which self-describing text formats like JSON turn into an array
+//! of numbers. Wrap a value in [`Base64Blobs`] to serialize its blobs as base64 strings instead.
+
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, SerializeStruct, Serializer};
+
+use crate::{
+  record::{Record, Value},
+  row::{ResultSet, Row},
+  stats::{BtreeStats, DatabaseStats},
+};
+
+impl Serialize for Value {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+      S: Serializer,
+  {
+    match self {
+      Value::Null => serializer.serialize_unit(),
+      Value::Integer(n) => serializer.serialize_i64(*n),
+      Value::Real(f) => serializer.serialize_f64(*f),
+      Value::Text(s) => serializer.serialize_str(s),
+      Value::Blob(b) => serializer.serialize_bytes(b),
+    }
+  }
+}
+
+impl Serialize for Record {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+      S: Serializer,
+  {
+    let mut record = serializer.serialize_struct("Record", 2)?;
+    record.serialize_field("row_id", &self.row_id)?;
+    record.serialize_field("values", &self.values)?;
+    record.end()
+  }
+}
+
+/// A row is serialized as a map from output column name to value.
+impl Serialize for Row {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+      S: Serializer,
+  {
+    serialize_row(self, serializer, |value| value)
+  }
+}
+
+impl Serialize for ResultSet {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+      S: Serializer,
+  {
+    let mut result_set = serializer.serialize_struct("ResultSet", 2)?;
+    result_set.serialize_field("columns", &*self.column_names)?;
+    result_set.serialize_field("rows", &self.rows)?;
+    result_set.end()
+  }
+}
+
+impl Serialize for BtreeStats {
+  fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+      S: Serializer,
+  {
+    let mut stats = serializer.serialize_struct("BtreeStats", 11)?;
+    stats.serialize_field("name", &self.name)?;
+    stats.serialize_field("kind", self.kind)?;
+    stats.serialize_field("rows", &self.rows)?;
+    stats.serialize_field("leaf_pages", &self.leaf_pages)?;
+    stats.serialize_field("interior_pages", &self.interior_pages)?;
+    stats.serialize_field("overflow_pages", &self.overflow_pages)?;
+    stats.serialize_field("payload_bytes", &self.payload_bytes)?;
+    stats.serialize_field("average_row_size", &self.average_row_size())?;
+    stats.serialize_field("depth", &self.depth)?;
+    stats.serialize_field("free_bytes", &self.free_bytes)?;
+    stats.serialize_field("fill_factor", &self.fill_factor())?;
+    stats.end()
+  }
+}
+
+impl Serialize for DatabaseStats {
+  fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+      S: Serializer,
+  {
+    let mut stats = serializer.serialize_struct("DatabaseStats", 4)?;
+    stats.serialize_field("page_size", &self.page_size)?;
+    stats.serialize_field("page_count", &self.page_count)?;
+    stats.serialize_field("freelist_pages", &self.freelist_pages)?;
+    stats.serialize_field("btrees", &self.btrees)?;
+    stats.end()
+  }
+}
+
+/// Serializes the wrapped value with its blobs as base64 strings, for formats without a byte
+/// string type.
+pub struct Base64Blobs<'a, T: ?Sized>(pub &'a T);
+
+impl Serialize for Base64Blobs<'_, Value> {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+      S: Serializer,
+  {
+    match self.0 {
+      Value::Blob(b) => serializer.serialize_str(&base64(b)),
+      value => value.serialize(serializer),
+    }
+  }
+}
+
+impl Serialize for Base64Blobs<'_, Row> {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+      S: Serializer,
+  {
+    serialize_row(self.0, serializer, Base64Blobs)
+  }
+}
+
+impl Serialize for Base64Blobs<'_, [Row]> {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+      S: Serializer,
+  {
+    let mut rows = serializer.serialize_seq(Some(self.0.len()))?;
+    for row in self.0 {
+      rows.serialize_element(&Base64Blobs(row))?;
+    }
+    rows.end()
+  }
+}
+
+impl Serialize for Base64Blobs<'_, ResultSet> {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+      S: Serializer,
+  {
+    let mut result_set = serializer.serialize_struct("ResultSet", 2)?;
+    result_set.serialize_field("columns", &*self.0.column_names)?;
+    result_set.serialize_field("rows", &Base64Blobs(self.0.rows.as_slice()))?;
+    result_set.end()
+  }
+}
+
+fn serialize_row<'a, S, V, F>(row: &'a Row, serializer: S, wrap: F) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+    V: Serialize,
+    F: Fn(&'a Value) -> V,
+{
+  let mut map = serializer.serialize_map(Some(row.values().len()))?;
+  for (column_name, value) in row.column_names().iter().zip(row.values()) {
+    map.serialize_entry(column_name, &wrap(value))?;
+  }
+  map.end()
+}
+
+fn base64(bytes: &[u8]) -> String {
+  const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+  let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+  for chunk in bytes.chunks(3) {
+    let n = (chunk[0] as u32) << 16
+      | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+      | *chunk.get(2).unwrap_or(&0) as u32;
+    for i in 0..4 {
+      if i <= chunk.len() {
+        encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+      } else {
+        encoded.push('=');
+      }
+    }
+  }
+  encoded
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{base64, Base64Blobs};
+  use crate::{
+    record::{Record, Value},
+    row::{ResultSet, Row},
+  };
+
+  fn result_set() -> ResultSet {
+    let column_names: std::sync::Arc<[String]> = ["n", "i", "r", "t", "b"]
+      .iter()
+      .map(|name| name.to_string())
+      .collect();
+    let record = Record {
+      row_id: Some(7),
+      values: vec![
+        Value::Null,
+        Value::Integer(-3),
+        Value::Real(1.5),
+        Value::Text("hi".to_string()),
+        Value::Blob(b"abc".to_vec()),
+      ],
+    };
+    ResultSet {
+      rows: vec![Row::new(column_names.clone(), record)],
+      column_names,
+    }
+  }
+
+  #[test]
+  fn serialize_every_value_type() {
+    let result_set = result_set();
+    assert_eq!(
+      serde_json::to_string(&result_set.rows[0]).unwrap(),
+      r#"{"n":null,"i":-3,"r":1.5,"t":"hi","b":[97,98,99]}"#
+    );
+    assert_eq!(
+      serde_json::to_string(&Base64Blobs(&result_set)).unwrap(),
+      r#"{"columns":["n","i","r","t","b"],"rows":[{"n":null,"i":-3,"r":1.5,"t":"hi","b":"YWJj"}]}"#
+    );
+  }
+
+  #[test]
+  fn serialize_stats() {
+    let file = std::fs::File::open("tests/fixtures/interior.db").unwrap();
+    let db = crate::database::Database::parse_header_and_schema(&file).unwrap();
+    let stats = serde_json::to_value(db.stats(&file).unwrap()).unwrap();
+    assert_eq!(stats["page_count"], 7);
+    assert_eq!(stats["btrees"][1]["name"], "users");
+    assert_eq!(stats["btrees"][1]["rows"], 22);
+    assert_eq!(stats["btrees"][1]["interior_pages"], 1);
+  }
+
+  #[test]
+  fn base64_padding() {
+    assert_eq!(base64(b""), "");
+    assert_eq!(base64(b"a"), "YQ==");
+    assert_eq!(base64(b"ab"), "YWI=");
+    assert_eq!(base64(b"abc"), "YWJj");
+  }
+}
@@ -0,0 +1,287 @@
+//! The grammar of the statements [`Query::parse`](crate::query::Query::parse) reads, which splits
+//! each into its clauses. Expressions and conditions are kept as written, for the expression and
+//! WHERE parsers, and so are column definitions, for the CREATE parser.
+
+use crate::{query::parse_literal, record::Value};
+
+/// A statement split into its clauses.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Statement<'a> {
+  Select(Select<'a>),
+  Create(Create<'a>),
+  Insert(Insert<'a>),
+  Delete(Delete<'a>),
+  Update(Update<'a>),
+}
+
+/// `SELECT list [FROM table] [WHERE condition] [ORDER BY term, ...] [LIMIT count [OFFSET count]]`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Select<'a> {
+  pub items: Vec<&'a str>,
+  pub table_name: Option<&'a str>,
+  pub condition: Option<&'a str>,
+  /// Each ORDER BY expression, and whether it's sorted DESC.
+  pub order_by: Vec<(&'a str, bool)>,
+  /// The LIMIT count and the OFFSET count after it, as written.
+  pub limit: Option<(&'a str, Option<&'a str>)>,
+}
+
+/// `CREATE [UNIQUE] [TEMP|TEMPORARY|VIRTUAL] TABLE|INDEX [IF NOT EXISTS] name ... (definition, ...)
+/// [option, ...]`, where anything between the name and the definitions, such as the table an
+/// index is on, is skipped.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Create<'a> {
+  pub unique: bool,
+  pub is_index: bool,
+  pub if_not_exists: bool,
+  pub name: &'a str,
+  /// The column definitions and table constraints, or the indexed columns, as written.
+  pub definitions: Vec<&'a str>,
+  pub options: Vec<TableOption>,
+}
+
+/// An option after the definitions of a table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TableOption {
+  WithoutRowid,
+  Strict,
+}
+
+/// `INSERT INTO table [(column, ...)] VALUES (value, ...)[, (value, ...) ...]`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Insert<'a> {
+  pub table_name: &'a str,
+  pub column_names: Option<Vec<&'a str>>,
+  pub rows: Vec<Vec<Value>>,
+}
+
+/// `DELETE FROM table [WHERE condition]`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Delete<'a> {
+  pub table_name: &'a str,
+  pub condition: Option<&'a str>,
+}
+
+/// `UPDATE table SET column = value, ... [WHERE condition]`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Update<'a> {
+  pub table_name: &'a str,
+  pub assignments: Vec<(&'a str, Value)>,
+  pub condition: Option<&'a str>,
+}
+
+peg::parser! {
+  grammar sql_parser() for str {
+    pub rule statement() -> Statement<'input> = wsz() statement:(
+      select:select() { Statement::Select(select) }
+      / create:create() { Statement::Create(create) }
+      / insert:insert() { Statement::Insert(insert) }
+      / delete:delete() { Statement::Delete(delete) }
+      / update:update() { Statement::Update(update) }
+    ) wsz() (";" wsz())* ![_] { statement }
+
+    rule select() -> Select<'input> = kw("SELECT") wsz() items:(item() ++ (wsz() "," wsz()))
+      table_name:(wsz() kw("FROM") wsz() name:identifier() { name })?
+      condition:where_clause()?
+      order_by:(wsz() kw("ORDER") ws() kw("BY") wsz() terms:(order_term() ++ (wsz() "," wsz())) { terms })?
+      limit:(wsz() kw("LIMIT") wsz() limit:clause() offset:(wsz() kw("OFFSET") wsz() offset:clause() { offset })? {
+        (limit, offset)
+      })? {
+      Select {
+        items,
+        table_name,
+        condition,
+        order_by: order_by.unwrap_or_default(),
+        limit,
+      }
+    }
+
+    rule order_term() -> (&'input str, bool) =
+      expr:$((!clause_keyword() !direction() token()) ++ wsz()) descending:(wsz() d:direction() { d })? {
+        (expr, descending.unwrap_or(false))
+      }
+    rule direction() -> bool = kw("DESC") { true } / kw("ASC") { false }
+
+    rule create() -> Create<'input> = kw("CREATE") ws() unique:(kw("UNIQUE") ws())?
+      ((kw("TEMP") / kw("TEMPORARY") / kw("VIRTUAL")) ws())*
+      is_index:(kw("TABLE") { false } / kw("INDEX") { true }) wsz()
+      if_not_exists:(kw("IF") ws() kw("NOT") ws() kw("EXISTS") wsz())?
+      name:identifier() (wsz() !"(" token())* wsz()
+      "(" wsz() definitions:(definition() ++ (wsz() "," wsz())) wsz() ")"
+      options:(wsz() option:table_option() ** (wsz() "," wsz()) { option }) {
+      Create {
+        unique: unique.is_some(),
+        is_index,
+        if_not_exists: if_not_exists.is_some(),
+        name,
+        definitions,
+        options,
+      }
+    }
+
+    rule definition() -> &'input str = $(token() ++ wsz())
+    rule table_option() -> TableOption = kw("WITHOUT") ws() kw("ROWID") { TableOption::WithoutRowid }
+      / kw("STRICT") { TableOption::Strict }
+
+    rule insert() -> Insert<'input> = kw("INSERT") ws() kw("INTO") wsz() table_name:identifier() wsz()
+      column_names:("(" wsz() names:(identifier() ++ (wsz() "," wsz())) wsz() ")" wsz() { names })?
+      kw("VALUES") wsz() rows:(("(" wsz() row:(literal() ++ (wsz() "," wsz())) wsz() ")" { row }) ++ (wsz() "," wsz())) {
+      Insert {
+        table_name,
+        column_names,
+        rows,
+      }
+    }
+
+    rule delete() -> Delete<'input> =
+      kw("DELETE") ws() kw("FROM") wsz() table_name:identifier() condition:where_clause()? {
+        Delete { table_name, condition }
+      }
+
+    rule update() -> Update<'input> = kw("UPDATE") ws() table_name:identifier() wsz() kw("SET") wsz()
+      assignments:((column:identifier() wsz() "=" wsz() value:literal() { (column, value) }) ++ (wsz() "," wsz()))
+      condition:where_clause()? {
+      Update {
+        table_name,
+        assignments,
+        condition,
+      }
+    }
+
+    rule where_clause() -> &'input str = wsz() kw("WHERE") wsz() condition:clause() { condition }
+
+    /// A select list item: the tokens up to a comma or the keyword starting the next clause.
+    rule item() -> &'input str = $((!clause_keyword() token()) ++ wsz())
+    /// The rest of a clause, up to the keyword starting the next one.
+    rule clause() -> &'input str = $((!clause_keyword() (token() / ",")) ++ wsz())
+    rule clause_keyword() = kw("FROM") / kw("WHERE") / kw("ORDER") / kw("LIMIT") / kw("OFFSET")
+
+    /// A word, quoted string or name, parenthesized group, or any other character but a comma or
+    /// semicolon, which end items and statements.
+    rule token() = word() / quoted() / "(" nested()* ")"
+      / [c if !c.is_whitespace() && !matches!(c, '(' | ')' | ',' | ';' | '\'' | '"' | '`' | '[')]
+    rule nested() = quoted() / "(" nested()* ")" / [^ '(' | ')' | '\'' | '"' | '`' | '[']
+    rule quoted() = "'" [^ '\'']* "'" / "\"" [^ '"']* "\"" / "`" [^ '`']* "`" / "[" [^ ']']* "]"
+    rule word() = [c if c.is_alphanumeric() || c == '_']+
+
+    /// A name, either a bare word or one quoted with double quotes, backticks or square brackets,
+    /// unquoted.
+    rule identifier() -> &'input str = "\"" name:$([^ '"']*) "\"" { name }
+      / "`" name:$([^ '`']*) "`" { name }
+      / "[" name:$([^ ']']*) "]" { name }
+      / $(word())
+
+    /// A NULL, number or single-quoted string, read by [`parse_literal`].
+    rule literal() -> Value = literal:$(
+      ("'" [^ '\'']* "'")+
+      / ['+' | '-']? ['0'..='9' | '.'] (['e' | 'E'] ['+' | '-'] / [c if c.is_alphanumeric() || c == '.'])*
+      / word()
+    ) {?
+      match parse_literal(literal) {
+        Some((value, "")) => Ok(value),
+        _ => Err("a literal"),
+      }
+    }
+
+    /// A case-insensitive keyword, as a whole word.
+    rule kw(keyword: &'static str) = word:$(word()) {?
+      if word.eq_ignore_ascii_case(keyword) { Ok(()) } else { Err(keyword) }
+    }
+    rule ws() = quiet!{[c if c.is_whitespace()]+}
+    rule wsz() = quiet!{[c if c.is_whitespace()]*}
+  }
+}
+
+/// Split `sql` into its clauses, or `None` if it isn't a statement of the grammar.
+pub(crate) fn parse(sql: &str) -> Option<Statement<'_>> {
+  sql_parser::statement(sql).ok()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn statements_split_into_clauses() {
+    assert_eq!(
+      parse("select name, count(*) from \"my table\" where a = 'x, from' and b > (1) order by name desc, 2 limit 5 offset 1;"),
+      Some(Statement::Select(Select {
+        items: vec!["name", "count(*)"],
+        table_name: Some("my table"),
+        condition: Some("a = 'x, from' and b > (1)"),
+        order_by: vec![("name", true), ("2", false)],
+        limit: Some(("5", Some("1"))),
+      }))
+    );
+    assert_eq!(
+      parse("CREATE UNIQUE INDEX IF NOT EXISTS i ON t (a, b DESC)"),
+      Some(Statement::Create(Create {
+        unique: true,
+        is_index: true,
+        if_not_exists: true,
+        name: "i",
+        definitions: vec!["a", "b DESC"],
+        options: vec![],
+      }))
+    );
+    assert_eq!(
+      parse("CREATE TABLE t (id INTEGER PRIMARY KEY, n NUMERIC(10, 2) CHECK (n > 0)) WITHOUT ROWID, STRICT"),
+      Some(Statement::Create(Create {
+        unique: false,
+        is_index: false,
+        if_not_exists: false,
+        name: "t",
+        definitions: vec!["id INTEGER PRIMARY KEY", "n NUMERIC(10, 2) CHECK (n > 0)"],
+        options: vec![TableOption::WithoutRowid, TableOption::Strict],
+      }))
+    );
+    assert_eq!(
+      parse("INSERT INTO t (a, [b]) VALUES (1, 'it''s'), (-2.5e-1, 'x')"),
+      Some(Statement::Insert(Insert {
+        table_name: "t",
+        column_names: Some(vec!["a", "b"]),
+        rows: vec![
+          vec![Value::Integer(1), Value::Text("it's".to_string())],
+          vec![Value::Real(-0.25), Value::Text("x".to_string())],
+        ],
+      }))
+    );
+    assert_eq!(
+      parse("UPDATE t SET a = 0x10 WHERE b IS NULL"),
+      Some(Statement::Update(Update {
+        table_name: "t",
+        assignments: vec![("a", Value::Integer(16))],
+        condition: Some("b IS NULL"),
+      }))
+    );
+    assert_eq!(
+      parse("DELETE FROM t"),
+      Some(Statement::Delete(Delete {
+        table_name: "t",
+        condition: None,
+      }))
+    );
+  }
+
+  #[test]
+  fn malformed_statements_do_not_parse() {
+    for sql in [
+      "SELECT",
+      "SELECT a FROM",
+      "SELECT a FROM t OFFSET 1",
+      "SELECT 'a FROM t",
+      "SELECT (a FROM t",
+      "SELECT a FROM t; SELECT b FROM t",
+      "SELECTa FROM t",
+      "CREATE TABLE t ()",
+      "CREATE TABLE t (a) STRICT,",
+      "INSERT INTO t VALUES ()",
+      "INSERT INTO t VALUES (a)",
+      "UPDATE t SET a = b",
+      "DELETE FROM t LIMIT 1",
+      "DROP TABLE t",
+    ] {
+      assert_eq!(parse(sql), None, "{}", sql);
+    }
+  }
+}
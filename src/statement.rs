@@ -0,0 +1,92 @@
+use crate::{
+  database::Database,
+  error::{Error, Result},
+  query::{Query, ResolvedSelect},
+  record::Value,
+  row::ResultSet,
+};
+
+/// A select query parsed and resolved against the schema once, to be executed repeatedly with
+/// different values bound to its `?` placeholders.
+#[derive(Debug, Clone)]
+pub struct Statement {
+  resolved: ResolvedSelect,
+  parameter_count: usize,
+  schema_cookie: u32,
+}
+
+impl Statement {
+  pub fn prepare(db: &Database, sql: &str) -> Result<Self> {
+    let select = match Query::parse(sql)? {
+      Query::Select(select) => select,
+      _ => return Err(Error::UnsupportedSql(sql.to_owned())),
+    };
+    Ok(Statement {
+      resolved: select.resolve(db)?,
+      parameter_count: select.parameter_count(),
+      schema_cookie: db.header.schema_cookie,
+    })
+  }
+
+  pub fn parameter_count(&self) -> usize {
+    self.parameter_count
+  }
+
+  /// Run the statement with `params` bound to its placeholders. Fails with
+  /// [`Error::SchemaChanged`] if the schema in the file changed since `db` was opened, as the
+  /// resolved root pages and columns may no longer be valid.
+  pub fn execute<R>(&self, db: &Database, mut file: R, params: &[Value]) -> Result<ResultSet>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    if params.len() != self.parameter_count {
+      return Err(Error::ParameterCount {
+        expected: self.parameter_count,
+        found: params.len(),
+      });
+    }
+    if db.read_schema_cookie(&mut file)? != self.schema_cookie {
+      return Err(Error::SchemaChanged);
+    }
+
+    self.resolved.execute(db, file, params)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::{Cursor, Seek, SeekFrom, Write};
+
+  use super::Statement;
+  use crate::{database::Database, error::Error, record::Value};
+
+  #[test]
+  fn execute_with_parameters() {
+    let data = std::fs::read("tests/fixtures/interior.db").unwrap();
+    let mut file = Cursor::new(data);
+    let db = Database::parse_header_and_schema(&mut file).unwrap();
+
+    let statement = Statement::prepare(&db, "SELECT username FROM users WHERE email = ?").unwrap();
+    assert_eq!(statement.parameter_count(), 1);
+    for (email, username) in [("bob@example.com", "Bob"), ("dave18@example.com", "Dave18")] {
+      let result_set = statement
+        .execute(&db, &mut file, &[Value::Text(email.to_string())])
+        .unwrap();
+      assert_eq!(result_set.rows.len(), 1);
+      assert_eq!(result_set.rows[0].get::<String>(0).unwrap(), username);
+    }
+    assert!(matches!(
+      statement.execute(&db, &mut file, &[]),
+      Err(Error::ParameterCount { expected: 1, found: 0 })
+    ));
+
+    // Bump the schema cookie, as a schema change by another connection would.
+    let cookie = db.header.schema_cookie + 1;
+    file.seek(SeekFrom::Start(40)).unwrap();
+    file.write_all(&cookie.to_be_bytes()).unwrap();
+    assert!(matches!(
+      statement.execute(&db, &mut file, &[Value::Text("bob@example.com".to_string())]),
+      Err(Error::SchemaChanged)
+    ));
+  }
+}
@@ -0,0 +1,212 @@
+//! Space used by each table and index, a small version of `sqlite3_analyzer`: how many entries
+//! and pages of each kind each b-tree has, how much payload it holds and how full its pages are.
+
+use std::fmt::{self, Display};
+
+use crate::{
+  btree::{BtreePage, BtreeVisitor},
+  database::{Database, ObjectSchema},
+  error::Result,
+};
+
+/// The shape of one b-tree, gathered by walking it.
+#[derive(Debug, Clone, Default)]
+pub struct BtreeStats {
+  pub name: String,
+  /// "table" or "index".
+  pub kind: &'static str,
+  /// The rows of a table, or the entries of an index or WITHOUT ROWID table.
+  pub rows: usize,
+  pub leaf_pages: usize,
+  pub interior_pages: usize,
+  pub overflow_pages: usize,
+  /// The payload of every entry, including the part on overflow pages.
+  pub payload_bytes: usize,
+  /// The number of levels of pages, 1 for a tree that's only a root leaf.
+  pub depth: usize,
+  /// The unused bytes of its leaf and interior pages.
+  pub free_bytes: usize,
+  /// The usable bytes of its leaf and interior pages.
+  pub page_bytes: usize,
+}
+
+impl BtreeStats {
+  pub fn average_row_size(&self) -> f64 {
+    if self.rows == 0 {
+      0.0
+    } else {
+      self.payload_bytes as f64 / self.rows as f64
+    }
+  }
+
+  /// The fraction of the usable bytes of its leaf and interior pages that's in use.
+  pub fn fill_factor(&self) -> f64 {
+    if self.page_bytes == 0 {
+      0.0
+    } else {
+      1.0 - self.free_bytes as f64 / self.page_bytes as f64
+    }
+  }
+}
+
+impl BtreeVisitor for BtreeStats {
+  fn enter_interior(&mut self, page: &BtreePage) {
+    self.interior_pages += 1;
+    self.payload_bytes += page.payload_bytes;
+    self.free_bytes += page.free_bytes;
+  }
+
+  fn enter_leaf(&mut self, page: &BtreePage) {
+    self.leaf_pages += 1;
+    self.depth = self.depth.max(page.depth);
+    self.payload_bytes += page.payload_bytes;
+    self.free_bytes += page.free_bytes;
+  }
+
+  fn leaf_cell(&mut self, _row_id: Option<i64>, _record: &crate::record::Record) {
+    self.rows += 1;
+  }
+
+  fn overflow(&mut self, _page: usize, _len: usize) {
+    self.overflow_pages += 1;
+  }
+}
+
+/// The statistics of every b-tree in a database, with the size of the file and its freelist.
+#[derive(Debug, Clone)]
+pub struct DatabaseStats {
+  pub page_size: usize,
+  pub page_count: usize,
+  pub freelist_pages: usize,
+  pub btrees: Vec<BtreeStats>,
+}
+
+impl Database {
+  /// Walk every b-tree of the database, the schema table's first, for [`DatabaseStats`].
+  pub fn stats<R>(&self, mut file: R) -> Result<DatabaseStats>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let schema_table = std::iter::once(("sqlite_schema", "table", 1));
+    let objects = self.schema.objects.iter().filter_map(|object| match object {
+      ObjectSchema::Table(table) => Some((table.name.as_str(), "table", table.root_page)),
+      ObjectSchema::Index(index) => Some((index.name.as_str(), "index", index.root_page)),
+      _ => None,
+    });
+
+    let mut btrees = Vec::new();
+    for (name, kind, root_page) in schema_table.chain(objects) {
+      let mut stats = BtreeStats {
+        name: name.to_string(),
+        kind,
+        ..BtreeStats::default()
+      };
+      self.walk_btree(&mut file, root_page, &mut stats)?;
+      stats.page_bytes = (stats.leaf_pages + stats.interior_pages) * self.usable_page_size();
+      btrees.push(stats);
+    }
+    Ok(DatabaseStats {
+      page_size: self.header.page_size,
+      page_count: self.header.size_in_pages,
+      freelist_pages: self.header.num_freelist_pages,
+      btrees,
+    })
+  }
+}
+
+/// An aligned table with a line for each b-tree, then the totals for the file.
+impl Display for DatabaseStats {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let header = ["name", "kind", "rows", "leaf", "interior", "overflow", "payload", "avg row", "depth", "fill"];
+    let lines = self.btrees.iter().map(|stats| {
+      [
+        stats.name.clone(),
+        stats.kind.to_string(),
+        stats.rows.to_string(),
+        stats.leaf_pages.to_string(),
+        stats.interior_pages.to_string(),
+        stats.overflow_pages.to_string(),
+        stats.payload_bytes.to_string(),
+        format!("{:.1}", stats.average_row_size()),
+        stats.depth.to_string(),
+        format!("{:.1}%", 100.0 * stats.fill_factor()),
+      ]
+    });
+    let lines = std::iter::once(header.map(str::to_string)).chain(lines).collect::<Vec<_>>();
+    let mut widths = [0; 10];
+    for line in lines.iter() {
+      for (width, field) in widths.iter_mut().zip(line.iter()) {
+        *width = (*width).max(field.chars().count());
+      }
+    }
+    for line in lines.iter() {
+      // Names and kinds are aligned left, numbers right.
+      let fields = line.iter().zip(widths).enumerate().map(|(i, (field, width))| match i {
+        0 | 1 => format!("{:<width$}", field, width = width),
+        _ => format!("{:>width$}", field, width = width),
+      });
+      writeln!(f, "{}", fields.collect::<Vec<_>>().join("  ").trim_end())?;
+    }
+    writeln!(f)?;
+    writeln!(f, "page size: {}", self.page_size)?;
+    writeln!(f, "page count: {}", self.page_count)?;
+    writeln!(f, "freelist pages: {} ({} bytes)", self.freelist_pages, self.freelist_pages * self.page_size)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn database_stats(path: &str) -> DatabaseStats {
+    let file = std::fs::File::open(path).unwrap();
+    let db = Database::parse_header_and_schema(&file).unwrap();
+    db.stats(&file).unwrap()
+  }
+
+  /// The row count, page counts, payload, depth and free bytes of `stats`.
+  fn shape(stats: &BtreeStats) -> (usize, usize, usize, usize, usize, usize, usize) {
+    let pages = (stats.leaf_pages, stats.interior_pages, stats.overflow_pages);
+    (stats.rows, pages.0, pages.1, pages.2, stats.payload_bytes, stats.depth, stats.free_bytes)
+  }
+
+  #[test]
+  fn btrees_are_measured_as_dbstat_measures_them() {
+    // The figures of sqlite3's dbstat table for each b-tree.
+    let stats = database_stats("tests/fixtures/interior.db");
+    let names = stats.btrees.iter().map(|btree| (btree.name.as_str(), btree.kind)).collect::<Vec<_>>();
+    assert_eq!(names, [("sqlite_schema", "table"), ("users", "table"), ("idx_users_email", "index")]);
+    assert_eq!(shape(&stats.btrees[0]), (2, 1, 0, 0, 183, 1, 213));
+    assert_eq!(shape(&stats.btrees[1]), (22, 2, 1, 0, 640, 2, 773));
+    assert_eq!(shape(&stats.btrees[2]), (22, 2, 1, 0, 473, 2, 965));
+    assert_eq!((stats.page_count, stats.freelist_pages), (7, 0));
+
+    // Overflow pages hold most of the payload, which counts in full.
+    let stats = database_stats("tests/fixtures/documents.db");
+    assert_eq!(shape(&stats.btrees[1]), (3, 3, 1, 6, 30768, 2, 10091));
+    assert!((stats.btrees[1].average_row_size() - 10256.0).abs() < 1e-9);
+  }
+
+  #[test]
+  fn stats_are_printed_as_an_aligned_table() {
+    let stats = database_stats("tests/fixtures/interior.db");
+    let fill = 100.0 * (1.0 - 773.0 / (3.0 * stats.page_size as f64));
+    assert_eq!(
+      stats.to_string(),
+      format!(
+        "name             kind   rows  leaf  interior  overflow  payload  avg row  depth   fill\n\
+         sqlite_schema    table     2     1         0         0      183     91.5      1  {:.1}%\n\
+         users            table    22     2         1         0      640     29.1      2  {:.1}%\n\
+         idx_users_email  index    22     2         1         0      473     21.5      2  {:.1}%\n\
+         \n\
+         page size: {}\n\
+         page count: 7\n\
+         freelist pages: 0 (0 bytes)\n",
+        100.0 * (1.0 - 213.0 / stats.page_size as f64),
+        fill,
+        100.0 * (1.0 - 965.0 / (3.0 * stats.page_size as f64)),
+        stats.page_size,
+      )
+    );
+  }
+}
@@ -0,0 +1,165 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use crate::{
+  database::{Database, PageData},
+  error::Result,
+  record::RecordType,
+};
+
+const LOCK_BYTE_OFFSET: usize = 1 << 30;
+
+/// Pages modified by a write. They are kept in memory until the write is committed, so that a
+/// write failing part way, e.g. on a full page, leaves the file untouched.
+pub struct Transaction<'a> {
+  db: &'a Database,
+  pages: BTreeMap<usize, Arc<Vec<u8>>>,
+}
+
+impl<'a> Transaction<'a> {
+  pub fn new(db: &'a Database) -> Self {
+    Transaction {
+      db,
+      pages: BTreeMap::new(),
+    }
+  }
+
+  /// The contents of a page, including the changes made to it by this transaction.
+  pub fn page<R>(&self, file: R, page_index: usize, kind: RecordType) -> Result<PageData>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    match self.pages.get(&page_index) {
+      Some(data) => Ok(PageData::Owned(data.clone())),
+      None => self.db.read_page_data(file, page_index, kind),
+    }
+  }
+
+  /// The contents of a page to modify, which are written back to the file on commit.
+  pub fn page_mut<R>(&mut self, file: R, page_index: usize, kind: RecordType) -> Result<&mut Vec<u8>>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    if !self.pages.contains_key(&page_index) {
+      let data = self.db.read_page_data(file, page_index, kind)?;
+      self.pages.insert(page_index, Arc::new(data.to_vec()));
+    }
+    Ok(Arc::make_mut(self.pages.get_mut(&page_index).unwrap()))
+  }
+
+  /// Allocate a page, reusing one from the freelist if it isn't empty and otherwise extending
+  /// the file. The returned page is zeroed and must be initialized by the caller.
+  pub fn allocate_page<R>(&mut self, mut file: R) -> Result<usize>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let page_size = self.db.header.page_size;
+    let header = self.page_mut(&mut file, 1, RecordType::Table)?;
+    let first_trunk_page = read_u32(header, 32) as usize;
+    let freelist_pages = read_u32(header, 36);
+
+    let page_index = if first_trunk_page != 0 {
+      // A trunk page lists the next trunk page, then the number of leaf pages and their numbers.
+      let trunk = self.page_mut(&mut file, first_trunk_page, RecordType::Table)?;
+      let leaf_count = read_u32(trunk, 4) as usize;
+      let page_index = if leaf_count > 0 {
+        let leaf = read_u32(trunk, 8 + 4 * (leaf_count - 1)) as usize;
+        write_u32(trunk, 4, leaf_count as u32 - 1);
+        leaf
+      } else {
+        let next_trunk_page = read_u32(trunk, 0);
+        let header = self.page_mut(&mut file, 1, RecordType::Table)?;
+        write_u32(header, 32, next_trunk_page);
+        first_trunk_page
+      };
+      let header = self.page_mut(&mut file, 1, RecordType::Table)?;
+      write_u32(header, 36, freelist_pages - 1);
+      page_index
+    } else {
+      let header = self.page_mut(&mut file, 1, RecordType::Table)?;
+      let mut page_index = read_u32(header, 28) as usize + 1;
+      // The page holding the byte at offset 2^30 is used for locking and is never allocated.
+      if page_index == LOCK_BYTE_OFFSET / page_size + 1 {
+        page_index += 1;
+      }
+      write_u32(header, 28, page_index as u32);
+      page_index
+    };
+
+    self
+      .pages
+      .insert(page_index, Arc::new(vec![0; page_size]));
+    Ok(page_index)
+  }
+
+  /// Add a page that is no longer used to the freelist.
+  pub fn free_page<R>(&mut self, mut file: R, page_index: usize) -> Result<()>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let page_size = self.db.header.page_size;
+    // Like sqlite, leave some room on trunk pages for compatibility with older versions.
+    let max_leaf_count = (page_size - self.db.header.end_page_reserved_bytes) / 4 - 8;
+    let header = self.page_mut(&mut file, 1, RecordType::Table)?;
+    let first_trunk_page = read_u32(header, 32) as usize;
+    let freelist_pages = read_u32(header, 36);
+    write_u32(header, 36, freelist_pages + 1);
+
+    if first_trunk_page != 0 {
+      let trunk = self.page_mut(&mut file, first_trunk_page, RecordType::Table)?;
+      let leaf_count = read_u32(trunk, 4) as usize;
+      if leaf_count < max_leaf_count {
+        write_u32(trunk, 8 + 4 * leaf_count, page_index as u32);
+        write_u32(trunk, 4, leaf_count as u32 + 1);
+        return Ok(());
+      }
+    }
+
+    // The freed page becomes the first trunk page, with no leaves yet.
+    let mut trunk = vec![0; page_size];
+    write_u32(&mut trunk, 0, first_trunk_page as u32);
+    self.pages.insert(page_index, Arc::new(trunk));
+    let header = self.page_mut(&mut file, 1, RecordType::Table)?;
+    write_u32(header, 32, page_index as u32);
+    Ok(())
+  }
+
+  /// Increment the schema cookie in the header, telling other connections that the schema
+  /// changed.
+  pub fn increment_schema_cookie<R>(&mut self, file: R) -> Result<()>
+    where
+      R: std::io::Read + std::io::Seek,
+  {
+    let header = self.page_mut(file, 1, RecordType::Table)?;
+    let schema_cookie = read_u32(header, 40).wrapping_add(1);
+    write_u32(header, 40, schema_cookie);
+    Ok(())
+  }
+
+  /// Write the modified pages back to `file`, or add them to the database's open transaction,
+  /// incrementing the file change counter in the header so that other connections know to
+  /// discard their cached pages.
+  pub fn commit<F>(mut self, mut file: F) -> Result<()>
+    where
+      F: std::io::Read + std::io::Write + std::io::Seek,
+  {
+    if self.pages.is_empty() {
+      return Ok(());
+    }
+
+    let header = self.page_mut(&mut file, 1, RecordType::Table)?;
+    let change_counter = read_u32(header, 24).wrapping_add(1);
+    write_u32(header, 24, change_counter);
+    // The version-valid-for number tells readers that the page count in the header is current.
+    write_u32(header, 92, change_counter);
+
+    self.db.write_pages(file, &self.pages)
+  }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+  u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn write_u32(data: &mut [u8], offset: usize, value: u32) {
+  data[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+}
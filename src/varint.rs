@@ -1,13 +1,55 @@
-use nom::IResult;
+use nom::{
+  error::{Error, ErrorKind},
+  IResult,
+};
 
 pub fn varint(input: &[u8]) -> IResult<&[u8], i64> {
-  let mut i = 0;
-  let mut value: i64 = (input[i] as i64) & 0x7f;
-  while high_bit(input[i]) && i < 8 {
-    i += 1;
-    value = (value << 7) | ((input[i] as i64) & 0x7f);
+  let byte = |i: usize| {
+    input
+      .get(i)
+      .copied()
+      .ok_or_else(|| nom::Err::Error(Error::new(input, ErrorKind::Eof)))
+  };
+  let mut value: i64 = 0;
+  for i in 0..8 {
+    value = (value << 7) | ((byte(i)? as i64) & 0x7f);
+    if !high_bit(byte(i)?) {
+      return Ok((&input[i + 1..], value));
+    }
   }
-  Ok((&input[i + 1..], value))
+  // The ninth byte holds 8 bits.
+  value = (value << 8) | byte(8)? as i64;
+  Ok((&input[9..], value))
+}
+
+/// Append the big-endian variable-length encoding of `value` to `output`, using the 9 byte form,
+/// whose last byte holds 8 bits, for values that don't fit in 56 bits.
+pub fn write_varint(value: i64, output: &mut Vec<u8>) {
+  let value = value as u64;
+  if value >> 56 != 0 {
+    let mut bytes = [0u8; 9];
+    bytes[8] = value as u8;
+    let mut rest = value >> 8;
+    for byte in bytes[..8].iter_mut().rev() {
+      *byte = 0x80 | (rest & 0x7f) as u8;
+      rest >>= 7;
+    }
+    output.extend_from_slice(&bytes);
+    return;
+  }
+
+  let mut bytes = Vec::with_capacity(8);
+  let mut rest = value;
+  loop {
+    bytes.push(0x80 | (rest & 0x7f) as u8);
+    rest >>= 7;
+    if rest == 0 {
+      break;
+    }
+  }
+  // The last byte (the first one pushed) has its high bit clear.
+  bytes[0] &= 0x7f;
+  output.extend(bytes.iter().rev());
 }
 
 fn high_bit(byte: u8) -> bool {
@@ -16,7 +58,7 @@ fn high_bit(byte: u8) -> bool {
 
 #[cfg(test)]
 mod tests {
-  use super::{high_bit, varint};
+  use super::{high_bit, varint, write_varint};
 
   #[test]
   fn test_high_bit() {
@@ -63,7 +105,9 @@ mod tests {
     let input = &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
     let (rest, value) = varint(input).unwrap();
     assert!(rest.is_empty());
-    assert_eq!(value, 9223372036854775807);
+    assert_eq!(value, -1);
+    let input = &[0xbf, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+    assert_eq!(varint(input).unwrap().1, 9223372036854775807);
   }
 
   #[test]
@@ -71,6 +115,54 @@ mod tests {
     let input = &[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xab];
     let (rest, value) = varint(input).unwrap();
     assert!(rest.len() == 1 && rest[0] == 0xab);
-    assert_eq!(value, 9223372036854775807);
+    assert_eq!(value, -1);
+  }
+
+  #[test]
+  fn truncated_input_is_an_error() {
+    assert!(varint(&[]).is_err());
+    assert!(varint(&[0x87]).is_err());
+    assert!(varint(&[0xff; 8]).is_err());
+  }
+
+  #[test]
+  fn varints_are_read_one_after_another() {
+    // The old context/ engine's varint tests. The ninth byte adds all 8 of its bits.
+    let ninth_byte = (1..=8).map(|i| 1i64 << (7 * i + 1)).sum::<i64>() + 1;
+    for (input, values, rest) in [
+      (&[0x01, 0xff][..], &[1][..], &[0xff][..]),
+      (&[0x81, 0x7f, 0x00], &[255], &[0x00]),
+      (&[0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0x81, 0x01], &[ninth_byte], &[]),
+      (&[0x7f], &[127], &[]),
+      (&[0x02, 0x82, 0x01, 0x81, 0x01], &[2, 257, 129], &[]),
+      (&[0x03, 0xff, 0xee], &[3], &[0xff, 0xee]),
+    ] {
+      let mut input = input;
+      for expected in values {
+        let (next, value) = varint(input).unwrap();
+        assert_eq!(value, *expected);
+        input = next;
+      }
+      assert_eq!(input, rest);
+    }
+    assert!(varint(&[0x80]).is_err());
+  }
+
+  #[test]
+  fn write_round_trips() {
+    for value in [0, 0x15, 127, 128, 1000, 1194265, 170307943, (1 << 56) - 1, 1 << 56, i64::MAX, i64::MIN, -1] {
+      let mut output = Vec::new();
+      write_varint(value, &mut output);
+      let (rest, decoded) = varint(&output).unwrap();
+      assert!(rest.is_empty());
+      assert_eq!(decoded, value);
+    }
+
+    let mut output = Vec::new();
+    write_varint(1000, &mut output);
+    assert_eq!(output, [0x87, 0x68]);
+    output.clear();
+    write_varint(-1, &mut output);
+    assert_eq!(output, [0xff; 9]);
   }
 }
@@ -0,0 +1,172 @@
+//! Golden-output tests, running the same commands through this program and the sqlite3 CLI and
+//! comparing what they print. They're skipped when sqlite3 isn't installed.
+
+use std::process::Command;
+
+fn sqlite3_available() -> bool {
+  let available = Command::new("sqlite3").arg("--version").output().is_ok();
+  if !available {
+    eprintln!("sqlite3 not found, skipping");
+  }
+  available
+}
+
+fn ours(path: &str, command: &str) -> String {
+  let output = Command::new(env!("CARGO_BIN_EXE_sqlite-starter-rust")).arg(path).arg(command).output().unwrap();
+  assert!(output.status.success(), "{} {}: {}", path, command, String::from_utf8_lossy(&output.stderr));
+  String::from_utf8(output.stdout).unwrap()
+}
+
+fn sqlite3(path: &str, command: &str) -> String {
+  let output = Command::new("sqlite3").arg("-readonly").arg(path).arg(command).output().unwrap();
+  assert!(output.status.success(), "{} {}: {}", path, command, String::from_utf8_lossy(&output.stderr));
+  String::from_utf8(output.stdout).unwrap()
+}
+
+/// A unified diff of the lines of `expected` and `found`, as a single hunk over the whole output.
+fn unified_diff(expected: &str, found: &str) -> String {
+  let a = expected.lines().collect::<Vec<_>>();
+  let b = found.lines().collect::<Vec<_>>();
+  // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..].
+  let mut lcs = vec![vec![0; b.len() + 1]; a.len() + 1];
+  for i in (0..a.len()).rev() {
+    for j in (0..b.len()).rev() {
+      lcs[i][j] = if a[i] == b[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+    }
+  }
+
+  let mut diff = format!("--- sqlite3\n+++ ours\n@@ -1,{} +1,{} @@\n", a.len(), b.len());
+  let (mut i, mut j) = (0, 0);
+  while i < a.len() || j < b.len() {
+    if i < a.len() && j < b.len() && a[i] == b[j] {
+      diff += &format!(" {}\n", a[i]);
+      i += 1;
+      j += 1;
+    } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+      diff += &format!("-{}\n", a[i]);
+      i += 1;
+    } else {
+      diff += &format!("+{}\n", b[j]);
+      j += 1;
+    }
+  }
+  diff
+}
+
+fn assert_same_output(path: &str, command: &str, expected: &str, found: &str) {
+  assert!(expected == found, "{} {:?} differs:\n{}", path, command, unified_diff(expected, found));
+}
+
+fn fixtures() -> Vec<String> {
+  let mut paths = std::fs::read_dir("tests/fixtures")
+    .unwrap()
+    .map(|entry| entry.unwrap().path().to_str().unwrap().to_owned())
+    .filter(|path| path.ends_with(".db"))
+    .collect::<Vec<_>>();
+  paths.sort();
+  paths
+}
+
+#[test]
+fn tables_match_sqlite3() {
+  if !sqlite3_available() {
+    return;
+  }
+  for path in fixtures() {
+    assert_same_output(&path, ".tables", &sqlite3(&path, ".tables"), &ours(&path, ".tables"));
+  }
+}
+
+#[test]
+fn shared_dbinfo_fields_match_sqlite3() {
+  if !sqlite3_available() {
+    return;
+  }
+  // sqlite3 prints more fields, aligned; only the values of those both print are compared.
+  let shared_fields = |output: &str| {
+    output
+      .lines()
+      .filter_map(|line| line.split_once(':'))
+      .filter(|(field, _)| {
+        ["database page size", "database page count", "schema format", "number of tables"].contains(field)
+      })
+      .map(|(field, value)| format!("{}: {}\n", field, value.trim()))
+      .collect::<String>()
+  };
+  for path in fixtures() {
+    let expected = shared_fields(&sqlite3(&path, ".dbinfo"));
+    assert_eq!(expected.lines().count(), 4, "{}", path);
+    assert_same_output(&path, ".dbinfo", &expected, &shared_fields(&ours(&path, ".dbinfo")));
+  }
+}
+
+#[test]
+fn selects_match_sqlite3() {
+  if !sqlite3_available() {
+    return;
+  }
+  for (path, sql) in [
+    ("tests/fixtures/interior.db", "SELECT id, username, age FROM users"),
+    ("tests/fixtures/interior.db", "SELECT username, email FROM users WHERE age > 30 ORDER BY email DESC"),
+    ("tests/fixtures/interior.db", "SELECT count(*) FROM users"),
+    ("tests/fixtures/companies.db", "SELECT id, name FROM companies WHERE country = 'country_123'"),
+    ("tests/fixtures/rowid_alias.db", "SELECT rowid, id, body FROM notes"),
+    ("tests/fixtures/reals.db", "SELECT id, value, label FROM readings"),
+    ("tests/fixtures/reals.db", "SELECT max(value) FROM readings"),
+    ("tests/fixtures/nocase.db", "SELECT name FROM people ORDER BY name"),
+    ("tests/fixtures/views.db", "SELECT name FROM sqlite_master"),
+    ("tests/fixtures/triggers.db", "SELECT type, name, tbl_name FROM sqlite_master"),
+    ("tests/fixtures/incremental_vacuum.db", "PRAGMA integrity_check"),
+    ("tests/fixtures/generated.db", "SELECT id, price, total, code, note, half, size FROM orders"),
+    ("tests/fixtures/generated.db", "PRAGMA table_info(orders)"),
+    ("tests/fixtures/added_columns.db", "SELECT id, name, price, added, stock, note, weight, label FROM items"),
+    ("tests/fixtures/added_columns.db", "PRAGMA table_info(items)"),
+    ("tests/fixtures/strict.db", "SELECT id, sensor, value, extra FROM readings WHERE sensor = 'a'"),
+    ("tests/fixtures/strict.db", "PRAGMA integrity_check"),
+    ("tests/fixtures/empty.db", "SELECT * FROM empty"),
+    ("tests/fixtures/empty.db", "SELECT count(*) FROM empty"),
+    ("tests/fixtures/empty.db", "SELECT * FROM notes"),
+    ("tests/fixtures/blobs.db", "SELECT id, name, length(data), typeof(data) FROM files"),
+    ("tests/fixtures/blobs.db", "SELECT name FROM files ORDER BY data"),
+  ] {
+    assert_same_output(path, sql, &sqlite3(path, sql), &ours(path, sql));
+  }
+}
+
+#[test]
+fn diff_exits_with_whether_databases_differ() {
+  let run = |a: &str, b: &str| {
+    Command::new(env!("CARGO_BIN_EXE_sqlite-starter-rust")).args(["diff", a, b]).output().unwrap()
+  };
+  // Tables without a rowid are matched on their primary key.
+  let output = run("tests/fixtures/strict.db", "tests/fixtures/strict.db");
+  assert_eq!(output.status.code(), Some(0), "{}", String::from_utf8_lossy(&output.stdout));
+  assert!(output.stdout.is_empty());
+
+  let output = run("tests/fixtures/strict.db", "tests/fixtures/strict_changed.db");
+  assert_eq!(output.status.code(), Some(1));
+  assert_eq!(
+    String::from_utf8(output.stdout).unwrap(),
+    "sensors: - row ('a') ('a', 'roof', 2021)\n\
+     sensors: ~ row ('b'): location 'cellar' -> 'attic'\n\
+     sensors: + row ('c') ('c', 'shed', 2024)\n\
+     tags: + row ('green') ('green', 2)\n"
+  );
+}
+
+#[test]
+fn batch_mode_prints_only_the_output() {
+  let run = |args: &[&str]| Command::new(env!("CARGO_BIN_EXE_sqlite-starter-rust")).args(args).output().unwrap();
+  // The schema spans several pages, with an interior page 1.
+  let path = "tests/fixtures/schema.db";
+  let output = run(&["--batch", path, ".dbinfo"]);
+  assert!(output.status.success());
+  assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+  assert!(String::from_utf8(output.stdout).unwrap().contains("number of tables: 201\n"));
+
+  for command in [".tables", "SELECT count(*) FROM t000", "SELECT name FROM sqlite_master WHERE type = 'index'"] {
+    let output = run(&["--batch", path, command]);
+    assert!(output.stderr.is_empty(), "{}: {}", command, String::from_utf8_lossy(&output.stderr));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), ours(path, command), "{}", command);
+  }
+}